@@ -0,0 +1,103 @@
+//! Measures the throughput of `zlib::inflate`, both on the images of the PngSuite and on large
+//! synthetic images
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use png_inflate::png::ChunkReader;
+use png_inflate::zlib;
+use std::fs::File;
+use std::hint::black_box;
+
+#[path = "../src/test_util/random.rs"]
+mod random;
+
+/// The concatenated IDAT data of each image in the PngSuite
+fn pngsuite_streams() -> Vec<Vec<u8>> {
+	let mut paths: Vec<_> =
+		std::fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/PngSuite"))
+			.expect("could not read PngSuite directory")
+			.map(|entry| entry.expect("could not read PngSuite directory").path())
+			// files beginning with `x` are intentionally corrupt
+			.filter(|path| path.extension().is_some_and(|x| x == "png"))
+			.filter(|path| !path.file_name().unwrap().to_string_lossy().starts_with('x'))
+			.collect();
+	paths.sort();
+	paths
+		.into_iter()
+		.map(|path| {
+			let file = File::open(path).expect("could not open file");
+			ChunkReader::new(file)
+				.expect("could not read file")
+				.map(|chunk| chunk.expect("could not read chunk"))
+				.filter(|chunk| chunk.typ == *b"IDAT")
+				.flat_map(|chunk| chunk.data)
+				.collect()
+		})
+		.filter(|stream: &Vec<u8>| zlib::inflate(stream).is_ok())
+		.collect()
+}
+
+/// The filtered rows of a 1024x1024 RGB image consisting of smooth gradients with some noise
+fn synthetic_image() -> Vec<u8> {
+	const SIZE: usize = 1024;
+	let mut bytes = random::pseudorandom(0x2545_F491, SIZE * SIZE).into_iter();
+	let mut retval = Vec::with_capacity(SIZE * (1 + 3 * SIZE));
+	for y in 0..SIZE {
+		// filter type Sub, so that the gradient becomes mostly-repeated small differences
+		retval.push(1);
+		for x in 0..SIZE {
+			let byte = bytes.next().expect("one random byte per pixel");
+			let noise = if byte < 32 { byte & 3 } else { 0 };
+			retval.push(u8::from(x % 4 == 0).wrapping_add(noise));
+			retval.push(u8::from(y % 2 == 0));
+			retval.push(noise);
+		}
+	}
+	retval
+}
+
+fn pngsuite(c: &mut Criterion) {
+	let streams = pngsuite_streams();
+	let inflated_size: usize = streams
+		.iter()
+		.map(|x| zlib::inflate(x).unwrap().len())
+		.sum();
+
+	let mut group = c.benchmark_group("pngsuite");
+	group.throughput(Throughput::Bytes(inflated_size as u64));
+	group.bench_function("inflate", |b| {
+		b.iter(|| {
+			for stream in &streams {
+				black_box(zlib::inflate(black_box(stream)).unwrap());
+			}
+		})
+	});
+	group.finish();
+}
+
+fn synthetic(c: &mut Criterion) {
+	let image = synthetic_image();
+
+	let mut group = c.benchmark_group("synthetic");
+	group.throughput(Throughput::Bytes(image.len() as u64));
+	group.sample_size(20);
+	let streams = [
+		("immediate", zlib::deflate_immediate(&image)),
+		(
+			"fastest",
+			zlib::deflate(&image, zlib::CompressionLevel::Fastest),
+		),
+		(
+			"slowest",
+			zlib::deflate(&image, zlib::CompressionLevel::Slowest),
+		),
+	];
+	for (name, stream) in &streams {
+		group.bench_with_input(BenchmarkId::new("inflate", name), stream, |b, stream| {
+			b.iter(|| zlib::inflate(black_box(stream)).unwrap())
+		});
+	}
+	group.finish();
+}
+
+criterion_group!(benches, pngsuite, synthetic);
+criterion_main!(benches);
@@ -91,8 +91,14 @@ pub fn generate_for_each_files(_input: TokenStream) -> TokenStream {
 	let mut cases_apng: Vec<PathBuf> = Vec::new();
 	let mut cases_badmagic: Vec<PathBuf> = Vec::new();
 	let mut cases_badchecksum: Vec<PathBuf> = Vec::new();
+	let mut cases_badcolortype: Vec<PathBuf> = Vec::new();
+	let mut cases_badbitdepth: Vec<PathBuf> = Vec::new();
 	let mut cases_otherinvalid: Vec<PathBuf> = Vec::new(); // cases that are invalid, but not in a way that png_inflate cares about
 	let mut cases_unsafecopy: Vec<PathBuf> = Vec::new();
+	let mut cases_chunkafteriend: Vec<PathBuf> = Vec::new();
+	let mut cases_bytesafteriend: Vec<PathBuf> = Vec::new();
+	let mut cases_nonconsecutiveidat: Vec<PathBuf> = Vec::new();
+	let mut cases_widezlibwindow: Vec<PathBuf> = Vec::new();
 
 	if let Ok(entries) = ::std::fs::read_dir(pngsuite_dir) {
 		for entry in entries {
@@ -110,6 +116,10 @@ pub fn generate_for_each_files(_input: TokenStream) -> TokenStream {
 						cases_badmagic.push(entry);
 					} else if file_stem[0..3] == *"xhd" || file_stem[0..3] == *"xcs" {
 						cases_badchecksum.push(entry);
+					} else if file_stem[0..2] == *"xc" {
+						cases_badcolortype.push(entry);
+					} else if file_stem[0..2] == *"xd" && file_stem[0..3] != *"xdt" {
+						cases_badbitdepth.push(entry);
 					} else {
 						cases_otherinvalid.push(entry);
 					}
@@ -123,13 +133,26 @@ pub fn generate_for_each_files(_input: TokenStream) -> TokenStream {
 	cases_apng.push(tests_dir.join("apng_twoframe.png"));
 	cases_valid.push(tests_dir.join("with_custom_safe_to_copy_chunk.png"));
 	cases_unsafecopy.push(tests_dir.join("with_custom_unsafe_to_copy_chunk.png"));
+	cases_chunkafteriend.push(tests_dir.join("with_chunk_after_iend.png"));
+	cases_bytesafteriend.push(tests_dir.join("with_bytes_after_iend.png"));
+	cases_nonconsecutiveidat.push(tests_dir.join("with_nonconsecutive_idats.png"));
+	cases_widezlibwindow.push(tests_dir.join("with_wide_zlib_window.png"));
 
 	tokens![
 		macro_template("for_each_valid_file", &cases_valid),
 		macro_template("for_each_apng_file", &cases_apng),
 		macro_template("for_each_badmagic_file", &cases_badmagic),
 		macro_template("for_each_badchecksum_file", &cases_badchecksum),
+		macro_template("for_each_badcolortype_file", &cases_badcolortype),
+		macro_template("for_each_badbitdepth_file", &cases_badbitdepth),
 		macro_template("for_each_otherinvalid_file", &cases_otherinvalid),
 		macro_template("for_each_unsafecopy_file", &cases_unsafecopy),
+		macro_template("for_each_chunkafteriend_file", &cases_chunkafteriend),
+		macro_template("for_each_bytesafteriend_file", &cases_bytesafteriend),
+		macro_template(
+			"for_each_nonconsecutiveidat_file",
+			&cases_nonconsecutiveidat
+		),
+		macro_template("for_each_widezlibwindow_file", &cases_widezlibwindow),
 	]
 }
@@ -0,0 +1,319 @@
+//! The checksums used by the png, zlib and gzip formats, calculated incrementally
+//!
+//! ```
+//! use png_inflate::checksum::Crc32;
+//!
+//! let mut crc = Crc32::new();
+//! crc.update(b"IE");
+//! crc.update(b"ND");
+//! assert_eq!(0xAE42_6082, crc.finish());
+//! ```
+
+/// The CRC-32 polynomial, with the x^0 term in the MSB
+const CRC_POLYNOMIAL: u32 = 0xEDB8_8320;
+
+/// `CRC_TABLES[0][x]` is the crc register's change after processing the byte `x`, and
+/// `CRC_TABLES[n][x]` is the change after processing the byte `x` followed by `n` zero bytes
+static CRC_TABLES: [[u32; 256]; 8] = crc_tables();
+
+const fn crc_tables() -> [[u32; 256]; 8] {
+	let mut retval = [[0u32; 256]; 8];
+	let mut byte = 0;
+	while byte < 256 {
+		let mut crc = byte as u32;
+		let mut bit = 0;
+		while bit < 8 {
+			crc = if crc & 1 != 0 {
+				CRC_POLYNOMIAL ^ (crc >> 1)
+			} else {
+				crc >> 1
+			};
+			bit += 1;
+		}
+		retval[0][byte] = crc;
+		byte += 1;
+	}
+	let mut table = 1;
+	while table < 8 {
+		let mut byte = 0;
+		while byte < 256 {
+			let previous = retval[table - 1][byte];
+			retval[table][byte] = (previous >> 8) ^ retval[0][(previous & 0xFF) as usize];
+			byte += 1;
+		}
+		table += 1;
+	}
+	retval
+}
+
+/// Calculates the CRC-32 used by png chunks and gzip members
+///
+/// Processes eight bytes at a time, using a lookup table for each byte position.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Crc32 {
+	/// the crc register, which is the complement of the checksum of the data so far
+	state: u32,
+}
+
+impl Crc32 {
+	/// Creates a hasher that has not processed any data yet
+	pub fn new() -> Crc32 {
+		Crc32 { state: u32::MAX }
+	}
+
+	/// Processes `data`, as if it followed the data processed so far
+	pub fn update(&mut self, data: &[u8]) {
+		let tables = &CRC_TABLES;
+		let mut crc = self.state;
+		let mut chunks = data.chunks_exact(8);
+		for chunk in &mut chunks {
+			let low = crc ^ u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+			let high = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+			crc = tables[7][(low & 0xFF) as usize]
+				^ tables[6][((low >> 8) & 0xFF) as usize]
+				^ tables[5][((low >> 16) & 0xFF) as usize]
+				^ tables[4][(low >> 24) as usize]
+				^ tables[3][(high & 0xFF) as usize]
+				^ tables[2][((high >> 8) & 0xFF) as usize]
+				^ tables[1][((high >> 16) & 0xFF) as usize]
+				^ tables[0][(high >> 24) as usize];
+		}
+		for byte in chunks.remainder() {
+			crc = (crc >> 8) ^ tables[0][((crc ^ u32::from(*byte)) & 0xFF) as usize];
+		}
+		self.state = crc;
+	}
+
+	/// The checksum of the data processed so far
+	pub fn finish(&self) -> u32 {
+		!self.state
+	}
+
+	/// Calculates the checksum of `data`
+	pub fn checksum(data: &[u8]) -> u32 {
+		let mut retval = Crc32::new();
+		retval.update(data);
+		retval.finish()
+	}
+}
+
+impl Default for Crc32 {
+	fn default() -> Crc32 {
+		Crc32::new()
+	}
+}
+
+/// The modulus of both of an adler32 checksum's sums
+const ADLER_MODULUS: u32 = 65521;
+/// The largest number of bytes that can be added to the sums before the larger sum could overflow,
+/// starting from sums less than `ADLER_MODULUS`
+const ADLER_MAX_RUN: usize = 5552;
+
+/// Calculates the Adler-32 checksum used by zlib streams
+///
+/// The sums are reduced once per run of several thousand bytes rather than once per byte.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Adler32 {
+	/// one plus the sum of the bytes so far
+	a: u32,
+	/// the sum of the values of `a` after each byte so far
+	b: u32,
+}
+
+impl Adler32 {
+	/// Creates a hasher that has not processed any data yet
+	pub fn new() -> Adler32 {
+		Adler32 { a: 1, b: 0 }
+	}
+
+	/// Processes `data`, as if it followed the data processed so far
+	pub fn update(&mut self, data: &[u8]) {
+		let mut a = self.a;
+		let mut b = self.b;
+		for run in data.chunks(ADLER_MAX_RUN) {
+			for byte in run {
+				a += u32::from(*byte);
+				b += a;
+			}
+			a %= ADLER_MODULUS;
+			b %= ADLER_MODULUS;
+		}
+		self.a = a;
+		self.b = b;
+	}
+
+	/// The checksum of the data processed so far
+	pub fn finish(&self) -> u32 {
+		(self.b << 16) | self.a
+	}
+
+	/// Calculates the checksum of `data`
+	pub fn checksum(data: &[u8]) -> u32 {
+		let mut retval = Adler32::new();
+		retval.update(data);
+		retval.finish()
+	}
+}
+
+impl Default for Adler32 {
+	fn default() -> Adler32 {
+		Adler32::new()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	/// Data without any particular pattern
+	fn sample(length: usize) -> Vec<u8> {
+		crate::test_util::pseudorandom(0x2545_F491, length)
+	}
+
+	mod crc32 {
+		use super::super::Crc32;
+		use super::sample;
+
+		/// Processes one bit at a time
+		fn reference(buffer: &[u8]) -> u32 {
+			const CRC_POLYNOMIAL: u32 = 0xedb8_8320;
+			fn update_crc(crc: u32, message: u8) -> u32 {
+				let message: u32 = u32::from(message);
+				let mut crc = crc ^ message;
+				for _ in 0..8 {
+					crc = (if crc & 1 != 0 { CRC_POLYNOMIAL } else { 0 }) ^ (crc >> 1);
+				}
+				crc
+			}
+
+			buffer
+				.iter()
+				.fold(u32::MAX, |crc, message| update_crc(crc, *message))
+				^ u32::MAX
+		}
+
+		#[test]
+		fn nul() {
+			let val: [u8; 0] = [];
+			let exp: u32 = 0;
+			let res = Crc32::checksum(&val);
+			assert!(exp == res, "{:x} != {:x}", exp, res);
+		}
+
+		#[test]
+		fn iend() {
+			let val: [u8; 4] = [0x49, 0x45, 0x4e, 0x44];
+			let exp: u32 = 0xae426082;
+			let res = Crc32::checksum(&val);
+			assert!(exp == res, "{:x} != {:x}", exp, res);
+		}
+
+		#[test]
+		fn ihdr_1() {
+			#[rustfmt::skip]
+			let val:[u8;17] = [
+				0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x01, 0x2c,
+				0x00, 0x00, 0x00, 0x96, 0x02, 0x03, 0x00, 0x00,
+				0x00
+			];
+			let exp: u32 = 0x19355d41;
+			let res = Crc32::checksum(&val);
+			assert!(exp == res, "{:x} != {:x}", exp, res);
+		}
+
+		#[test]
+		fn matches_reference() {
+			let data = sample(10_000);
+			for length in [1, 7, 8, 9, 15, 16, 17, 100, 10_000] {
+				assert_eq!(
+					reference(&data[..length]),
+					Crc32::checksum(&data[..length]),
+					"{}",
+					length
+				);
+			}
+		}
+
+		#[test]
+		fn update_in_pieces() {
+			let data = sample(1000);
+			for split in [0, 1, 3, 8, 13, 500, 999, 1000] {
+				let (first, second) = data.split_at(split);
+				let mut dut = Crc32::new();
+				dut.update(first);
+				dut.update(second);
+				assert_eq!(reference(&data), dut.finish(), "{}", split);
+			}
+		}
+	}
+
+	mod adler32 {
+		use super::super::Adler32;
+		use super::sample;
+
+		/// Takes the modulus after every byte
+		fn reference(input: &[u8]) -> u32 {
+			const DIVISOR: u32 = 65521;
+			let mut s1: u32 = 1;
+			let mut s2: u32 = 0;
+
+			for x in input {
+				s1 += u32::from(*x);
+				s1 %= DIVISOR;
+				s2 += s1;
+				s2 %= DIVISOR;
+			}
+
+			(s2 << 16) | s1
+		}
+
+		#[test]
+		fn wikipedia() {
+			let exp = 0x11E60398;
+			let data = b"Wikipedia";
+			let res = Adler32::checksum(data);
+			assert_eq!(exp, res);
+		}
+		#[test]
+		fn zero_x1() {
+			let exp = 0x00010001;
+			let data = [0u8; 1];
+			let res = Adler32::checksum(&data);
+			assert_eq!(exp, res);
+		}
+		#[test]
+		fn zero_x65536() {
+			let exp = 0x000F0001;
+			let data = [0u8; 65536];
+			let res = Adler32::checksum(&data);
+			assert_eq!(exp, res, "{:x} {:x}", exp, res);
+		}
+		#[test]
+		fn max_bytes() {
+			// the largest sums, to check that the sums do not overflow between reductions
+			let data = [0xFFu8; 100_000];
+			assert_eq!(reference(&data), Adler32::checksum(&data));
+		}
+		#[test]
+		fn matches_reference() {
+			let data = sample(100_000);
+			for length in [1, 5551, 5552, 5553, 11_104, 100_000] {
+				assert_eq!(
+					reference(&data[..length]),
+					Adler32::checksum(&data[..length]),
+					"{}",
+					length
+				);
+			}
+		}
+		#[test]
+		fn update_in_pieces() {
+			let data = sample(20_000);
+			for split in [0, 4, 5552, 5553, 19_999] {
+				let (first, second) = data.split_at(split);
+				let mut dut = Adler32::new();
+				dut.update(first);
+				dut.update(second);
+				assert_eq!(reference(&data), dut.finish(), "{}", split);
+			}
+		}
+	}
+}
@@ -51,15 +51,18 @@ fn from(src: &Option<String>) -> FileOrStdout {
 }
 
 impl FileOrStdout {
-	pub fn write<R, F>(&mut self, f: F) -> ::std::io::Result<R>
+	pub fn write<R, E, F>(&mut self, f: F) -> ::std::result::Result<R, E>
 	where
-		F: FnOnce(&mut dyn Write) -> ::std::io::Result<R>,
+		F: FnOnce(&mut dyn Write) -> ::std::result::Result<R, E>,
+		E: From<::std::io::Error>,
 	{
 		match self {
 			FileOrStdout::File(x) => {
-				let a: std::result::Result<R, atomicwrites::Error<std::io::Error>> =
-					x.write(|file| f(file));
-				let b: std::result::Result<R, std::io::Error> = a.map_err(|e| e.into());
+				let a: std::result::Result<R, atomicwrites::Error<E>> = x.write(|file| f(file));
+				let b: std::result::Result<R, E> = a.map_err(|e| match e {
+					atomicwrites::Error::Internal(e) => E::from(e),
+					atomicwrites::Error::User(e) => e,
+				});
 				b
 			},
 			FileOrStdout::Stdout(x) => f(x),
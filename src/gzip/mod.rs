@@ -0,0 +1,605 @@
+//! "GZIP file format specification version 4.3" <https://www.ietf.org/rfc/rfc1952.txt>
+
+use crate::checksum::Crc32;
+use crate::zlib::deflate;
+use crate::zlib::InflateError;
+
+/// The gzip magic header
+pub const MAGIC: [u8; 2] = [0x1F, 0x8B];
+/// The only compression method that gzip defines
+const METHOD_DEFLATE: u8 = 8;
+
+/// Header flag indicating that the data is probably text
+const FTEXT: u8 = 0x01;
+/// Header flag indicating that the header ends with a crc16 of the header
+const FHCRC: u8 = 0x02;
+/// Header flag indicating that the header contains extra fields
+const FEXTRA: u8 = 0x04;
+/// Header flag indicating that the header contains a file name
+const FNAME: u8 = 0x08;
+/// Header flag indicating that the header contains a comment
+const FCOMMENT: u8 = 0x10;
+/// Header flags that have no meaning yet
+const FRESERVED: u8 = 0xE0;
+
+/// The metadata in the header of a gzip member
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Header {
+	/// Whether the data is probably ASCII text
+	pub is_text: bool,
+	/// The modification time of the original file, in seconds since the unix epoch, or zero if not available
+	pub modification_time: u32,
+	/// Hints about the compression used: `2` for the slowest compression, `4` for the fastest
+	pub extra_flags: u8,
+	/// The type of filesystem on which the compression took place, or `255` if unknown
+	pub operating_system: u8,
+	/// The extra fields, which are subfields consisting of a two-byte id, a two-byte length and that
+	/// many bytes of data
+	pub extra: Option<Vec<u8>>,
+	/// The name of the original file, in ISO 8859-1, without the terminating nul
+	pub filename: Option<Vec<u8>>,
+	/// A comment, in ISO 8859-1, without the terminating nul
+	pub comment: Option<Vec<u8>>,
+	/// Whether the header ends with a crc16 of the header
+	pub has_header_crc: bool,
+}
+
+impl Default for Header {
+	fn default() -> Header {
+		Header {
+			is_text: false,
+			modification_time: 0,
+			extra_flags: 0,
+			operating_system: 255,
+			extra: None,
+			filename: None,
+			comment: None,
+			has_header_crc: false,
+		}
+	}
+}
+
+impl Header {
+	/// Reads a header from the start of `r`, returning the header and the number of bytes it used
+	fn read(r: &[u8]) -> Result<(Header, usize), ReadError> {
+		let mut pos: usize = 0;
+		let fixed = take(r, &mut pos, 10)?;
+		let magic = [fixed[0], fixed[1]];
+		if magic != MAGIC {
+			return Err(ReadError::MagicMismatch(magic));
+		}
+		if fixed[2] != METHOD_DEFLATE {
+			return Err(ReadError::UnknownCompressionMethod(fixed[2]));
+		}
+		let flags = fixed[3];
+		if flags & FRESERVED != 0 {
+			return Err(ReadError::ReservedFlags(flags & FRESERVED));
+		}
+		let modification_time = u32::from_le_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+		let extra_flags = fixed[8];
+		let operating_system = fixed[9];
+
+		let extra = if flags & FEXTRA != 0 {
+			let length = take(r, &mut pos, 2)?;
+			let length = u16::from_le_bytes([length[0], length[1]]);
+			Some(take(r, &mut pos, usize::from(length))?.to_vec())
+		} else {
+			None
+		};
+		let filename = if flags & FNAME != 0 {
+			Some(take_nul_terminated(r, &mut pos)?.to_vec())
+		} else {
+			None
+		};
+		let comment = if flags & FCOMMENT != 0 {
+			Some(take_nul_terminated(r, &mut pos)?.to_vec())
+		} else {
+			None
+		};
+		let has_header_crc = flags & FHCRC != 0;
+		if has_header_crc {
+			let calculated = Crc32::checksum(&r[..pos]) as u16;
+			let given = take(r, &mut pos, 2)?;
+			let given = u16::from_le_bytes([given[0], given[1]]);
+			if given != calculated {
+				return Err(ReadError::HeaderChecksumMismatch { given, calculated });
+			}
+		}
+
+		Ok((
+			Header {
+				is_text: flags & FTEXT != 0,
+				modification_time,
+				extra_flags,
+				operating_system,
+				extra,
+				filename,
+				comment,
+				has_header_crc,
+			},
+			pos,
+		))
+	}
+
+	/// Appends the serialized header to `retval`
+	///
+	/// # Panics
+	/// If `extra` is longer than 65535 bytes, or if `filename` or `comment` contain a nul byte
+	fn write(&self, retval: &mut Vec<u8>) {
+		let start = retval.len();
+		let flags = if self.is_text { FTEXT } else { 0 }
+			| if self.has_header_crc { FHCRC } else { 0 }
+			| if self.extra.is_some() { FEXTRA } else { 0 }
+			| if self.filename.is_some() { FNAME } else { 0 }
+			| if self.comment.is_some() { FCOMMENT } else { 0 };
+		retval.extend_from_slice(&MAGIC);
+		retval.push(METHOD_DEFLATE);
+		retval.push(flags);
+		retval.extend_from_slice(&self.modification_time.to_le_bytes());
+		retval.push(self.extra_flags);
+		retval.push(self.operating_system);
+		if let Some(extra) = &self.extra {
+			let length = u16::try_from(extra.len()).expect("gzip extra field is too long");
+			retval.extend_from_slice(&length.to_le_bytes());
+			retval.extend_from_slice(extra);
+		}
+		for text in [&self.filename, &self.comment].into_iter().flatten() {
+			assert!(!text.contains(&0), "gzip header string contains a nul");
+			retval.extend_from_slice(text);
+			retval.push(0);
+		}
+		if self.has_header_crc {
+			let crc = Crc32::checksum(&retval[start..]) as u16;
+			retval.extend_from_slice(&crc.to_le_bytes());
+		}
+	}
+}
+
+/// A single member of a gzip file, which is a header followed by compressed data
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Member {
+	pub header: Header,
+	/// The member's data, after decompression
+	pub data: Vec<u8>,
+}
+
+/// Returns the next `count` bytes of `r` after `pos`, and advances `pos` past those bytes
+fn take<'a>(r: &'a [u8], pos: &mut usize, count: usize) -> Result<&'a [u8], ReadError> {
+	let retval = r
+		.get(*pos..)
+		.and_then(|x| x.get(..count))
+		.ok_or(ReadError::UnexpectedEof)?;
+	*pos += count;
+	Ok(retval)
+}
+
+/// Returns the bytes of `r` after `pos` before the next nul, and advances `pos` past the nul
+fn take_nul_terminated<'a>(r: &'a [u8], pos: &mut usize) -> Result<&'a [u8], ReadError> {
+	let rest = r.get(*pos..).ok_or(ReadError::UnexpectedEof)?;
+	let length = rest
+		.iter()
+		.position(|x| *x == 0)
+		.ok_or(ReadError::UnexpectedEof)?;
+	*pos += length + 1;
+	Ok(&rest[..length])
+}
+
+/// Limits on what [`read_limited`] will read, which protect against files that would otherwise
+/// use an unreasonable amount of memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadLimits {
+	/// The maximum length of the gzip file
+	pub max_file_length: usize,
+	/// The maximum number of members in a file
+	pub max_member_count: usize,
+	/// The maximum size of the data inflated from a single member
+	pub max_member_size: usize,
+	/// The maximum total size of the data inflated from all the members
+	pub max_total_size: usize,
+}
+
+impl Default for ReadLimits {
+	fn default() -> ReadLimits {
+		ReadLimits {
+			max_file_length: 1 << 28,
+			max_member_count: 100_000,
+			max_member_size: 1 << 30,
+			max_total_size: 1 << 31,
+		}
+	}
+}
+
+/// Reads each member of a gzip file
+pub fn read(r: &[u8]) -> Result<Vec<Member>, ReadError> {
+	read_limited(
+		r,
+		&ReadLimits {
+			max_file_length: usize::MAX,
+			max_member_count: usize::MAX,
+			max_member_size: usize::MAX,
+			max_total_size: usize::MAX,
+		},
+	)
+}
+
+/// Reads each member of a gzip file, failing instead of exceeding any of the `limits`
+///
+/// A member that would inflate to too much data results in [`InflateError::OutputLimitExceeded`].
+pub fn read_limited(r: &[u8], limits: &ReadLimits) -> Result<Vec<Member>, ReadError> {
+	if r.len() > limits.max_file_length {
+		return Err(ReadError::FileTooLong(limits.max_file_length));
+	}
+	let mut retval = Vec::new();
+	let mut pos: usize = 0;
+	let mut remaining_size = limits.max_total_size;
+	loop {
+		if retval.len() == limits.max_member_count {
+			return Err(ReadError::TooManyMembers(limits.max_member_count));
+		}
+		let (header, header_length) = Header::read(&r[pos..])?;
+		pos += header_length;
+
+		let mut compressed = &r[pos..];
+		let data = deflate::inflate(&mut compressed, limits.max_member_size.min(remaining_size))
+			.map_err(|err| ReadError::from(InflateError::from(err)))?;
+		pos = r.len() - compressed.len();
+		remaining_size -= data.len();
+
+		let trailer = take(r, &mut pos, 8)?;
+		let given = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+		let calculated = Crc32::checksum(&data);
+		if given != calculated {
+			return Err(ReadError::ChecksumMismatch { given, calculated });
+		}
+		let given = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+		let calculated = data.len() as u32;
+		if given != calculated {
+			return Err(ReadError::SizeMismatch { given, calculated });
+		}
+
+		retval.push(Member { header, data });
+		if pos == r.len() {
+			return Ok(retval);
+		}
+	}
+}
+
+/// Writes the members as a gzip file, storing each member's data entirely using immediate mode
+pub fn write_immediate(members: &[Member]) -> Vec<u8> {
+	let mut retval = Vec::new();
+	for member in members {
+		member.header.write(&mut retval);
+		retval.extend(deflate::deflate_immediate(member.data.iter().cloned()));
+		retval.extend_from_slice(&Crc32::checksum(&member.data).to_le_bytes());
+		retval.extend_from_slice(&(member.data.len() as u32).to_le_bytes());
+	}
+	retval
+}
+
+/// An error that can occur while reading a gzip file
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum ReadError {
+	/// The file ended before the end of a member
+	UnexpectedEof,
+	/// A member did not start with the gzip magic header
+	MagicMismatch([u8; 2]),
+	/// A member's compression method is not deflate
+	UnknownCompressionMethod(u8),
+	/// A member's header sets flags that have no meaning
+	ReservedFlags(u8),
+	/// A member's header crc16 did not match the header
+	HeaderChecksumMismatch { given: u16, calculated: u16 },
+	/// A member's compressed data could not be decompressed
+	Inflate(InflateError),
+	/// The crc32 of a member's inflated data did not match the crc32 given in the member's trailer
+	ChecksumMismatch { given: u32, calculated: u32 },
+	/// The length of a member's inflated data did not match the size given in the member's trailer
+	SizeMismatch { given: u32, calculated: u32 },
+	/// The file is longer than the limit
+	FileTooLong(usize),
+	/// The file has more members than the limit
+	TooManyMembers(usize),
+}
+
+impl From<InflateError> for ReadError {
+	fn from(src: InflateError) -> ReadError {
+		match src {
+			InflateError::UnexpectedEof => ReadError::UnexpectedEof,
+			x => ReadError::Inflate(x),
+		}
+	}
+}
+
+impl ::std::fmt::Display for ReadError {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		match self {
+			ReadError::UnexpectedEof => write!(f, "Unexpected End of File"),
+			ReadError::MagicMismatch(magic) => {
+				write!(f, "GZip Magic Mismatch: `{:x}{:x}`", magic[0], magic[1])
+			},
+			ReadError::UnknownCompressionMethod(method) => {
+				write!(f, "Unsupported GZip Compression Method: {method:X}")
+			},
+			ReadError::ReservedFlags(flags) => write!(f, "GZip Reserved Flags Set: {flags:x}"),
+			ReadError::HeaderChecksumMismatch { given, calculated } => {
+				write!(
+					f,
+					"GZip Header Checksum Mismatch: given `{given:x}`, calculated `{calculated:x}`"
+				)
+			},
+			ReadError::Inflate(x) => write!(f, "{}", x),
+			ReadError::ChecksumMismatch { given, calculated } => {
+				write!(
+					f,
+					"GZip Checksum Mismatch: given `{given:x}`, calculated `{calculated:x}`"
+				)
+			},
+			ReadError::SizeMismatch { given, calculated } => {
+				write!(
+					f,
+					"GZip Size Mismatch: given `{given}`, calculated `{calculated}`"
+				)
+			},
+			ReadError::FileTooLong(limit) => {
+				write!(f, "GZip File Longer Than The Limit Of {limit} Bytes")
+			},
+			ReadError::TooManyMembers(limit) => {
+				write!(f, "GZip File Has More Than The Limit Of {limit} Members")
+			},
+		}
+	}
+}
+
+impl ::std::error::Error for ReadError {
+	fn source(&self) -> Option<&(dyn ::std::error::Error + 'static)> {
+		match self {
+			ReadError::Inflate(x) => Some(x),
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	mod read {
+		use super::super::read;
+		use super::super::read_limited;
+		use super::super::Header;
+		use super::super::InflateError;
+		use super::super::Member;
+		use super::super::ReadError;
+		use super::super::ReadLimits;
+
+		// python: gzip.compress(b"Hello, World! Hello, World!", compresslevel=9, mtime=0)
+		#[rustfmt::skip]
+		const HELLO_WORLD: [u8; 37] = [
+			0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xf3, 0x48, 0xcd, 0xc9,
+			0xc9, 0xd7, 0x51, 0x08, 0xcf, 0x2f, 0xca, 0x49, 0x51, 0x54, 0xf0, 0x40, 0xe6, 0x01,
+			0x00, 0xb1, 0x16, 0xcf, 0x0c, 0x1b, 0x00, 0x00, 0x00,
+		];
+
+		// python: gzip.GzipFile(filename="hello.txt", mtime=1234567890), writing b"hello\n"
+		#[rustfmt::skip]
+		const FILENAME: [u8; 36] = [
+			0x1f, 0x8b, 0x08, 0x08, 0xd2, 0x02, 0x96, 0x49, 0x02, 0xff, 0x68, 0x65, 0x6c, 0x6c,
+			0x6f, 0x2e, 0x74, 0x78, 0x74, 0x00, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xe7, 0x02, 0x00,
+			0x20, 0x30, 0x3a, 0x36, 0x06, 0x00, 0x00, 0x00,
+		];
+
+		// b"abc", with every optional header field
+		#[rustfmt::skip]
+		const ALL_FIELDS: [u8; 44] = [
+			0x1f, 0x8b, 0x08, 0x1f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x04, 0x00, 0x61, 0x62,
+			0x00, 0x00, 0x6e, 0x61, 0x6d, 0x65, 0x00, 0x63, 0x6f, 0x6d, 0x6d, 0x65, 0x6e, 0x74,
+			0x00, 0xdd, 0xc1, 0x4b, 0x4c, 0x4a, 0x06, 0x00, 0xc2, 0x41, 0x24, 0x35, 0x03, 0x00,
+			0x00, 0x00,
+		];
+
+		#[test]
+		fn hello_world() {
+			let exp = vec![Member {
+				header: Header {
+					extra_flags: 2,
+					operating_system: 3,
+					..Header::default()
+				},
+				data: b"Hello, World! Hello, World!".to_vec(),
+			}];
+			assert_eq!(Ok(exp), read(&HELLO_WORLD));
+		}
+
+		#[test]
+		fn filename() {
+			let exp = vec![Member {
+				header: Header {
+					modification_time: 1234567890,
+					extra_flags: 2,
+					filename: Some(b"hello.txt".to_vec()),
+					..Header::default()
+				},
+				data: b"hello\n".to_vec(),
+			}];
+			assert_eq!(Ok(exp), read(&FILENAME));
+		}
+
+		#[test]
+		fn all_fields() {
+			let exp = vec![Member {
+				header: Header {
+					is_text: true,
+					operating_system: 3,
+					extra: Some(b"ab\0\0".to_vec()),
+					filename: Some(b"name".to_vec()),
+					comment: Some(b"comment".to_vec()),
+					has_header_crc: true,
+					..Header::default()
+				},
+				data: b"abc".to_vec(),
+			}];
+			assert_eq!(Ok(exp), read(&ALL_FIELDS));
+		}
+
+		#[test]
+		fn multiple_members() {
+			let dut = [&HELLO_WORLD[..], &FILENAME[..]].concat();
+			let res = read(&dut).unwrap();
+			assert_eq!(2, res.len());
+			assert_eq!(b"Hello, World! Hello, World!".to_vec(), res[0].data);
+			assert_eq!(b"hello\n".to_vec(), res[1].data);
+		}
+
+		#[test]
+		fn magic_mismatch() {
+			let mut dut = HELLO_WORLD;
+			dut[1] = 0x8c;
+			assert_eq!(Err(ReadError::MagicMismatch([0x1f, 0x8c])), read(&dut));
+		}
+
+		#[test]
+		fn reserved_flags() {
+			let mut dut = HELLO_WORLD;
+			dut[3] = 0x20;
+			assert_eq!(Err(ReadError::ReservedFlags(0x20)), read(&dut));
+		}
+
+		#[test]
+		fn header_checksum_mismatch() {
+			let mut dut = ALL_FIELDS;
+			dut[29] ^= 1;
+			assert!(matches!(
+				read(&dut),
+				Err(ReadError::HeaderChecksumMismatch { .. })
+			));
+		}
+
+		#[test]
+		fn checksum_mismatch() {
+			let mut dut = HELLO_WORLD;
+			dut[29] ^= 1;
+			assert!(matches!(
+				read(&dut),
+				Err(ReadError::ChecksumMismatch { .. })
+			));
+		}
+
+		#[test]
+		fn size_mismatch() {
+			let mut dut = HELLO_WORLD;
+			dut[33] ^= 1;
+			assert_eq!(
+				Err(ReadError::SizeMismatch {
+					given: 26,
+					calculated: 27
+				}),
+				read(&dut)
+			);
+		}
+
+		#[test]
+		fn truncated() {
+			for length in [0, 5, 20, 35] {
+				assert_eq!(
+					Err(ReadError::UnexpectedEof),
+					read(&HELLO_WORLD[..length]),
+					"{}",
+					length
+				);
+			}
+		}
+
+		#[test]
+		fn trailing_garbage() {
+			let dut = [&HELLO_WORLD[..], b"trailing garbage"].concat();
+			assert!(matches!(read(&dut), Err(ReadError::MagicMismatch(_))));
+		}
+
+		fn limits(change: impl FnOnce(&mut ReadLimits)) -> ReadLimits {
+			let mut retval = ReadLimits::default();
+			change(&mut retval);
+			retval
+		}
+
+		#[test]
+		fn total_limit_applies_to_all_members() {
+			let dut = [&HELLO_WORLD[..], &FILENAME[..]].concat();
+			assert!(read_limited(&dut, &limits(|x| x.max_total_size = 33)).is_ok());
+			assert_eq!(
+				Err(ReadError::Inflate(InflateError::OutputLimitExceeded)),
+				read_limited(&dut, &limits(|x| x.max_total_size = 32))
+			);
+		}
+
+		#[test]
+		fn member_limit_applies_to_each_member() {
+			let dut = [&HELLO_WORLD[..], &HELLO_WORLD[..]].concat();
+			assert!(read_limited(&dut, &limits(|x| x.max_member_size = 27)).is_ok());
+			assert_eq!(
+				Err(ReadError::Inflate(InflateError::OutputLimitExceeded)),
+				read_limited(&dut, &limits(|x| x.max_member_size = 26))
+			);
+		}
+
+		#[test]
+		fn member_count_limit() {
+			let dut = [&HELLO_WORLD[..], &HELLO_WORLD[..]].concat();
+			assert!(read_limited(&dut, &limits(|x| x.max_member_count = 2)).is_ok());
+			assert_eq!(
+				Err(ReadError::TooManyMembers(1)),
+				read_limited(&dut, &limits(|x| x.max_member_count = 1))
+			);
+		}
+
+		#[test]
+		fn file_length_limit() {
+			assert!(read_limited(&HELLO_WORLD, &limits(|x| x.max_file_length = 37)).is_ok());
+			assert_eq!(
+				Err(ReadError::FileTooLong(36)),
+				read_limited(&HELLO_WORLD, &limits(|x| x.max_file_length = 36))
+			);
+		}
+	}
+
+	mod write_immediate {
+		use super::super::read;
+		use super::super::write_immediate;
+		use super::super::Header;
+		use super::super::Member;
+
+		#[test]
+		fn empty() {
+			let members = vec![Member::default()];
+			let res = write_immediate(&members);
+			#[rustfmt::skip]
+			let exp = [
+				0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff,
+				0x01, 0x00, 0x00, 0xff, 0xff,
+				0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+			];
+			assert_eq!(exp.to_vec(), res);
+		}
+
+		#[test]
+		fn round_trip() {
+			let members = vec![
+				Member {
+					header: Header {
+						is_text: true,
+						modification_time: 1234567890,
+						extra: Some(b"ab\x02\0xy".to_vec()),
+						filename: Some(b"name".to_vec()),
+						comment: Some(b"comment".to_vec()),
+						has_header_crc: true,
+						..Header::default()
+					},
+					data: b"abc".to_vec(),
+				},
+				Member {
+					header: Header::default(),
+					data: (0..100_000u32).map(|x| (x % 251) as u8).collect(),
+				},
+			];
+			assert_eq!(Ok(members.clone()), read(&write_immediate(&members)));
+		}
+	}
+}
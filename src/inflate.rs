@@ -1,11 +1,31 @@
 //! A program that takes a png file and deflates the compressed chunks
 
 mod file_or_stdio;
-mod png;
-mod zlib;
 
 use self::file_or_stdio::FileOrStdin;
 use self::file_or_stdio::FileOrStdout;
+use png_inflate::gzip;
+use png_inflate::png;
+use png_inflate::png::canonical::CanonicalForm;
+use png_inflate::transform::Canonicalize;
+use png_inflate::transform::CheckChunkOrder;
+use png_inflate::transform::CheckCopySafety;
+use png_inflate::transform::ChunkTransform;
+use png_inflate::transform::ConcatinateIdats;
+use png_inflate::transform::DeflateChunks;
+use png_inflate::transform::InflateBudget;
+use png_inflate::transform::InflateChunks;
+use png_inflate::transform::InflateLimits;
+use png_inflate::transform::NormalizeFilters;
+use png_inflate::transform::NormalizePalette;
+use png_inflate::transform::Pipeline;
+use png_inflate::transform::RestoreChunks;
+use png_inflate::transform::ReversibleInflateChunks;
+use png_inflate::transform::SetInterlaceMethod;
+use png_inflate::transform::SplitIdats;
+use png_inflate::transform::ValidateIhdr;
+use png_inflate::Error;
+use std::io::Read;
 use std::result::Result;
 
 const PROGRAM_NAME: &str = env!("CARGO_PKG_NAME");
@@ -27,296 +47,335 @@ fn main() {
 		::std::process::exit(0);
 	}
 
-	let input = {
-		let mut infile = FileOrStdin::from(&args.input_file);
-		png::read(&mut infile)
-	};
-
+	let mut infile = FileOrStdin::from(&args.input_file);
 	let mut outfile = FileOrStdout::from(&args.output_file);
-	let ignore_unsafe_to_copy = args.ignore_unsafe_to_copy;
-	let process_apng = args.process_apng;
 	let reported_infilename = args
 		.input_file
-		.or(args.assume_filename)
+		.clone()
+		.or(args.assume_filename.clone())
 		.unwrap_or("stdin".to_string());
-	let reported_outfilename = args.output_file.unwrap_or("stdout".to_string());
+	let reported_outfilename = args.output_file.clone().unwrap_or("stdout".to_string());
 
-	match input {
-		Result::Ok(indata) => {
-			let outdata: Result<Vec<png::Chunk>, Error> = indata
-				.iter()
-				.cloned()
-				.concat_idats()
-				.map(|x| deflate_chunks(x, ignore_unsafe_to_copy, process_apng))
-				.collect();
+	// the transforms that inflate data share one budget, so that the limit on the total inflated
+	// size applies to the file rather than to each transform
+	let budget = InflateBudget::new(args.inflate_limits);
+	// so that the output can be read back using the same limits
+	let default_idat_size = args
+		.read_limits
+		.max_chunk_length
+		.clamp(1, png::MAX_CHUNK_LENGTH) as usize;
+	let pipeline = Pipeline::new();
+	let pipeline = match args.chunk_order {
+		None => pipeline,
+		Some(ChunkOrderMode::Reject) => pipeline.with(CheckChunkOrder::reject()),
+		Some(ChunkOrderMode::Warn) => {
+			let filename = reported_infilename.clone();
+			pipeline.with(CheckChunkOrder::warn(move |x| {
+				eprintln!("Warning: {}: {}", filename, x)
+			}))
+		},
+		Some(ChunkOrderMode::Fix) => pipeline.with(CheckChunkOrder::fix()),
+	};
+	let pipeline = match args.mode {
+		// merging IDATs would lose the original split, so those modes merge IDATs themselves
+		Mode::Reversible | Mode::Restore => pipeline,
+		Mode::Inflate | Mode::Compress => pipeline.with(ConcatinateIdats::new()),
+	};
+	let pipeline = pipeline
+		.with(CheckCopySafety {
+			ignore_unsafe_to_copy: args.ignore_unsafe_to_copy,
+			process_apng: args.process_apng,
+			process_reconstruction: matches!(args.mode, Mode::Restore),
+		})
+		.with(ValidateIhdr {
+			strict: args.strict,
+		});
+	let pipeline = match (&args.mode, args.interlace_method) {
+		(Mode::Inflate | Mode::Compress, Some(interlace_method)) => {
+			pipeline.with(SetInterlaceMethod::new(interlace_method).with_limits(budget.clone()))
+		},
+		(Mode::Reversible | Mode::Restore, Some(_)) => {
+			panic!("--deinterlace and --interlace cannot be used with --reversible or --restore")
+		},
+		(_, None) => pipeline,
+	};
+	let pipeline = match (&args.mode, args.canonicalize, args.canonical_form) {
+		(Mode::Inflate | Mode::Compress, true, form) => {
+			pipeline.with(Canonicalize::new(form.unwrap_or_default()).with_limits(budget.clone()))
+		},
+		(Mode::Reversible | Mode::Restore, true, _) => {
+			panic!("--canonicalize cannot be used with --reversible or --restore")
+		},
+		(_, false, Some(_)) => panic!("--canonical-form requires --canonicalize"),
+		(_, false, None) => pipeline,
+	};
+	let pipeline = match (&args.mode, args.normalize_palette) {
+		(Mode::Inflate | Mode::Compress, true) => {
+			pipeline.with(NormalizePalette::new().with_limits(budget.clone()))
+		},
+		(Mode::Reversible | Mode::Restore, true) => {
+			panic!("--normalize-palette cannot be used with --reversible or --restore")
+		},
+		(_, false) => pipeline,
+	};
+	let pipeline = match (&args.mode, args.normalize_filters) {
+		(Mode::Inflate | Mode::Compress, true) => {
+			pipeline.with(NormalizeFilters::new().with_limits(budget.clone()))
+		},
+		(Mode::Reversible | Mode::Restore, true) => {
+			panic!("--normalize-filters cannot be used with --reversible or --restore")
+		},
+		(_, false) => pipeline,
+	};
+	let pipeline = match args.mode {
+		Mode::Inflate => {
+			pipeline.with(InflateChunks::new(args.process_apng).with_limits(budget.clone()))
+		},
+		Mode::Compress => pipeline.with(
+			DeflateChunks::new(args.process_apng, Default::default()).with_limits(budget.clone()),
+		),
+		Mode::Reversible => pipeline.with(
+			ReversibleInflateChunks::new(args.process_apng)
+				.with_max_idat_length(default_idat_size)
+				.with_limits(budget.clone()),
+		),
+		Mode::Restore => {
+			pipeline.with(RestoreChunks::new(args.process_apng).with_limits(budget.clone()))
+		},
+	};
+	let pipeline = match (&args.mode, args.idat_size) {
+		(Mode::Inflate | Mode::Compress, Some(idat_size)) => {
+			pipeline.with(SplitIdats::new(idat_size))
+		},
+		(Mode::Inflate | Mode::Compress, None) => pipeline.with(SplitIdats::new(default_idat_size)),
+		// the reconstruction data describes the original split, which must be kept
+		(Mode::Reversible | Mode::Restore, Some(_)) => {
+			panic!("--idat-size cannot be used with --reversible or --restore")
+		},
+		(Mode::Reversible | Mode::Restore, None) => pipeline,
+	};
+	// gzip files are recognized by their magic header; anything else is expected to be a png file
+	let mut magic = Vec::new();
+	let result = (&mut infile)
+		.take(gzip::MAGIC.len() as u64)
+		.read_to_end(&mut magic)
+		.map_err(|err| ProcessError::Read(png::ReadError::Io(err)));
+	let result = result.and_then(|_| {
+		if magic == gzip::MAGIC {
+			process_gzip(&magic, infile, &mut outfile, &args)
+		} else {
+			process_png(
+				&magic,
+				infile,
+				&mut outfile,
+				pipeline,
+				&args,
+				&reported_infilename,
+			)
+		}
+	});
 
-			match outdata {
-				Result::Ok(outdata) => {
-					match outfile.write(|f| png::write(f, outdata)) {
-						Result::Ok(()) => {
-							// Ok
-						},
-						Result::Err(x) => {
-							eprintln!("Could not write: {}: {}", reported_outfilename, x);
-							::std::process::exit(1);
-						},
-					}
-				},
-				Result::Err(x) => {
-					eprintln!("Could not transform: {}: {}", reported_infilename, x);
-					::std::process::exit(1);
-				},
-			}
+	match result {
+		Result::Ok(()) => {
+			// Ok
+		},
+		Result::Err(ProcessError::Read(x)) => {
+			eprintln!("Could not read: {}: {}", reported_infilename, x);
+			::std::process::exit(1);
+		},
+		Result::Err(ProcessError::ReadAfterIend(x)) => {
+			eprintln!(
+				"Could not read: {}: {} (--after-iend {})",
+				reported_infilename, x, args.after_iend
+			);
+			::std::process::exit(1);
 		},
-		Result::Err(x) => {
+		Result::Err(ProcessError::ReadGzip(x)) => {
 			eprintln!("Could not read: {}: {}", reported_infilename, x);
 			::std::process::exit(1);
 		},
+		Result::Err(ProcessError::Transform(x)) => {
+			eprintln!("Could not transform: {}: {}", reported_infilename, x);
+			::std::process::exit(1);
+		},
+		Result::Err(ProcessError::UnsupportedGzipMode) => {
+			eprintln!(
+				"Could not transform: {}: gzip files can only be inflated",
+				reported_infilename
+			);
+			::std::process::exit(1);
+		},
+		Result::Err(ProcessError::Write(x)) => {
+			eprintln!("Could not write: {}: {}", reported_outfilename, x);
+			::std::process::exit(1);
+		},
 	}
 }
 
-#[derive(Debug)]
-enum Error {
-	CannotCopySafely([u8; 4]),
-	UnsupportedCompressionMethod,
-	Zlib(zlib::InflateError),
-}
+/// Transforms the chunks of a png file, which starts with `magic` followed by the rest of `infile`
+fn process_png(
+	magic: &[u8],
+	infile: FileOrStdin,
+	outfile: &mut FileOrStdout,
+	mut pipeline: Pipeline,
+	args: &Args,
+	reported_infilename: &str,
+) -> Result<(), ProcessError> {
+	let after_iend = match args.after_iend {
+		AfterIendMode::Keep => png::AfterIend::Read,
+		AfterIendMode::Strip | AfterIendMode::Raw => png::AfterIend::Stop,
+		AfterIendMode::Error => png::AfterIend::Reject,
+	};
+	png::ChunkReader::new(magic.chain(infile))
+		.map(|reader| {
+			reader
+				.with_limits(args.read_limits)
+				.with_after_iend(after_iend)
+		})
+		.map_err(ProcessError::Read)
+		.and_then(|mut indata| {
+			outfile.write(|f| {
+				let mut outdata = png::ChunkWriter::new(f)?;
+				let mut seen_iend = false;
+				for chunk in indata.by_ref() {
+					let chunk = chunk.map_err(|err| {
+						if seen_iend {
+							ProcessError::ReadAfterIend(err)
+						} else {
+							ProcessError::Read(err)
+						}
+					})?;
+					seen_iend |= chunk.typ == *b"IEND";
+					for chunk in pipeline.push(chunk).map_err(ProcessError::Transform)? {
+						outdata.write_chunk(&chunk)?;
+					}
+				}
+				for chunk in pipeline.finish().map_err(ProcessError::Transform)? {
+					outdata.write_chunk(&chunk)?;
+				}
 
-impl From<zlib::InflateError> for Error {
-	fn from(src: zlib::InflateError) -> Error {
-		Error::Zlib(src)
-	}
+				let mut rest = indata.into_inner();
+				match args.after_iend {
+					AfterIendMode::Keep | AfterIendMode::Error => {},
+					AfterIendMode::Strip => {
+						let mut next_byte = Vec::new();
+						(&mut rest)
+							.take(1)
+							.read_to_end(&mut next_byte)
+							.map_err(|err| ProcessError::ReadAfterIend(png::ReadError::Io(err)))?;
+						if !next_byte.is_empty() {
+							eprintln!(
+								"Warning: {}: Discarded the data after the IEND chunk (--after-iend strip)",
+								reported_infilename
+							);
+						}
+					},
+					AfterIendMode::Raw => {
+						let mut rest_data = Vec::new();
+						rest.read_to_end(&mut rest_data)
+							.map_err(|err| ProcessError::ReadAfterIend(png::ReadError::Io(err)))?;
+						outdata.into_inner().write_all(&rest_data)?;
+					},
+				}
+				Ok(())
+			})
+		})
 }
 
-impl ::std::fmt::Display for Error {
-	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
-		match self {
-			Error::Zlib(zlib::InflateError::UnexpectedEof) => {
-				write!(f, "Unexpected End of File")
-			},
-			Error::CannotCopySafely(typ) => {
-				// if `typ` were non-alpha, the typ would have triggered ChunkReadError::InvalidTyp
-				// and not have gotten this far
-				let chars: String = typ.iter().map(|x| char::from(*x)).collect();
-				write!(f, "Found non-safe-to-copy chunk {}", chars)
-			},
-			Error::UnsupportedCompressionMethod => {
-				write!(f, "Unsupported PNG Compression Method")
-			},
-			Error::Zlib(zlib::InflateError::ChecksumMismatchHeader) => {
-				write!(f, "ZLib Header Checksum Mismatch")
-			},
-			Error::Zlib(zlib::InflateError::UnknownCompressionMethod(method)) => {
-				write!(f, "Unsupported Zlib Compression Method: {method:X}")
-			},
-			Error::Zlib(zlib::InflateError::ChecksumMismatch { given, calculated }) => {
-				write!(
-					f,
-					"ZLib Checksum Mismatch: given `{given:x}`, calculated `{calculated:x}`"
-				)
-			},
-			Error::Zlib(zlib::InflateError::HasPresetDictionary) => {
-				write!(f, "ZLib Segment has preset dictionary")
-			},
-			Error::Zlib(zlib::InflateError::DeflateNonCompressedLengthInvalid) => {
-				write!(f, "Malformed deflate block: LEN and NLEN mismatch")
-			},
-			Error::Zlib(zlib::InflateError::DeflateInvalidBtype) => {
-				write!(f, "Malformed deflate block: invalid BTYPE")
-			},
-		}
+/// Rewrites a gzip file, which starts with `magic` followed by the rest of `infile`, so that each
+/// member's data is stored without compression
+fn process_gzip(
+	magic: &[u8],
+	infile: FileOrStdin,
+	outfile: &mut FileOrStdout,
+	args: &Args,
+) -> Result<(), ProcessError> {
+	if args.mode != Mode::Inflate {
+		return Err(ProcessError::UnsupportedGzipMode);
+	}
+	// the file is read whole, much like a single chunk, and each member counts as a chunk
+	let limits = gzip::ReadLimits {
+		max_file_length: args.read_limits.max_chunk_length as usize,
+		max_member_count: args.read_limits.max_chunk_count,
+		max_member_size: args.inflate_limits.max_stream_size,
+		max_total_size: args.inflate_limits.max_total_size,
+	};
+	let mut indata = magic.to_vec();
+	// one byte past the limit is enough to tell that the file is too long
+	infile
+		.take((limits.max_file_length.saturating_sub(magic.len()) as u64).saturating_add(1))
+		.read_to_end(&mut indata)
+		.map_err(|err| ProcessError::Read(png::ReadError::Io(err)))?;
+	let mut members = gzip::read_limited(&indata, &limits).map_err(ProcessError::ReadGzip)?;
+	for member in &mut members {
+		// the data is no longer compressed by any particular method
+		member.header.extra_flags = 0;
 	}
+	outfile.write(|f| {
+		f.write_all(&gzip::write_immediate(&members))?;
+		Ok(())
+	})
 }
 
-fn deflate_chunks(
-	indata: png::Chunk,
-	ignore_unsafe_to_copy: bool,
-	process_apng: bool,
-) -> Result<png::Chunk, Error> {
-	// Union cases are listed in the order the chunks are specified in <https://w3c.github.io/png/#11Chunks>
-	// followed by order in <https://w3c.github.io/png/extensions/Overview.html>
-	match indata.typ.as_ref() {
-		// Not compressed, but contains data that can be validated
-		b"IHDR" => {
-			// byte 10 is the Compression Method; everything about this program assumes
-			// that the only valid compression method is zero
-			if indata.data[10] == 0 {
-				Ok(indata)
-			} else {
-				Err(Error::UnsupportedCompressionMethod)
-			}
-		},
-		// Contains only compressed data
-		b"IDAT" => Ok(png::Chunk {
-			typ: *b"IDAT",
-			data: zlib::deflate_immediate(&zlib::inflate(&indata.data)?),
-		}),
-		// Contains a cstring, followed by a method flag, followed by compressed data
-		b"zTXt" | b"iCCP" => {
-			let mut iter = indata.data.iter().cloned();
-			let keyword: Vec<u8> = iter.by_ref().take_while(|x| *x != 0).collect();
-			let method = iter.next();
-			if Some(0) == method {
-				let value: Vec<u8> = iter.collect();
-				let value = zlib::deflate_immediate(&zlib::inflate(&value)?);
-				let newdata = keyword
-					.iter()
-					.chain([0, 0].iter())
-					.chain(value.iter())
-					.cloned()
-					.collect();
-				Ok(png::Chunk {
-					typ: indata.typ,
-					data: newdata,
-				})
-			} else {
-				Err(Error::UnsupportedCompressionMethod)
-			}
-		},
-		// Contains a: cstring, byte flag, byte flag, cstring, cstring, compressed data
-		b"iTXt" => {
-			let mut iter = indata.data.iter().cloned();
-			let keyword: Vec<u8> = iter.by_ref().take_while(|x| *x != 0).collect();
-			let is_compressed = iter.next();
-			if Some(0) == is_compressed {
-				// Not compressed, so make no changes
-				let newdata: Vec<u8> = keyword
-					.iter()
-					.cloned()
-					.chain(std::iter::repeat_n(0, 2))
-					.chain(iter)
-					.collect();
-				Ok(png::Chunk {
-					typ: indata.typ,
-					data: newdata,
-				})
-			} else {
-				let method = iter.next();
-				if Some(0) == method {
-					let language: Vec<u8> = iter.by_ref().take_while(|x| *x != 0).collect();
-					let translated_keyword: Vec<u8> =
-						iter.by_ref().take_while(|x| *x != 0).collect();
-					let value: Vec<u8> = iter.collect();
-					let value = zlib::deflate_immediate(&zlib::inflate(&value)?);
+/// The ways that processing a file can fail, distinguished by which stage failed
+#[derive(Debug)]
+enum ProcessError {
+	Read(png::ReadError),
+	/// An error reading the data that follows the IEND chunk
+	ReadAfterIend(png::ReadError),
+	ReadGzip(gzip::ReadError),
+	Transform(Error),
+	/// The input is a gzip file, which cannot be processed in the selected mode
+	UnsupportedGzipMode,
+	Write(::std::io::Error),
+}
 
-					let newdata: Vec<u8> = keyword
-						.iter()
-						.cloned()
-						.chain([0, 1, 0].iter().cloned())
-						.chain(language.iter().cloned())
-						.chain(std::iter::once(0))
-						.chain(translated_keyword.iter().cloned())
-						.chain(std::iter::once(0))
-						.chain(value.iter().cloned())
-						.collect();
-					Ok(png::Chunk {
-						typ: indata.typ,
-						data: newdata,
-					})
-				} else {
-					Err(Error::UnsupportedCompressionMethod)
-				}
-			}
-		},
-		// Contain no compression, and are not affected by compression details of other chunks
-		#[rustfmt::skip]
-		b"PLTE" | b"IEND" | b"tRNS" |
-		b"cHRM" | b"gAMA" | b"sBIT" | b"sRGB" | b"cICP" | b"mDCV" | b"cLLI" |
-		b"tEXt" |
-		b"bKGD" | b"hIST" | b"pHYs" | b"sPLT" | b"eXIf" |
-		b"tIME" |
-		b"oFFs" | b"pCAL" | b"sCAL" | b"gIFg" | b"gIFx" | b"sTER" |
-		b"gIFt" => {
-			Ok(indata)
-		},
-		// (apng) Contain no compression, and are not affected by compression details of other chunks
-		b"acTL" | b"fcTL" => {
-			if ignore_unsafe_to_copy || process_apng {
-				Ok(indata)
-			} else {
-				Err(Error::CannotCopySafely(indata.typ))
-			}
-		},
-		// (apng) Contains a u32 followed by compressed data
-		b"fdAT" => {
-			if process_apng {
-				let mut iter = indata.data.iter().cloned();
-				let sequence_number: Vec<u8> = iter.by_ref().take(4).collect();
-				let value: Vec<u8> = iter.collect();
-				let value = zlib::deflate_immediate(&zlib::inflate(&value)?);
-				let newdata = sequence_number
-					.iter()
-					.chain(value.iter())
-					.cloned()
-					.collect();
-				Ok(png::Chunk {
-					typ: indata.typ,
-					data: newdata,
-				})
-			} else if ignore_unsafe_to_copy {
-				Ok(indata)
-			} else {
-				Err(Error::CannotCopySafely(indata.typ))
-			}
-		},
-		// unknown chunks
-		_ => {
-			if ignore_unsafe_to_copy || indata.safe_to_copy() {
-				Ok(indata)
-			} else {
-				Err(Error::CannotCopySafely(indata.typ))
-			}
-		},
+impl From<::std::io::Error> for ProcessError {
+	fn from(src: ::std::io::Error) -> ProcessError {
+		ProcessError::Write(src)
 	}
 }
 
-/// An iterator transformer that merges sequential IDATs, but otherwise passes through chunks
-struct ConcatinateIdats<I: Iterator<Item = png::Chunk>> {
-	backing: ::std::iter::Peekable<I>,
+/// What the program does to the compressed chunks
+#[derive(Debug, Default, PartialEq)]
+enum Mode {
+	#[default]
+	Inflate,
+	Compress,
+	Reversible,
+	Restore,
 }
 
-impl<I: Iterator<Item = png::Chunk>> Iterator for ConcatinateIdats<I> {
-	type Item = png::Chunk;
-	fn next(&mut self) -> Option<png::Chunk> {
-		match self.backing.next() {
-			None => None,
-			Some(sum) => {
-				if sum.typ == *b"IDAT" {
-					let mut retval_data = sum.data;
-					while self.backing.peek().map(|x| x.typ) == Some(*b"IDAT") {
-						retval_data.extend_from_slice(&self.backing.next().unwrap().data);
-					}
-					Some(png::Chunk {
-						typ: *b"IDAT",
-						data: retval_data,
-					})
-				} else {
-					Some(sum)
-				}
-			},
-		}
-	}
+/// What the program does with chunks that are not in an order allowed by the png specification
+#[derive(Debug, PartialEq)]
+enum ChunkOrderMode {
+	Warn,
+	Reject,
+	Fix,
 }
 
-impl<I: Iterator<Item = png::Chunk>> ConcatinateIdats<I> {
-	fn new(backing: I) -> ConcatinateIdats<I> {
-		ConcatinateIdats {
-			backing: backing.peekable(),
-		}
-	}
+/// What the program does with the data that follows the IEND chunk
+#[derive(Debug, Default, PartialEq)]
+enum AfterIendMode {
+	/// Read the data as chunks, and process those chunks like any other
+	#[default]
+	Keep,
+	/// Discard the data, with a warning
+	Strip,
+	/// Reject the file
+	Error,
+	/// Copy the data to the output unchanged, whether or not it is made of chunks
+	Raw,
 }
 
-trait IteratorExt {
-	fn concat_idats(self) -> ConcatinateIdats<Self>
-	where
-		Self: Sized + Iterator<Item = png::Chunk>;
-}
-impl<I: Sized + Iterator<Item = png::Chunk>> IteratorExt for I {
-	fn concat_idats(self) -> ConcatinateIdats<I>
-	where
-		Self: Sized + Iterator<Item = png::Chunk>,
-	{
-		ConcatinateIdats::new(self)
+impl ::std::fmt::Display for AfterIendMode {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		f.write_str(match self {
+			AfterIendMode::Keep => "keep",
+			AfterIendMode::Strip => "strip",
+			AfterIendMode::Error => "error",
+			AfterIendMode::Raw => "raw",
+		})
 	}
 }
 
@@ -325,7 +384,15 @@ enum ArgsState {
 	#[default]
 	Open,
 	ForcePositional,
+	AfterIend,
+	CanonicalForm,
 	AssumeFilename,
+	ChunkOrder,
+	IdatSize,
+	MaxChunkSize,
+	MaxChunks,
+	MaxInflatedSize,
+	MaxTotalInflatedSize,
 }
 
 /// A representation of the program arguments
@@ -337,7 +404,20 @@ struct Args {
 	version: bool,
 	process_apng: bool,
 	ignore_unsafe_to_copy: bool,
+	strict: bool,
+	normalize_filters: bool,
+	normalize_palette: bool,
+	interlace_method: Option<u8>,
+	canonicalize: bool,
+	canonical_form: Option<CanonicalForm>,
+	chunk_order: Option<ChunkOrderMode>,
+	after_iend_provided: bool,
+	after_iend: AfterIendMode,
+	idat_size: Option<usize>,
+	mode: Mode,
 	assume_filename: Option<String>,
+	read_limits: png::ReadLimits,
+	inflate_limits: InflateLimits,
 
 	program_name: Option<String>,
 	input_file: Option<String>,
@@ -357,34 +437,164 @@ fn print_usage(program_name:&str) {
 		println!();
 		println!("{}", PROGRAM_DESCRIPTION);
 		println!();
+		println!("  {:3} {:30} {}", "", "--after-iend keep|strip|error|raw", "what to do with data after the IEND chunk (default keep)");
 		println!("  {:3} {:30} {}", "", "--apng", "process apng chunks");
 		println!("  {:3} {:30} {}", "", "--assume-filename filename", "When reading from stdin, use this filename in error reporting");
+		println!("  {:3} {:30} {}", "", "--canonical-form smallest|rgba", "with --canonicalize, the smallest lossless color type and bit depth, or RGBA (default smallest)");
+		println!("  {:3} {:30} {}", "", "--canonicalize", "store the pixels using a canonical color type and bit depth, when that is lossless");
+		println!("  {:3} {:30} {}", "", "--chunk-order warn|reject|fix", "check that chunks are in an order allowed by the png specification");
+		println!("  {:3} {:30} {}", "", "--compress", "compress the compressed chunks instead of inflating them");
 		println!("  {:3} {:30} {}", "", "--copy-unsafe", "pass though unknown not-safe-to-copy chunks");
+		println!("  {:3} {:30} {}", "", "--deinterlace", "store the image data without interlacing");
+		println!("  {:3} {:30} {}", "", "--idat-size bytes", "split the image data into IDAT chunks of at most this size (default the --max-chunk-size)");
+		println!("  {:3} {:30} {}", "", "--interlace", "store the image data using Adam7 interlacing");
+		println!("  {:3} {:30} {}", "", "--lenient-zlib-window", "accept zlib streams whose window size is larger than the zlib specification allows, or smaller than their matches need");
+		println!("  {:3} {:30} {}", "", "--max-chunk-size bytes", "reject chunks longer than this (default 268435456)");
+		println!("  {:3} {:30} {}", "", "--max-chunks count", "reject files with more chunks than this (default 100000)");
+		println!("  {:3} {:30} {}", "", "--max-inflated-size bytes", "reject zlib streams that inflate to more than this (default 1073741824)");
+		println!("  {:3} {:30} {}", "", "--max-total-inflated-size bytes", "reject files whose zlib streams inflate to more than this in total (default 2147483648)");
+		println!("  {:3} {:30} {}", "", "--normalize-filters", "remove the filter from each row of the image data");
+		println!("  {:3} {:30} {}", "", "--normalize-palette", "sort the palette, and remove unused and duplicate palette entries");
+		println!("  {:3} {:30} {}", "", "--restore", "restore the chunks changed by --reversible");
+		println!("  {:3} {:30} {}", "", "--reversible", "also record how to restore the original compressed chunks");
+		println!("  {:3} {:30} {}", "", "--strict", "reject files that do not follow the png specification, even where that does not affect processing");
 		println!("  {:3} {:30} {}", "-?,", "--help", "display this help message");
 		println!("  {:3} {:30} {}", "", "--version", "display program version");
 	}
 
+	fn set_after_iend(&mut self, arg: &str) {
+		if self.after_iend_provided {
+			panic!("--after-iend provided multiple times");
+		}
+		self.after_iend = match arg {
+			"keep" => AfterIendMode::Keep,
+			"strip" => AfterIendMode::Strip,
+			"error" => AfterIendMode::Error,
+			"raw" => AfterIendMode::Raw,
+			_ => panic!("--after-iend requires one of keep, strip, error or raw"),
+		};
+		self.after_iend_provided = true;
+	}
+
+	fn set_interlace_method(&mut self, interlace_method: u8) {
+		if self.interlace_method.is_some() {
+			panic!("Only one of --deinterlace and --interlace may be provided");
+		}
+		self.interlace_method = Some(interlace_method);
+	}
+
+	fn set_mode(&mut self, mode: Mode) {
+		if self.mode != Mode::Inflate {
+			panic!("Only one of --compress, --restore and --reversible may be provided");
+		}
+		self.mode = mode;
+	}
+
 	/// Decode arg, add the result to self, then return self.
 	/// Intended as the lambda in a Iter::fold invocation.
 	fn push(mut self, arg: String) -> Args {
 		#[allow(clippy::iter_nth_zero)]
 		let arg_zeroth_char = arg.chars().nth(0).unwrap_or('\0');
-		if self.state == ArgsState::AssumeFilename {
+		if self.state == ArgsState::AfterIend {
+			self.set_after_iend(&arg);
+			self.state = ArgsState::Open;
+		} else if self.state == ArgsState::CanonicalForm {
+			if self.canonical_form.is_some() {
+				panic!("--canonical-form provided multiple times");
+			}
+			self.canonical_form = Some(match arg.as_str() {
+				"smallest" => CanonicalForm::Smallest,
+				"rgba" => CanonicalForm::Rgba,
+				_ => panic!("--canonical-form requires one of smallest or rgba"),
+			});
+			self.state = ArgsState::Open;
+		} else if self.state == ArgsState::AssumeFilename {
 			if self.assume_filename.is_some() {
 				panic!("--assume-filename provided multiple times");
 			}
 			self.assume_filename = Option::Some(arg);
 			self.state = ArgsState::Open;
+		} else if self.state == ArgsState::ChunkOrder {
+			if self.chunk_order.is_some() {
+				panic!("--chunk-order provided multiple times");
+			}
+			self.chunk_order = Some(match arg.as_str() {
+				"warn" => ChunkOrderMode::Warn,
+				"reject" => ChunkOrderMode::Reject,
+				"fix" => ChunkOrderMode::Fix,
+				_ => panic!("--chunk-order requires one of warn, reject or fix"),
+			});
+			self.state = ArgsState::Open;
+		} else if self.state == ArgsState::IdatSize {
+			let idat_size: usize = parse_number(&arg, "--idat-size");
+			if idat_size == 0 || idat_size > png::MAX_CHUNK_LENGTH as usize {
+				panic!(
+					"--idat-size must be between 1 and {}",
+					png::MAX_CHUNK_LENGTH
+				);
+			}
+			self.idat_size = Some(idat_size);
+			self.state = ArgsState::Open;
+		} else if self.state == ArgsState::MaxChunkSize {
+			self.read_limits.max_chunk_length = parse_number(&arg, "--max-chunk-size");
+			self.state = ArgsState::Open;
+		} else if self.state == ArgsState::MaxChunks {
+			self.read_limits.max_chunk_count = parse_number(&arg, "--max-chunks");
+			self.state = ArgsState::Open;
+		} else if self.state == ArgsState::MaxInflatedSize {
+			self.inflate_limits.max_stream_size = parse_number(&arg, "--max-inflated-size");
+			self.state = ArgsState::Open;
+		} else if self.state == ArgsState::MaxTotalInflatedSize {
+			self.inflate_limits.max_total_size = parse_number(&arg, "--max-total-inflated-size");
+			self.state = ArgsState::Open;
 		} else if self.state != ArgsState::ForcePositional && arg_zeroth_char == '-' {
 			// then the argument is a named argument
 			if arg == "--" {
 				self.state = ArgsState::ForcePositional;
+			} else if arg == "--after-iend" || arg == "/after-iend" {
+				self.state = ArgsState::AfterIend;
+			} else if let Some(value) = arg.strip_prefix("--after-iend=") {
+				self.set_after_iend(value);
 			} else if arg == "--apng" || arg == "/apng" {
 				self.process_apng = true;
 			} else if arg == "--assume-filename" || arg == "/assume-filename" {
 				self.state = ArgsState::AssumeFilename;
+			} else if arg == "--canonical-form" || arg == "/canonical-form" {
+				self.state = ArgsState::CanonicalForm;
+			} else if arg == "--canonicalize" || arg == "/canonicalize" {
+				self.canonicalize = true;
+			} else if arg == "--chunk-order" || arg == "/chunk-order" {
+				self.state = ArgsState::ChunkOrder;
+			} else if arg == "--compress" || arg == "/compress" {
+				self.set_mode(Mode::Compress);
 			} else if arg == "--copy-unsafe" || arg == "/copy-unsafe" {
 				self.ignore_unsafe_to_copy = true;
+			} else if arg == "--deinterlace" || arg == "/deinterlace" {
+				self.set_interlace_method(0);
+			} else if arg == "--idat-size" || arg == "/idat-size" {
+				self.state = ArgsState::IdatSize;
+			} else if arg == "--interlace" || arg == "/interlace" {
+				self.set_interlace_method(1);
+			} else if arg == "--lenient-zlib-window" || arg == "/lenient-zlib-window" {
+				self.inflate_limits.lenient_window = true;
+			} else if arg == "--max-chunk-size" || arg == "/max-chunk-size" {
+				self.state = ArgsState::MaxChunkSize;
+			} else if arg == "--max-chunks" || arg == "/max-chunks" {
+				self.state = ArgsState::MaxChunks;
+			} else if arg == "--max-inflated-size" || arg == "/max-inflated-size" {
+				self.state = ArgsState::MaxInflatedSize;
+			} else if arg == "--max-total-inflated-size" || arg == "/max-total-inflated-size" {
+				self.state = ArgsState::MaxTotalInflatedSize;
+			} else if arg == "--normalize-filters" || arg == "/normalize-filters" {
+				self.normalize_filters = true;
+			} else if arg == "--normalize-palette" || arg == "/normalize-palette" {
+				self.normalize_palette = true;
+			} else if arg == "--restore" || arg == "/restore" {
+				self.set_mode(Mode::Restore);
+			} else if arg == "--reversible" || arg == "/reversible" {
+				self.set_mode(Mode::Reversible);
+			} else if arg == "--strict" || arg == "/strict" {
+				self.strict = true;
 			} else if arg == "-?" || arg == "--help" || arg == "/?" || arg == "/help" {
 				self.help = true;
 			} else if arg == "--version" {
@@ -408,48 +618,10 @@ fn push(mut self, arg: String) -> Args {
 	}
 }
 
-#[cfg(test)]
-mod tests {
-	mod concatinate_idats {
-		use super::super::png;
-		use super::super::IteratorExt;
-
-		#[rustfmt::skip]
-		#[test]
-		fn concatinates_conecutive_idats() {
-			let data = [
-				png::Chunk{typ : *b"IDAT", data: b"12345".to_vec()},
-				png::Chunk{typ : *b"IDAT", data: b"6789A".to_vec()},
-			];
-			let mut dut = data.iter().cloned().concat_idats();
-			assert_eq!(png::Chunk{typ : *b"IDAT", data: b"123456789A".to_vec()}, dut.next().unwrap());
-			assert!(dut.next().is_none());
-		}
-
-		#[rustfmt::skip]
-		#[test]
-		fn does_not_merge_consecutive_nonidats() {
-			let data = [
-				png::Chunk{typ : *b"iTXt", data: b"12345".to_vec()},
-				png::Chunk{typ : *b"iTXt", data: b"6789A".to_vec()},
-			];
-			let mut dut = data.iter().cloned().concat_idats();
-			assert_eq!(png::Chunk{typ : *b"iTXt", data: b"12345".to_vec()}, dut.next().unwrap());
-			assert_eq!(png::Chunk{typ : *b"iTXt", data: b"6789A".to_vec()}, dut.next().unwrap());
-			assert!(dut.next().is_none());
-		}
-
-		#[rustfmt::skip]
-		#[test]
-		fn does_not_merge_disparate_chunks() {
-			let data = [
-				png::Chunk{typ : *b"IDAT", data: b"12345".to_vec()},
-				png::Chunk{typ : *b"iTXt", data: b"6789A".to_vec()},
-			];
-			let mut dut = data.iter().cloned().concat_idats();
-			assert_eq!(png::Chunk{typ : *b"IDAT", data: b"12345".to_vec()}, dut.next().unwrap());
-			assert_eq!(png::Chunk{typ : *b"iTXt", data: b"6789A".to_vec()}, dut.next().unwrap());
-			assert!(dut.next().is_none());
-		}
+/// Parses the value of a numeric argument
+fn parse_number<N: ::std::str::FromStr>(arg: &str, flag: &str) -> N {
+	match arg.parse() {
+		Ok(x) => x,
+		Err(_) => panic!("{} requires a non-negative integer argument", flag),
 	}
 }
@@ -0,0 +1,155 @@
+//! Reads png files and rewrites their compressed chunks so that the compressed data is stored
+//! without compression.
+//!
+//! The [`png`] module reads and writes the chunk structure of a png file, the [`zlib`] module
+//! reads and writes the zlib streams contained in some of those chunks, and the [`transform`]
+//! module combines the two to transform a stream of chunks. The [`gzip`] module reads and writes
+//! gzip files, which contain the same compressed data format as zlib streams.
+//!
+//! ```no_run
+//! use png_inflate::png::{ChunkReader, ChunkWriter};
+//! use png_inflate::transform::{
+//!     CheckCopySafety, ChunkTransform, ConcatinateIdats, InflateChunks, Pipeline, ValidateIhdr,
+//! };
+//!
+//! let mut pipeline = Pipeline::new()
+//!     .with(ConcatinateIdats::new())
+//!     .with(CheckCopySafety::default())
+//!     .with(ValidateIhdr::default())
+//!     .with(InflateChunks::default());
+//!
+//! let infile = std::fs::File::open("in.png")?;
+//! let outfile = std::fs::File::create("out.png")?;
+//! let mut outfile = ChunkWriter::new(outfile)?;
+//! for chunk in ChunkReader::new(infile)? {
+//!     for chunk in pipeline.push(chunk?)? {
+//!         outfile.write_chunk(&chunk)?;
+//!     }
+//! }
+//! for chunk in pipeline.finish()? {
+//!     outfile.write_chunk(&chunk)?;
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+pub mod checksum;
+pub mod gzip;
+pub mod png;
+pub mod transform;
+pub mod zlib;
+
+#[cfg(test)]
+mod test_util;
+
+/// An error that can occur while transforming a chunk
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+	/// The chunk is not safe-to-copy, and the transform was not told that it could copy unsafe chunks
+	CannotCopySafely([u8; 4]),
+	/// The chunk declares a compression method other than zlib-deflate
+	UnsupportedCompressionMethod,
+	/// The chunk's compressed data could not be decompressed
+	Zlib(zlib::InflateError),
+	/// The chunk's data does not have the structure required by the chunk's type
+	MalformedChunk(png::typed::ParseError),
+	/// The chunk's original compressed data could not be restored
+	Reconstruction(zlib::reconstruct::RebuildError),
+	/// The total size of the data inflated from the file's chunks is larger than the limit
+	TotalInflatedSizeExceeded,
+	/// The IHDR declares a width or height that is zero or larger than 2^31-1
+	InvalidImageSize { width: u32, height: u32 },
+	/// The IHDR declares a color type that does not exist
+	InvalidColorType(u8),
+	/// The IHDR declares a bit depth that is not allowed for its color type
+	InvalidBitDepth { color_type: u8, bit_depth: u8 },
+	/// The IHDR declares a filter method other than adaptive filtering
+	UnsupportedFilterMethod(u8),
+	/// The IHDR declares an interlace method other than none or Adam7
+	UnsupportedInterlaceMethod(u8),
+	/// The chunks are not in an order allowed by the png specification
+	ChunkOrder(png::order::Diagnostic),
+	/// The image data's scanline filters could not be reversed
+	Filter(png::filter::FilterError),
+	/// The file is an APNG, whose frames cannot be rearranged to use a different interlace method
+	CannotReinterlaceAnimation,
+}
+
+impl From<zlib::InflateError> for Error {
+	fn from(src: zlib::InflateError) -> Error {
+		Error::Zlib(src)
+	}
+}
+
+impl From<png::typed::ParseError> for Error {
+	fn from(src: png::typed::ParseError) -> Error {
+		Error::MalformedChunk(src)
+	}
+}
+
+impl From<png::filter::FilterError> for Error {
+	fn from(src: png::filter::FilterError) -> Error {
+		Error::Filter(src)
+	}
+}
+
+impl From<zlib::reconstruct::RebuildError> for Error {
+	fn from(src: zlib::reconstruct::RebuildError) -> Error {
+		Error::Reconstruction(src)
+	}
+}
+
+impl ::std::fmt::Display for Error {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		match self {
+			Error::CannotCopySafely(typ) => {
+				// if `typ` were non-alpha, the typ would have triggered ChunkReadError::InvalidTyp
+				// and not have gotten this far
+				let chars: String = typ.iter().map(|x| char::from(*x)).collect();
+				write!(f, "Found non-safe-to-copy chunk {}", chars)
+			},
+			Error::UnsupportedCompressionMethod => {
+				write!(f, "Unsupported PNG Compression Method")
+			},
+			Error::Zlib(x) => write!(f, "{}", x),
+			Error::MalformedChunk(x) => write!(f, "Malformed chunk: {}", x),
+			Error::Reconstruction(x) => write!(f, "Could not restore compressed data: {}", x),
+			Error::TotalInflatedSizeExceeded => {
+				write!(f, "Total inflated data is larger than the size limit")
+			},
+			Error::InvalidImageSize { width, height } => {
+				write!(f, "Invalid image size {}x{}", width, height)
+			},
+			Error::InvalidColorType(x) => write!(f, "Invalid color type {}", x),
+			Error::InvalidBitDepth {
+				color_type,
+				bit_depth,
+			} => write!(
+				f,
+				"Bit depth {} is not allowed for color type {}",
+				bit_depth, color_type
+			),
+			Error::UnsupportedFilterMethod(x) => write!(f, "Unsupported PNG Filter Method {}", x),
+			Error::UnsupportedInterlaceMethod(x) => {
+				write!(f, "Unsupported PNG Interlace Method {}", x)
+			},
+			Error::ChunkOrder(x) => write!(f, "Invalid chunk order: {}", x),
+			Error::Filter(x) => write!(f, "Could not reverse scanline filters: {}", x),
+			Error::CannotReinterlaceAnimation => {
+				write!(f, "Cannot change the interlace method of an animated image")
+			},
+		}
+	}
+}
+
+impl ::std::error::Error for Error {
+	fn source(&self) -> Option<&(dyn ::std::error::Error + 'static)> {
+		match self {
+			Error::Zlib(x) => Some(x),
+			Error::MalformedChunk(x) => Some(x),
+			Error::Reconstruction(x) => Some(x),
+			Error::Filter(x) => Some(x),
+			_ => None,
+		}
+	}
+}
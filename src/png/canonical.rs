@@ -0,0 +1,803 @@
+//! Rewrites a png image's pixels using a canonical color type and bit depth
+//!
+//! The same pixels can be stored using several color types and bit depths: an opaque grayscale
+//! image can be stored as RGBA, an image with few colors as a palette image, and an 8-bit image
+//! as a 16-bit image whose samples repeat each byte. [`canonicalize`] decodes the pixels and
+//! stores them in the representation that a [`CanonicalForm`] picks, which depends only on the
+//! pixels and on the chunks that describe them. A representation is only picked if every pixel,
+//! and the data of the PLTE, tRNS, sBIT and bKGD chunks, can be stored in it exactly.
+//!
+//! ```
+//! use png_inflate::png::canonical::{canonicalize, CanonicalForm, ColorChunks};
+//! use png_inflate::png::typed::Ihdr;
+//!
+//! let chunks = ColorChunks {
+//!     ihdr: Ihdr {
+//!         width: 2,
+//!         height: 1,
+//!         bit_depth: 8,
+//!         color_type: 2,
+//!         compression_method: 0,
+//!         filter_method: 0,
+//!         interlace_method: 0,
+//!     },
+//!     plte: None,
+//!     trns: None,
+//!     sbit: None,
+//!     bkgd: None,
+//!     has_iccp: false,
+//! };
+//! // a black pixel and a white pixel, stored as RGB
+//! let data = vec![0, 0, 0, 0, 255, 255, 255];
+//! let (chunks, data) = canonicalize(&chunks, &data, CanonicalForm::Smallest)?.unwrap();
+//! // are stored as 1-bit grayscale
+//! assert_eq!((0, 1), (chunks.ihdr.color_type, chunks.ihdr.bit_depth));
+//! assert_eq!(vec![0, 0b0100_0000], data);
+//! # Ok::<(), png_inflate::png::filter::FilterError>(())
+//! ```
+
+use super::filter;
+use super::filter::FilterError;
+use super::interlace;
+use super::typed::Ihdr;
+use super::typed::Plte;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+
+/// Which representation [`canonicalize`] picks for an image
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CanonicalForm {
+	/// The color type and bit depth with the fewest bits per pixel. If several have the same
+	/// number, grayscale is picked over palette, and palette over the others.
+	#[default]
+	Smallest,
+	/// RGBA with a bit depth of 8, or of 16 if some sample cannot be stored using 8 bits
+	Rgba,
+}
+
+/// The color types and bit depths that [`CanonicalForm::Smallest`] tries, in order
+#[rustfmt::skip]
+const SMALLEST_ORDER: [(u8, u8); 15] = [
+	(0, 1), (3, 1), (0, 2), (3, 2), (0, 4), (3, 4), (0, 8), (3, 8),
+	(4, 8), (0, 16), (2, 8), (4, 16), (6, 8), (2, 16), (6, 16),
+];
+
+/// The color types and bit depths that [`CanonicalForm::Rgba`] tries, in order
+const RGBA_ORDER: [(u8, u8); 2] = [(6, 8), (6, 16)];
+
+/// The chunks whose data depends on an image's color type and bit depth
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorChunks {
+	pub ihdr: Ihdr,
+	pub plte: Option<Plte>,
+	/// The data of the tRNS chunk
+	pub trns: Option<Vec<u8>>,
+	/// The data of the sBIT chunk
+	pub sbit: Option<Vec<u8>>,
+	/// The data of the bKGD chunk
+	pub bkgd: Option<Vec<u8>>,
+	/// Whether the image has an iCCP chunk. An ICC profile describes either grayscale or color
+	/// images, so a grayscale image with one stays grayscale, and a color image stays color.
+	pub has_iccp: bool,
+}
+
+/// A pixel's red, green, blue and alpha samples, scaled to 16 bits
+type Pixel = [u16; 4];
+
+/// The factor that a sample of the given bit depth is multiplied by to scale it to 16 bits
+fn scale(bit_depth: u8) -> u16 {
+	(u32::from(u16::MAX) / ((1 << bit_depth) - 1)) as u16
+}
+
+/// Whether a sample scaled to 16 bits can be stored exactly using the given bit depth
+fn fits(sample: u16, bit_depth: u8) -> bool {
+	sample.is_multiple_of(scale(bit_depth))
+}
+
+/// The smallest bit depth that can store every sample exactly
+fn min_bit_depth(samples: impl Iterator<Item = u16>) -> u8 {
+	let mut retval = 1;
+	for sample in samples {
+		// a sample that fits a bit depth also fits every larger bit depth
+		while !fits(sample, retval) {
+			retval *= 2;
+		}
+	}
+	retval
+}
+
+/// Reads the sample at `index` from a scanline of samples with the given bit depth
+pub(crate) fn read_sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+	match bit_depth {
+		16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+		8 => u16::from(row[index]),
+		_ => {
+			let bit = index * usize::from(bit_depth);
+			let mask = (1u8 << bit_depth) - 1;
+			u16::from((row[bit / 8] >> (8 - usize::from(bit_depth) - bit % 8)) & mask)
+		},
+	}
+}
+
+/// Writes the sample at `index` into a scanline of samples with the given bit depth
+pub(crate) fn write_sample(row: &mut [u8], index: usize, bit_depth: u8, value: u16) {
+	match bit_depth {
+		16 => row[index * 2..index * 2 + 2].copy_from_slice(&value.to_be_bytes()),
+		8 => row[index] = value as u8,
+		_ => {
+			let bit = index * usize::from(bit_depth);
+			row[bit / 8] |= (value as u8) << (8 - usize::from(bit_depth) - bit % 8);
+		},
+	}
+}
+
+/// Reads a sequence of big-endian two-byte values
+fn read_u16s(data: &[u8]) -> Vec<u16> {
+	data.chunks_exact(2)
+		.map(|x| u16::from_be_bytes([x[0], x[1]]))
+		.collect()
+}
+
+/// Whether the color type is a grayscale color type
+fn is_grayscale(color_type: u8) -> bool {
+	color_type == 0 || color_type == 4
+}
+
+/// The order of a canonical palette: entries that are not opaque first, so that the tRNS is as
+/// short as possible, then by the entries' samples
+pub(crate) fn palette_order(entry: &[u8; 4]) -> (bool, [u8; 4]) {
+	(entry[3] == u8::MAX, *entry)
+}
+
+/// Decodes every pixel of the image, in order from left to right then top to bottom. Returns
+/// None if the color chunks are not consistent with the IHDR.
+fn decode(chunks: &ColorChunks, data: &[u8]) -> Result<Option<Vec<Pixel>>, FilterError> {
+	let ihdr = &chunks.ihdr;
+	let bit_depth = ihdr.bit_depth;
+	let bits_per_pixel = filter::bits_per_pixel(ihdr)?;
+	let channels = bits_per_pixel / usize::from(bit_depth);
+	let row_length = filter::row_length(ihdr.width, bits_per_pixel)?;
+	let scale = scale(bit_depth);
+
+	// the samples of the color that tRNS makes transparent, or the alpha of each palette entry
+	let (transparent, palette) = match (ihdr.color_type, &chunks.trns, &chunks.plte) {
+		(0, None, _) | (2, None, _) | (4, None, _) | (6, None, _) => (None, None),
+		(0, Some(trns), _) if trns.len() == 2 => (Some(read_u16s(trns)), None),
+		(2, Some(trns), _) if trns.len() == 6 => (Some(read_u16s(trns)), None),
+		(3, trns, Some(plte)) => {
+			let trns = trns.as_deref().unwrap_or(&[]);
+			if trns.len() > plte.entries.len() {
+				return Ok(None);
+			}
+			let palette: Vec<Pixel> = plte
+				.entries
+				.iter()
+				.enumerate()
+				.map(|(i, [r, g, b])| {
+					let a = trns.get(i).copied().unwrap_or(u8::MAX);
+					[r, g, b, &a].map(|x| u16::from(*x) * 257)
+				})
+				.collect();
+			(None, Some(palette))
+		},
+		_ => return Ok(None),
+	};
+
+	let data = interlace::reinterlace(ihdr, data, 0)?;
+	let mut retval = Vec::with_capacity(ihdr.width as usize * ihdr.height as usize);
+	let mut samples = Vec::with_capacity(channels);
+	for row in data.chunks_exact(row_length + 1) {
+		let row = &row[1..];
+		for x in 0..ihdr.width as usize {
+			samples.clear();
+			samples.extend((0..channels).map(|c| read_sample(row, x * channels + c, bit_depth)));
+			let pixel = match ihdr.color_type {
+				0 | 2 => {
+					let alpha = if transparent.as_deref() == Some(&samples[..]) {
+						0
+					} else {
+						u16::MAX
+					};
+					let color = if channels == 1 {
+						[samples[0]; 3]
+					} else {
+						[samples[0], samples[1], samples[2]]
+					};
+					let [r, g, b] = color.map(|x| x * scale);
+					[r, g, b, alpha]
+				},
+				3 => match palette
+					.as_ref()
+					.and_then(|p| p.get(usize::from(samples[0])))
+				{
+					Some(entry) => *entry,
+					None => return Ok(None),
+				},
+				4 => {
+					let [g, a] = [samples[0], samples[1]].map(|x| x * scale);
+					[g, g, g, a]
+				},
+				_ => [samples[0], samples[1], samples[2], samples[3]].map(|x| x * scale),
+			};
+			retval.push(pixel);
+		}
+	}
+	Ok(Some(retval))
+}
+
+/// The background color described by a bKGD chunk, as red, green and blue samples scaled to 16
+/// bits. Returns None if the chunk is not consistent with the IHDR.
+fn decode_bkgd(chunks: &ColorChunks, bkgd: &[u8]) -> Option<[u16; 3]> {
+	let bit_depth = chunks.ihdr.bit_depth;
+	let in_range = |x: &u16| bit_depth == 16 || *x < 1 << bit_depth;
+	match (chunks.ihdr.color_type, bkgd.len()) {
+		(0 | 4, 2) => {
+			let gray = read_u16s(bkgd)[0];
+			in_range(&gray).then(|| [gray * scale(bit_depth); 3])
+		},
+		(2 | 6, 6) => {
+			let color = read_u16s(bkgd);
+			color
+				.iter()
+				.all(in_range)
+				.then(|| [color[0], color[1], color[2]].map(|x| x * scale(bit_depth)))
+		},
+		(3, 1) => {
+			let entry = chunks.plte.as_ref()?.entries.get(usize::from(bkgd[0]))?;
+			Some(entry.map(|x| u16::from(x) * 257))
+		},
+		_ => None,
+	}
+}
+
+/// The significant bits described by an sBIT chunk, as the red, green and blue samples' and, if
+/// the image has an alpha channel, the alpha sample's. Returns None if the chunk is not
+/// consistent with the IHDR.
+fn decode_sbit(ihdr: &Ihdr, sbit: &[u8]) -> Option<([u8; 3], Option<u8>)> {
+	let max = if ihdr.color_type == 3 {
+		8
+	} else {
+		ihdr.bit_depth
+	};
+	if sbit.iter().any(|x| *x == 0 || *x > max) {
+		return None;
+	}
+	match (ihdr.color_type, sbit) {
+		(0, [g]) => Some(([*g; 3], None)),
+		(2 | 3, [r, g, b]) => Some(([*r, *g, *b], None)),
+		(4, [g, a]) => Some(([*g; 3], Some(*a))),
+		(6, [r, g, b, a]) => Some(([*r, *g, *b], Some(*a))),
+		_ => None,
+	}
+}
+
+/// What is known about an image's pixels
+struct Stats {
+	/// Whether every pixel is gray
+	grayscale: bool,
+	/// The smallest bit depth that stores every red, green and blue sample exactly
+	color_bit_depth: u8,
+	/// The smallest bit depth that stores every alpha sample exactly
+	alpha_bit_depth: u8,
+	/// Whether every pixel is opaque
+	opaque: bool,
+	/// If every pixel is either opaque or transparent, every transparent pixel has the same color,
+	/// and no opaque pixel has that color, then that color
+	transparent_color: Option<[u16; 3]>,
+	/// The distinct pixels, in palette order, if every sample can be stored using 8 bits and
+	/// there are at most 256 distinct pixels
+	palette: Option<Vec<[u8; 4]>>,
+}
+
+impl Stats {
+	fn new(pixels: &[Pixel]) -> Stats {
+		let color_bit_depth = min_bit_depth(pixels.iter().flat_map(|p| p[..3].iter().copied()));
+		let alpha_bit_depth = min_bit_depth(pixels.iter().map(|p| p[3]));
+
+		let transparent: BTreeSet<[u16; 3]> = pixels
+			.iter()
+			.filter(|p| p[3] == 0)
+			.map(|p| [p[0], p[1], p[2]])
+			.collect();
+		let transparent_color = match (transparent.len(), transparent.first()) {
+			(1, Some(color)) => pixels
+				.iter()
+				.all(|p| match p[3] {
+					0 => true,
+					u16::MAX => p[..3] != color[..],
+					_ => false,
+				})
+				.then_some(*color),
+			_ => None,
+		};
+
+		let palette = if color_bit_depth <= 8 && alpha_bit_depth <= 8 {
+			let mut palette = BTreeSet::new();
+			for pixel in pixels {
+				palette.insert(pixel.map(|x| (x / 257) as u8));
+				if palette.len() > 256 {
+					break;
+				}
+			}
+			let mut palette: Vec<[u8; 4]> = palette.into_iter().collect();
+			palette.sort_by_key(palette_order);
+			Some(palette).filter(|x| x.len() <= 256)
+		} else {
+			None
+		};
+
+		Stats {
+			grayscale: pixels.iter().all(|p| p[0] == p[1] && p[1] == p[2]),
+			color_bit_depth,
+			alpha_bit_depth,
+			opaque: pixels.iter().all(|p| p[3] == u16::MAX),
+			transparent_color,
+			palette,
+		}
+	}
+}
+
+/// Stores the pixels using the given color type and bit depth, or returns None if they, or the
+/// color chunks, cannot be stored exactly that way
+fn encode(
+	chunks: &ColorChunks,
+	pixels: &[Pixel],
+	stats: &Stats,
+	bkgd: Option<[u16; 3]>,
+	sbit: Option<([u8; 3], Option<u8>)>,
+	(color_type, bit_depth): (u8, u8),
+) -> Result<Option<(ColorChunks, Vec<u8>)>, FilterError> {
+	let source_color_type = chunks.ihdr.color_type;
+	if chunks.has_iccp && is_grayscale(source_color_type) != is_grayscale(color_type) {
+		return Ok(None);
+	}
+	// a truecolor image's PLTE is a suggested palette, which only a truecolor image can keep
+	let suggested_palette = match source_color_type {
+		2 | 6 => chunks.plte.clone(),
+		_ => None,
+	};
+	if suggested_palette.is_some() && color_type != 2 && color_type != 6 {
+		return Ok(None);
+	}
+
+	let has_alpha = color_type == 4 || color_type == 6;
+	// the bit depth of the red, green and blue samples
+	let sample_bit_depth = if color_type == 3 { 8 } else { bit_depth };
+	let fits_color = |color: &[u16; 3]| color.iter().all(|x| fits(*x, sample_bit_depth));
+	let grayscale = is_grayscale(color_type);
+	if (grayscale && !stats.grayscale)
+		|| stats.color_bit_depth > sample_bit_depth
+		|| (has_alpha && stats.alpha_bit_depth > bit_depth)
+		|| (!has_alpha && color_type != 3 && !stats.opaque && stats.transparent_color.is_none())
+		|| (color_type == 3 && stats.palette.is_none())
+	{
+		return Ok(None);
+	}
+	if let Some(bkgd) = bkgd {
+		if (grayscale && !(bkgd[0] == bkgd[1] && bkgd[1] == bkgd[2])) || !fits_color(&bkgd) {
+			return Ok(None);
+		}
+	}
+
+	let sbit = match sbit {
+		None => None,
+		Some(([r, g, b], alpha)) => {
+			if [r, g, b].iter().any(|x| *x > sample_bit_depth)
+				|| (grayscale && !(r == g && g == b))
+				|| (!has_alpha && alpha.is_some() && stats.alpha_bit_depth > 1)
+				|| (has_alpha && alpha.is_some_and(|x| x > bit_depth))
+			{
+				return Ok(None);
+			}
+			Some(match color_type {
+				0 => vec![g],
+				2 | 3 => vec![r, g, b],
+				4 => vec![g, alpha.unwrap_or(bit_depth)],
+				_ => vec![r, g, b, alpha.unwrap_or(bit_depth)],
+			})
+		},
+	};
+
+	let palette = if color_type == 3 {
+		let mut palette = stats.palette.clone().unwrap_or_default();
+		if let Some(bkgd) = bkgd {
+			let bkgd = bkgd.map(|x| (x / 257) as u8);
+			if !palette.iter().any(|entry| entry[..3] == bkgd) {
+				palette.push([bkgd[0], bkgd[1], bkgd[2], u8::MAX]);
+				palette.sort_by_key(palette_order);
+			}
+		}
+		if palette.len() > 1 << bit_depth {
+			return Ok(None);
+		}
+		palette
+	} else {
+		Vec::new()
+	};
+	let indexes: HashMap<[u8; 4], u16> = palette
+		.iter()
+		.enumerate()
+		.map(|(i, entry)| (*entry, i as u16))
+		.collect();
+
+	let scale = scale(bit_depth);
+	let trns = match color_type {
+		0 | 2 if !stats.opaque => stats.transparent_color.map(|color| {
+			let color: &[u16] = if color_type == 0 { &color[..1] } else { &color };
+			color
+				.iter()
+				.flat_map(|x| (x / scale).to_be_bytes())
+				.collect()
+		}),
+		3 => {
+			let trns: Vec<u8> = palette
+				.iter()
+				.take_while(|entry| entry[3] != u8::MAX)
+				.map(|entry| entry[3])
+				.collect();
+			Some(trns).filter(|x| !x.is_empty())
+		},
+		_ => None,
+	};
+	let bkgd = bkgd.map(|color| match color_type {
+		0 | 4 => (color[0] / scale).to_be_bytes().to_vec(),
+		2 | 6 => color
+			.iter()
+			.flat_map(|x| (x / scale).to_be_bytes())
+			.collect(),
+		_ => {
+			let index = palette
+				.iter()
+				.position(|entry| entry[..3] == color.map(|x| (x / 257) as u8))
+				.expect("the background color was added to the palette");
+			vec![index as u8]
+		},
+	});
+
+	let ihdr = Ihdr {
+		color_type,
+		bit_depth,
+		interlace_method: 0,
+		..chunks.ihdr.clone()
+	};
+	let bits_per_pixel = filter::bits_per_pixel(&ihdr)?;
+	let channels = bits_per_pixel / usize::from(bit_depth);
+	let row_length = filter::row_length(ihdr.width, bits_per_pixel)?;
+	let mut data = vec![0; filter::data_length(&ihdr, bits_per_pixel)?];
+	let width = ihdr.width as usize;
+	if width != 0 {
+		for (row, pixels) in data
+			.chunks_exact_mut(row_length + 1)
+			.zip(pixels.chunks_exact(width))
+		{
+			let row = &mut row[1..];
+			for (x, pixel) in pixels.iter().enumerate() {
+				let samples: &[u16] = match color_type {
+					0 => &pixel[..1],
+					2 => &pixel[..3],
+					4 => &[pixel[0], pixel[3]],
+					6 => &pixel[..],
+					_ => {
+						let index = indexes[&pixel.map(|x| (x / 257) as u8)];
+						write_sample(row, x, bit_depth, index);
+						continue;
+					},
+				};
+				for (c, sample) in samples.iter().enumerate() {
+					write_sample(row, x * channels + c, bit_depth, sample / scale);
+				}
+			}
+		}
+	}
+
+	let ihdr = Ihdr {
+		interlace_method: chunks.ihdr.interlace_method,
+		..ihdr
+	};
+	if ihdr.interlace_method != 0 {
+		data = interlace::reinterlace(
+			&Ihdr {
+				interlace_method: 0,
+				..ihdr.clone()
+			},
+			&data,
+			ihdr.interlace_method,
+		)?;
+	}
+
+	let plte = if color_type == 3 {
+		Some(Plte {
+			entries: palette.iter().map(|x| [x[0], x[1], x[2]]).collect(),
+		})
+	} else {
+		suggested_palette
+	};
+	Ok(Some((
+		ColorChunks {
+			ihdr,
+			plte,
+			trns,
+			sbit,
+			bkgd,
+			has_iccp: chunks.has_iccp,
+		},
+		data,
+	)))
+}
+
+/// Decodes the inflated image data `data` of an image described by `chunks`, and stores the
+/// pixels using the representation that `form` picks
+///
+/// Returns the chunks describing the new representation, and the new image data. The new image
+/// data has the same interlace method as the old, and every scanline is unfiltered. A PLTE, tRNS,
+/// sBIT or bKGD is only present in the result if it is needed to describe the image exactly;
+/// except for a PLTE that is added for a palette image or a tRNS that is added for transparency,
+/// each is present only if it was present in `chunks`.
+///
+/// Returns None if no representation that the form allows can store the image exactly, or if the
+/// color chunks are not consistent with the IHDR.
+pub fn canonicalize(
+	chunks: &ColorChunks,
+	data: &[u8],
+	form: CanonicalForm,
+) -> Result<Option<(ColorChunks, Vec<u8>)>, FilterError> {
+	if chunks.ihdr.filter_method != 0 {
+		return Err(FilterError::UnsupportedFilterMethod(
+			chunks.ihdr.filter_method,
+		));
+	}
+	let bkgd = match &chunks.bkgd {
+		None => None,
+		Some(bkgd) => match decode_bkgd(chunks, bkgd) {
+			None => return Ok(None),
+			x => x,
+		},
+	};
+	let sbit = match &chunks.sbit {
+		None => None,
+		Some(sbit) => match decode_sbit(&chunks.ihdr, sbit) {
+			None => return Ok(None),
+			x => x,
+		},
+	};
+	let pixels = match decode(chunks, data)? {
+		None => return Ok(None),
+		Some(pixels) => pixels,
+	};
+	let stats = Stats::new(&pixels);
+
+	let order: &[(u8, u8)] = match form {
+		CanonicalForm::Smallest => &SMALLEST_ORDER,
+		CanonicalForm::Rgba => &RGBA_ORDER,
+	};
+	for target in order {
+		if let Some(retval) = encode(chunks, &pixels, &stats, bkgd, sbit, *target)? {
+			return Ok(Some(retval));
+		}
+	}
+	Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+	mod canonicalize {
+		use super::super::super::typed::Plte;
+		use super::super::canonicalize;
+		use super::super::CanonicalForm;
+		use super::super::ColorChunks;
+		use crate::test_util::ihdr;
+
+		fn chunks(width: u32, height: u32, color_type: u8, bit_depth: u8) -> ColorChunks {
+			ColorChunks {
+				ihdr: ihdr(width, height, bit_depth, color_type, 0),
+				plte: None,
+				trns: None,
+				sbit: None,
+				bkgd: None,
+				has_iccp: false,
+			}
+		}
+
+		fn smallest(chunks: &ColorChunks, data: &[u8]) -> (ColorChunks, Vec<u8>) {
+			canonicalize(chunks, data, CanonicalForm::Smallest)
+				.unwrap()
+				.unwrap()
+		}
+
+		#[test]
+		fn gray_with_few_levels_uses_low_bit_depth() {
+			// five levels are too many for a 2-bit palette
+			let image = [0, 0x00, 0x11, 0x22, 0x33, 0xFF];
+			let (res, data) = smallest(&chunks(5, 1, 0, 8), &image);
+			assert_eq!((0, 4), (res.ihdr.color_type, res.ihdr.bit_depth));
+			assert_eq!(vec![0, 0x01, 0x23, 0xF0], data);
+		}
+
+		#[test]
+		fn sixteen_bit_with_repeated_bytes_uses_eight_bits() {
+			#[rustfmt::skip]
+			let image = [0, 0x12, 0x12, 0x34, 0x34, 0x56, 0x56, 0xFF, 0xFF];
+			let (res, data) = canonicalize(&chunks(1, 1, 6, 16), &image, CanonicalForm::Rgba)
+				.unwrap()
+				.unwrap();
+			assert_eq!((6, 8), (res.ihdr.color_type, res.ihdr.bit_depth));
+			assert_eq!(vec![0, 0x12, 0x34, 0x56, 0xFF], data);
+		}
+
+		#[test]
+		fn sixteen_bit_without_repeated_bytes_stays_sixteen_bit() {
+			let image = [0, 0x12, 0x13, 0x34, 0x34, 0x56, 0x56, 0xFF, 0xFF];
+			let (res, data) = canonicalize(&chunks(1, 1, 6, 16), &image, CanonicalForm::Rgba)
+				.unwrap()
+				.unwrap();
+			assert_eq!((6, 16), (res.ihdr.color_type, res.ihdr.bit_depth));
+			assert_eq!(image.to_vec(), data);
+		}
+
+		#[test]
+		fn few_colors_use_palette() {
+			#[rustfmt::skip]
+			let image = [0, 255, 0, 0, 0, 0, 255, 255, 0, 0];
+			let (res, data) = smallest(&chunks(3, 1, 2, 8), &image);
+			assert_eq!((3, 1), (res.ihdr.color_type, res.ihdr.bit_depth));
+			assert_eq!(
+				Some(Plte {
+					entries: vec![[0, 0, 255], [255, 0, 0]]
+				}),
+				res.plte
+			);
+			assert_eq!(None, res.trns);
+			assert_eq!(vec![0, 0b1010_0000], data);
+		}
+
+		#[test]
+		fn palette_puts_translucent_entries_first() {
+			#[rustfmt::skip]
+			let image = [0, 255, 0, 0, 255, 0, 0, 255, 128];
+			let (res, data) = smallest(&chunks(2, 1, 6, 8), &image);
+			assert_eq!((3, 1), (res.ihdr.color_type, res.ihdr.bit_depth));
+			assert_eq!(
+				Some(Plte {
+					entries: vec![[0, 0, 255], [255, 0, 0]]
+				}),
+				res.plte
+			);
+			assert_eq!(Some(vec![128]), res.trns);
+			assert_eq!(vec![0, 0b1000_0000], data);
+		}
+
+		#[test]
+		fn binary_transparency_uses_trns() {
+			// three gray levels, of which only black is transparent
+			#[rustfmt::skip]
+			let image = [0, 0x00, 0x00, 0x55, 0xFF, 0xFF, 0xFF];
+			let (res, data) = smallest(&chunks(3, 1, 4, 8), &image);
+			assert_eq!((0, 2), (res.ihdr.color_type, res.ihdr.bit_depth));
+			assert_eq!(Some(vec![0, 0]), res.trns);
+			assert_eq!(vec![0, 0b0001_1100], data);
+		}
+
+		#[test]
+		fn transparent_pixels_of_different_colors_keep_alpha() {
+			#[rustfmt::skip]
+			let image = [0, 0x00, 0x00, 0xFF, 0x00];
+			let (res, _) = smallest(&chunks(2, 1, 4, 8), &image);
+			// a palette keeps the color of each transparent pixel
+			assert_eq!((3, 1), (res.ihdr.color_type, res.ihdr.bit_depth));
+			assert_eq!(Some(vec![0, 0]), res.trns);
+		}
+
+		#[test]
+		fn palette_to_rgba() {
+			let source = ColorChunks {
+				plte: Some(Plte {
+					entries: vec![[1, 2, 3], [4, 5, 6]],
+				}),
+				trns: Some(vec![7]),
+				bkgd: Some(vec![1]),
+				sbit: Some(vec![5, 6, 7]),
+				..chunks(2, 1, 3, 8)
+			};
+			let (res, data) = canonicalize(&source, &[0, 1, 0], CanonicalForm::Rgba)
+				.unwrap()
+				.unwrap();
+			assert_eq!((6, 8), (res.ihdr.color_type, res.ihdr.bit_depth));
+			assert_eq!(None, res.plte);
+			assert_eq!(None, res.trns);
+			assert_eq!(Some(vec![0, 4, 0, 5, 0, 6]), res.bkgd);
+			assert_eq!(Some(vec![5, 6, 7, 8]), res.sbit);
+			assert_eq!(vec![0, 4, 5, 6, 255, 1, 2, 3, 7], data);
+		}
+
+		#[test]
+		fn background_color_is_added_to_palette() {
+			let source = ColorChunks {
+				bkgd: Some(vec![0, 9, 0, 9, 0, 9]),
+				..chunks(1, 1, 2, 8)
+			};
+			let (res, data) = smallest(&source, &[0, 1, 2, 3]);
+			assert_eq!((3, 1), (res.ihdr.color_type, res.ihdr.bit_depth));
+			assert_eq!(
+				Some(Plte {
+					entries: vec![[1, 2, 3], [9, 9, 9]]
+				}),
+				res.plte
+			);
+			assert_eq!(Some(vec![1]), res.bkgd);
+			assert_eq!(vec![0, 0b0000_0000], data);
+		}
+
+		#[test]
+		fn sbit_that_does_not_fit_prevents_lower_bit_depth() {
+			let source = ColorChunks {
+				sbit: Some(vec![8]),
+				..chunks(2, 1, 0, 8)
+			};
+			// a palette's samples are always 8 bits
+			let (res, _) = smallest(&source, &[0, 0, 255]);
+			assert_eq!((3, 1), (res.ihdr.color_type, res.ihdr.bit_depth));
+			assert_eq!(Some(vec![8, 8, 8]), res.sbit);
+		}
+
+		#[test]
+		fn alpha_sbit_that_does_not_fit_prevents_lower_bit_depth() {
+			let source = ColorChunks {
+				sbit: Some(vec![8, 16]),
+				..chunks(2, 1, 4, 16)
+			};
+			#[rustfmt::skip]
+			let image = [0, 0x12, 0x12, 0x80, 0x80, 0x34, 0x34, 0xFF, 0xFF];
+			// the alpha samples fit in eight bits, but the sBIT's alpha entry does not
+			let (res, data) = smallest(&source, &image);
+			assert_eq!((4, 16), (res.ihdr.color_type, res.ihdr.bit_depth));
+			assert_eq!(Some(vec![8, 16]), res.sbit);
+			assert_eq!(image.to_vec(), data);
+		}
+
+		#[test]
+		fn iccp_keeps_grayscale() {
+			let source = ColorChunks {
+				has_iccp: true,
+				..chunks(1, 1, 0, 8)
+			};
+			assert_eq!(
+				None,
+				canonicalize(&source, &[0, 7], CanonicalForm::Rgba).unwrap()
+			);
+			let (res, _) = smallest(&source, &[0, 7]);
+			assert_eq!((0, 8), (res.ihdr.color_type, res.ihdr.bit_depth));
+		}
+
+		#[test]
+		fn interlaced_stays_interlaced() {
+			let source = ColorChunks {
+				ihdr: ihdr(2, 2, 8, 0, 1),
+				..chunks(2, 2, 0, 8)
+			};
+			let (res, data) = smallest(&source, &[0, 0, 0, 255, 0, 255, 0]);
+			assert_eq!(
+				(0, 1, 1),
+				(
+					res.ihdr.color_type,
+					res.ihdr.bit_depth,
+					res.ihdr.interlace_method
+				)
+			);
+			assert_eq!(vec![0, 0b0000_0000, 0, 0b1000_0000, 0, 0b1000_0000], data);
+		}
+
+		#[test]
+		fn palette_index_out_of_range_is_unchanged() {
+			let source = ColorChunks {
+				plte: Some(Plte {
+					entries: vec![[1, 2, 3]],
+				}),
+				..chunks(1, 1, 3, 8)
+			};
+			assert_eq!(
+				None,
+				canonicalize(&source, &[0, 1], CanonicalForm::Smallest).unwrap()
+			);
+		}
+	}
+}
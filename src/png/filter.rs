@@ -0,0 +1,420 @@
+//! Reverses the scanline filters of a png image's inflated image data
+//!
+//! Each scanline of the image data starts with a filter type byte, which says how the rest of the
+//! scanline was predicted from the bytes to its left and above it. The same pixels can be filtered
+//! in many ways, so [`normalize`] undoes every filter and marks every scanline as unfiltered,
+//! which leaves data that depends only on the pixels.
+//!
+//! ```
+//! use png_inflate::png::filter::normalize;
+//! use png_inflate::png::typed::Ihdr;
+//!
+//! let ihdr = Ihdr {
+//!     width: 2,
+//!     height: 2,
+//!     bit_depth: 8,
+//!     color_type: 0,
+//!     compression_method: 0,
+//!     filter_method: 0,
+//!     interlace_method: 0,
+//! };
+//! // a Sub-filtered scanline followed by an Up-filtered scanline
+//! let mut data = vec![1, 10, 5, 2, 1, 1];
+//! normalize(&ihdr, &mut data)?;
+//! assert_eq!(vec![0, 10, 15, 0, 11, 16], data);
+//! # Ok::<(), png_inflate::png::filter::FilterError>(())
+//! ```
+
+use super::typed::Ihdr;
+
+/// The position and spacing of the pixels in each pass of an Adam7-interlaced image, as
+/// `(x_start, y_start, x_step, y_step)`
+pub(crate) const ADAM7: [(u32, u32, u32, u32); 7] = [
+	(0, 0, 8, 8),
+	(4, 0, 8, 8),
+	(0, 4, 4, 8),
+	(2, 0, 4, 4),
+	(0, 2, 2, 4),
+	(1, 0, 2, 2),
+	(0, 1, 1, 2),
+];
+
+/// Represents an error that can occur when reversing the filters of an image's data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FilterError {
+	/// The IHDR declares a combination of color type and bit depth that does not exist
+	UnsupportedFormat { color_type: u8, bit_depth: u8 },
+	/// The IHDR declares a filter method other than adaptive filtering
+	UnsupportedFilterMethod(u8),
+	/// The IHDR declares an interlace method other than none or Adam7
+	UnsupportedInterlaceMethod(u8),
+	/// The image data is too large to be held in memory
+	ImageTooLarge,
+	/// A scanline starts with a filter type other than the five defined filter types
+	UnknownFilterType { scanline: usize, filter_type: u8 },
+	/// The image data is shorter than the IHDR requires
+	TooShort { expected: usize, actual: usize },
+}
+
+impl ::std::fmt::Display for FilterError {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		match self {
+			FilterError::UnsupportedFormat {
+				color_type,
+				bit_depth,
+			} => write!(
+				f,
+				"Bit depth {} with color type {} is not supported",
+				bit_depth, color_type
+			),
+			FilterError::UnsupportedFilterMethod(x) => {
+				write!(f, "Unsupported PNG Filter Method {}", x)
+			},
+			FilterError::UnsupportedInterlaceMethod(x) => {
+				write!(f, "Unsupported PNG Interlace Method {}", x)
+			},
+			FilterError::ImageTooLarge => write!(f, "Image data is too large"),
+			FilterError::UnknownFilterType {
+				scanline,
+				filter_type,
+			} => write!(
+				f,
+				"Scanline {} has unknown filter type {}",
+				scanline, filter_type
+			),
+			FilterError::TooShort { expected, actual } => write!(
+				f,
+				"Image data is {} bytes, but should be {} bytes",
+				actual, expected
+			),
+		}
+	}
+}
+
+impl ::std::error::Error for FilterError {}
+
+/// The number of bits in each pixel of an image with the given color type and bit depth
+pub(crate) fn bits_per_pixel(ihdr: &Ihdr) -> Result<usize, FilterError> {
+	let channels = match (ihdr.color_type, ihdr.bit_depth) {
+		(0, 1 | 2 | 4 | 8 | 16) => 1,
+		(3, 1 | 2 | 4 | 8) => 1,
+		(2, 8 | 16) => 3,
+		(4, 8 | 16) => 2,
+		(6, 8 | 16) => 4,
+		_ => {
+			return Err(FilterError::UnsupportedFormat {
+				color_type: ihdr.color_type,
+				bit_depth: ihdr.bit_depth,
+			})
+		},
+	};
+	Ok(channels * usize::from(ihdr.bit_depth))
+}
+
+/// The width and height of each of the image's passes, or of the whole image if the image is not
+/// interlaced. A pass with no pixels has a width or height of zero.
+pub(crate) fn pass_sizes(ihdr: &Ihdr) -> Result<Vec<(u32, u32)>, FilterError> {
+	match ihdr.interlace_method {
+		0 => Ok(vec![(ihdr.width, ihdr.height)]),
+		1 => Ok(ADAM7
+			.iter()
+			.map(|&(x_start, y_start, x_step, y_step)| {
+				(
+					ihdr.width.saturating_sub(x_start).div_ceil(x_step),
+					ihdr.height.saturating_sub(y_start).div_ceil(y_step),
+				)
+			})
+			.collect()),
+		x => Err(FilterError::UnsupportedInterlaceMethod(x)),
+	}
+}
+
+/// The number of bytes in a scanline of `width` pixels, not counting the filter type byte
+pub(crate) fn row_length(width: u32, bits_per_pixel: usize) -> Result<usize, FilterError> {
+	usize::try_from(width)
+		.ok()
+		.and_then(|width| width.checked_mul(bits_per_pixel))
+		.map(|bits| bits.div_ceil(8))
+		.ok_or(FilterError::ImageTooLarge)
+}
+
+/// Reverses the filter of each scanline in `data`, which is the inflated image data of an image
+/// described by `ihdr`, and sets each scanline's filter type to None
+///
+/// Each pass of an interlaced image is unfiltered separately, as the png specification requires.
+/// Any data after the last scanline is left unchanged.
+pub fn normalize(ihdr: &Ihdr, data: &mut [u8]) -> Result<(), FilterError> {
+	if ihdr.filter_method != 0 {
+		return Err(FilterError::UnsupportedFilterMethod(ihdr.filter_method));
+	}
+	let bits_per_pixel = bits_per_pixel(ihdr)?;
+	// the distance to the corresponding byte of the pixel to the left
+	let left_distance = bits_per_pixel.div_ceil(8);
+
+	let expected = data_length(ihdr, bits_per_pixel)?;
+	if data.len() < expected {
+		return Err(FilterError::TooShort {
+			expected,
+			actual: data.len(),
+		});
+	}
+
+	let mut offset = 0;
+	let mut scanline = 0;
+	for (width, height) in pass_sizes(ihdr)? {
+		if width == 0 {
+			continue;
+		}
+		let row_length = row_length(width, bits_per_pixel)?;
+		let mut previous_row: Option<usize> = None;
+		for _ in 0..height {
+			let (before, row) = data[..offset + 1 + row_length].split_at_mut(offset);
+			let filter_type = row[0];
+			let row = &mut row[1..];
+			let above = previous_row.map(|start| &before[start..start + row_length]);
+			unfilter_row(filter_type, row, above, left_distance).ok_or(
+				FilterError::UnknownFilterType {
+					scanline,
+					filter_type,
+				},
+			)?;
+			data[offset] = 0;
+
+			previous_row = Some(offset + 1);
+			offset += row_length + 1;
+			scanline += 1;
+		}
+	}
+	Ok(())
+}
+
+/// The total length of the image data, including filter type bytes, of an image described by
+/// `ihdr`
+pub(crate) fn data_length(ihdr: &Ihdr, bits_per_pixel: usize) -> Result<usize, FilterError> {
+	let mut retval: usize = 0;
+	for (width, height) in pass_sizes(ihdr)? {
+		if width == 0 {
+			continue;
+		}
+		retval = (row_length(width, bits_per_pixel)? + 1)
+			.checked_mul(height as usize)
+			.and_then(|x| x.checked_add(retval))
+			.ok_or(FilterError::ImageTooLarge)?;
+	}
+	Ok(retval)
+}
+
+/// Reverses the filter of a single scanline in place. `above` is the unfiltered previous
+/// scanline of the same pass, if there is one. Returns None if the filter type is unknown.
+fn unfilter_row(
+	filter_type: u8,
+	row: &mut [u8],
+	above: Option<&[u8]>,
+	left_distance: usize,
+) -> Option<()> {
+	let above_at = |i: usize| above.map_or(0, |above| above[i]);
+	match filter_type {
+		0 => {},
+		1 => {
+			for i in left_distance..row.len() {
+				row[i] = row[i].wrapping_add(row[i - left_distance]);
+			}
+		},
+		2 => {
+			for (i, byte) in row.iter_mut().enumerate() {
+				*byte = byte.wrapping_add(above_at(i));
+			}
+		},
+		3 => {
+			for i in 0..row.len() {
+				let left = if i >= left_distance {
+					row[i - left_distance]
+				} else {
+					0
+				};
+				let average = ((u16::from(left) + u16::from(above_at(i))) / 2) as u8;
+				row[i] = row[i].wrapping_add(average);
+			}
+		},
+		4 => {
+			for i in 0..row.len() {
+				let (left, upper_left) = if i >= left_distance {
+					(row[i - left_distance], above_at(i - left_distance))
+				} else {
+					(0, 0)
+				};
+				row[i] = row[i].wrapping_add(paeth(left, above_at(i), upper_left));
+			}
+		},
+		_ => return None,
+	}
+	Some(())
+}
+
+/// The Paeth predictor, which picks whichever of the three neighbouring bytes is closest to
+/// `left + above - upper_left`
+fn paeth(left: u8, above: u8, upper_left: u8) -> u8 {
+	let estimate = i16::from(left) + i16::from(above) - i16::from(upper_left);
+	let distance_left = (estimate - i16::from(left)).abs();
+	let distance_above = (estimate - i16::from(above)).abs();
+	let distance_upper_left = (estimate - i16::from(upper_left)).abs();
+	if distance_left <= distance_above && distance_left <= distance_upper_left {
+		left
+	} else if distance_above <= distance_upper_left {
+		above
+	} else {
+		upper_left
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	mod normalize {
+		use super::super::normalize;
+		use super::super::FilterError;
+		use crate::test_util::ihdr;
+
+		#[test]
+		fn none() {
+			let mut dut = vec![0, 1, 2, 3, 0, 4, 5, 6];
+			normalize(&ihdr(3, 2, 8, 0, 0), &mut dut).unwrap();
+			assert_eq!(vec![0, 1, 2, 3, 0, 4, 5, 6], dut);
+		}
+
+		#[test]
+		fn sub_uses_the_byte_one_pixel_to_the_left() {
+			let mut dut = vec![1, 1, 2, 3, 4, 5, 6];
+			normalize(&ihdr(3, 1, 8, 4, 0), &mut dut).unwrap();
+			assert_eq!(vec![0, 1, 2, 4, 6, 9, 12], dut);
+		}
+
+		#[test]
+		fn sub_with_low_bit_depth_uses_the_previous_byte() {
+			let mut dut = vec![1, 0x11, 0x11];
+			normalize(&ihdr(16, 1, 1, 0, 0), &mut dut).unwrap();
+			assert_eq!(vec![0, 0x11, 0x22], dut);
+		}
+
+		#[test]
+		fn up() {
+			let mut dut = vec![0, 1, 2, 2, 1, 1, 2, 255, 255];
+			normalize(&ihdr(2, 3, 8, 0, 0), &mut dut).unwrap();
+			assert_eq!(vec![0, 1, 2, 0, 2, 3, 0, 1, 2], dut);
+		}
+
+		#[test]
+		fn up_on_first_row_is_none() {
+			let mut dut = vec![2, 7, 8];
+			normalize(&ihdr(2, 1, 8, 0, 0), &mut dut).unwrap();
+			assert_eq!(vec![0, 7, 8], dut);
+		}
+
+		#[test]
+		fn average() {
+			let mut dut = vec![0, 10, 20, 3, 1, 2];
+			normalize(&ihdr(2, 2, 8, 0, 0), &mut dut).unwrap();
+			// 1 + (0 + 10) / 2 = 6; 2 + (6 + 20) / 2 = 15
+			assert_eq!(vec![0, 10, 20, 0, 6, 15], dut);
+		}
+
+		#[test]
+		fn average_does_not_overflow() {
+			let mut dut = vec![0, 255, 255, 3, 0, 0];
+			normalize(&ihdr(2, 2, 8, 0, 0), &mut dut).unwrap();
+			assert_eq!(vec![0, 255, 255, 0, 127, 191], dut);
+		}
+
+		#[test]
+		fn paeth() {
+			let mut dut = vec![0, 10, 20, 4, 1, 1];
+			normalize(&ihdr(2, 2, 8, 0, 0), &mut dut).unwrap();
+			// first byte: left = 0, above = 10, upper left = 0, so predicts above
+			// second byte: left = 11, above = 20, upper left = 10, so estimate is 21 and predicts above
+			assert_eq!(vec![0, 10, 20, 0, 11, 21], dut);
+		}
+
+		#[test]
+		fn interlaced_passes_are_unfiltered_separately() {
+			// a 2x2 image has passes 1, 6 and 7, of sizes 1x1, 1x1 and 2x1
+			let mut dut = vec![0, 5, 2, 6, 2, 1, 2];
+			normalize(&ihdr(2, 2, 8, 0, 1), &mut dut).unwrap();
+			// the first row of each pass has no row above it
+			assert_eq!(vec![0, 5, 0, 6, 0, 1, 2], dut);
+		}
+
+		#[test]
+		fn leaves_trailing_data_unchanged() {
+			let mut dut = vec![1, 1, 1, 3, 4];
+			normalize(&ihdr(2, 1, 8, 0, 0), &mut dut).unwrap();
+			assert_eq!(vec![0, 1, 2, 3, 4], dut);
+		}
+
+		#[test]
+		fn rejects_unknown_filter_type() {
+			let mut dut = vec![0, 1, 5, 2];
+			assert_eq!(
+				Err(FilterError::UnknownFilterType {
+					scanline: 1,
+					filter_type: 5
+				}),
+				normalize(&ihdr(1, 2, 8, 0, 0), &mut dut)
+			);
+		}
+
+		#[test]
+		fn rejects_short_data() {
+			let mut dut = vec![0, 1, 0];
+			assert_eq!(
+				Err(FilterError::TooShort {
+					expected: 4,
+					actual: 3
+				}),
+				normalize(&ihdr(1, 2, 8, 0, 0), &mut dut)
+			);
+		}
+
+		#[test]
+		fn rejects_invalid_bit_depth() {
+			let mut dut = vec![];
+			assert_eq!(
+				Err(FilterError::UnsupportedFormat {
+					color_type: 2,
+					bit_depth: 4
+				}),
+				normalize(&ihdr(1, 1, 4, 2, 0), &mut dut)
+			);
+		}
+
+		#[test]
+		fn rejects_unknown_interlace_method() {
+			let mut dut = vec![];
+			assert_eq!(
+				Err(FilterError::UnsupportedInterlaceMethod(2)),
+				normalize(&ihdr(1, 1, 8, 0, 2), &mut dut)
+			);
+		}
+	}
+
+	mod pass_sizes {
+		use super::super::pass_sizes;
+		use crate::test_util::ihdr;
+
+		#[test]
+		fn not_interlaced() {
+			assert_eq!(vec![(5, 7)], pass_sizes(&ihdr(5, 7, 8, 0, 0)).unwrap());
+		}
+
+		#[test]
+		fn interlaced() {
+			assert_eq!(
+				vec![(1, 1), (1, 1), (2, 1), (2, 2), (4, 2), (4, 4), (8, 4)],
+				pass_sizes(&ihdr(8, 8, 8, 0, 1)).unwrap()
+			);
+			assert_eq!(
+				vec![(1, 1), (0, 1), (1, 1), (1, 2), (2, 1), (1, 3), (3, 2)],
+				pass_sizes(&ihdr(3, 5, 8, 0, 1)).unwrap()
+			);
+		}
+	}
+}
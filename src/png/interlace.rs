@@ -0,0 +1,271 @@
+//! Converts a png image's inflated image data between interlace methods
+//!
+//! An Adam7-interlaced image stores its pixels in seven passes, each of which is a smaller image
+//! made of every eighth, fourth or second pixel, while an image that is not interlaced stores its
+//! pixels row by row. [`reinterlace`] moves the pixels from one layout to the other.
+//!
+//! ```
+//! use png_inflate::png::interlace::reinterlace;
+//! use png_inflate::png::typed::Ihdr;
+//!
+//! let ihdr = Ihdr {
+//!     width: 2,
+//!     height: 2,
+//!     bit_depth: 8,
+//!     color_type: 0,
+//!     compression_method: 0,
+//!     filter_method: 0,
+//!     interlace_method: 1,
+//! };
+//! // a 2x2 image has three non-empty passes: the top left pixel, the top right pixel, and the
+//! // bottom row
+//! let data = vec![0, 1, 0, 2, 0, 3, 4];
+//! assert_eq!(vec![0, 1, 2, 0, 3, 4], reinterlace(&ihdr, &data, 0)?);
+//! # Ok::<(), png_inflate::png::filter::FilterError>(())
+//! ```
+
+use super::filter;
+use super::filter::FilterError;
+use super::typed::Ihdr;
+
+/// The position and spacing of the pixels of the only pass of an image that is not interlaced
+const PROGRESSIVE: [(u32, u32, u32, u32); 1] = [(0, 0, 1, 1)];
+
+/// Where a pass's pixels are in the image, and where the pass's scanlines are in the image data
+struct Pass {
+	x_start: u32,
+	y_start: u32,
+	x_step: u32,
+	y_step: u32,
+	/// The offset of the pass's first filter type byte
+	offset: usize,
+	/// The number of bytes in each of the pass's scanlines, not counting the filter type byte
+	row_length: usize,
+	width: u32,
+	height: u32,
+}
+
+/// The passes of image data with the given size, bit depth and interlace method
+fn passes(
+	width: u32,
+	height: u32,
+	bits_per_pixel: usize,
+	interlace_method: u8,
+) -> Result<Vec<Pass>, FilterError> {
+	let geometry: &[(u32, u32, u32, u32)] = match interlace_method {
+		0 => &PROGRESSIVE,
+		1 => &filter::ADAM7,
+		x => return Err(FilterError::UnsupportedInterlaceMethod(x)),
+	};
+	let mut offset: usize = 0;
+	let mut retval = Vec::with_capacity(geometry.len());
+	for &(x_start, y_start, x_step, y_step) in geometry {
+		let pass_width = width.saturating_sub(x_start).div_ceil(x_step);
+		let pass_height = height.saturating_sub(y_start).div_ceil(y_step);
+		let row_length = filter::row_length(pass_width, bits_per_pixel)?;
+		retval.push(Pass {
+			x_start,
+			y_start,
+			x_step,
+			y_step,
+			offset,
+			row_length,
+			width: pass_width,
+			height: pass_height,
+		});
+		if pass_width != 0 {
+			offset = (row_length + 1)
+				.checked_mul(pass_height as usize)
+				.and_then(|x| x.checked_add(offset))
+				.ok_or(FilterError::ImageTooLarge)?;
+		}
+	}
+	Ok(retval)
+}
+
+/// Finds the pass that contains the pixel at `(x, y)`, and returns the offset of the start of
+/// the scanline, after the filter type byte, that the pixel is in and the pixel's index in that
+/// scanline
+fn locate(passes: &[Pass], x: u32, y: u32) -> (usize, u32) {
+	let pass = passes
+		.iter()
+		.find(|pass| {
+			x >= pass.x_start
+				&& y >= pass.y_start
+				&& (x - pass.x_start).is_multiple_of(pass.x_step)
+				&& (y - pass.y_start).is_multiple_of(pass.y_step)
+		})
+		.expect("the passes cover every pixel");
+	let row = ((y - pass.y_start) / pass.y_step) as usize;
+	(
+		pass.offset + row * (pass.row_length + 1) + 1,
+		(x - pass.x_start) / pass.x_step,
+	)
+}
+
+/// Copies the pixel at index `from_x` of the scanline at `from` to index `to_x` of the scanline at
+/// `to`
+fn copy_pixel(from: &[u8], from_x: u32, to: &mut [u8], to_x: u32, bits_per_pixel: usize) {
+	if bits_per_pixel >= 8 {
+		let bytes = bits_per_pixel / 8;
+		let from_start = from_x as usize * bytes;
+		let to_start = to_x as usize * bytes;
+		to[to_start..to_start + bytes].copy_from_slice(&from[from_start..from_start + bytes]);
+	} else {
+		// pixels are packed into bytes starting with the most significant bits
+		let from_bit = from_x as usize * bits_per_pixel;
+		let to_bit = to_x as usize * bits_per_pixel;
+		let mask = (1u8 << bits_per_pixel) - 1;
+		let value = (from[from_bit / 8] >> (8 - bits_per_pixel - from_bit % 8)) & mask;
+		let to_shift = 8 - bits_per_pixel - to_bit % 8;
+		to[to_bit / 8] = (to[to_bit / 8] & !(mask << to_shift)) | (value << to_shift);
+	}
+}
+
+/// Returns the image data of an image described by `ihdr`, rearranged to use the interlace
+/// method `interlace_method`
+///
+/// The filters of `data` are reversed using [`filter::normalize`], and every scanline of the
+/// result is unfiltered. Any data after the last scanline of `data` is discarded.
+pub fn reinterlace(ihdr: &Ihdr, data: &[u8], interlace_method: u8) -> Result<Vec<u8>, FilterError> {
+	let bits_per_pixel = filter::bits_per_pixel(ihdr)?;
+	let from_passes = passes(
+		ihdr.width,
+		ihdr.height,
+		bits_per_pixel,
+		ihdr.interlace_method,
+	)?;
+	let to_passes = passes(ihdr.width, ihdr.height, bits_per_pixel, interlace_method)?;
+
+	let mut from = data.to_vec();
+	filter::normalize(ihdr, &mut from)?;
+	let mut to = vec![
+		0;
+		filter::data_length(
+			&Ihdr {
+				interlace_method,
+				..ihdr.clone()
+			},
+			bits_per_pixel,
+		)?
+	];
+
+	for pass in &from_passes {
+		if pass.width == 0 {
+			continue;
+		}
+		for row in 0..pass.height {
+			let from_row = pass.offset + row as usize * (pass.row_length + 1) + 1;
+			let from_row = &from[from_row..from_row + pass.row_length];
+			let y = pass.y_start + row * pass.y_step;
+			for column in 0..pass.width {
+				let x = pass.x_start + column * pass.x_step;
+				let (to_row, to_x) = locate(&to_passes, x, y);
+				copy_pixel(from_row, column, &mut to[to_row..], to_x, bits_per_pixel);
+			}
+		}
+	}
+	Ok(to)
+}
+
+#[cfg(test)]
+mod tests {
+	mod reinterlace {
+		use super::super::super::typed::Ihdr;
+		use super::super::reinterlace;
+		use crate::test_util::ihdr;
+
+		/// The image data of an image that is not interlaced, where each pixel is its index
+		fn progressive(width: u32, height: u32) -> Vec<u8> {
+			(0..height)
+				.flat_map(|y| {
+					[0].into_iter()
+						.chain((0..width).map(move |x| (y * width + x) as u8))
+				})
+				.collect()
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn deinterlace_8x8() {
+			let interlaced = vec![
+				0, 0,
+				0, 4,
+				0, 32, 36,
+				0, 2, 6,
+				0, 34, 38,
+				0, 16, 18, 20, 22,
+				0, 48, 50, 52, 54,
+				0, 1, 3, 5, 7,
+				0, 17, 19, 21, 23,
+				0, 33, 35, 37, 39,
+				0, 49, 51, 53, 55,
+				0, 8, 9, 10, 11, 12, 13, 14, 15,
+				0, 24, 25, 26, 27, 28, 29, 30, 31,
+				0, 40, 41, 42, 43, 44, 45, 46, 47,
+				0, 56, 57, 58, 59, 60, 61, 62, 63,
+			];
+			assert_eq!(progressive(8, 8), reinterlace(&ihdr(8, 8, 8, 0, 1), &interlaced, 0).unwrap());
+			assert_eq!(interlaced, reinterlace(&ihdr(8, 8, 8, 0, 0), &progressive(8, 8), 1).unwrap());
+		}
+
+		#[test]
+		fn round_trip_odd_sizes() {
+			for (width, height) in [(1, 1), (3, 5), (9, 2), (13, 17)] {
+				let data = progressive(width, height);
+				let interlaced = reinterlace(&ihdr(width, height, 8, 0, 0), &data, 1).unwrap();
+				assert_eq!(
+					data,
+					reinterlace(&ihdr(width, height, 8, 0, 1), &interlaced, 0).unwrap(),
+					"{}x{}",
+					width,
+					height
+				);
+			}
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn deinterlace_1_bit() {
+			// a 3x2 image, with pixels 101 and 011
+			// the passes are 1 (top left), 4 (top right), 6 (top middle) and 7 (bottom row)
+			let interlaced = vec![
+				0, 0b1000_0000,
+				0, 0b1000_0000,
+				0, 0b0000_0000,
+				0, 0b0110_0000,
+			];
+			assert_eq!(
+				vec![0, 0b1010_0000, 0, 0b0110_0000],
+				reinterlace(&ihdr(3, 2, 1, 0, 1), &interlaced, 0).unwrap()
+			);
+		}
+
+		#[test]
+		fn reverses_filters() {
+			// Sub-filtered
+			assert_eq!(
+				vec![0, 1, 3],
+				reinterlace(&ihdr(2, 1, 8, 0, 0), &[1, 1, 2], 0).unwrap()
+			);
+		}
+
+		#[test]
+		fn round_trip_16_bit_rgba() {
+			let ihdr = Ihdr {
+				color_type: 6,
+				..ihdr(3, 3, 16, 0, 0)
+			};
+			let data: Vec<u8> = (0..3u8)
+				.flat_map(|y| [0].into_iter().chain((0..24).map(move |x| y * 24 + x)))
+				.collect();
+			let interlaced = reinterlace(&ihdr, &data, 1).unwrap();
+			assert_ne!(data, interlaced);
+			let ihdr = Ihdr {
+				interlace_method: 1,
+				..ihdr
+			};
+			assert_eq!(data, reinterlace(&ihdr, &interlaced, 0).unwrap());
+		}
+	}
+}
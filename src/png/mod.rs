@@ -1,5 +1,13 @@
 //! http://www.libpng.org/pub/png/
 
+pub mod canonical;
+pub mod filter;
+pub mod interlace;
+pub mod order;
+pub mod palette;
+pub mod typed;
+
+use crate::checksum::Crc32;
 use std::io::ErrorKind;
 use std::io::Read;
 use std::io::Write;
@@ -10,54 +18,193 @@
 /// The PNG magic header
 const MAGIC: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
 
+/// The largest length of a chunk's data that a png file can represent
+pub const MAX_CHUNK_LENGTH: u32 = (1 << 31) - 1;
+
 /// Reads a png file, and returns the chunks contained in that file
 pub fn read(file: &mut dyn Read) -> Result<Vec<Chunk>, ReadError> {
-	let mut magic: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 0];
-	file.read_exact(&mut magic)
-		.map_err(ReadError::Io)
-		.and_then(|_| {
-			let magic = magic;
-			if magic == MAGIC {
-				let mut retval: Vec<Chunk> = Vec::new();
-
-				loop {
-					match Chunk::read(file) {
-						ChunkReadResult::Ok(x) => {
-							retval.push(x);
-						},
-						ChunkReadResult::Eof => {
-							break Ok(retval);
-						},
-						ChunkReadResult::Err(x) => {
-							break Err(ReadError::from(x));
-						},
-					}
-				}
-			} else {
-				Err(ReadError::MagicMismatch(magic))
-			}
-		})
+	ChunkReader::new(file)?.collect()
 }
 
 /// Writes a sequence of Chunks to form a png file
 pub fn write(file: &mut dyn Write, chunks: Vec<Chunk>) -> Result<(), ::std::io::Error> {
-	file.write_all(&MAGIC)?;
+	let mut file = ChunkWriter::new(file)?;
 	for chunk in chunks {
-		chunk.write(file)?;
+		file.write_chunk(&chunk)?;
 	}
 	Ok(())
 }
 
+/// Limits on the chunks that a [`ChunkReader`] will read, which protect against files that
+/// would otherwise use an unreasonable amount of memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadLimits {
+	/// The maximum length of a chunk's data
+	pub max_chunk_length: u32,
+	/// The maximum number of chunks in a file
+	pub max_chunk_count: usize,
+}
+
+impl Default for ReadLimits {
+	fn default() -> ReadLimits {
+		ReadLimits {
+			max_chunk_length: 1 << 28,
+			max_chunk_count: 100_000,
+		}
+	}
+}
+
+/// What a [`ChunkReader`] does with the data that follows the IEND chunk
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AfterIend {
+	/// Read the data after the IEND as chunks, like the data before it
+	#[default]
+	Read,
+	/// End the iteration at the IEND, leaving the rest of the backing stream unread. The rest can
+	/// be retrieved using [`ChunkReader::into_inner`].
+	Stop,
+	/// End the iteration at the IEND, with a [`ReadError::DataAfterIend`] if the backing stream
+	/// has any data after the IEND
+	Reject,
+}
+
+/// An iterator over the chunks in a png file, which reads each chunk from the backing stream
+/// only when that chunk is requested
+///
+/// The iterator ends after the first error.
+#[derive(Debug)]
+pub struct ChunkReader<R: Read> {
+	backing: R,
+	finished: bool,
+	limits: ReadLimits,
+	after_iend: AfterIend,
+	/// Whether the IEND has been read, and the policy is to not read chunks after it
+	stopped_at_iend: bool,
+	/// The number of chunks read so far
+	count: usize,
+}
+
+impl<R: Read> ChunkReader<R> {
+	/// Reads the png magic header from the backing stream, and returns a reader over the chunks that follow it
+	///
+	/// The reader uses the default [`ReadLimits`].
+	pub fn new(mut backing: R) -> Result<ChunkReader<R>, ReadError> {
+		let mut magic: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 0];
+		backing.read_exact(&mut magic).map_err(ReadError::Io)?;
+		if magic == MAGIC {
+			Ok(ChunkReader {
+				backing,
+				finished: false,
+				limits: ReadLimits::default(),
+				after_iend: AfterIend::default(),
+				stopped_at_iend: false,
+				count: 0,
+			})
+		} else {
+			Err(ReadError::MagicMismatch(magic))
+		}
+	}
+
+	/// Replaces the limits on the chunks that this reader will read
+	pub fn with_limits(mut self, limits: ReadLimits) -> ChunkReader<R> {
+		self.limits = limits;
+		self
+	}
+
+	/// Replaces what this reader does with the data that follows the IEND chunk
+	pub fn with_after_iend(mut self, after_iend: AfterIend) -> ChunkReader<R> {
+		self.after_iend = after_iend;
+		self
+	}
+
+	/// Returns the backing stream
+	pub fn into_inner(self) -> R {
+		self.backing
+	}
+}
+
+impl<R: Read> Iterator for ChunkReader<R> {
+	type Item = Result<Chunk, ReadError>;
+
+	fn next(&mut self) -> Option<Result<Chunk, ReadError>> {
+		if self.finished {
+			return None;
+		}
+		if self.stopped_at_iend {
+			self.finished = true;
+			if self.after_iend != AfterIend::Reject {
+				return None;
+			}
+			let mut next_byte = Vec::new();
+			return match (&mut self.backing).take(1).read_to_end(&mut next_byte) {
+				Ok(0) => None,
+				Ok(_) => Some(Err(ReadError::DataAfterIend)),
+				Err(e) => Some(Err(ReadError::Io(e))),
+			};
+		}
+		match Chunk::read(&mut self.backing, self.limits.max_chunk_length) {
+			ChunkReadResult::Ok(_) if self.count >= self.limits.max_chunk_count => {
+				self.finished = true;
+				Some(Err(ReadError::TooManyChunks(self.limits.max_chunk_count)))
+			},
+			ChunkReadResult::Ok(x) => {
+				self.count += 1;
+				self.stopped_at_iend = x.typ == *b"IEND" && self.after_iend != AfterIend::Read;
+				Some(Ok(x))
+			},
+			ChunkReadResult::Eof => {
+				self.finished = true;
+				None
+			},
+			ChunkReadResult::Err(x) => {
+				self.finished = true;
+				Some(Err(ReadError::from(x)))
+			},
+		}
+	}
+}
+
+impl<R: Read> ::std::iter::FusedIterator for ChunkReader<R> {}
+
+/// Writes chunks to a png file one at a time
+#[derive(Debug)]
+pub struct ChunkWriter<W: Write> {
+	backing: W,
+}
+
+impl<W: Write> ChunkWriter<W> {
+	/// Writes the png magic header to the backing stream, and returns a writer that can write chunks after it
+	pub fn new(mut backing: W) -> Result<ChunkWriter<W>, ::std::io::Error> {
+		backing.write_all(&MAGIC)?;
+		Ok(ChunkWriter { backing })
+	}
+
+	/// Writes a chunk to the backing stream
+	///
+	/// Fails with an `ErrorKind::InvalidInput` error, without writing anything, if the chunk's data
+	/// is longer than [`MAX_CHUNK_LENGTH`].
+	pub fn write_chunk(&mut self, chunk: &Chunk) -> Result<(), ::std::io::Error> {
+		chunk.write(&mut self.backing)
+	}
+
+	/// Returns the backing stream
+	pub fn into_inner(self) -> W {
+		self.backing
+	}
+}
+
 /// Represents a PNG data chunk
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Chunk {
+	/// The chunk's four-letter type code
 	pub typ: [u8; 4],
+	/// The chunk's data, not including the length, type or CRC
 	pub data: Vec<u8>,
 }
 
 impl Chunk {
-	/// Reads a PNG chunk from a data stream
-	fn read(file: &mut dyn Read) -> ChunkReadResult {
+	/// Reads a PNG chunk from a data stream, failing if the chunk's data is longer than `max_length`
+	fn read(file: &mut dyn Read, max_length: u32) -> ChunkReadResult {
 		let mut size: [u8; 4] = [0, 0, 0, 0];
 		let (size_head, size_tail) = size.split_at_mut(1);
 		if let Err(e) = file.read_exact(size_head) {
@@ -85,9 +232,21 @@ fn read(file: &mut dyn Read) -> ChunkReadResult {
 			return ChunkReadResult::Err(ChunkReadError::InvalidTyp(typ));
 		}
 
-		let mut data: Vec<u8> = vec![0; u32_to_usize(size)];
-		if let Err(e) = file.read_exact(&mut data) {
-			return ChunkReadResult::Err(ChunkReadError::Io(e));
+		if size > max_length {
+			return ChunkReadResult::Err(ChunkReadError::TooLong {
+				length: size,
+				limit: max_length,
+			});
+		}
+
+		// The length has not been verified yet, so allocate only as much as is actually read
+		let mut data: Vec<u8> = Vec::new();
+		match file.take(u64::from(size)).read_to_end(&mut data) {
+			Ok(count) if count == u32_to_usize(size) => {},
+			Ok(_) => {
+				return ChunkReadResult::Err(ChunkReadError::Io(ErrorKind::UnexpectedEof.into()));
+			},
+			Err(e) => return ChunkReadResult::Err(ChunkReadError::Io(e)),
 		}
 		let data = data;
 
@@ -96,7 +255,10 @@ fn read(file: &mut dyn Read) -> ChunkReadResult {
 			return ChunkReadResult::Err(ChunkReadError::Io(e));
 		}
 		let stated_crc = u32::from_be_bytes(stated_crc);
-		let calcuated_crc = calculate_crc(typ.iter().chain(data.iter()));
+		let mut calcuated_crc = Crc32::new();
+		calcuated_crc.update(&typ);
+		calcuated_crc.update(&data);
+		let calcuated_crc = calcuated_crc.finish();
 
 		if stated_crc != calcuated_crc {
 			return ChunkReadResult::Err(ChunkReadError::CrcMismatch {
@@ -109,11 +271,27 @@ fn read(file: &mut dyn Read) -> ChunkReadResult {
 	}
 
 	/// Writes a PNG chunk to a data stream
-	fn write(self, file: &mut dyn Write) -> Result<(), ::std::io::Error> {
-		file.write_all(&(self.data.len() as u32).to_be_bytes())?;
+	fn write(&self, file: &mut dyn Write) -> Result<(), ::std::io::Error> {
+		let length = u32::try_from(self.data.len())
+			.ok()
+			.filter(|x| *x <= MAX_CHUNK_LENGTH)
+			.ok_or_else(|| {
+				::std::io::Error::new(
+					ErrorKind::InvalidInput,
+					format!(
+						"Chunk length {} is greater than the png limit of {} bytes",
+						self.data.len(),
+						MAX_CHUNK_LENGTH
+					),
+				)
+			})?;
+		file.write_all(&length.to_be_bytes())?;
 		file.write_all(&self.typ)?;
 		file.write_all(&self.data)?;
-		file.write_all(&(calculate_crc(self.typ.iter().chain(self.data.iter()))).to_be_bytes())?;
+		let mut crc = Crc32::new();
+		crc.update(&self.typ);
+		crc.update(&self.data);
+		file.write_all(&crc.finish().to_be_bytes())?;
 		Ok(())
 	}
 
@@ -123,8 +301,9 @@ pub fn safe_to_copy(&self) -> bool {
 	}
 }
 
-/// Represents an error that can occur when decoding a PNG Chunk
+/// Represents an error that can occur when decoding a PNG file
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum ReadError {
 	/** An IO error */
 	Io(::std::io::Error),
@@ -134,6 +313,12 @@ pub enum ReadError {
 	CrcMismatch { stated: u32, calculated: u32 },
 	/** The given magic header didn't match the expected PNG header */
 	MagicMismatch([u8; 8]),
+	/** The chunk's length is greater than the limit */
+	ChunkTooLong { length: u32, limit: u32 },
+	/** The file has more chunks than the limit */
+	TooManyChunks(usize),
+	/** The file has data after the IEND chunk, and the reader was told to reject such data */
+	DataAfterIend,
 }
 
 impl ::std::fmt::Display for ReadError {
@@ -155,6 +340,24 @@ fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
 					.collect();
 				write!(f, "Magic didn't match expected: {:?} | {:?}", bytes, chars)
 			},
+			ReadError::ChunkTooLong { length, limit } => write!(
+				f,
+				"Chunk length {} is greater than the limit of {} bytes",
+				length, limit
+			),
+			ReadError::TooManyChunks(limit) => {
+				write!(f, "File has more than the limit of {} chunks", limit)
+			},
+			ReadError::DataAfterIend => write!(f, "File has data after the IEND chunk"),
+		}
+	}
+}
+
+impl ::std::error::Error for ReadError {
+	fn source(&self) -> Option<&(dyn ::std::error::Error + 'static)> {
+		match self {
+			ReadError::Io(x) => Some(x),
+			_ => None,
 		}
 	}
 }
@@ -167,6 +370,7 @@ fn from(src: ChunkReadError) -> ReadError {
 			ChunkReadError::CrcMismatch { stated, calculated } => {
 				ReadError::CrcMismatch { stated, calculated }
 			},
+			ChunkReadError::TooLong { length, limit } => ReadError::ChunkTooLong { length, limit },
 		}
 	}
 }
@@ -180,6 +384,8 @@ enum ChunkReadError {
 	InvalidTyp([u8; 4]),
 	/** The calculated CRC did not match the given CRC */
 	CrcMismatch { stated: u32, calculated: u32 },
+	/** The chunk's length is greater than the limit */
+	TooLong { length: u32, limit: u32 },
 }
 
 impl ::std::fmt::Display for ChunkReadError {
@@ -200,6 +406,11 @@ fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
 				"CRC mismatch: file {:x}; calculated {:x}",
 				stated, calculated
 			),
+			ChunkReadError::TooLong { length, limit } => write!(
+				f,
+				"Chunk length {} is greater than the limit of {} bytes",
+				length, limit
+			),
 		}
 	}
 }
@@ -249,58 +460,8 @@ fn u32_to_usize(a: u32) -> usize {
 	a as usize
 }
 
-fn calculate_crc<'a, I: IntoIterator<Item = &'a u8>>(buffer: I) -> u32 {
-	const CRC_POLYNOMIAL: u32 = 0xedb8_8320;
-	fn update_crc(crc: u32, message: u8) -> u32 {
-		let message: u32 = u32::from(message);
-		let mut crc = crc ^ message;
-		for _ in 0..8 {
-			crc = (if crc & 1 != 0 { CRC_POLYNOMIAL } else { 0 }) ^ (crc >> 1);
-		}
-		crc
-	}
-
-	buffer
-		.into_iter()
-		.fold(u32::MAX, |crc, message| update_crc(crc, *message))
-		^ u32::MAX
-}
-
 #[cfg(test)]
 mod tests {
-	mod calculate_crc {
-		use super::super::calculate_crc;
-
-		#[test]
-		fn nul() {
-			let val: [u8; 0] = [];
-			let exp: u32 = 0;
-			let res = calculate_crc(val.iter());
-			assert!(exp == res, "{:x} != {:x}", exp, res);
-		}
-
-		#[test]
-		fn iend() {
-			let val: [u8; 4] = [0x49, 0x45, 0x4e, 0x44];
-			let exp: u32 = 0xae426082;
-			let res = calculate_crc(&val);
-			assert!(exp == res, "{:x} != {:x}", exp, res);
-		}
-
-		#[test]
-		fn ihdr_1() {
-			#[rustfmt::skip]
-			let val:[u8;17] = [
-				0x49, 0x48, 0x44, 0x52, 0x00, 0x00, 0x01, 0x2c,
-				0x00, 0x00, 0x00, 0x96, 0x02, 0x03, 0x00, 0x00,
-				0x00
-			];
-			let exp: u32 = 0x19355d41;
-			let res = calculate_crc(&val);
-			assert!(exp == res, "{:x} != {:x}", exp, res);
-		}
-	}
-
 	mod chunk_read {
 		use super::super::Chunk;
 		use super::super::ChunkReadError;
@@ -329,7 +490,7 @@ fn exact_size() {
 			let exp = Chunk{typ:*b"ABCD", data:vec![61, 62, 63, 64]};
 			#[rustfmt::skip]
 			let mut dut:&[u8] = &[0, 0, 0, 4, 0x41, 0x42, 0x43, 0x44, 61, 62, 63, 64, 0x75, 0x88, 0x7C, 0x4B];
-			let res = Chunk::read(&mut dut).unwrap();
+			let res = Chunk::read(&mut dut, u32::MAX).unwrap();
 			assert!(exp == res);
 			assert!(dut.is_empty());
 		}
@@ -342,7 +503,7 @@ fn reads_only_the_amount_needed() {
 				0, 0, 0, 4, 0x41, 0x42, 0x43, 0x44, 61, 62, 63, 64, 0x75, 0x88, 0x7C, 0x4B, 11, 22,
 				33, 44, 55,
 			];
-			let res = Chunk::read(&mut dut).unwrap();
+			let res = Chunk::read(&mut dut, u32::MAX).unwrap();
 			assert!(exp == res);
 			assert!(dut.len() == 5);
 		}
@@ -352,7 +513,7 @@ fn errors_if_unexpected_crc() {
 			let mut dut: &[u8] = &[
 				0, 0, 0, 4, 0x41, 0x42, 0x43, 0x44, 61, 62, 63, 64, 1, 2, 3, 4,
 			];
-			match Chunk::read(&mut dut) {
+			match Chunk::read(&mut dut, u32::MAX) {
 				ChunkReadResult::Err(ChunkReadError::CrcMismatch { stated, calculated }) => {
 					if 0x01020304 != stated || 0x75887C4B != calculated {
 						panic!(
@@ -372,35 +533,58 @@ fn errors_if_unexpected_eof_crc() {
 			let mut dut: &[u8] = &[
 				0, 0, 0, 4, 0x41, 0x42, 0x43, 0x44, 61, 62, 63, 64, 0x75, 0x88,
 			];
-			let res = Chunk::read(&mut dut);
+			let res = Chunk::read(&mut dut, u32::MAX);
+			assert_is_err_eof(res);
+		}
+
+		#[test]
+		fn errors_if_longer_than_limit() {
+			let mut dut: &[u8] = &[
+				0, 0, 0, 4, 0x41, 0x42, 0x43, 0x44, 61, 62, 63, 64, 0x75, 0x88, 0x7C, 0x4B,
+			];
+			match Chunk::read(&mut dut, 3) {
+				ChunkReadResult::Err(ChunkReadError::TooLong {
+					length: 4,
+					limit: 3,
+				}) => {
+					// pass
+				},
+				other => panic!("Not TooLong {:?}", other),
+			}
+		}
+
+		#[test]
+		fn errors_if_unexpected_eof_data_with_huge_length() {
+			let mut dut: &[u8] = &[0xFF, 0xFF, 0xFF, 0xF0, 0x41, 0x42, 0x43, 0x44, 61, 62];
+			let res = Chunk::read(&mut dut, u32::MAX);
 			assert_is_err_eof(res);
 		}
 
 		#[test]
 		fn errors_if_unexpected_eof_data() {
 			let mut dut: &[u8] = &[0, 0, 0, 4, 0x41, 0x42, 0x43, 0x44, 61, 62];
-			let res = Chunk::read(&mut dut);
+			let res = Chunk::read(&mut dut, u32::MAX);
 			assert_is_err_eof(res);
 		}
 
 		#[test]
 		fn errors_if_unexpected_eof_typ() {
 			let mut dut: &[u8] = &[0, 0, 0, 4, 0x41, 0x42];
-			let res = Chunk::read(&mut dut);
+			let res = Chunk::read(&mut dut, u32::MAX);
 			assert_is_err_eof(res);
 		}
 
 		#[test]
 		fn errors_if_unexpected_eof_size() {
 			let mut dut: &[u8] = &[0];
-			let res = Chunk::read(&mut dut);
+			let res = Chunk::read(&mut dut, u32::MAX);
 			assert_is_err_eof(res);
 		}
 
 		#[test]
 		fn reports_valid_eof() {
 			let mut dut: &[u8] = &[];
-			let res = Chunk::read(&mut dut);
+			let res = Chunk::read(&mut dut, u32::MAX);
 			assert!(res.is_eof(), "Expected Eof; Was {:?}", res);
 			assert!(dut.is_empty());
 		}
@@ -513,6 +697,162 @@ fn incorrect_magic() {
 		}
 	}
 
+	mod chunk_reader {
+		use super::super::AfterIend;
+		use super::super::Chunk;
+		use super::super::ChunkReader;
+		use super::super::ReadError;
+		use super::super::ReadLimits;
+
+		#[test]
+		fn reads_only_the_chunks_requested() {
+			#[rustfmt::skip]
+			let mut dut:&[u8] = &[
+				137, b'P', b'N', b'G', b'\r', b'\n', 26, b'\n',
+				0, 0, 0, 0, b'F', b'I', b'R', b'S', 0x9A, 0x9F, 0x51, 0x2A,
+				0, 0, 0, 0, b'S', b'E', b'C', b'O', 0xB3, 0x9A, 0x70, 0xBC,
+			];
+			let mut reader = ChunkReader::new(&mut dut).unwrap();
+			#[rustfmt::skip]
+			let exp = Chunk{typ:*b"FIRS", data:vec![]};
+			assert_eq!(exp, reader.next().unwrap().unwrap());
+			assert!(reader.into_inner().len() == 12);
+		}
+
+		#[test]
+		fn stops_after_first_error() {
+			#[rustfmt::skip]
+			let mut dut:&[u8] = &[
+				137, b'P', b'N', b'G', b'\r', b'\n', 26, b'\n',
+				0, 0, 0, 0, b'F', b'I', b'R', b'S', 1, 2, 3, 4,
+				0, 0, 0, 0, b'S', b'E', b'C', b'O', 0xB3, 0x9A, 0x70, 0xBC,
+			];
+			let mut reader = ChunkReader::new(&mut dut).unwrap();
+			match reader.next() {
+				Some(Err(ReadError::CrcMismatch { .. })) => {
+					// pass
+				},
+				other => panic!("Not CrcMismatch {:?}", other),
+			}
+			assert!(reader.next().is_none());
+		}
+
+		#[test]
+		fn stops_after_too_many_chunks() {
+			#[rustfmt::skip]
+			let mut dut:&[u8] = &[
+				137, b'P', b'N', b'G', b'\r', b'\n', 26, b'\n',
+				0, 0, 0, 0, b'F', b'I', b'R', b'S', 0x9A, 0x9F, 0x51, 0x2A,
+				0, 0, 0, 0, b'S', b'E', b'C', b'O', 0xB3, 0x9A, 0x70, 0xBC,
+			];
+			let mut reader = ChunkReader::new(&mut dut).unwrap().with_limits(ReadLimits {
+				max_chunk_count: 1,
+				..ReadLimits::default()
+			});
+			assert!(reader.next().unwrap().is_ok());
+			match reader.next() {
+				Some(Err(ReadError::TooManyChunks(1))) => {
+					// pass
+				},
+				other => panic!("Not TooManyChunks {:?}", other),
+			}
+			assert!(reader.next().is_none());
+		}
+
+		/// A file containing an IEND chunk followed by `trailing`
+		fn with_trailing(trailing: &[u8]) -> Vec<u8> {
+			#[rustfmt::skip]
+			let retval = [
+				&[137, b'P', b'N', b'G', b'\r', b'\n', 26, b'\n'][..],
+				&[0, 0, 0, 0, b'F', b'I', b'R', b'S', 0x9A, 0x9F, 0x51, 0x2A],
+				&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82],
+				trailing,
+			].concat();
+			retval
+		}
+
+		#[test]
+		fn after_iend_read_reads_chunks() {
+			let dut = with_trailing(&[0, 0, 0, 0, b'T', b'H', b'I', b'R', 0xBF, 0x7C, 0x5F, 0x05]);
+			let reader = ChunkReader::new(&dut[..]).unwrap();
+			let res: Vec<[u8; 4]> = reader.map(|x| x.unwrap().typ).collect();
+			assert_eq!(vec![*b"FIRS", *b"IEND", *b"THIR"], res);
+		}
+
+		#[test]
+		fn after_iend_stop_leaves_trailing_data() {
+			let dut = with_trailing(b"PK\x03\x04 not a chunk");
+			let mut reader = ChunkReader::new(&dut[..])
+				.unwrap()
+				.with_after_iend(AfterIend::Stop);
+			let res: Vec<[u8; 4]> = reader.by_ref().map(|x| x.unwrap().typ).collect();
+			assert_eq!(vec![*b"FIRS", *b"IEND"], res);
+			assert_eq!(b"PK\x03\x04 not a chunk", reader.into_inner());
+		}
+
+		#[test]
+		fn after_iend_reject_with_trailing_data() {
+			let dut = with_trailing(b"x");
+			let mut reader = ChunkReader::new(&dut[..])
+				.unwrap()
+				.with_after_iend(AfterIend::Reject);
+			assert!(reader.next().unwrap().is_ok());
+			assert!(reader.next().unwrap().is_ok());
+			match reader.next() {
+				Some(Err(ReadError::DataAfterIend)) => {
+					// pass
+				},
+				other => panic!("Not DataAfterIend {:?}", other),
+			}
+			assert!(reader.next().is_none());
+		}
+
+		#[test]
+		fn after_iend_reject_without_trailing_data() {
+			let dut = with_trailing(&[]);
+			let reader = ChunkReader::new(&dut[..])
+				.unwrap()
+				.with_after_iend(AfterIend::Reject);
+			let res: Vec<[u8; 4]> = reader.map(|x| x.unwrap().typ).collect();
+			assert_eq!(vec![*b"FIRS", *b"IEND"], res);
+		}
+
+		#[test]
+		fn checks_magic_before_reading_chunks() {
+			#[rustfmt::skip]
+			let mut dut:&[u8] = &[
+				138, b'M', b'N', b'G', b'\r', b'\n', 26, b'\n',
+			];
+			match ChunkReader::new(&mut dut) {
+				Err(ReadError::MagicMismatch(_)) => {
+					// pass
+				},
+				other => panic!("Not MagicMismatch {:?}", other),
+			}
+		}
+	}
+
+	mod chunk_writer {
+		use super::super::Chunk;
+		use super::super::ChunkWriter;
+
+		#[test]
+		fn writes_magic_then_chunks() {
+			#[rustfmt::skip]
+			let exp:&[u8] = &[
+				137, b'P', b'N', b'G', b'\r', b'\n', 26, b'\n',
+				0, 0, 0, 0, b'F', b'I', b'R', b'S', 0x9A, 0x9F, 0x51, 0x2A,
+				0, 0, 0, 4, 0x41, 0x42, 0x43, 0x44, 61, 62, 63, 64, 0x75, 0x88, 0x7C, 0x4B,
+			];
+			let mut dut = ChunkWriter::new(Vec::new()).unwrap();
+			#[rustfmt::skip]
+			dut.write_chunk(&Chunk{typ:*b"FIRS", data:vec![]}).unwrap();
+			#[rustfmt::skip]
+			dut.write_chunk(&Chunk{typ:*b"ABCD", data:vec![61, 62, 63, 64]}).unwrap();
+			assert_eq!(exp, dut.into_inner().as_slice());
+		}
+	}
+
 	mod chunk_safe_to_copy {
 		use super::super::Chunk;
 
@@ -0,0 +1,501 @@
+//! Checks the order and the number of the chunks in a png file
+//!
+//! The rules are those of the PNG Third Edition specification
+//! <https://w3c.github.io/png/#5ChunkOrdering> and of APNG: the IHDR comes first, the IEND comes
+//! last, IDATs are consecutive, some chunks must come before or after the PLTE or the IDATs, and
+//! some chunk types may appear at most once.
+//!
+//! ```
+//! use png_inflate::png::order::{check, Diagnostic, Problem};
+//! use png_inflate::png::Chunk;
+//!
+//! let chunks = [*b"IHDR", *b"IDAT", *b"tRNS", *b"IEND"]
+//!     .map(|typ| Chunk { typ, data: vec![] });
+//! assert_eq!(
+//!     vec![Diagnostic { index: 2, typ: *b"tRNS", problem: Problem::MustPrecede(*b"IDAT") }],
+//!     check(&chunks)
+//! );
+//! ```
+
+use super::Chunk;
+use std::collections::HashSet;
+
+/// Chunks that must precede the PLTE and the IDATs
+pub(crate) const BEFORE_PLTE: [[u8; 4]; 8] = [
+	*b"cHRM", *b"gAMA", *b"iCCP", *b"sBIT", *b"sRGB", *b"cICP", *b"mDCV", *b"cLLI",
+];
+
+/// Chunks that must follow the PLTE, if there is one, and precede the IDATs
+const AFTER_PLTE: [[u8; 4]; 3] = [*b"tRNS", *b"bKGD", *b"hIST"];
+
+/// Chunks that must precede the IDATs, but may be on either side of the PLTE
+const BEFORE_IDAT: [[u8; 4]; 7] = [
+	*b"pHYs", *b"sPLT", *b"oFFs", *b"pCAL", *b"sCAL", *b"sTER", *b"acTL",
+];
+
+/// Chunks that may appear at most once
+#[rustfmt::skip]
+const SINGLE: [[u8; 4]; 22] = [
+	*b"IHDR", *b"PLTE", *b"IEND",
+	*b"cHRM", *b"gAMA", *b"iCCP", *b"sBIT", *b"sRGB", *b"cICP", *b"mDCV", *b"cLLI",
+	*b"tRNS", *b"bKGD", *b"hIST",
+	*b"pHYs", *b"oFFs", *b"pCAL", *b"sCAL", *b"sTER", *b"acTL",
+	*b"eXIf", *b"tIME",
+];
+
+/// The chunk types that a chunk of type `typ` must precede
+fn must_precede(typ: &[u8; 4]) -> &'static [[u8; 4]] {
+	const PLTE_AND_IDAT: [[u8; 4]; 2] = [*b"PLTE", *b"IDAT"];
+	const IDAT: [[u8; 4]; 1] = [*b"IDAT"];
+	const AFTER_PLTE_AND_IDAT: [[u8; 4]; 4] = [*b"IDAT", *b"tRNS", *b"bKGD", *b"hIST"];
+	const FDAT: [[u8; 4]; 1] = [*b"fdAT"];
+	if BEFORE_PLTE.contains(typ) {
+		&PLTE_AND_IDAT
+	} else if AFTER_PLTE.contains(typ) || BEFORE_IDAT.contains(typ) {
+		&IDAT
+	} else if *typ == *b"PLTE" {
+		&AFTER_PLTE_AND_IDAT
+	} else if *typ == *b"IDAT" {
+		&FDAT
+	} else {
+		&[]
+	}
+}
+
+/// A way that a chunk breaks the ordering rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Problem {
+	/// The chunk is an IHDR, but is not the first chunk
+	IhdrNotFirst,
+	/// The chunk's type may appear at most once, and a chunk of that type has already appeared
+	Duplicate,
+	/// The chunk must precede chunks of the given type, and such a chunk has already appeared
+	MustPrecede([u8; 4]),
+	/// The chunk is an IDAT that is separated from the previous IDAT by other chunks
+	NonConsecutiveIdat,
+	/// The chunk follows the IEND
+	AfterIend,
+	/// The file does not contain a chunk of the required type. The diagnostic's `index` is the
+	/// number of chunks in the file, and its `typ` is the missing type.
+	Missing,
+}
+
+/// A chunk that breaks the ordering rules
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Diagnostic {
+	/// The position of the chunk in the file, counting from zero
+	pub index: usize,
+	/// The chunk's type
+	pub typ: [u8; 4],
+	/// How the chunk breaks the rules
+	pub problem: Problem,
+}
+
+impl ::std::fmt::Display for Diagnostic {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		let typ = typ_to_string(&self.typ);
+		match self.problem {
+			Problem::IhdrNotFirst => {
+				write!(f, "IHDR is chunk {} instead of the first chunk", self.index)
+			},
+			Problem::Duplicate => write!(f, "Chunk {} is a second {} chunk", self.index, typ),
+			Problem::MustPrecede(other) => write!(
+				f,
+				"Chunk {} ({}) appears after a {} chunk, which it must precede",
+				self.index,
+				typ,
+				typ_to_string(&other)
+			),
+			Problem::NonConsecutiveIdat => write!(
+				f,
+				"Chunk {} is an IDAT that does not immediately follow the previous IDAT",
+				self.index
+			),
+			Problem::AfterIend => {
+				write!(f, "Chunk {} ({}) appears after the IEND", self.index, typ)
+			},
+			Problem::Missing => write!(f, "File has no {} chunk", typ),
+		}
+	}
+}
+
+fn typ_to_string(typ: &[u8; 4]) -> String {
+	// chunk types that made it this far consist of ascii letters
+	typ.iter().map(|x| char::from(*x)).collect()
+}
+
+/// Checks chunks against the ordering rules one chunk at a time
+#[derive(Debug, Default, Clone)]
+pub struct OrderChecker {
+	/// The types of the chunks that have been checked
+	seen: HashSet<[u8; 4]>,
+	/// The type of the most recently checked chunk
+	previous: Option<[u8; 4]>,
+	/// The number of chunks that have been checked
+	count: usize,
+}
+
+impl OrderChecker {
+	/// Creates a checker that has not seen any chunks
+	pub fn new() -> OrderChecker {
+		OrderChecker::default()
+	}
+
+	/// Checks the next chunk of the file, which has type `typ`. Returns the first problem with the
+	/// chunk, if any.
+	pub fn push(&mut self, typ: [u8; 4]) -> Option<Diagnostic> {
+		let problem = self.problem(&typ);
+		let retval = problem.map(|problem| Diagnostic {
+			index: self.count,
+			typ,
+			problem,
+		});
+		self.seen.insert(typ);
+		self.previous = Some(typ);
+		self.count += 1;
+		retval
+	}
+
+	fn problem(&self, typ: &[u8; 4]) -> Option<Problem> {
+		if self.seen.contains(b"IEND") {
+			return Some(Problem::AfterIend);
+		}
+		if *typ == *b"IHDR" && self.count != 0 && !self.seen.contains(b"IHDR") {
+			return Some(Problem::IhdrNotFirst);
+		}
+		if SINGLE.contains(typ) && self.seen.contains(typ) {
+			return Some(Problem::Duplicate);
+		}
+		if *typ == *b"IDAT" && self.seen.contains(b"IDAT") && self.previous != Some(*b"IDAT") {
+			return Some(Problem::NonConsecutiveIdat);
+		}
+		must_precede(typ)
+			.iter()
+			.find(|other| self.seen.contains(*other))
+			.map(|other| Problem::MustPrecede(*other))
+	}
+
+	/// Signals that the file has no more chunks. Returns the problems with the file as a whole.
+	pub fn finish(&self) -> Vec<Diagnostic> {
+		[*b"IHDR", *b"IDAT", *b"IEND"]
+			.into_iter()
+			.filter(|typ| !self.seen.contains(typ))
+			.map(|typ| Diagnostic {
+				index: self.count,
+				typ,
+				problem: Problem::Missing,
+			})
+			.collect()
+	}
+}
+
+/// Checks a file's chunks against the ordering rules, and returns every problem found
+pub fn check(chunks: &[Chunk]) -> Vec<Diagnostic> {
+	let mut checker = OrderChecker::new();
+	let mut retval: Vec<Diagnostic> = chunks.iter().filter_map(|x| checker.push(x.typ)).collect();
+	retval.extend(checker.finish());
+	retval
+}
+
+/// Rearranges chunks so that they follow the ordering rules as far as possible
+///
+/// Chunks of a type that may appear at most once are dropped after the first, chunks that must
+/// precede or follow the PLTE or the IDATs are moved to the nearest place where they may be, and
+/// the IEND is moved to the end, or added if there is none. Otherwise, chunks keep their relative
+/// order, so chunks that may appear anywhere stay next to the chunks that they followed.
+///
+/// Missing IHDR and IDAT chunks cannot be fixed, and neither can APNG fdAT chunks that precede the IDATs.
+pub fn fix(chunks: Vec<Chunk>) -> Vec<Chunk> {
+	// Each chunk is given a rank, and the chunks are sorted by rank. Chunks whose place is not
+	// fixed by their type are ranked just after the chunk before them, so that they move with it.
+	const FLOATING_OFFSET: u8 = 5;
+	const IDAT_RANK: u8 = 40;
+	const IEND_RANK: u8 = 60;
+	fn rank(typ: &[u8; 4]) -> Option<u8> {
+		if *typ == *b"IHDR" {
+			Some(0)
+		} else if BEFORE_PLTE.contains(typ) {
+			Some(10)
+		} else if *typ == *b"PLTE" {
+			Some(20)
+		} else if AFTER_PLTE.contains(typ) {
+			Some(30)
+		} else if *typ == *b"IDAT" {
+			Some(IDAT_RANK)
+		} else if *typ == *b"IEND" {
+			Some(IEND_RANK)
+		} else {
+			None
+		}
+	}
+
+	let mut seen: HashSet<[u8; 4]> = HashSet::new();
+	let mut current_rank: u8 = 0;
+	let mut ranked: Vec<(u8, Chunk)> = Vec::with_capacity(chunks.len() + 1);
+	for chunk in chunks {
+		if SINGLE.contains(&chunk.typ) && !seen.insert(chunk.typ) {
+			continue;
+		}
+		let chunk_rank = match rank(&chunk.typ) {
+			Some(x) => {
+				current_rank = current_rank.max(x);
+				x
+			},
+			None if BEFORE_IDAT.contains(&chunk.typ) => {
+				(current_rank + FLOATING_OFFSET).min(IDAT_RANK - FLOATING_OFFSET)
+			},
+			None => (current_rank + FLOATING_OFFSET).min(IEND_RANK - FLOATING_OFFSET),
+		};
+		ranked.push((chunk_rank, chunk));
+	}
+	if !seen.contains(b"IEND") {
+		ranked.push((
+			IEND_RANK,
+			Chunk {
+				typ: *b"IEND",
+				data: Vec::new(),
+			},
+		));
+	}
+	ranked.sort_by_key(|(rank, _)| *rank);
+	ranked.into_iter().map(|(_, chunk)| chunk).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::super::Chunk;
+
+	fn chunks(types: &[&[u8; 4]]) -> Vec<Chunk> {
+		types
+			.iter()
+			.map(|typ| Chunk {
+				typ: **typ,
+				data: Vec::new(),
+			})
+			.collect()
+	}
+
+	mod check {
+		use super::super::check;
+		use super::super::Diagnostic;
+		use super::super::Problem;
+		use super::chunks;
+
+		fn diagnostic(index: usize, typ: &[u8; 4], problem: Problem) -> Diagnostic {
+			Diagnostic {
+				index,
+				typ: *typ,
+				problem,
+			}
+		}
+
+		#[test]
+		fn valid() {
+			let dut = chunks(&[
+				b"IHDR", b"gAMA", b"PLTE", b"tRNS", b"pHYs", b"IDAT", b"IDAT", b"tEXt", b"IEND",
+			]);
+			assert_eq!(Vec::<Diagnostic>::new(), check(&dut));
+		}
+
+		#[test]
+		fn valid_apng() {
+			let dut = chunks(&[
+				b"IHDR", b"acTL", b"fcTL", b"IDAT", b"fcTL", b"fdAT", b"fcTL", b"fdAT", b"IEND",
+			]);
+			assert_eq!(Vec::<Diagnostic>::new(), check(&dut));
+		}
+
+		#[test]
+		fn ihdr_not_first() {
+			let dut = chunks(&[b"tEXt", b"IHDR", b"IDAT", b"IEND"]);
+			assert_eq!(
+				vec![diagnostic(1, b"IHDR", Problem::IhdrNotFirst)],
+				check(&dut)
+			);
+		}
+
+		#[test]
+		fn duplicates() {
+			let dut = chunks(&[
+				b"IHDR", b"IHDR", b"IDAT", b"tEXt", b"tEXt", b"IEND", b"IEND",
+			]);
+			assert_eq!(
+				vec![
+					diagnostic(1, b"IHDR", Problem::Duplicate),
+					diagnostic(6, b"IEND", Problem::AfterIend),
+				],
+				check(&dut)
+			);
+		}
+
+		#[test]
+		fn plte_after_idat() {
+			let dut = chunks(&[b"IHDR", b"IDAT", b"PLTE", b"IEND"]);
+			assert_eq!(
+				vec![diagnostic(2, b"PLTE", Problem::MustPrecede(*b"IDAT"))],
+				check(&dut)
+			);
+		}
+
+		#[test]
+		fn color_space_after_plte() {
+			let dut = chunks(&[b"IHDR", b"PLTE", b"sRGB", b"IDAT", b"IEND"]);
+			assert_eq!(
+				vec![diagnostic(2, b"sRGB", Problem::MustPrecede(*b"PLTE"))],
+				check(&dut)
+			);
+		}
+
+		#[test]
+		fn trns_before_plte() {
+			let dut = chunks(&[b"IHDR", b"tRNS", b"PLTE", b"IDAT", b"IEND"]);
+			assert_eq!(
+				vec![diagnostic(2, b"PLTE", Problem::MustPrecede(*b"tRNS"))],
+				check(&dut)
+			);
+		}
+
+		#[test]
+		fn trns_after_idat() {
+			let dut = chunks(&[b"IHDR", b"IDAT", b"tRNS", b"IEND"]);
+			assert_eq!(
+				vec![diagnostic(2, b"tRNS", Problem::MustPrecede(*b"IDAT"))],
+				check(&dut)
+			);
+		}
+
+		#[test]
+		fn actl_after_idat() {
+			let dut = chunks(&[b"IHDR", b"IDAT", b"acTL", b"IEND"]);
+			assert_eq!(
+				vec![diagnostic(2, b"acTL", Problem::MustPrecede(*b"IDAT"))],
+				check(&dut)
+			);
+		}
+
+		#[test]
+		fn fdat_before_idat() {
+			let dut = chunks(&[b"IHDR", b"acTL", b"fcTL", b"fdAT", b"IDAT", b"IEND"]);
+			assert_eq!(
+				vec![diagnostic(4, b"IDAT", Problem::MustPrecede(*b"fdAT"))],
+				check(&dut)
+			);
+		}
+
+		#[test]
+		fn non_consecutive_idat() {
+			let dut = chunks(&[b"IHDR", b"IDAT", b"tEXt", b"IDAT", b"IEND"]);
+			assert_eq!(
+				vec![diagnostic(3, b"IDAT", Problem::NonConsecutiveIdat)],
+				check(&dut)
+			);
+		}
+
+		#[test]
+		fn after_iend() {
+			let dut = chunks(&[b"IHDR", b"IDAT", b"IEND", b"tEXt"]);
+			assert_eq!(
+				vec![diagnostic(3, b"tEXt", Problem::AfterIend)],
+				check(&dut)
+			);
+		}
+
+		#[test]
+		fn missing() {
+			let dut = chunks(&[b"tEXt"]);
+			assert_eq!(
+				vec![
+					diagnostic(1, b"IHDR", Problem::Missing),
+					diagnostic(1, b"IDAT", Problem::Missing),
+					diagnostic(1, b"IEND", Problem::Missing),
+				],
+				check(&dut)
+			);
+		}
+	}
+
+	mod fix {
+		use super::super::check;
+		use super::super::fix;
+		use super::chunks;
+
+		fn assert_fix(expected: &[&[u8; 4]], input: &[&[u8; 4]]) {
+			let res = fix(chunks(input));
+			assert_eq!(chunks(expected), res);
+			assert!(check(&res).is_empty());
+		}
+
+		#[test]
+		fn valid_is_unchanged() {
+			let dut: [&[u8; 4]; 9] = [
+				b"IHDR", b"gAMA", b"tEXt", b"PLTE", b"tRNS", b"IDAT", b"IDAT", b"zTXt", b"IEND",
+			];
+			assert_fix(&dut, &dut);
+		}
+
+		#[test]
+		fn moves_ihdr_first() {
+			assert_fix(
+				&[b"IHDR", b"tEXt", b"IDAT", b"IEND"],
+				&[b"tEXt", b"IHDR", b"IDAT", b"IEND"],
+			);
+		}
+
+		#[test]
+		fn drops_duplicates() {
+			assert_fix(
+				&[b"IHDR", b"gAMA", b"IDAT", b"IEND"],
+				&[
+					b"IHDR", b"gAMA", b"IHDR", b"gAMA", b"IDAT", b"IEND", b"IEND",
+				],
+			);
+		}
+
+		#[test]
+		fn moves_chunks_before_plte_and_idat() {
+			assert_fix(
+				&[
+					b"IHDR", b"sRGB", b"PLTE", b"tRNS", b"pHYs", b"IDAT", b"IEND",
+				],
+				&[
+					b"IHDR", b"PLTE", b"sRGB", b"IDAT", b"tRNS", b"pHYs", b"IEND",
+				],
+			);
+		}
+
+		#[test]
+		fn moves_plte_before_trns() {
+			assert_fix(
+				&[b"IHDR", b"PLTE", b"tRNS", b"IDAT", b"IEND"],
+				&[b"IHDR", b"tRNS", b"PLTE", b"IDAT", b"IEND"],
+			);
+		}
+
+		#[test]
+		fn joins_idats() {
+			assert_fix(
+				&[b"IHDR", b"IDAT", b"IDAT", b"tEXt", b"IEND"],
+				&[b"IHDR", b"IDAT", b"tEXt", b"IDAT", b"IEND"],
+			);
+		}
+
+		#[test]
+		fn keeps_apng_frames_in_order() {
+			let dut: [&[u8; 4]; 8] = [
+				b"IHDR", b"acTL", b"fcTL", b"IDAT", b"fcTL", b"fdAT", b"fcTL", b"fdAT",
+			];
+			let mut exp = dut.to_vec();
+			exp.push(b"IEND");
+			assert_fix(&exp, &dut);
+		}
+
+		#[test]
+		fn moves_iend_last() {
+			assert_fix(
+				&[b"IHDR", b"IDAT", b"tEXt", b"IEND"],
+				&[b"IHDR", b"IDAT", b"IEND", b"tEXt"],
+			);
+		}
+	}
+}
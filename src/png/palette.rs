@@ -0,0 +1,380 @@
+//! Rewrites a palette image's palette in a canonical order
+//!
+//! Editors often reorder a palette's entries, or leave behind entries that no pixel uses, so the
+//! same pixels can be stored using many different palettes, and a change to one pixel can change
+//! every index in the image data. [`normalize`] drops the unused and duplicate entries, sorts the
+//! rest, and changes the image data's indexes to match, which leaves a palette and image data that
+//! depend only on the pixels.
+//!
+//! ```
+//! use png_inflate::png::palette::{normalize, PaletteChunks};
+//! use png_inflate::png::typed::{Ihdr, Plte};
+//!
+//! let chunks = PaletteChunks {
+//!     ihdr: Ihdr {
+//!         width: 2,
+//!         height: 1,
+//!         bit_depth: 8,
+//!         color_type: 3,
+//!         compression_method: 0,
+//!         filter_method: 0,
+//!         interlace_method: 0,
+//!     },
+//!     plte: Plte {
+//!         entries: vec![[255, 0, 0], [0, 255, 0], [0, 0, 0]],
+//!     },
+//!     trns: None,
+//!     hist: None,
+//!     bkgd: None,
+//! };
+//! // a black pixel and a red pixel; no pixel is green
+//! let data = vec![0, 2, 0];
+//! let (chunks, data) = normalize(&chunks, &data)?.unwrap();
+//! assert_eq!(vec![[0, 0, 0], [255, 0, 0]], chunks.plte.entries);
+//! assert_eq!(vec![0, 0, 1], data);
+//! # Ok::<(), png_inflate::png::filter::FilterError>(())
+//! ```
+
+use super::canonical::palette_order;
+use super::canonical::read_sample;
+use super::canonical::write_sample;
+use super::filter;
+use super::filter::FilterError;
+use super::typed::Hist;
+use super::typed::Ihdr;
+use super::typed::Plte;
+
+/// The chunks whose data depends on the order of a palette image's palette
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaletteChunks {
+	pub ihdr: Ihdr,
+	pub plte: Plte,
+	/// The data of the tRNS chunk
+	pub trns: Option<Vec<u8>>,
+	pub hist: Option<Hist>,
+	/// The data of the bKGD chunk
+	pub bkgd: Option<Vec<u8>>,
+}
+
+/// The offset of each scanline of the image data, after the filter type byte, and the number of
+/// pixels in that scanline
+fn scanlines(ihdr: &Ihdr, bits_per_pixel: usize) -> Result<Vec<(usize, usize)>, FilterError> {
+	let mut offset: usize = 0;
+	let mut retval = Vec::new();
+	for (width, height) in filter::pass_sizes(ihdr)? {
+		if width == 0 {
+			continue;
+		}
+		let row_length = filter::row_length(width, bits_per_pixel)?;
+		for _ in 0..height {
+			retval.push((offset + 1, width as usize));
+			offset += row_length + 1;
+		}
+	}
+	Ok(retval)
+}
+
+/// Sorts the palette of the image described by `chunks` into a canonical order, after removing
+/// the entries that no pixel in the inflated image data `data` uses and the entries that repeat
+/// another entry's color and alpha, and changes the indexes of `data` to match
+///
+/// Returns the chunks describing the new palette, and the new image data. The tRNS, hIST and bKGD
+/// are changed to describe the same entries as before; the counts of the hIST entries that were
+/// merged are added together. An entry that no pixel uses is kept if the bKGD refers to it and no
+/// other entry has its color. The new image data has the same interlace method and bit depth as
+/// the old, and every scanline is unfiltered.
+///
+/// Returns None if the image is not a palette image, or if the image data or the other chunks
+/// refer to entries that the palette does not have.
+pub fn normalize(
+	chunks: &PaletteChunks,
+	data: &[u8],
+) -> Result<Option<(PaletteChunks, Vec<u8>)>, FilterError> {
+	let ihdr = &chunks.ihdr;
+	if ihdr.color_type != 3 {
+		return Ok(None);
+	}
+	let bits_per_pixel = filter::bits_per_pixel(ihdr)?;
+	let bit_depth = ihdr.bit_depth;
+	let entries = &chunks.plte.entries;
+	let trns = chunks.trns.as_deref().unwrap_or(&[]);
+	if trns.len() > entries.len()
+		|| chunks
+			.hist
+			.as_ref()
+			.is_some_and(|hist| hist.frequencies.len() != entries.len())
+	{
+		return Ok(None);
+	}
+	let bkgd = match chunks.bkgd.as_deref() {
+		None => None,
+		Some([index]) if usize::from(*index) < entries.len() => Some(usize::from(*index)),
+		Some(_) => return Ok(None),
+	};
+	let rgba = |index: usize| {
+		let [r, g, b] = entries[index];
+		[r, g, b, trns.get(index).copied().unwrap_or(u8::MAX)]
+	};
+
+	let mut unfiltered = data.to_vec();
+	filter::normalize(ihdr, &mut unfiltered)?;
+	let scanlines = scanlines(ihdr, bits_per_pixel)?;
+
+	let mut used = vec![false; entries.len()];
+	for (offset, width) in &scanlines {
+		for x in 0..*width {
+			let index = usize::from(read_sample(&unfiltered[*offset..], x, bit_depth));
+			match used.get_mut(index) {
+				Some(used) => *used = true,
+				None => return Ok(None),
+			}
+		}
+	}
+
+	let mut palette: Vec<[u8; 4]> = (0..entries.len())
+		.filter(|index| used[*index])
+		.map(rgba)
+		.collect();
+	if let Some(bkgd) = bkgd {
+		let [r, g, b] = entries[bkgd];
+		if !palette.iter().any(|entry| entry[..3] == [r, g, b]) {
+			palette.push([r, g, b, u8::MAX]);
+		}
+	}
+	palette.sort_by_key(palette_order);
+	palette.dedup();
+	if palette.len() > 1 << bit_depth {
+		return Ok(None);
+	}
+	// the new index of each old entry that a pixel uses
+	let remap: Vec<Option<u8>> = (0..entries.len())
+		.map(|index| {
+			used[index]
+				.then(|| palette.iter().position(|entry| *entry == rgba(index)))
+				.flatten()
+				.map(|x| x as u8)
+		})
+		.collect();
+
+	let mut new_data = vec![0; filter::data_length(ihdr, bits_per_pixel)?];
+	for (offset, width) in &scanlines {
+		for x in 0..*width {
+			let index = usize::from(read_sample(&unfiltered[*offset..], x, bit_depth));
+			let index = remap[index].expect("every used entry is in the palette");
+			write_sample(&mut new_data[*offset..], x, bit_depth, u16::from(index));
+		}
+	}
+
+	let trns: Vec<u8> = palette
+		.iter()
+		.take_while(|entry| entry[3] != u8::MAX)
+		.map(|entry| entry[3])
+		.collect();
+	let hist = chunks.hist.as_ref().map(|hist| {
+		let mut frequencies = vec![0u16; palette.len()];
+		for (index, frequency) in hist.frequencies.iter().enumerate() {
+			if let Some(new_index) = remap[index] {
+				let new_frequency = &mut frequencies[usize::from(new_index)];
+				*new_frequency = new_frequency.saturating_add(*frequency);
+			}
+		}
+		Hist { frequencies }
+	});
+	let bkgd = bkgd.map(|bkgd| {
+		let index = palette
+			.iter()
+			.position(|entry| entry[..3] == entries[bkgd])
+			.expect("the background color is in the palette");
+		vec![index as u8]
+	});
+
+	Ok(Some((
+		PaletteChunks {
+			ihdr: ihdr.clone(),
+			plte: Plte {
+				entries: palette.iter().map(|x| [x[0], x[1], x[2]]).collect(),
+			},
+			trns: Some(trns).filter(|x| !x.is_empty()),
+			hist,
+			bkgd,
+		},
+		new_data,
+	)))
+}
+
+#[cfg(test)]
+mod tests {
+	mod normalize {
+		use super::super::super::typed::Hist;
+		use super::super::super::typed::Plte;
+		use super::super::normalize;
+		use super::super::PaletteChunks;
+		use crate::test_util::ihdr;
+
+		fn chunks(width: u32, height: u32, bit_depth: u8, entries: &[[u8; 3]]) -> PaletteChunks {
+			PaletteChunks {
+				ihdr: ihdr(width, height, bit_depth, 3, 0),
+				plte: Plte {
+					entries: entries.to_vec(),
+				},
+				trns: None,
+				hist: None,
+				bkgd: None,
+			}
+		}
+
+		#[test]
+		fn sorts_entries() {
+			let chunks = chunks(3, 1, 8, &[[9, 9, 9], [1, 1, 1], [5, 5, 5]]);
+			let (res, data) = normalize(&chunks, &[0, 0, 1, 2]).unwrap().unwrap();
+			assert_eq!(vec![[1, 1, 1], [5, 5, 5], [9, 9, 9]], res.plte.entries);
+			assert_eq!(vec![0, 2, 0, 1], data);
+		}
+
+		#[test]
+		fn drops_unused_and_duplicate_entries() {
+			let chunks = chunks(3, 1, 8, &[[9, 9, 9], [1, 1, 1], [5, 5, 5], [9, 9, 9]]);
+			let (res, data) = normalize(&chunks, &[0, 0, 3, 2]).unwrap().unwrap();
+			assert_eq!(vec![[5, 5, 5], [9, 9, 9]], res.plte.entries);
+			assert_eq!(vec![0, 1, 1, 0], data);
+		}
+
+		#[test]
+		fn entries_with_different_alpha_are_not_duplicates() {
+			let mut chunks = chunks(2, 1, 8, &[[9, 9, 9], [9, 9, 9]]);
+			chunks.trns = Some(vec![255, 0]);
+			let (res, data) = normalize(&chunks, &[0, 0, 1]).unwrap().unwrap();
+			assert_eq!(vec![[9, 9, 9], [9, 9, 9]], res.plte.entries);
+			assert_eq!(Some(vec![0]), res.trns);
+			assert_eq!(vec![0, 1, 0], data);
+		}
+
+		#[test]
+		fn opaque_trns_is_removed() {
+			let mut chunks = chunks(1, 1, 8, &[[9, 9, 9], [1, 1, 1]]);
+			chunks.trns = Some(vec![255, 0]);
+			let (res, _) = normalize(&chunks, &[0, 0]).unwrap().unwrap();
+			assert_eq!(None, res.trns);
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn packed_indexes() {
+			for bit_depth in [1, 2, 4] {
+				let chunks = chunks(5, 2, bit_depth, &[[9, 9, 9], [1, 1, 1]]);
+				let pixels_per_byte = 8 / bit_depth;
+				let row_length = 5_usize.div_ceil(usize::from(pixels_per_byte));
+				let pack = |pixels: &[u8]| -> Vec<u8> {
+					let mut retval = vec![0; row_length];
+					for (x, pixel) in pixels.iter().enumerate() {
+						let bit = x * usize::from(bit_depth);
+						retval[bit / 8] |= pixel << (8 - usize::from(bit_depth) - bit % 8);
+					}
+					retval
+				};
+				let data: Vec<u8> = [[0, 1, 1, 0, 1], [1, 1, 0, 0, 0]]
+					.iter()
+					.flat_map(|row| [0].into_iter().chain(pack(row)))
+					.collect();
+				let expected: Vec<u8> = [[1, 0, 0, 1, 0], [0, 0, 1, 1, 1]]
+					.iter()
+					.flat_map(|row| [0].into_iter().chain(pack(row)))
+					.collect();
+				let (res, data) = normalize(&chunks, &data).unwrap().unwrap();
+				assert_eq!(vec![[1, 1, 1], [9, 9, 9]], res.plte.entries);
+				assert_eq!(expected, data, "bit depth {}", bit_depth);
+			}
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn interlaced() {
+			let mut chunks = chunks(2, 2, 8, &[[9, 9, 9], [1, 1, 1], [5, 5, 5]]);
+			chunks.ihdr.interlace_method = 1;
+			// passes 1 (top left), 6 (top right) and 7 (bottom row)
+			let data = vec![
+				0, 0,
+				0, 1,
+				0, 2, 0,
+			];
+			let expected = vec![
+				0, 2,
+				0, 0,
+				0, 1, 2,
+			];
+			let (_, data) = normalize(&chunks, &data).unwrap().unwrap();
+			assert_eq!(expected, data);
+		}
+
+		#[test]
+		fn reverses_filters() {
+			let chunks = chunks(2, 1, 8, &[[9, 9, 9], [1, 1, 1]]);
+			// Sub-filtered
+			let (_, data) = normalize(&chunks, &[1, 0, 1]).unwrap().unwrap();
+			assert_eq!(vec![0, 1, 0], data);
+		}
+
+		#[test]
+		fn moves_transparent_entries_first() {
+			let mut chunks = chunks(3, 1, 8, &[[1, 1, 1], [9, 9, 9], [5, 5, 5]]);
+			chunks.trns = Some(vec![255, 128, 0]);
+			let (res, data) = normalize(&chunks, &[0, 0, 1, 2]).unwrap().unwrap();
+			assert_eq!(vec![[5, 5, 5], [9, 9, 9], [1, 1, 1]], res.plte.entries);
+			assert_eq!(Some(vec![0, 128]), res.trns);
+			assert_eq!(vec![0, 2, 1, 0], data);
+		}
+
+		#[test]
+		fn hist_follows_entries() {
+			let mut chunks = chunks(3, 1, 8, &[[9, 9, 9], [1, 1, 1], [5, 5, 5], [9, 9, 9]]);
+			chunks.hist = Some(Hist {
+				frequencies: vec![10, 20, 30, 40],
+			});
+			let (res, _) = normalize(&chunks, &[0, 0, 3, 2]).unwrap().unwrap();
+			assert_eq!(
+				Some(Hist {
+					frequencies: vec![30, 50]
+				}),
+				res.hist
+			);
+		}
+
+		#[test]
+		fn bkgd_follows_entry() {
+			let mut chunks = chunks(2, 1, 8, &[[9, 9, 9], [1, 1, 1]]);
+			chunks.bkgd = Some(vec![0]);
+			let (res, _) = normalize(&chunks, &[0, 0, 1]).unwrap().unwrap();
+			assert_eq!(Some(vec![1]), res.bkgd);
+		}
+
+		#[test]
+		fn unused_bkgd_entry_is_kept() {
+			let mut chunks = chunks(1, 1, 8, &[[9, 9, 9], [1, 1, 1], [5, 5, 5]]);
+			chunks.bkgd = Some(vec![1]);
+			let (res, data) = normalize(&chunks, &[0, 2]).unwrap().unwrap();
+			assert_eq!(vec![[1, 1, 1], [5, 5, 5]], res.plte.entries);
+			assert_eq!(Some(vec![0]), res.bkgd);
+			assert_eq!(vec![0, 1], data);
+		}
+
+		#[test]
+		fn index_past_palette_is_none() {
+			let chunks = chunks(1, 1, 8, &[[9, 9, 9]]);
+			assert_eq!(None, normalize(&chunks, &[0, 1]).unwrap());
+		}
+
+		#[test]
+		fn bkgd_past_palette_is_none() {
+			let mut chunks = chunks(1, 1, 8, &[[9, 9, 9]]);
+			chunks.bkgd = Some(vec![1]);
+			assert_eq!(None, normalize(&chunks, &[0, 0]).unwrap());
+		}
+
+		#[test]
+		fn not_palette_is_none() {
+			let mut chunks = chunks(1, 1, 8, &[[9, 9, 9]]);
+			chunks.ihdr.color_type = 0;
+			assert_eq!(None, normalize(&chunks, &[0, 0]).unwrap());
+		}
+	}
+}
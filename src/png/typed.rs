@@ -0,0 +1,1106 @@
+//! Typed representations of the data of known chunk types
+//!
+//! Each chunk type's data is represented by a struct that implements [`ChunkData`], and
+//! [`ParsedChunk`] is a sum type of all of those structs. Parsing is lossless: serializing a
+//! successfully parsed chunk results in the same bytes as the chunk that was parsed.
+//!
+//! Some chunks, such as tRNS, sBIT and bKGD, have a structure that depends on the image's color
+//! type; those chunks' data is kept as bytes.
+
+use super::Chunk;
+
+/// A chunk type whose data can be converted to and from a typed representation
+pub trait ChunkData: Sized {
+	/// The chunk type that this represents
+	const TYP: [u8; 4];
+
+	/// Decodes a chunk's data
+	fn parse(data: &[u8]) -> Result<Self, ParseError>;
+
+	/// Encodes this as a chunk's data
+	fn serialize(&self) -> Vec<u8>;
+
+	/// Encodes this as a chunk
+	fn to_chunk(&self) -> Chunk {
+		Chunk {
+			typ: Self::TYP,
+			data: self.serialize(),
+		}
+	}
+}
+
+/// Represents an error that can occur when decoding a chunk's data
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseError {
+	/** The chunk's data is too short or too long for the chunk's type */
+	InvalidLength { typ: [u8; 4], length: usize },
+	/** The chunk's data does not contain a null separator where one is required */
+	MissingNullSeparator([u8; 4]),
+	/** The chunk's data contains a count that does not match the number of following items */
+	CountMismatch([u8; 4]),
+}
+
+impl ::std::fmt::Display for ParseError {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		match self {
+			ParseError::InvalidLength { typ, length } => write!(
+				f,
+				"{} chunk has invalid length {}",
+				typ_to_string(typ),
+				length
+			),
+			ParseError::MissingNullSeparator(typ) => {
+				write!(
+					f,
+					"{} chunk is missing a null separator",
+					typ_to_string(typ)
+				)
+			},
+			ParseError::CountMismatch(typ) => write!(
+				f,
+				"{} chunk's item count does not match its items",
+				typ_to_string(typ)
+			),
+		}
+	}
+}
+
+impl ::std::error::Error for ParseError {}
+
+fn typ_to_string(typ: &[u8; 4]) -> String {
+	typ.iter()
+		.map(|x| char::from(*x))
+		.map(|x| if x.is_ascii_graphic() { x } else { '.' })
+		.collect()
+}
+
+/// Reads values from the front of a chunk's data
+struct Reader<'a> {
+	typ: [u8; 4],
+	original_length: usize,
+	data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+	fn new(typ: [u8; 4], data: &'a [u8]) -> Reader<'a> {
+		Reader {
+			typ,
+			original_length: data.len(),
+			data,
+		}
+	}
+
+	fn invalid_length(&self) -> ParseError {
+		ParseError::InvalidLength {
+			typ: self.typ,
+			length: self.original_length,
+		}
+	}
+
+	fn bytes<const N: usize>(&mut self) -> Result<[u8; N], ParseError> {
+		if self.data.len() < N {
+			return Err(self.invalid_length());
+		}
+		let (head, tail) = self.data.split_at(N);
+		self.data = tail;
+		Ok(head
+			.try_into()
+			.expect("split_at returns a slice of length N"))
+	}
+
+	fn u8(&mut self) -> Result<u8, ParseError> {
+		self.bytes::<1>().map(|x| x[0])
+	}
+
+	fn u16(&mut self) -> Result<u16, ParseError> {
+		self.bytes().map(u16::from_be_bytes)
+	}
+
+	fn u32(&mut self) -> Result<u32, ParseError> {
+		self.bytes().map(u32::from_be_bytes)
+	}
+
+	fn i32(&mut self) -> Result<i32, ParseError> {
+		self.bytes().map(i32::from_be_bytes)
+	}
+
+	/// Reads bytes up to the next null byte, and consumes that null byte
+	fn cstring(&mut self) -> Result<Vec<u8>, ParseError> {
+		match self.data.iter().position(|x| *x == 0) {
+			Some(index) => {
+				let retval = self.data[..index].to_vec();
+				self.data = &self.data[index + 1..];
+				Ok(retval)
+			},
+			None => Err(ParseError::MissingNullSeparator(self.typ)),
+		}
+	}
+
+	/// Reads all remaining bytes
+	fn rest(&mut self) -> Vec<u8> {
+		let retval = self.data.to_vec();
+		self.data = &[];
+		retval
+	}
+
+	/// Asserts that all bytes have been read
+	fn finish(self) -> Result<(), ParseError> {
+		if self.data.is_empty() {
+			Ok(())
+		} else {
+			Err(self.invalid_length())
+		}
+	}
+}
+
+/// Writes a cstring and its null terminator
+fn push_cstring(retval: &mut Vec<u8>, value: &[u8]) {
+	retval.extend_from_slice(value);
+	retval.push(0);
+}
+
+/// Defines the struct for a chunk type whose data is a fixed-size sequence of integers
+macro_rules! fixed_chunk {
+	(
+		$(#[$meta:meta])*
+		$name:ident, $typ:literal, { $( $(#[$field_meta:meta])* $field:ident : $field_typ:ident ),* $(,)? }
+	) => {
+		$(#[$meta])*
+		#[derive(Debug, Clone, PartialEq, Eq)]
+		pub struct $name {
+			$( $(#[$field_meta])* pub $field: $field_typ, )*
+		}
+
+		impl ChunkData for $name {
+			const TYP: [u8; 4] = *$typ;
+
+			fn parse(data: &[u8]) -> Result<Self, ParseError> {
+				let mut reader = Reader::new(Self::TYP, data);
+				let retval = $name {
+					$( $field: reader.$field_typ()?, )*
+				};
+				reader.finish()?;
+				Ok(retval)
+			}
+
+			fn serialize(&self) -> Vec<u8> {
+				let mut retval = Vec::new();
+				$( retval.extend_from_slice(&self.$field.to_be_bytes()); )*
+				retval
+			}
+		}
+	};
+}
+
+/// Defines the struct for a chunk type whose data is opaque bytes
+macro_rules! bytes_chunk {
+	(
+		$(#[$meta:meta])*
+		$name:ident, $typ:literal
+	) => {
+		$(#[$meta])*
+		#[derive(Debug, Clone, PartialEq, Eq)]
+		pub struct $name {
+			/// The chunk's data
+			pub data: Vec<u8>,
+		}
+
+		impl ChunkData for $name {
+			const TYP: [u8; 4] = *$typ;
+
+			fn parse(data: &[u8]) -> Result<Self, ParseError> {
+				Ok($name {
+					data: data.to_vec(),
+				})
+			}
+
+			fn serialize(&self) -> Vec<u8> {
+				self.data.clone()
+			}
+		}
+	};
+}
+
+fixed_chunk!(
+	/// Image header
+	Ihdr, b"IHDR", {
+		width: u32,
+		height: u32,
+		bit_depth: u8,
+		color_type: u8,
+		compression_method: u8,
+		filter_method: u8,
+		interlace_method: u8,
+	}
+);
+
+/// Palette
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plte {
+	/// The palette's colors, as `[red, green, blue]`
+	pub entries: Vec<[u8; 3]>,
+}
+
+impl ChunkData for Plte {
+	const TYP: [u8; 4] = *b"PLTE";
+
+	fn parse(data: &[u8]) -> Result<Self, ParseError> {
+		if !data.len().is_multiple_of(3) {
+			return Err(ParseError::InvalidLength {
+				typ: Self::TYP,
+				length: data.len(),
+			});
+		}
+		Ok(Plte {
+			entries: data.chunks_exact(3).map(|x| [x[0], x[1], x[2]]).collect(),
+		})
+	}
+
+	fn serialize(&self) -> Vec<u8> {
+		self.entries.iter().flatten().cloned().collect()
+	}
+}
+
+bytes_chunk!(
+	/// Image data; a portion of a zlib stream
+	Idat, b"IDAT"
+);
+
+/// Image trailer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Iend;
+
+impl ChunkData for Iend {
+	const TYP: [u8; 4] = *b"IEND";
+
+	fn parse(data: &[u8]) -> Result<Self, ParseError> {
+		Reader::new(Self::TYP, data).finish()?;
+		Ok(Iend)
+	}
+
+	fn serialize(&self) -> Vec<u8> {
+		Vec::new()
+	}
+}
+
+bytes_chunk!(
+	/// Transparency; the structure depends on the image's color type
+	Trns, b"tRNS"
+);
+
+fixed_chunk!(
+	/// Primary chromaticities and white point, each times 100000
+	Chrm, b"cHRM", {
+		white_point_x: u32,
+		white_point_y: u32,
+		red_x: u32,
+		red_y: u32,
+		green_x: u32,
+		green_y: u32,
+		blue_x: u32,
+		blue_y: u32,
+	}
+);
+
+fixed_chunk!(
+	/// Image gamma, times 100000
+	Gama, b"gAMA", {
+		gamma: u32,
+	}
+);
+
+bytes_chunk!(
+	/// Significant bits; the structure depends on the image's color type
+	Sbit, b"sBIT"
+);
+
+fixed_chunk!(
+	/// Standard RGB color space
+	Srgb, b"sRGB", {
+		rendering_intent: u8,
+	}
+);
+
+fixed_chunk!(
+	/// Coding-independent code points for video signal type identification
+	Cicp, b"cICP", {
+		colour_primaries: u8,
+		transfer_function: u8,
+		matrix_coefficients: u8,
+		video_full_range_flag: u8,
+	}
+);
+
+fixed_chunk!(
+	/// Mastering display color volume
+	Mdcv, b"mDCV", {
+		red_x: u16,
+		red_y: u16,
+		green_x: u16,
+		green_y: u16,
+		blue_x: u16,
+		blue_y: u16,
+		white_point_x: u16,
+		white_point_y: u16,
+		max_luminance: u32,
+		min_luminance: u32,
+	}
+);
+
+fixed_chunk!(
+	/// Content light level information
+	Clli, b"cLLI", {
+		max_content_light_level: u32,
+		max_frame_average_light_level: u32,
+	}
+);
+
+/// Textual data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Text {
+	/// The keyword, in Latin-1
+	pub keyword: Vec<u8>,
+	/// The text, in Latin-1
+	pub text: Vec<u8>,
+}
+
+impl ChunkData for Text {
+	const TYP: [u8; 4] = *b"tEXt";
+
+	fn parse(data: &[u8]) -> Result<Self, ParseError> {
+		let mut reader = Reader::new(Self::TYP, data);
+		let keyword = reader.cstring()?;
+		let text = reader.rest();
+		Ok(Text { keyword, text })
+	}
+
+	fn serialize(&self) -> Vec<u8> {
+		let mut retval = Vec::new();
+		push_cstring(&mut retval, &self.keyword);
+		retval.extend_from_slice(&self.text);
+		retval
+	}
+}
+
+/// Compressed textual data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ztxt {
+	/// The keyword, in Latin-1
+	pub keyword: Vec<u8>,
+	/// The compression method; `0` is zlib-deflate
+	pub method: u8,
+	/// The compressed text
+	pub data: Vec<u8>,
+}
+
+impl ChunkData for Ztxt {
+	const TYP: [u8; 4] = *b"zTXt";
+
+	fn parse(data: &[u8]) -> Result<Self, ParseError> {
+		let mut reader = Reader::new(Self::TYP, data);
+		let keyword = reader.cstring()?;
+		let method = reader.u8()?;
+		let data = reader.rest();
+		Ok(Ztxt {
+			keyword,
+			method,
+			data,
+		})
+	}
+
+	fn serialize(&self) -> Vec<u8> {
+		let mut retval = Vec::new();
+		push_cstring(&mut retval, &self.keyword);
+		retval.push(self.method);
+		retval.extend_from_slice(&self.data);
+		retval
+	}
+}
+
+/// International textual data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Itxt {
+	/// The keyword, in Latin-1
+	pub keyword: Vec<u8>,
+	/// `0` if the text is uncompressed; `1` if the text is compressed
+	pub compression_flag: u8,
+	/// The compression method; `0` is zlib-deflate
+	pub compression_method: u8,
+	/// The language tag, in ASCII
+	pub language_tag: Vec<u8>,
+	/// The translated keyword, in UTF-8
+	pub translated_keyword: Vec<u8>,
+	/// The text, in UTF-8, possibly compressed
+	pub text: Vec<u8>,
+}
+
+impl ChunkData for Itxt {
+	const TYP: [u8; 4] = *b"iTXt";
+
+	fn parse(data: &[u8]) -> Result<Self, ParseError> {
+		let mut reader = Reader::new(Self::TYP, data);
+		let keyword = reader.cstring()?;
+		let compression_flag = reader.u8()?;
+		let compression_method = reader.u8()?;
+		let language_tag = reader.cstring()?;
+		let translated_keyword = reader.cstring()?;
+		let text = reader.rest();
+		Ok(Itxt {
+			keyword,
+			compression_flag,
+			compression_method,
+			language_tag,
+			translated_keyword,
+			text,
+		})
+	}
+
+	fn serialize(&self) -> Vec<u8> {
+		let mut retval = Vec::new();
+		push_cstring(&mut retval, &self.keyword);
+		retval.push(self.compression_flag);
+		retval.push(self.compression_method);
+		push_cstring(&mut retval, &self.language_tag);
+		push_cstring(&mut retval, &self.translated_keyword);
+		retval.extend_from_slice(&self.text);
+		retval
+	}
+}
+
+bytes_chunk!(
+	/// Background color; the structure depends on the image's color type
+	Bkgd, b"bKGD"
+);
+
+/// Image histogram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hist {
+	/// The approximate usage frequency of each palette entry
+	pub frequencies: Vec<u16>,
+}
+
+impl ChunkData for Hist {
+	const TYP: [u8; 4] = *b"hIST";
+
+	fn parse(data: &[u8]) -> Result<Self, ParseError> {
+		if !data.len().is_multiple_of(2) {
+			return Err(ParseError::InvalidLength {
+				typ: Self::TYP,
+				length: data.len(),
+			});
+		}
+		Ok(Hist {
+			frequencies: data
+				.chunks_exact(2)
+				.map(|x| u16::from_be_bytes([x[0], x[1]]))
+				.collect(),
+		})
+	}
+
+	fn serialize(&self) -> Vec<u8> {
+		self.frequencies
+			.iter()
+			.flat_map(|x| x.to_be_bytes())
+			.collect()
+	}
+}
+
+fixed_chunk!(
+	/// Physical pixel dimensions
+	Phys, b"pHYs", {
+		pixels_per_unit_x: u32,
+		pixels_per_unit_y: u32,
+		unit: u8,
+	}
+);
+
+/// Suggested palette
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Splt {
+	/// The palette name, in Latin-1
+	pub name: Vec<u8>,
+	/// The sample depth of the entries; either 8 or 16
+	pub sample_depth: u8,
+	/// The palette entries, as bytes
+	pub entries: Vec<u8>,
+}
+
+impl ChunkData for Splt {
+	const TYP: [u8; 4] = *b"sPLT";
+
+	fn parse(data: &[u8]) -> Result<Self, ParseError> {
+		let mut reader = Reader::new(Self::TYP, data);
+		let name = reader.cstring()?;
+		let sample_depth = reader.u8()?;
+		let entries = reader.rest();
+		Ok(Splt {
+			name,
+			sample_depth,
+			entries,
+		})
+	}
+
+	fn serialize(&self) -> Vec<u8> {
+		let mut retval = Vec::new();
+		push_cstring(&mut retval, &self.name);
+		retval.push(self.sample_depth);
+		retval.extend_from_slice(&self.entries);
+		retval
+	}
+}
+
+bytes_chunk!(
+	/// Exchangeable Image File (Exif) Profile
+	Exif, b"eXIf"
+);
+
+fixed_chunk!(
+	/// Image last-modification time
+	Time, b"tIME", {
+		year: u16,
+		month: u8,
+		day: u8,
+		hour: u8,
+		minute: u8,
+		second: u8,
+	}
+);
+
+/// Embedded ICC profile
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Iccp {
+	/// The profile name, in Latin-1
+	pub name: Vec<u8>,
+	/// The compression method; `0` is zlib-deflate
+	pub method: u8,
+	/// The compressed profile
+	pub data: Vec<u8>,
+}
+
+impl ChunkData for Iccp {
+	const TYP: [u8; 4] = *b"iCCP";
+
+	fn parse(data: &[u8]) -> Result<Self, ParseError> {
+		let mut reader = Reader::new(Self::TYP, data);
+		let name = reader.cstring()?;
+		let method = reader.u8()?;
+		let data = reader.rest();
+		Ok(Iccp { name, method, data })
+	}
+
+	fn serialize(&self) -> Vec<u8> {
+		let mut retval = Vec::new();
+		push_cstring(&mut retval, &self.name);
+		retval.push(self.method);
+		retval.extend_from_slice(&self.data);
+		retval
+	}
+}
+
+fixed_chunk!(
+	/// (apng) Animation control
+	Actl, b"acTL", {
+		num_frames: u32,
+		num_plays: u32,
+	}
+);
+
+fixed_chunk!(
+	/// (apng) Frame control
+	Fctl, b"fcTL", {
+		sequence_number: u32,
+		width: u32,
+		height: u32,
+		x_offset: u32,
+		y_offset: u32,
+		delay_num: u16,
+		delay_den: u16,
+		dispose_op: u8,
+		blend_op: u8,
+	}
+);
+
+/// (apng) Frame data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fdat {
+	/// The position of this chunk in the sequence of animation chunks
+	pub sequence_number: u32,
+	/// A portion of a zlib stream
+	pub data: Vec<u8>,
+}
+
+impl ChunkData for Fdat {
+	const TYP: [u8; 4] = *b"fdAT";
+
+	fn parse(data: &[u8]) -> Result<Self, ParseError> {
+		let mut reader = Reader::new(Self::TYP, data);
+		let sequence_number = reader.u32()?;
+		let data = reader.rest();
+		Ok(Fdat {
+			sequence_number,
+			data,
+		})
+	}
+
+	fn serialize(&self) -> Vec<u8> {
+		let mut retval = Vec::new();
+		retval.extend_from_slice(&self.sequence_number.to_be_bytes());
+		retval.extend_from_slice(&self.data);
+		retval
+	}
+}
+
+fixed_chunk!(
+	/// (extension) Image offset
+	Offs, b"oFFs", {
+		x: i32,
+		y: i32,
+		unit: u8,
+	}
+);
+
+/// (extension) Calibration of pixel values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pcal {
+	/// The calibration name, in Latin-1
+	pub name: Vec<u8>,
+	/// The lower limit of the original sample range
+	pub original_zero: i32,
+	/// The upper limit of the original sample range
+	pub original_max: i32,
+	/// The equation type
+	pub equation_type: u8,
+	/// The unit name, in Latin-1
+	pub unit: Vec<u8>,
+	/// The equation parameters
+	pub parameters: PcalParameters,
+}
+
+/// The equation parameters of a pCAL chunk, as ASCII floating-point numbers
+///
+/// The chunk stores the number of parameters in a single byte, so there are at most
+/// [`PcalParameters::MAX_COUNT`] parameters.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PcalParameters(Vec<Vec<u8>>);
+
+impl PcalParameters {
+	/// The largest number of parameters that a pCAL chunk can store
+	pub const MAX_COUNT: usize = u8::MAX as usize;
+
+	/// Returns None if there are more than [`PcalParameters::MAX_COUNT`] parameters
+	pub fn new(parameters: Vec<Vec<u8>>) -> Option<PcalParameters> {
+		(parameters.len() <= Self::MAX_COUNT).then_some(PcalParameters(parameters))
+	}
+
+	/// The number of parameters, as stored in the chunk
+	pub fn count(&self) -> u8 {
+		self.0.len() as u8
+	}
+
+	/// Returns the parameters as a vector
+	pub fn into_vec(self) -> Vec<Vec<u8>> {
+		self.0
+	}
+}
+
+impl ::std::ops::Deref for PcalParameters {
+	type Target = [Vec<u8>];
+
+	fn deref(&self) -> &[Vec<u8>] {
+		&self.0
+	}
+}
+
+impl ChunkData for Pcal {
+	const TYP: [u8; 4] = *b"pCAL";
+
+	fn parse(data: &[u8]) -> Result<Self, ParseError> {
+		let mut reader = Reader::new(Self::TYP, data);
+		let name = reader.cstring()?;
+		let original_zero = reader.i32()?;
+		let original_max = reader.i32()?;
+		let equation_type = reader.u8()?;
+		let parameter_count = reader.u8()?;
+		let unit = reader.cstring()?;
+		let parameters: Vec<Vec<u8>> = if parameter_count == 0 {
+			reader.finish()?;
+			Vec::new()
+		} else {
+			reader
+				.rest()
+				.split(|x| *x == 0)
+				.map(|x| x.to_vec())
+				.collect()
+		};
+		if parameters.len() != usize::from(parameter_count) {
+			return Err(ParseError::CountMismatch(Self::TYP));
+		}
+		Ok(Pcal {
+			name,
+			original_zero,
+			original_max,
+			equation_type,
+			unit,
+			parameters: PcalParameters(parameters),
+		})
+	}
+
+	fn serialize(&self) -> Vec<u8> {
+		let mut retval = Vec::new();
+		push_cstring(&mut retval, &self.name);
+		retval.extend_from_slice(&self.original_zero.to_be_bytes());
+		retval.extend_from_slice(&self.original_max.to_be_bytes());
+		retval.push(self.equation_type);
+		retval.push(self.parameters.count());
+		push_cstring(&mut retval, &self.unit);
+		retval.extend_from_slice(&self.parameters.join(&0));
+		retval
+	}
+}
+
+/// (extension) Physical scale of image subject
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scal {
+	/// The unit specifier; `1` is meters and `2` is radians
+	pub unit: u8,
+	/// The pixel width, as an ASCII floating-point number
+	pub pixel_width: Vec<u8>,
+	/// The pixel height, as an ASCII floating-point number
+	pub pixel_height: Vec<u8>,
+}
+
+impl ChunkData for Scal {
+	const TYP: [u8; 4] = *b"sCAL";
+
+	fn parse(data: &[u8]) -> Result<Self, ParseError> {
+		let mut reader = Reader::new(Self::TYP, data);
+		let unit = reader.u8()?;
+		let pixel_width = reader.cstring()?;
+		let pixel_height = reader.rest();
+		Ok(Scal {
+			unit,
+			pixel_width,
+			pixel_height,
+		})
+	}
+
+	fn serialize(&self) -> Vec<u8> {
+		let mut retval = vec![self.unit];
+		push_cstring(&mut retval, &self.pixel_width);
+		retval.extend_from_slice(&self.pixel_height);
+		retval
+	}
+}
+
+fixed_chunk!(
+	/// (extension) GIF Graphic Control Extension
+	Gifg, b"gIFg", {
+		disposal_method: u8,
+		user_input_flag: u8,
+		delay_time: u16,
+	}
+);
+
+/// (extension) GIF Application Extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gifx {
+	/// The application identifier
+	pub application_identifier: [u8; 8],
+	/// The application authentication code
+	pub authentication_code: [u8; 3],
+	/// The application data
+	pub data: Vec<u8>,
+}
+
+impl ChunkData for Gifx {
+	const TYP: [u8; 4] = *b"gIFx";
+
+	fn parse(data: &[u8]) -> Result<Self, ParseError> {
+		let mut reader = Reader::new(Self::TYP, data);
+		let application_identifier = reader.bytes()?;
+		let authentication_code = reader.bytes()?;
+		let data = reader.rest();
+		Ok(Gifx {
+			application_identifier,
+			authentication_code,
+			data,
+		})
+	}
+
+	fn serialize(&self) -> Vec<u8> {
+		let mut retval = Vec::new();
+		retval.extend_from_slice(&self.application_identifier);
+		retval.extend_from_slice(&self.authentication_code);
+		retval.extend_from_slice(&self.data);
+		retval
+	}
+}
+
+fixed_chunk!(
+	/// (extension) Indicator of Stereo Image
+	Ster, b"sTER", {
+		mode: u8,
+	}
+);
+
+/// (extension, deprecated) GIF Plain Text Extension
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gift {
+	/// The text grid's left position, in pixels
+	pub grid_left: i32,
+	/// The text grid's top position, in pixels
+	pub grid_top: i32,
+	/// The text grid's width, in pixels
+	pub grid_width: i32,
+	/// The text grid's height, in pixels
+	pub grid_height: i32,
+	/// The width of a character cell, in pixels
+	pub cell_width: u8,
+	/// The height of a character cell, in pixels
+	pub cell_height: u8,
+	/// The text foreground color, as `[red, green, blue]`
+	pub foreground: [u8; 3],
+	/// The text background color, as `[red, green, blue]`
+	pub background: [u8; 3],
+	/// The text, in ASCII
+	pub text: Vec<u8>,
+}
+
+impl ChunkData for Gift {
+	const TYP: [u8; 4] = *b"gIFt";
+
+	fn parse(data: &[u8]) -> Result<Self, ParseError> {
+		let mut reader = Reader::new(Self::TYP, data);
+		Ok(Gift {
+			grid_left: reader.i32()?,
+			grid_top: reader.i32()?,
+			grid_width: reader.i32()?,
+			grid_height: reader.i32()?,
+			cell_width: reader.u8()?,
+			cell_height: reader.u8()?,
+			foreground: reader.bytes()?,
+			background: reader.bytes()?,
+			text: reader.rest(),
+		})
+	}
+
+	fn serialize(&self) -> Vec<u8> {
+		let mut retval = Vec::new();
+		retval.extend_from_slice(&self.grid_left.to_be_bytes());
+		retval.extend_from_slice(&self.grid_top.to_be_bytes());
+		retval.extend_from_slice(&self.grid_width.to_be_bytes());
+		retval.extend_from_slice(&self.grid_height.to_be_bytes());
+		retval.push(self.cell_width);
+		retval.push(self.cell_height);
+		retval.extend_from_slice(&self.foreground);
+		retval.extend_from_slice(&self.background);
+		retval.extend_from_slice(&self.text);
+		retval
+	}
+}
+
+/// Defines the ParsedChunk enum, with one variant for each of the given ChunkData types
+macro_rules! parsed_chunk {
+	( $( $name:ident ),* $(,)? ) => {
+		/// A chunk whose data has been decoded according to the chunk's type
+		#[derive(Debug, Clone, PartialEq, Eq)]
+		#[non_exhaustive]
+		pub enum ParsedChunk {
+			$( $name($name), )*
+			/** A chunk whose type is not one of the known types */
+			Unknown(Chunk),
+		}
+
+		impl ParsedChunk {
+			/// Decodes a chunk's data according to the chunk's type
+			pub fn parse(chunk: &Chunk) -> Result<ParsedChunk, ParseError> {
+				$(
+					if chunk.typ == $name::TYP {
+						return $name::parse(&chunk.data).map(ParsedChunk::$name);
+					}
+				)*
+				Ok(ParsedChunk::Unknown(chunk.clone()))
+			}
+
+			/// Encodes this as a chunk
+			pub fn serialize(&self) -> Chunk {
+				match self {
+					$( ParsedChunk::$name(x) => x.to_chunk(), )*
+					ParsedChunk::Unknown(x) => x.clone(),
+				}
+			}
+		}
+
+		$(
+			impl From<$name> for ParsedChunk {
+				fn from(src: $name) -> ParsedChunk {
+					ParsedChunk::$name(src)
+				}
+			}
+		)*
+	};
+}
+
+// Listed in the order the chunks are specified in <https://w3c.github.io/png/#11Chunks>
+// followed by order in <https://w3c.github.io/png/extensions/Overview.html>
+parsed_chunk!(
+	Ihdr, Plte, Idat, Iend, Trns, Chrm, Gama, Sbit, Srgb, Cicp, Mdcv, Clli, Text, Ztxt, Itxt, Bkgd,
+	Hist, Phys, Splt, Exif, Time, Iccp, Actl, Fctl, Fdat, Offs, Pcal, Scal, Gifg, Gifx, Ster, Gift,
+);
+
+#[cfg(test)]
+mod tests {
+	mod parse {
+		use super::super::super::Chunk;
+		use super::super::*;
+
+		fn assert_round_trip(chunk: Chunk) {
+			let parsed = ParsedChunk::parse(&chunk).unwrap();
+			assert!(
+				!matches!(parsed, ParsedChunk::Unknown(_)),
+				"parsed as unknown"
+			);
+			assert_eq!(chunk, parsed.serialize());
+		}
+
+		#[test]
+		fn ihdr() {
+			#[rustfmt::skip]
+			let dut = Chunk{typ:*b"IHDR", data:vec![0, 0, 1, 0x2c, 0, 0, 0, 0x96, 2, 3, 0, 0, 1]};
+			let exp = ParsedChunk::Ihdr(Ihdr {
+				width: 300,
+				height: 150,
+				bit_depth: 2,
+				color_type: 3,
+				compression_method: 0,
+				filter_method: 0,
+				interlace_method: 1,
+			});
+			assert_eq!(exp, ParsedChunk::parse(&dut).unwrap());
+			assert_round_trip(dut);
+		}
+
+		#[test]
+		fn ihdr_too_short() {
+			#[rustfmt::skip]
+			let dut = Chunk{typ:*b"IHDR", data:vec![0, 0, 1, 0x2c, 0, 0, 0, 0x96, 2, 3, 0, 0]};
+			let exp = ParseError::InvalidLength {
+				typ: *b"IHDR",
+				length: 12,
+			};
+			assert_eq!(Err(exp), ParsedChunk::parse(&dut));
+		}
+
+		#[test]
+		fn ihdr_too_long() {
+			#[rustfmt::skip]
+			let dut = Chunk{typ:*b"IHDR", data:vec![0, 0, 1, 0x2c, 0, 0, 0, 0x96, 2, 3, 0, 0, 1, 0]};
+			let exp = ParseError::InvalidLength {
+				typ: *b"IHDR",
+				length: 14,
+			};
+			assert_eq!(Err(exp), ParsedChunk::parse(&dut));
+		}
+
+		#[test]
+		fn plte() {
+			#[rustfmt::skip]
+			let dut = Chunk{typ:*b"PLTE", data:vec![1, 2, 3, 4, 5, 6]};
+			let exp = ParsedChunk::Plte(Plte {
+				entries: vec![[1, 2, 3], [4, 5, 6]],
+			});
+			assert_eq!(exp, ParsedChunk::parse(&dut).unwrap());
+			assert_round_trip(dut);
+		}
+
+		#[test]
+		fn ztxt() {
+			#[rustfmt::skip]
+			let dut = Chunk{typ:*b"zTXt", data:b"Title\0\0xyz".to_vec()};
+			let exp = ParsedChunk::Ztxt(Ztxt {
+				keyword: b"Title".to_vec(),
+				method: 0,
+				data: b"xyz".to_vec(),
+			});
+			assert_eq!(exp, ParsedChunk::parse(&dut).unwrap());
+			assert_round_trip(dut);
+		}
+
+		#[test]
+		fn ztxt_without_keyword_terminator() {
+			#[rustfmt::skip]
+			let dut = Chunk{typ:*b"zTXt", data:b"Title".to_vec()};
+			let exp = ParseError::MissingNullSeparator(*b"zTXt");
+			assert_eq!(Err(exp), ParsedChunk::parse(&dut));
+		}
+
+		#[test]
+		fn itxt() {
+			#[rustfmt::skip]
+			let dut = Chunk{typ:*b"iTXt", data:b"Title\0\x01\0en\0Titel\0xyz".to_vec()};
+			let exp = ParsedChunk::Itxt(Itxt {
+				keyword: b"Title".to_vec(),
+				compression_flag: 1,
+				compression_method: 0,
+				language_tag: b"en".to_vec(),
+				translated_keyword: b"Titel".to_vec(),
+				text: b"xyz".to_vec(),
+			});
+			assert_eq!(exp, ParsedChunk::parse(&dut).unwrap());
+			assert_round_trip(dut);
+		}
+
+		#[test]
+		fn fctl() {
+			#[rustfmt::skip]
+			let dut = Chunk{typ:*b"fcTL", data:vec![
+				0, 0, 0, 1,  0, 0, 0, 2,  0, 0, 0, 3,  0, 0, 0, 4,  0, 0, 0, 5,
+				0, 6,  0, 7,  1,  0,
+			]};
+			let exp = ParsedChunk::Fctl(Fctl {
+				sequence_number: 1,
+				width: 2,
+				height: 3,
+				x_offset: 4,
+				y_offset: 5,
+				delay_num: 6,
+				delay_den: 7,
+				dispose_op: 1,
+				blend_op: 0,
+			});
+			assert_eq!(exp, ParsedChunk::parse(&dut).unwrap());
+			assert_round_trip(dut);
+		}
+
+		#[test]
+		fn pcal() {
+			#[rustfmt::skip]
+			let dut = Chunk{typ:*b"pCAL", data:b"name\0\0\0\0\0\0\0\x01\0\x00\x02m\x001.0\x002.5".to_vec()};
+			assert_round_trip(dut);
+		}
+
+		#[test]
+		fn pcal_parameters_limit() {
+			assert!(PcalParameters::new(vec![b"1.0".to_vec(); 255]).is_some());
+			assert!(PcalParameters::new(vec![b"1.0".to_vec(); 256]).is_none());
+		}
+
+		#[test]
+		fn pcal_count_mismatch() {
+			#[rustfmt::skip]
+			let dut = Chunk{typ:*b"pCAL", data:b"name\0\0\0\0\0\0\0\x01\0\x00\x03m\x001.0\x002.5".to_vec()};
+			let exp = ParseError::CountMismatch(*b"pCAL");
+			assert_eq!(Err(exp), ParsedChunk::parse(&dut));
+		}
+
+		#[test]
+		fn unknown() {
+			#[rustfmt::skip]
+			let dut = Chunk{typ:*b"abCd", data:vec![1, 2, 3]};
+			let exp = ParsedChunk::Unknown(dut.clone());
+			assert_eq!(exp, ParsedChunk::parse(&dut).unwrap());
+			assert_eq!(dut, exp.serialize());
+		}
+	}
+}
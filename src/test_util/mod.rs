@@ -0,0 +1,56 @@
+//! Helpers shared by the tests
+
+mod random;
+
+pub(crate) use random::pseudorandom;
+
+use crate::png;
+use crate::png::typed::Ihdr;
+use crate::transform::ChunkTransform;
+use crate::zlib;
+
+/// An IHDR with the given size and format, and the only compression and filter methods
+pub(crate) fn ihdr(
+	width: u32,
+	height: u32,
+	bit_depth: u8,
+	color_type: u8,
+	interlace_method: u8,
+) -> Ihdr {
+	Ihdr {
+		width,
+		height,
+		bit_depth,
+		color_type,
+		compression_method: 0,
+		filter_method: 0,
+		interlace_method,
+	}
+}
+
+/// A chunk of the given type containing a copy of `data`
+pub(crate) fn chunk(typ: &[u8; 4], data: &[u8]) -> png::Chunk {
+	png::Chunk {
+		typ: *typ,
+		data: data.to_vec(),
+	}
+}
+
+/// An IDAT containing the compressed image data
+pub(crate) fn idat(image: &[u8]) -> png::Chunk {
+	png::Chunk {
+		typ: *b"IDAT",
+		data: zlib::deflate(image, Default::default()),
+	}
+}
+
+/// Pushes each chunk through the transform, then finishes the transform, returning every chunk
+/// that the transform returned
+pub(crate) fn push_all<T: ChunkTransform>(dut: &mut T, chunks: Vec<png::Chunk>) -> Vec<png::Chunk> {
+	let mut retval = Vec::new();
+	for chunk in chunks {
+		retval.extend(dut.push(chunk).unwrap());
+	}
+	retval.extend(dut.finish().unwrap());
+	retval
+}
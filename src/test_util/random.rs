@@ -0,0 +1,16 @@
+//! Pseudorandom data, which the benchmarks also use
+
+/// Data without any particular pattern, which is the same for the same seed
+///
+/// Each byte is the high byte of the next state of a xorshift32 generator. `seed` must not be zero.
+pub(crate) fn pseudorandom(seed: u32, len: usize) -> Vec<u8> {
+	let mut state = seed;
+	(0..len)
+		.map(|_| {
+			state ^= state << 13;
+			state ^= state >> 17;
+			state ^= state << 5;
+			(state >> 24) as u8
+		})
+		.collect()
+}
@@ -0,0 +1,2252 @@
+//! Composable steps that each process a stream of png chunks
+//!
+//! A [`ChunkTransform`] receives chunks one at a time, and returns the chunks that should be
+//! passed on to the next step. A [`Pipeline`] runs a sequence of transforms, feeding the output
+//! of each step into the next.
+//!
+//! Any `FnMut(png::Chunk) -> Result<png::Chunk, Error>` is also a transform, which allows adding
+//! handlers for private chunks:
+//!
+//! ```
+//! use png_inflate::png::Chunk;
+//! use png_inflate::transform::{
+//!     CheckCopySafety, ChunkTransform, ConcatinateIdats, InflateChunks, Pipeline,
+//! };
+//!
+//! let mut pipeline = Pipeline::new()
+//!     .with(ConcatinateIdats::new())
+//!     .with(|chunk: Chunk| {
+//!         if chunk.typ == *b"prIV" {
+//!             Ok(Chunk { typ: *b"prIv", data: chunk.data })
+//!         } else {
+//!             Ok(chunk)
+//!         }
+//!     })
+//!     .with(CheckCopySafety::default())
+//!     .with(InflateChunks::default());
+//!
+//! let res = pipeline.push(Chunk { typ: *b"prIV", data: vec![] })?;
+//! assert_eq!(vec![Chunk { typ: *b"prIv", data: vec![] }], res);
+//! # Ok::<(), png_inflate::Error>(())
+//! ```
+
+use super::png;
+use super::png::typed::ChunkData;
+use super::zlib;
+use super::Error;
+use std::cell::Cell;
+use std::rc::Rc;
+use std::result::Result;
+
+/// A step in the processing of a stream of png chunks
+pub trait ChunkTransform {
+	/// Processes a chunk, returning the chunks that should be passed to the next step
+	///
+	/// A transform may hold on to chunks and return them in response to a later `push` or `finish`.
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error>;
+
+	/// Signals that there are no more chunks, returning any chunks that this step is holding on to
+	fn finish(&mut self) -> Result<Vec<png::Chunk>, Error> {
+		Ok(Vec::new())
+	}
+}
+
+impl<F: FnMut(png::Chunk) -> Result<png::Chunk, Error>> ChunkTransform for F {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		self(chunk).map(|x| vec![x])
+	}
+}
+
+/// A sequence of transforms, where the output of each step is the input of the next step
+#[derive(Default)]
+pub struct Pipeline {
+	stages: Vec<Box<dyn ChunkTransform>>,
+}
+
+impl Pipeline {
+	/// Creates a pipeline with no steps, which passes through every chunk unchanged
+	pub fn new() -> Pipeline {
+		Pipeline { stages: Vec::new() }
+	}
+
+	/// Adds a step to the end of this pipeline
+	pub fn with<T: ChunkTransform + 'static>(mut self, stage: T) -> Pipeline {
+		self.add(stage);
+		self
+	}
+
+	/// Adds a step to the end of this pipeline
+	pub fn add<T: ChunkTransform + 'static>(&mut self, stage: T) {
+		self.stages.push(Box::new(stage));
+	}
+
+	/// Passes the chunks through each stage starting with the stage at index `start`
+	fn push_from(
+		&mut self,
+		start: usize,
+		chunks: Vec<png::Chunk>,
+	) -> Result<Vec<png::Chunk>, Error> {
+		let mut chunks = chunks;
+		for stage in self.stages[start..].iter_mut() {
+			let mut next_chunks = Vec::new();
+			for chunk in chunks {
+				next_chunks.extend(stage.push(chunk)?);
+			}
+			chunks = next_chunks;
+		}
+		Ok(chunks)
+	}
+}
+
+impl ChunkTransform for Pipeline {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		self.push_from(0, vec![chunk])
+	}
+
+	fn finish(&mut self) -> Result<Vec<png::Chunk>, Error> {
+		let mut retval = Vec::new();
+		for index in 0..self.stages.len() {
+			let flushed = self.stages[index].finish()?;
+			retval.extend(self.push_from(index + 1, flushed)?);
+		}
+		Ok(retval)
+	}
+}
+
+/// A transform that merges sequential IDATs, but otherwise passes through chunks
+#[derive(Debug, Default)]
+pub struct ConcatinateIdats {
+	pending: Option<png::Chunk>,
+}
+
+impl ConcatinateIdats {
+	/// Creates a transform that is not holding any IDATs
+	pub fn new() -> ConcatinateIdats {
+		ConcatinateIdats { pending: None }
+	}
+}
+
+impl ChunkTransform for ConcatinateIdats {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		if chunk.typ == *b"IDAT" {
+			match self.pending.as_mut() {
+				Some(pending) => pending.data.extend_from_slice(&chunk.data),
+				None => self.pending = Some(chunk),
+			}
+			Ok(Vec::new())
+		} else {
+			Ok(self.pending.take().into_iter().chain([chunk]).collect())
+		}
+	}
+
+	fn finish(&mut self) -> Result<Vec<png::Chunk>, Error> {
+		Ok(self.pending.take().into_iter().collect())
+	}
+}
+
+/// A transform that splits IDATs longer than `max_length` into consecutive IDATs of at most
+/// `max_length` bytes, but otherwise passes through chunks
+///
+/// The default `max_length` is [`png::MAX_CHUNK_LENGTH`].
+#[derive(Debug, Clone, Copy)]
+pub struct SplitIdats {
+	max_length: usize,
+}
+
+impl SplitIdats {
+	/// `max_length` must be at least one, and at most [`png::MAX_CHUNK_LENGTH`]
+	pub fn new(max_length: usize) -> SplitIdats {
+		assert!(
+			(1..=png::MAX_CHUNK_LENGTH as usize).contains(&max_length),
+			"IDAT length must be between 1 and {}",
+			png::MAX_CHUNK_LENGTH
+		);
+		SplitIdats { max_length }
+	}
+}
+
+impl Default for SplitIdats {
+	fn default() -> SplitIdats {
+		SplitIdats::new(png::MAX_CHUNK_LENGTH as usize)
+	}
+}
+
+impl ChunkTransform for SplitIdats {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		if chunk.typ != *b"IDAT" || chunk.data.len() <= self.max_length {
+			return Ok(vec![chunk]);
+		}
+		Ok(chunk
+			.data
+			.chunks(self.max_length)
+			.map(|data| png::Chunk {
+				typ: *b"IDAT",
+				data: data.to_vec(),
+			})
+			.collect())
+	}
+}
+
+/// A transform that rejects chunks that cannot be safely passed through, but otherwise passes
+/// through chunks
+///
+/// An unknown chunk that is not safe-to-copy results in an [`Error::CannotCopySafely`], unless
+/// `ignore_unsafe_to_copy` is true. APNG chunks are unknown chunks unless `process_apng` is true,
+/// and [`RECONSTRUCTION_TYP`] chunks are unknown chunks unless `process_reconstruction` is true.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CheckCopySafety {
+	/// Whether to pass through unknown chunks that are not safe-to-copy
+	pub ignore_unsafe_to_copy: bool,
+	/// Whether to treat APNG chunks as known chunks
+	pub process_apng: bool,
+	/// Whether to treat [`RECONSTRUCTION_TYP`] chunks as known chunks
+	pub process_reconstruction: bool,
+}
+
+impl CheckCopySafety {
+	fn apply(&self, indata: png::Chunk) -> Result<png::Chunk, Error> {
+		// Union cases are listed in the order the chunks are specified in <https://w3c.github.io/png/#11Chunks>
+		// followed by order in <https://w3c.github.io/png/extensions/Overview.html>
+		match indata.typ.as_ref() {
+			#[rustfmt::skip]
+			b"IHDR" | b"PLTE" | b"IDAT" | b"IEND" | b"tRNS" |
+			b"cHRM" | b"gAMA" | b"iCCP" | b"sBIT" | b"sRGB" | b"cICP" | b"mDCV" | b"cLLI" |
+			b"tEXt" | b"zTXt" | b"iTXt" |
+			b"bKGD" | b"hIST" | b"pHYs" | b"sPLT" | b"eXIf" |
+			b"tIME" |
+			b"oFFs" | b"pCAL" | b"sCAL" | b"gIFg" | b"gIFx" | b"sTER" |
+			b"gIFt" => {
+				Ok(indata)
+			},
+			b"acTL" | b"fcTL" | b"fdAT" => {
+				if self.ignore_unsafe_to_copy || self.process_apng {
+					Ok(indata)
+				} else {
+					Err(Error::CannotCopySafely(indata.typ))
+				}
+			},
+			typ if typ == RECONSTRUCTION_TYP && self.process_reconstruction => Ok(indata),
+			// unknown chunks
+			_ => {
+				if self.ignore_unsafe_to_copy || indata.safe_to_copy() {
+					Ok(indata)
+				} else {
+					Err(Error::CannotCopySafely(indata.typ))
+				}
+			},
+		}
+	}
+}
+
+impl ChunkTransform for CheckCopySafety {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		self.apply(chunk).map(|x| vec![x])
+	}
+}
+
+/// A transform that rejects an IHDR that declares a compression method other than zlib-deflate,
+/// but otherwise passes through chunks
+///
+/// If `strict` is true, the IHDR must also follow the rest of the png specification: the image
+/// size, the combination of bit depth and color type, the filter method and the interlace method
+/// are all checked.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ValidateIhdr {
+	/// Whether to check the parts of the IHDR that do not affect this crate's processing
+	pub strict: bool,
+}
+
+impl ChunkTransform for ValidateIhdr {
+	fn push(&mut self, indata: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		if indata.typ == png::typed::Ihdr::TYP {
+			if self.strict {
+				let ihdr = png::typed::Ihdr::parse(&indata.data)?;
+				check_ihdr(&ihdr)?;
+			}
+			// everything about this program assumes that the only valid compression method is zero
+			match indata.data.get(10) {
+				Some(0) => {},
+				Some(_) => return Err(Error::UnsupportedCompressionMethod),
+				None => {
+					return Err(Error::MalformedChunk(
+						png::typed::ParseError::InvalidLength {
+							typ: png::typed::Ihdr::TYP,
+							length: indata.data.len(),
+						},
+					))
+				},
+			}
+		}
+		Ok(vec![indata])
+	}
+}
+
+/// Checks the fields of an IHDR other than the compression method against the png specification
+fn check_ihdr(ihdr: &png::typed::Ihdr) -> Result<(), Error> {
+	const MAX_DIMENSION: u32 = (1 << 31) - 1;
+	if !(1..=MAX_DIMENSION).contains(&ihdr.width) || !(1..=MAX_DIMENSION).contains(&ihdr.height) {
+		return Err(Error::InvalidImageSize {
+			width: ihdr.width,
+			height: ihdr.height,
+		});
+	}
+	let allowed_bit_depths: &[u8] = match ihdr.color_type {
+		0 => &[1, 2, 4, 8, 16],
+		3 => &[1, 2, 4, 8],
+		2 | 4 | 6 => &[8, 16],
+		_ => return Err(Error::InvalidColorType(ihdr.color_type)),
+	};
+	if !allowed_bit_depths.contains(&ihdr.bit_depth) {
+		return Err(Error::InvalidBitDepth {
+			color_type: ihdr.color_type,
+			bit_depth: ihdr.bit_depth,
+		});
+	}
+	if ihdr.filter_method != 0 {
+		return Err(Error::UnsupportedFilterMethod(ihdr.filter_method));
+	}
+	if ihdr.interlace_method > 1 {
+		return Err(Error::UnsupportedInterlaceMethod(ihdr.interlace_method));
+	}
+	Ok(())
+}
+
+/// What [`CheckChunkOrder`] does with chunks that break the ordering rules
+enum OrderMode {
+	Reject,
+	Warn(Box<dyn FnMut(&png::order::Diagnostic)>),
+	/// Holds the chunks received so far
+	Fix(Vec<png::Chunk>),
+}
+
+/// A transform that checks that chunks follow the ordering and multiplicity rules described in
+/// [`png::order`]
+///
+/// Depending on how it is created, the transform fails at the first problem, reports each problem
+/// to a function while passing through chunks unchanged, or rearranges the chunks using
+/// [`png::order::fix`]. Chunk indexes are counted from the first chunk that this transform
+/// receives, so this should be the first step of a pipeline.
+///
+/// When failing or reporting problems, the chunks that follow an IDAT are held until the next
+/// IDAT, the IEND or the end of the file, so that an IDAT separated from the previous IDATs is
+/// reported before the later steps receive image data that is missing that IDAT.
+pub struct CheckChunkOrder {
+	mode: OrderMode,
+	checker: png::order::OrderChecker,
+	/// The chunks received since the most recent IDAT, if there has been an IDAT and not yet an
+	/// IEND
+	after_idat: Option<Vec<png::Chunk>>,
+}
+
+impl CheckChunkOrder {
+	fn new(mode: OrderMode) -> CheckChunkOrder {
+		CheckChunkOrder {
+			mode,
+			checker: png::order::OrderChecker::new(),
+			after_idat: None,
+		}
+	}
+
+	/// Returns the chunks that can be passed on now that `chunk` has been received
+	fn release(&mut self, chunk: png::Chunk) -> Vec<png::Chunk> {
+		if chunk.typ == png::typed::Idat::TYP {
+			let mut retval = self.after_idat.replace(Vec::new()).unwrap_or_default();
+			retval.push(chunk);
+			retval
+		} else if chunk.typ == png::typed::Iend::TYP {
+			let mut retval = self.after_idat.take().unwrap_or_default();
+			retval.push(chunk);
+			retval
+		} else if let Some(held) = &mut self.after_idat {
+			held.push(chunk);
+			Vec::new()
+		} else {
+			vec![chunk]
+		}
+	}
+
+	/// Creates a transform that fails with an [`Error::ChunkOrder`] at the first problem
+	pub fn reject() -> CheckChunkOrder {
+		CheckChunkOrder::new(OrderMode::Reject)
+	}
+
+	/// Creates a transform that calls `f` with each problem, and passes through chunks unchanged
+	pub fn warn<F: FnMut(&png::order::Diagnostic) + 'static>(f: F) -> CheckChunkOrder {
+		CheckChunkOrder::new(OrderMode::Warn(Box::new(f)))
+	}
+
+	/// Creates a transform that rearranges the chunks to follow the rules, and fails with an
+	/// [`Error::ChunkOrder`] if that is not possible
+	///
+	/// Every chunk is held until `finish` is called.
+	pub fn fix() -> CheckChunkOrder {
+		CheckChunkOrder::new(OrderMode::Fix(Vec::new()))
+	}
+}
+
+impl ChunkTransform for CheckChunkOrder {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		let diagnostic = self.checker.push(chunk.typ);
+		match &mut self.mode {
+			OrderMode::Reject => match diagnostic {
+				Some(diagnostic) => Err(Error::ChunkOrder(diagnostic)),
+				None => Ok(self.release(chunk)),
+			},
+			OrderMode::Warn(f) => {
+				if let Some(diagnostic) = &diagnostic {
+					f(diagnostic);
+				}
+				Ok(self.release(chunk))
+			},
+			OrderMode::Fix(held) => {
+				held.push(chunk);
+				Ok(Vec::new())
+			},
+		}
+	}
+
+	fn finish(&mut self) -> Result<Vec<png::Chunk>, Error> {
+		let diagnostics = self.checker.finish();
+		let held = self.after_idat.take().unwrap_or_default();
+		match &mut self.mode {
+			OrderMode::Reject => match diagnostics.first() {
+				Some(diagnostic) => Err(Error::ChunkOrder(*diagnostic)),
+				None => Ok(held),
+			},
+			OrderMode::Warn(f) => {
+				for diagnostic in &diagnostics {
+					f(diagnostic);
+				}
+				Ok(held)
+			},
+			OrderMode::Fix(held) => {
+				let held = std::mem::take(held);
+				if png::order::check(&held).is_empty() {
+					return Ok(held);
+				}
+				let fixed = png::order::fix(held);
+				match png::order::check(&fixed).into_iter().next() {
+					Some(diagnostic) => Err(Error::ChunkOrder(diagnostic)),
+					None => Ok(fixed),
+				}
+			},
+		}
+	}
+}
+
+/// Limits on the size of inflated data, which protect against zlib streams that inflate to an
+/// unreasonable amount of data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InflateLimits {
+	/// The maximum size of the data inflated from a single zlib stream
+	pub max_stream_size: usize,
+	/// The maximum total size of the data inflated from all the zlib streams passed to the
+	/// transforms that share an [`InflateBudget`]
+	pub max_total_size: usize,
+	/// Whether to inflate zlib streams that only follow the window size rules leniently, as
+	/// described by [`zlib::InflateOptions::lenient_window`]
+	pub lenient_window: bool,
+}
+
+impl Default for InflateLimits {
+	fn default() -> InflateLimits {
+		InflateLimits {
+			max_stream_size: 1 << 30,
+			max_total_size: 1 << 31,
+			lenient_window: false,
+		}
+	}
+}
+
+/// Counts the data inflated by transforms against a set of [`InflateLimits`]
+///
+/// Clones share the total size of the data inflated so far, so that each transform of a pipeline
+/// can be given a clone to have every transform count against the same `max_total_size`.
+#[derive(Debug, Default, Clone)]
+pub struct InflateBudget {
+	limits: InflateLimits,
+	/// The total size of the data inflated so far
+	used: Rc<Cell<usize>>,
+}
+
+impl InflateBudget {
+	/// Creates a budget that has not yet counted any inflated data
+	pub fn new(limits: InflateLimits) -> InflateBudget {
+		InflateBudget {
+			limits,
+			used: Rc::new(Cell::new(0)),
+		}
+	}
+
+	/// Calls `f` with the largest size that the next stream may inflate to, and counts the size of
+	/// the inflated data that `f` returns
+	fn inflate_with<T, F>(&mut self, f: F) -> Result<(Vec<u8>, T), Error>
+	where
+		F: FnOnce(usize) -> Result<(Vec<u8>, T), zlib::InflateError>,
+	{
+		let remaining = self.limits.max_total_size.saturating_sub(self.used.get());
+		match f(self.limits.max_stream_size.min(remaining)) {
+			Err(zlib::InflateError::OutputLimitExceeded)
+				if remaining < self.limits.max_stream_size =>
+			{
+				Err(Error::TotalInflatedSizeExceeded)
+			},
+			Err(err) => Err(err.into()),
+			Ok(result) => {
+				self.used.set(self.used.get() + result.0.len());
+				Ok(result)
+			},
+		}
+	}
+
+	/// Inflates a zlib stream, counting the inflated data against the limits
+	fn inflate(&mut self, stream: &[u8]) -> Result<Vec<u8>, Error> {
+		let lenient_window = self.limits.lenient_window;
+		self.inflate_with(|limit| {
+			let options = zlib::InflateOptions {
+				max_size: limit,
+				lenient_window,
+				..zlib::InflateOptions::default()
+			};
+			Ok((zlib::inflate_with_options(stream, &options)?, ()))
+		})
+		.map(|(data, ())| data)
+	}
+}
+
+impl From<InflateLimits> for InflateBudget {
+	fn from(limits: InflateLimits) -> InflateBudget {
+		InflateBudget::new(limits)
+	}
+}
+
+/// A transform that replaces the compressed data in a chunk with an equivalent zlib stream that
+/// uses only non-compressed blocks
+///
+/// Chunks that do not contain compressed data are passed through unchanged. IDAT chunks are
+/// inflated individually, so sequential IDATs should be merged using [`ConcatinateIdats`] first.
+/// fdAT chunks are only inflated if `process_apng` is true.
+#[derive(Debug, Default, Clone)]
+pub struct InflateChunks {
+	/// Whether to inflate fdAT chunks
+	pub process_apng: bool,
+	budget: InflateBudget,
+}
+
+impl InflateChunks {
+	/// `process_apng` determines whether fdAT chunks are inflated
+	pub fn new(process_apng: bool) -> InflateChunks {
+		InflateChunks {
+			process_apng,
+			budget: InflateBudget::default(),
+		}
+	}
+
+	/// Replaces the limits on the size of the inflated data, which may be a budget shared with
+	/// other transforms
+	pub fn with_limits(mut self, limits: impl Into<InflateBudget>) -> InflateChunks {
+		self.budget = limits.into();
+		self
+	}
+
+	fn apply(&mut self, indata: png::Chunk) -> Result<png::Chunk, Error> {
+		let budget = &mut self.budget;
+		map_zlib_stream(indata, self.process_apng, |stream| {
+			Ok(zlib::deflate_immediate(&budget.inflate(stream)?))
+		})
+	}
+}
+
+impl ChunkTransform for InflateChunks {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		self.apply(chunk).map(|x| vec![x])
+	}
+}
+
+/// A transform that reverses the scanline filter of every row of the image data, using
+/// [`png::filter::normalize`], but otherwise passes through chunks
+///
+/// The image data is stored in an equivalent zlib stream that uses only non-compressed blocks.
+/// The IDATs should be merged using [`ConcatinateIdats`] first. The frames of an APNG's fdAT
+/// chunks are not changed.
+#[derive(Debug, Default, Clone)]
+pub struct NormalizeFilters {
+	ihdr: Option<png::typed::Ihdr>,
+	budget: InflateBudget,
+}
+
+impl NormalizeFilters {
+	/// Creates a transform that has not yet seen an IHDR
+	pub fn new() -> NormalizeFilters {
+		NormalizeFilters::default()
+	}
+
+	/// Replaces the limits on the size of the inflated data, which may be a budget shared with
+	/// other transforms
+	pub fn with_limits(mut self, limits: impl Into<InflateBudget>) -> NormalizeFilters {
+		self.budget = limits.into();
+		self
+	}
+}
+
+impl ChunkTransform for NormalizeFilters {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		if chunk.typ == png::typed::Ihdr::TYP {
+			self.ihdr = Some(png::typed::Ihdr::parse(&chunk.data)?);
+		}
+		match &self.ihdr {
+			Some(ihdr) if chunk.typ == png::typed::Idat::TYP => {
+				let mut data = self.budget.inflate(&chunk.data)?;
+				png::filter::normalize(ihdr, &mut data)?;
+				Ok(vec![png::Chunk {
+					typ: chunk.typ,
+					data: zlib::deflate_immediate(&data),
+				}])
+			},
+			_ => Ok(vec![chunk]),
+		}
+	}
+}
+
+/// A transform that rearranges the image data to use a different interlace method, using
+/// [`png::interlace::reinterlace`], and changes the IHDR to match, but otherwise passes through
+/// chunks
+///
+/// The image data is stored in an equivalent zlib stream that uses only non-compressed blocks,
+/// with every scanline unfiltered. The IDATs should be merged using [`ConcatinateIdats`] first. An
+/// image that already uses the interlace method is passed through unchanged; otherwise, an acTL
+/// results in an [`Error::CannotReinterlaceAnimation`].
+#[derive(Debug, Clone)]
+pub struct SetInterlaceMethod {
+	interlace_method: u8,
+	/// The IHDR as it was received
+	ihdr: Option<png::typed::Ihdr>,
+	budget: InflateBudget,
+}
+
+impl SetInterlaceMethod {
+	/// `interlace_method` must be 0, for no interlacing, or 1, for Adam7 interlacing
+	pub fn new(interlace_method: u8) -> SetInterlaceMethod {
+		assert!(interlace_method <= 1, "interlace method must be 0 or 1");
+		SetInterlaceMethod {
+			interlace_method,
+			ihdr: None,
+			budget: InflateBudget::default(),
+		}
+	}
+
+	/// Replaces the limits on the size of the inflated data, which may be a budget shared with
+	/// other transforms
+	pub fn with_limits(mut self, limits: impl Into<InflateBudget>) -> SetInterlaceMethod {
+		self.budget = limits.into();
+		self
+	}
+}
+
+impl ChunkTransform for SetInterlaceMethod {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		if chunk.typ == png::typed::Ihdr::TYP {
+			let ihdr = png::typed::Ihdr::parse(&chunk.data)?;
+			let changed = png::typed::Ihdr {
+				interlace_method: self.interlace_method,
+				..ihdr.clone()
+			};
+			self.ihdr = Some(ihdr);
+			return Ok(vec![changed.to_chunk()]);
+		}
+		match &self.ihdr {
+			Some(ihdr) if ihdr.interlace_method != self.interlace_method => {
+				if chunk.typ == png::typed::Actl::TYP {
+					Err(Error::CannotReinterlaceAnimation)
+				} else if chunk.typ == png::typed::Idat::TYP {
+					let data = self.budget.inflate(&chunk.data)?;
+					let data = png::interlace::reinterlace(ihdr, &data, self.interlace_method)?;
+					Ok(vec![png::Chunk {
+						typ: chunk.typ,
+						data: zlib::deflate_immediate(&data),
+					}])
+				} else {
+					Ok(vec![chunk])
+				}
+			},
+			_ => Ok(vec![chunk]),
+		}
+	}
+}
+
+/// Known chunk types that are not safe-to-copy, but whose data does not depend on the image's
+/// color type or bit depth
+const COLOR_INDEPENDENT_UNSAFE: [[u8; 4]; 8] = [
+	*b"cHRM", *b"gAMA", *b"iCCP", *b"sRGB", *b"cICP", *b"mDCV", *b"cLLI", *b"sTER",
+];
+
+/// Whether the pixels of an image with the given chunks can be stored differently without losing
+/// information, provided that the chunks of the types in `changed` are changed to match
+fn pixels_are_changeable(chunks: &[png::Chunk], changed: &[[u8; 4]]) -> bool {
+	chunks.iter().all(|chunk| {
+		chunk.typ != png::typed::Actl::TYP
+			&& (chunk.safe_to_copy()
+				|| COLOR_INDEPENDENT_UNSAFE.contains(&chunk.typ)
+				|| changed.contains(&chunk.typ))
+	})
+}
+
+/// A transform that stores the image using the representation that a
+/// [`png::canonical::CanonicalForm`] picks, using [`png::canonical::canonicalize`], and changes
+/// the IHDR, PLTE, tRNS, sBIT and bKGD chunks to match, but otherwise passes through chunks
+///
+/// The chunks from the IHDR to the image data are held until the image data is received. The
+/// image data is stored in an equivalent zlib stream that uses only non-compressed blocks, with
+/// every scanline unfiltered. The IDATs should be merged using [`ConcatinateIdats`] first.
+///
+/// Images that cannot be changed without losing information are passed through unchanged: APNGs,
+/// images with a hIST, images with an unknown chunk that is not safe-to-copy, and images that no
+/// representation allowed by the form can store exactly.
+#[derive(Debug, Default, Clone)]
+pub struct Canonicalize {
+	form: png::canonical::CanonicalForm,
+	/// The chunks that have been received but not yet passed on
+	held: Vec<png::Chunk>,
+	/// Whether the image data has been received, after which chunks are passed through
+	done: bool,
+	budget: InflateBudget,
+}
+
+impl Canonicalize {
+	/// Creates a transform that stores images using the representation that `form` picks
+	pub fn new(form: png::canonical::CanonicalForm) -> Canonicalize {
+		Canonicalize {
+			form,
+			..Canonicalize::default()
+		}
+	}
+
+	/// Replaces the limits on the size of the inflated data, which may be a budget shared with
+	/// other transforms
+	pub fn with_limits(mut self, limits: impl Into<InflateBudget>) -> Canonicalize {
+		self.budget = limits.into();
+		self
+	}
+
+	/// Returns the held chunks followed by the IDAT, changed to use the canonical representation
+	/// if possible
+	fn apply(&mut self, idat: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		let mut held = std::mem::take(&mut self.held);
+		let find = |typ: [u8; 4]| held.iter().find(|chunk| chunk.typ == typ);
+		let changeable =
+			pixels_are_changeable(&held, &[*b"IHDR", *b"PLTE", *b"tRNS", *b"sBIT", *b"bKGD"]);
+		let ihdr = match find(png::typed::Ihdr::TYP) {
+			Some(ihdr) if changeable => png::typed::Ihdr::parse(&ihdr.data)?,
+			_ => {
+				held.push(idat);
+				return Ok(held);
+			},
+		};
+		let source = png::canonical::ColorChunks {
+			ihdr,
+			plte: find(png::typed::Plte::TYP)
+				.map(|x| png::typed::Plte::parse(&x.data))
+				.transpose()?,
+			trns: find(png::typed::Trns::TYP).map(|x| x.data.clone()),
+			sbit: find(png::typed::Sbit::TYP).map(|x| x.data.clone()),
+			bkgd: find(png::typed::Bkgd::TYP).map(|x| x.data.clone()),
+			has_iccp: find(png::typed::Iccp::TYP).is_some(),
+		};
+
+		let data = self.budget.inflate(&idat.data)?;
+		let (target, data) = match png::canonical::canonicalize(&source, &data, self.form)? {
+			Some(x) => x,
+			None => {
+				held.push(idat);
+				return Ok(held);
+			},
+		};
+
+		let mut plte = target.plte.map(|x| x.to_chunk());
+		let mut trns = target.trns.map(|data| png::Chunk {
+			typ: *b"tRNS",
+			data,
+		});
+		let sbit = target.sbit.map(|data| png::Chunk {
+			typ: *b"sBIT",
+			data,
+		});
+		let bkgd = target.bkgd.map(|data| png::Chunk {
+			typ: *b"bKGD",
+			data,
+		});
+		let mut retval: Vec<png::Chunk> = Vec::with_capacity(held.len() + 3);
+		for chunk in held {
+			let replacement = match &chunk.typ {
+				b"IHDR" => Some(target.ihdr.to_chunk()),
+				b"PLTE" => plte.take(),
+				b"tRNS" => trns.take(),
+				b"sBIT" => sbit.clone(),
+				b"bKGD" => bkgd.clone(),
+				_ => Some(chunk),
+			};
+			retval.extend(replacement);
+		}
+		if let Some(plte) = plte {
+			// a new PLTE follows the chunks that must precede it, and precedes every other chunk
+			retval.push(plte);
+			retval.sort_by_key(|chunk| match &chunk.typ {
+				b"IHDR" => 0,
+				typ if png::order::BEFORE_PLTE.contains(typ) => 1,
+				b"PLTE" => 2,
+				_ => 3,
+			});
+		}
+		retval.extend(trns);
+		retval.push(png::Chunk {
+			typ: idat.typ,
+			data: zlib::deflate_immediate(&data),
+		});
+		Ok(retval)
+	}
+}
+
+impl ChunkTransform for Canonicalize {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		if self.done {
+			Ok(vec![chunk])
+		} else if chunk.typ == png::typed::Idat::TYP {
+			self.done = true;
+			self.apply(chunk)
+		} else if chunk.typ == png::typed::Iend::TYP {
+			self.done = true;
+			self.held.push(chunk);
+			Ok(std::mem::take(&mut self.held))
+		} else {
+			self.held.push(chunk);
+			Ok(Vec::new())
+		}
+	}
+
+	fn finish(&mut self) -> Result<Vec<png::Chunk>, Error> {
+		Ok(std::mem::take(&mut self.held))
+	}
+}
+
+/// A transform that sorts a palette image's palette, using [`png::palette::normalize`], and changes
+/// the image data and the tRNS, hIST and bKGD chunks to match, but otherwise passes through chunks
+///
+/// The chunks from the IHDR to the image data are held until the image data is received. The
+/// image data is stored in an equivalent zlib stream that uses only non-compressed blocks, with
+/// every scanline unfiltered. The IDATs should be merged using [`ConcatinateIdats`] first.
+///
+/// Images that are not palette images are passed through unchanged, as are images whose palette
+/// cannot be changed without losing information: APNGs, images with an unknown chunk that is not
+/// safe-to-copy, and images whose chunks refer to entries that the palette does not have.
+#[derive(Debug, Default, Clone)]
+pub struct NormalizePalette {
+	/// The chunks that have been received but not yet passed on
+	held: Vec<png::Chunk>,
+	/// Whether the image data has been received, after which chunks are passed through
+	done: bool,
+	budget: InflateBudget,
+}
+
+impl NormalizePalette {
+	/// Creates a transform that has not yet seen an image
+	pub fn new() -> NormalizePalette {
+		NormalizePalette::default()
+	}
+
+	/// Replaces the limits on the size of the inflated data, which may be a budget shared with
+	/// other transforms
+	pub fn with_limits(mut self, limits: impl Into<InflateBudget>) -> NormalizePalette {
+		self.budget = limits.into();
+		self
+	}
+
+	/// Returns the held chunks followed by the IDAT, changed to use a sorted palette if possible
+	fn apply(&mut self, idat: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		let mut held = std::mem::take(&mut self.held);
+		let find = |typ: [u8; 4]| held.iter().find(|chunk| chunk.typ == typ);
+		let changeable = pixels_are_changeable(
+			&held,
+			&[*b"IHDR", *b"PLTE", *b"tRNS", *b"sBIT", *b"bKGD", *b"hIST"],
+		);
+		let source = match (find(png::typed::Ihdr::TYP), find(png::typed::Plte::TYP)) {
+			(Some(ihdr), Some(plte)) if changeable => png::palette::PaletteChunks {
+				ihdr: png::typed::Ihdr::parse(&ihdr.data)?,
+				plte: png::typed::Plte::parse(&plte.data)?,
+				trns: find(png::typed::Trns::TYP).map(|x| x.data.clone()),
+				hist: find(png::typed::Hist::TYP)
+					.map(|x| png::typed::Hist::parse(&x.data))
+					.transpose()?,
+				bkgd: find(png::typed::Bkgd::TYP).map(|x| x.data.clone()),
+			},
+			_ => {
+				held.push(idat);
+				return Ok(held);
+			},
+		};
+		if source.ihdr.color_type != 3 {
+			held.push(idat);
+			return Ok(held);
+		}
+
+		let data = self.budget.inflate(&idat.data)?;
+		let (target, data) = match png::palette::normalize(&source, &data)? {
+			Some(x) => x,
+			None => {
+				held.push(idat);
+				return Ok(held);
+			},
+		};
+
+		let mut trns = target.trns.map(|data| png::Chunk {
+			typ: *b"tRNS",
+			data,
+		});
+		let mut retval: Vec<png::Chunk> = Vec::with_capacity(held.len() + 1);
+		for chunk in held {
+			let replacement = match &chunk.typ {
+				b"PLTE" => Some(target.plte.to_chunk()),
+				b"tRNS" => trns.take(),
+				b"hIST" => target.hist.as_ref().map(|x| x.to_chunk()),
+				b"bKGD" => target.bkgd.clone().map(|data| png::Chunk {
+					typ: *b"bKGD",
+					data,
+				}),
+				_ => Some(chunk),
+			};
+			retval.extend(replacement);
+		}
+		retval.push(png::Chunk {
+			typ: idat.typ,
+			data: zlib::deflate_immediate(&data),
+		});
+		Ok(retval)
+	}
+}
+
+impl ChunkTransform for NormalizePalette {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		if self.done {
+			Ok(vec![chunk])
+		} else if chunk.typ == png::typed::Idat::TYP {
+			self.done = true;
+			self.apply(chunk)
+		} else if chunk.typ == png::typed::Iend::TYP {
+			self.done = true;
+			self.held.push(chunk);
+			Ok(std::mem::take(&mut self.held))
+		} else {
+			self.held.push(chunk);
+			Ok(Vec::new())
+		}
+	}
+
+	fn finish(&mut self) -> Result<Vec<png::Chunk>, Error> {
+		Ok(std::mem::take(&mut self.held))
+	}
+}
+
+/// A transform that replaces the compressed data in a chunk with an equivalent zlib stream that
+/// is compressed using [`zlib::deflate`]
+///
+/// This is the reverse of [`InflateChunks`]; the same chunks are affected, and the same caveats
+/// apply.
+#[derive(Debug, Default, Clone)]
+pub struct DeflateChunks {
+	/// Whether to deflate fdAT chunks
+	pub process_apng: bool,
+	/// How hard to try to compress the data
+	pub level: zlib::CompressionLevel,
+	budget: InflateBudget,
+}
+
+impl DeflateChunks {
+	/// `process_apng` determines whether fdAT chunks are deflated
+	pub fn new(process_apng: bool, level: zlib::CompressionLevel) -> DeflateChunks {
+		DeflateChunks {
+			process_apng,
+			level,
+			budget: InflateBudget::default(),
+		}
+	}
+
+	/// Replaces the limits on the size of the inflated data, which may be a budget shared with
+	/// other transforms
+	pub fn with_limits(mut self, limits: impl Into<InflateBudget>) -> DeflateChunks {
+		self.budget = limits.into();
+		self
+	}
+
+	fn apply(&mut self, indata: png::Chunk) -> Result<png::Chunk, Error> {
+		let budget = &mut self.budget;
+		let level = self.level;
+		map_zlib_stream(indata, self.process_apng, |stream| {
+			Ok(zlib::deflate(&budget.inflate(stream)?, level))
+		})
+	}
+}
+
+impl ChunkTransform for DeflateChunks {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		self.apply(chunk).map(|x| vec![x])
+	}
+}
+
+/// The type of the private chunk that holds the data needed to restore the original zlib stream
+/// of the chunk that follows it
+///
+/// The type is not safe-to-copy, since the data only applies to the exact image data that follows
+/// it; an editor that does not know this type drops it when changing the image data.
+pub const RECONSTRUCTION_TYP: [u8; 4] = *b"zlRC";
+
+/// Reconstruction data may be at most this many bytes plus a quarter of the compressed stream's
+/// size; otherwise, the stream is left compressed
+const RECONSTRUCTION_SLACK: usize = 16;
+
+/// A transform that inflates chunks like [`InflateChunks`], but that also records what is needed
+/// to restore the original compressed chunks using [`RestoreChunks`]
+///
+/// Each chunk that is changed is preceded by a [`RECONSTRUCTION_TYP`] chunk. Sequential IDATs are
+/// merged and then split into IDATs of at most `max_idat_length` bytes, and both the original
+/// split and the new split are recorded alongside the original zlib stream. If a zlib stream
+/// cannot be restored from a reasonably small amount of data, its chunks are passed through
+/// unchanged instead.
+///
+/// The default `max_idat_length` is [`png::MAX_CHUNK_LENGTH`].
+#[derive(Debug, Clone)]
+pub struct ReversibleInflateChunks {
+	process_apng: bool,
+	max_idat_length: usize,
+	/// The IDATs that have been received but not yet passed on
+	idats: Vec<png::Chunk>,
+	budget: InflateBudget,
+}
+
+impl ReversibleInflateChunks {
+	/// `process_apng` determines whether fdAT chunks are inflated
+	pub fn new(process_apng: bool) -> ReversibleInflateChunks {
+		ReversibleInflateChunks {
+			process_apng,
+			max_idat_length: png::MAX_CHUNK_LENGTH as usize,
+			idats: Vec::new(),
+			budget: InflateBudget::default(),
+		}
+	}
+
+	/// Replaces the maximum length of the IDATs that hold the inflated image data, which must be
+	/// at least one, and at most [`png::MAX_CHUNK_LENGTH`]
+	pub fn with_max_idat_length(mut self, max_length: usize) -> ReversibleInflateChunks {
+		assert!(
+			(1..=png::MAX_CHUNK_LENGTH as usize).contains(&max_length),
+			"IDAT length must be between 1 and {}",
+			png::MAX_CHUNK_LENGTH
+		);
+		self.max_idat_length = max_length;
+		self
+	}
+
+	/// Replaces the limits on the size of the inflated data, which may be a budget shared with
+	/// other transforms
+	pub fn with_limits(mut self, limits: impl Into<InflateBudget>) -> ReversibleInflateChunks {
+		self.budget = limits.into();
+		self
+	}
+
+	fn flush_idats(&mut self) -> Result<Vec<png::Chunk>, Error> {
+		if self.idats.is_empty() {
+			return Ok(Vec::new());
+		}
+		let idats = std::mem::take(&mut self.idats);
+		let stream: Vec<u8> = idats.iter().flat_map(|x| x.data.iter().cloned()).collect();
+		let chunk_lengths: Vec<usize> = idats.iter().map(|x| x.data.len()).collect();
+		match inflate_reversibly(
+			&mut self.budget,
+			&stream,
+			&chunk_lengths,
+			self.max_idat_length,
+		)? {
+			Some((pieces, record)) => Ok(record
+				.into_iter()
+				.chain(pieces.into_iter().map(|data| png::Chunk {
+					typ: *b"IDAT",
+					data,
+				}))
+				.collect()),
+			None => Ok(idats),
+		}
+	}
+}
+
+impl Default for ReversibleInflateChunks {
+	fn default() -> ReversibleInflateChunks {
+		ReversibleInflateChunks::new(false)
+	}
+}
+
+impl ChunkTransform for ReversibleInflateChunks {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		if chunk.typ == *b"IDAT" {
+			self.idats.push(chunk);
+			return Ok(Vec::new());
+		}
+		let mut retval = self.flush_idats()?;
+		let mut record = None;
+		let budget = &mut self.budget;
+		let chunk = map_zlib_stream(chunk, self.process_apng, |stream| {
+			Ok(match inflate_reversibly(budget, stream, &[], usize::MAX)? {
+				Some((pieces, new_record)) => {
+					record = new_record;
+					pieces.concat()
+				},
+				None => stream.to_vec(),
+			})
+		})?;
+		retval.extend(record);
+		retval.push(chunk);
+		Ok(retval)
+	}
+
+	fn finish(&mut self) -> Result<Vec<png::Chunk>, Error> {
+		self.flush_idats()
+	}
+}
+
+/// An inflated zlib stream split into pieces, and the reconstruction chunk needed to restore the
+/// original stream
+type ReversiblyInflated = (Vec<Vec<u8>>, Option<png::Chunk>);
+
+/// Inflates the zlib stream that was split into chunks of the given lengths, returning the new
+/// stream split into pieces of at most `max_length` bytes and the reconstruction chunk needed to
+/// restore the original, or None if the stream should be left as it is
+///
+/// The reconstruction chunk is None if the original is already the new stream, in which case the
+/// new stream is not split.
+fn inflate_reversibly(
+	budget: &mut InflateBudget,
+	stream: &[u8],
+	chunk_lengths: &[usize],
+	max_length: usize,
+) -> Result<Option<ReversiblyInflated>, Error> {
+	let (data, record) =
+		match budget.inflate_with(|limit| zlib::reconstruct::analyze(stream, limit)) {
+			// reconstruction data cannot describe a stream that only inflates leniently, so such a
+			// stream is left as it is, once it is known to inflate
+			Err(_) if budget.limits.lenient_window => {
+				budget.inflate(stream)?;
+				return Ok(None);
+			},
+			result => result?,
+		};
+	let inflated = zlib::deflate_immediate(&data);
+	if inflated == stream && chunk_lengths.len() <= 1 {
+		return Ok(Some((vec![inflated], None)));
+	}
+	match record {
+		Some(record) if record.len() <= RECONSTRUCTION_SLACK + stream.len() / 4 => {
+			let pieces: Vec<Vec<u8>> = inflated.chunks(max_length).map(<[u8]>::to_vec).collect();
+			let mut chunk_data: Vec<u8> = Vec::new();
+			chunk_data.extend((pieces.len() as u32).to_be_bytes());
+			chunk_data.extend((chunk_lengths.len() as u32).to_be_bytes());
+			for length in chunk_lengths {
+				chunk_data.extend((*length as u32).to_be_bytes());
+			}
+			chunk_data.extend(record);
+			let record = png::Chunk {
+				typ: RECONSTRUCTION_TYP,
+				data: chunk_data,
+			};
+			Ok(Some((pieces, Some(record))))
+		},
+		_ => Ok(None),
+	}
+}
+
+/// A transform that restores the chunks changed by [`ReversibleInflateChunks`]
+///
+/// Each [`RECONSTRUCTION_TYP`] chunk is removed, and the chunks following it that it applies to,
+/// which are either one chunk or several IDATs, are replaced with the original chunk or chunks.
+/// Other chunks are passed through unchanged.
+#[derive(Debug, Default, Clone)]
+pub struct RestoreChunks {
+	process_apng: bool,
+	/// A reconstruction chunk that applies to the next chunks
+	pending: Option<png::Chunk>,
+	/// The chunks after the pending reconstruction chunk that have been received so far
+	held: Vec<png::Chunk>,
+	budget: InflateBudget,
+}
+
+impl RestoreChunks {
+	/// `process_apng` determines whether fdAT chunks are restored
+	pub fn new(process_apng: bool) -> RestoreChunks {
+		RestoreChunks {
+			process_apng,
+			pending: None,
+			held: Vec::new(),
+			budget: InflateBudget::default(),
+		}
+	}
+
+	/// Replaces the limits on the size of the inflated data, which may be a budget shared with
+	/// other transforms
+	pub fn with_limits(mut self, limits: impl Into<InflateBudget>) -> RestoreChunks {
+		self.budget = limits.into();
+		self
+	}
+
+	fn restore(&mut self, record: &[u8], chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		let malformed = zlib::reconstruct::RebuildError::Malformed;
+		let count = record.get(0..4).ok_or(malformed)?;
+		let count = u32::from_be_bytes([count[0], count[1], count[2], count[3]]) as usize;
+		let lengths_end = count
+			.checked_mul(4)
+			.and_then(|x| x.checked_add(4))
+			.filter(|x| *x <= record.len())
+			.ok_or(malformed)?;
+		let chunk_lengths: Vec<usize> = record[4..lengths_end]
+			.chunks(4)
+			.map(|x| u32::from_be_bytes([x[0], x[1], x[2], x[3]]) as usize)
+			.collect();
+		let record = &record[lengths_end..];
+
+		if (chunk.typ == *b"IDAT") != (count != 0) {
+			return Err(malformed.into());
+		}
+		let mut applied = false;
+		let budget = &mut self.budget;
+		let chunk = map_zlib_stream(chunk, self.process_apng, |stream| {
+			applied = true;
+			let data = budget.inflate(stream)?;
+			Ok(zlib::reconstruct::rebuild(&data, record)?)
+		})?;
+		if !applied {
+			return Err(malformed.into());
+		}
+
+		if chunk.typ == *b"IDAT" {
+			if chunk_lengths.iter().sum::<usize>() != chunk.data.len() {
+				return Err(malformed.into());
+			}
+			let mut rest: &[u8] = &chunk.data;
+			Ok(chunk_lengths
+				.iter()
+				.map(|length| {
+					let (data, new_rest) = rest.split_at(*length);
+					rest = new_rest;
+					png::Chunk {
+						typ: *b"IDAT",
+						data: data.to_vec(),
+					}
+				})
+				.collect())
+		} else {
+			Ok(vec![chunk])
+		}
+	}
+}
+
+impl ChunkTransform for RestoreChunks {
+	fn push(&mut self, chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+		if chunk.typ == RECONSTRUCTION_TYP {
+			if self.pending.replace(chunk).is_some() {
+				return Err(zlib::reconstruct::RebuildError::Malformed.into());
+			}
+			return Ok(Vec::new());
+		}
+		let malformed = zlib::reconstruct::RebuildError::Malformed;
+		let count = match &self.pending {
+			None => return Ok(vec![chunk]),
+			Some(record) => record.data.get(0..4).ok_or(malformed)?,
+		};
+		let count = u32::from_be_bytes([count[0], count[1], count[2], count[3]]) as usize;
+		self.held.push(chunk);
+		if self.held.len() < count {
+			return Ok(Vec::new());
+		}
+		let record = self.pending.take().ok_or(malformed)?;
+		let mut held = std::mem::take(&mut self.held);
+		let chunk = if count == 1 {
+			held.remove(0)
+		} else if count > 1 && held.iter().all(|x| x.typ == *b"IDAT") {
+			png::Chunk {
+				typ: *b"IDAT",
+				data: held.into_iter().flat_map(|x| x.data).collect(),
+			}
+		} else {
+			return Err(malformed.into());
+		};
+		self.restore(&record.data[4..], chunk)
+	}
+
+	fn finish(&mut self) -> Result<Vec<png::Chunk>, Error> {
+		match self.pending {
+			Some(_) => Err(zlib::reconstruct::RebuildError::Malformed.into()),
+			None => Ok(Vec::new()),
+		}
+	}
+}
+
+/// Replaces the zlib stream in the chunk with the result of calling `f` on that stream
+///
+/// Chunks that do not contain a zlib stream are returned unchanged, without calling `f`.
+fn map_zlib_stream<F>(indata: png::Chunk, process_apng: bool, f: F) -> Result<png::Chunk, Error>
+where
+	F: FnOnce(&[u8]) -> Result<Vec<u8>, Error>,
+{
+	match indata.typ.as_ref() {
+		// Contains only compressed data
+		b"IDAT" => Ok(png::Chunk {
+			typ: *b"IDAT",
+			data: f(&indata.data)?,
+		}),
+		// Contains a cstring, followed by a method flag, followed by compressed data
+		b"zTXt" => {
+			let value = png::typed::Ztxt::parse(&indata.data)?;
+			if value.method == 0 {
+				Ok(png::typed::Ztxt {
+					data: f(&value.data)?,
+					..value
+				}
+				.to_chunk())
+			} else {
+				Err(Error::UnsupportedCompressionMethod)
+			}
+		},
+		b"iCCP" => {
+			let value = png::typed::Iccp::parse(&indata.data)?;
+			if value.method == 0 {
+				Ok(png::typed::Iccp {
+					data: f(&value.data)?,
+					..value
+				}
+				.to_chunk())
+			} else {
+				Err(Error::UnsupportedCompressionMethod)
+			}
+		},
+		// Contains a: cstring, byte flag, byte flag, cstring, cstring, compressed data
+		b"iTXt" => {
+			let compression_flag = indata
+				.data
+				.iter()
+				.position(|x| *x == 0)
+				.and_then(|keyword_length| indata.data.get(keyword_length + 1));
+			if compression_flag == Some(&0) {
+				// Not compressed, so make no changes; the rest of the chunk is not inspected
+				return Ok(indata);
+			}
+			let value = png::typed::Itxt::parse(&indata.data)?;
+			if value.compression_method == 0 {
+				Ok(png::typed::Itxt {
+					text: f(&value.text)?,
+					..value
+				}
+				.to_chunk())
+			} else {
+				Err(Error::UnsupportedCompressionMethod)
+			}
+		},
+		// (apng) Contains a u32 followed by compressed data
+		b"fdAT" if process_apng => {
+			let value = png::typed::Fdat::parse(&indata.data)?;
+			Ok(png::typed::Fdat {
+				data: f(&value.data)?,
+				..value
+			}
+			.to_chunk())
+		},
+		// Contain no compression, and are not affected by compression details of other chunks
+		_ => Ok(indata),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	mod pipeline {
+		use super::super::super::png;
+		use super::super::ChunkTransform;
+		use super::super::ConcatinateIdats;
+		use super::super::Pipeline;
+
+		#[rustfmt::skip]
+		#[test]
+		fn empty_pipeline_passes_through() {
+			let mut dut = Pipeline::new();
+			assert_eq!(vec![png::Chunk{typ : *b"IDAT", data: b"12345".to_vec()}], dut.push(png::Chunk{typ : *b"IDAT", data: b"12345".to_vec()}).unwrap());
+			assert!(dut.finish().unwrap().is_empty());
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn stages_are_applied_in_order() {
+			let mut dut = Pipeline::new()
+				.with(|mut x: png::Chunk| {x.data.push(b'1'); Ok(x)})
+				.with(|mut x: png::Chunk| {x.data.push(b'2'); Ok(x)});
+			assert_eq!(vec![png::Chunk{typ : *b"tEXt", data: b"12".to_vec()}], dut.push(png::Chunk{typ : *b"tEXt", data: vec![]}).unwrap());
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn finish_passes_held_chunks_through_later_stages() {
+			let mut dut = Pipeline::new()
+				.with(ConcatinateIdats::new())
+				.with(|mut x: png::Chunk| {x.data.push(b'!'); Ok(x)});
+			assert!(dut.push(png::Chunk{typ : *b"IDAT", data: b"12345".to_vec()}).unwrap().is_empty());
+			assert!(dut.push(png::Chunk{typ : *b"IDAT", data: b"6789A".to_vec()}).unwrap().is_empty());
+			assert_eq!(vec![png::Chunk{typ : *b"IDAT", data: b"123456789A!".to_vec()}], dut.finish().unwrap());
+		}
+	}
+
+	mod concatinate_idats {
+		use super::super::super::png;
+		use super::super::ChunkTransform;
+		use super::super::ConcatinateIdats;
+
+		#[rustfmt::skip]
+		#[test]
+		fn concatinates_conecutive_idats() {
+			let mut dut = ConcatinateIdats::new();
+			assert!(dut.push(png::Chunk{typ : *b"IDAT", data: b"12345".to_vec()}).unwrap().is_empty());
+			assert!(dut.push(png::Chunk{typ : *b"IDAT", data: b"6789A".to_vec()}).unwrap().is_empty());
+			assert_eq!(vec![png::Chunk{typ : *b"IDAT", data: b"123456789A".to_vec()}], dut.finish().unwrap());
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn does_not_merge_consecutive_nonidats() {
+			let mut dut = ConcatinateIdats::new();
+			assert_eq!(vec![png::Chunk{typ : *b"iTXt", data: b"12345".to_vec()}], dut.push(png::Chunk{typ : *b"iTXt", data: b"12345".to_vec()}).unwrap());
+			assert_eq!(vec![png::Chunk{typ : *b"iTXt", data: b"6789A".to_vec()}], dut.push(png::Chunk{typ : *b"iTXt", data: b"6789A".to_vec()}).unwrap());
+			assert!(dut.finish().unwrap().is_empty());
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn does_not_merge_disparate_chunks() {
+			let mut dut = ConcatinateIdats::new();
+			assert!(dut.push(png::Chunk{typ : *b"IDAT", data: b"12345".to_vec()}).unwrap().is_empty());
+			assert_eq!(
+				vec![
+					png::Chunk{typ : *b"IDAT", data: b"12345".to_vec()},
+					png::Chunk{typ : *b"iTXt", data: b"6789A".to_vec()},
+				],
+				dut.push(png::Chunk{typ : *b"iTXt", data: b"6789A".to_vec()}).unwrap()
+			);
+			assert!(dut.finish().unwrap().is_empty());
+		}
+	}
+
+	mod split_idats {
+		use super::super::super::png;
+		use super::super::ChunkTransform;
+		use super::super::SplitIdats;
+
+		#[rustfmt::skip]
+		#[test]
+		fn splits_long_idats() {
+			let mut dut = SplitIdats::new(4);
+			assert_eq!(
+				vec![
+					png::Chunk{typ : *b"IDAT", data: b"1234".to_vec()},
+					png::Chunk{typ : *b"IDAT", data: b"5678".to_vec()},
+					png::Chunk{typ : *b"IDAT", data: b"9".to_vec()},
+				],
+				dut.push(png::Chunk{typ : *b"IDAT", data: b"123456789".to_vec()}).unwrap()
+			);
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn passes_short_idats_and_other_chunks() {
+			let mut dut = SplitIdats::new(4);
+			assert_eq!(vec![png::Chunk{typ : *b"IDAT", data: b"1234".to_vec()}], dut.push(png::Chunk{typ : *b"IDAT", data: b"1234".to_vec()}).unwrap());
+			assert_eq!(vec![png::Chunk{typ : *b"IDAT", data: vec![]}], dut.push(png::Chunk{typ : *b"IDAT", data: vec![]}).unwrap());
+			assert_eq!(vec![png::Chunk{typ : *b"tEXt", data: b"123456789".to_vec()}], dut.push(png::Chunk{typ : *b"tEXt", data: b"123456789".to_vec()}).unwrap());
+		}
+	}
+
+	mod check_copy_safety {
+		use super::super::super::png;
+		use super::super::super::Error;
+		use super::super::CheckCopySafety;
+		use super::super::ChunkTransform;
+		use super::super::RECONSTRUCTION_TYP;
+
+		#[rustfmt::skip]
+		#[test]
+		fn rejects_unknown_unsafe_to_copy() {
+			let mut dut = CheckCopySafety::default();
+			match dut.push(png::Chunk{typ : *b"abCD", data: vec![]}) {
+				Err(Error::CannotCopySafely(typ)) => assert_eq!(*b"abCD", typ),
+				other => panic!("Not CannotCopySafely {:?}", other),
+			}
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn passes_unknown_unsafe_to_copy_if_ignoring() {
+			let mut dut = CheckCopySafety { ignore_unsafe_to_copy: true, process_apng: false, process_reconstruction: false };
+			assert_eq!(vec![png::Chunk{typ : *b"abCD", data: vec![]}], dut.push(png::Chunk{typ : *b"abCD", data: vec![]}).unwrap());
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn passes_apng_if_processing_apng() {
+			let mut dut = CheckCopySafety { ignore_unsafe_to_copy: false, process_apng: true, process_reconstruction: false };
+			assert_eq!(vec![png::Chunk{typ : *b"acTL", data: vec![]}], dut.push(png::Chunk{typ : *b"acTL", data: vec![]}).unwrap());
+		}
+
+		#[test]
+		fn reconstruction_is_not_safe_to_copy() {
+			assert!(!png::Chunk {
+				typ: RECONSTRUCTION_TYP,
+				data: vec![]
+			}
+			.safe_to_copy());
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn rejects_reconstruction_unless_processing_reconstruction() {
+			let mut dut = CheckCopySafety::default();
+			match dut.push(png::Chunk{typ : RECONSTRUCTION_TYP, data: vec![]}) {
+				Err(Error::CannotCopySafely(typ)) => assert_eq!(RECONSTRUCTION_TYP, typ),
+				other => panic!("Not CannotCopySafely {:?}", other),
+			}
+			let mut dut = CheckCopySafety { ignore_unsafe_to_copy: false, process_apng: false, process_reconstruction: true };
+			assert_eq!(vec![png::Chunk{typ : RECONSTRUCTION_TYP, data: vec![]}], dut.push(png::Chunk{typ : RECONSTRUCTION_TYP, data: vec![]}).unwrap());
+		}
+	}
+
+	mod validate_ihdr {
+		use super::super::super::png;
+		use super::super::super::png::typed::ChunkData;
+		use super::super::super::Error;
+		use super::super::ChunkTransform;
+		use super::super::ValidateIhdr;
+
+		fn push_strict(chunk: png::Chunk) -> Result<Vec<png::Chunk>, Error> {
+			ValidateIhdr { strict: true }.push(chunk)
+		}
+
+		/// An IHDR for a 1x1 8-bit greyscale image, with the given changes
+		fn ihdr(change: impl FnOnce(&mut png::typed::Ihdr)) -> png::Chunk {
+			let mut retval = png::typed::Ihdr {
+				width: 1,
+				height: 1,
+				bit_depth: 8,
+				color_type: 0,
+				compression_method: 0,
+				filter_method: 0,
+				interlace_method: 0,
+			};
+			change(&mut retval);
+			retval.to_chunk()
+		}
+
+		#[test]
+		fn valid_combinations_pass() {
+			let combinations: [(u8, &[u8]); 5] = [
+				(0, &[1, 2, 4, 8, 16]),
+				(2, &[8, 16]),
+				(3, &[1, 2, 4, 8]),
+				(4, &[8, 16]),
+				(6, &[8, 16]),
+			];
+			for (color_type, bit_depths) in combinations {
+				for bit_depth in bit_depths {
+					let dut = ihdr(|x| {
+						x.color_type = color_type;
+						x.bit_depth = *bit_depth;
+						x.interlace_method = 1;
+						x.width = 0x7FFF_FFFF;
+					});
+					assert_eq!(vec![dut.clone()], push_strict(dut).unwrap());
+				}
+			}
+		}
+
+		#[test]
+		fn zero_width() {
+			match push_strict(ihdr(|x| x.width = 0)) {
+				Err(Error::InvalidImageSize {
+					width: 0,
+					height: 1,
+				}) => {},
+				other => panic!("Not InvalidImageSize {:?}", other),
+			}
+		}
+
+		#[test]
+		fn too_large_height() {
+			match push_strict(ihdr(|x| x.height = 0x8000_0000)) {
+				Err(Error::InvalidImageSize {
+					width: 1,
+					height: 0x8000_0000,
+				}) => {},
+				other => panic!("Not InvalidImageSize {:?}", other),
+			}
+		}
+
+		#[test]
+		fn invalid_color_type() {
+			match push_strict(ihdr(|x| x.color_type = 1)) {
+				Err(Error::InvalidColorType(1)) => {},
+				other => panic!("Not InvalidColorType {:?}", other),
+			}
+		}
+
+		#[test]
+		fn invalid_bit_depth() {
+			match push_strict(ihdr(|x| {
+				x.color_type = 3;
+				x.bit_depth = 16;
+			})) {
+				Err(Error::InvalidBitDepth {
+					color_type: 3,
+					bit_depth: 16,
+				}) => {},
+				other => panic!("Not InvalidBitDepth {:?}", other),
+			}
+		}
+
+		#[test]
+		fn invalid_filter_method() {
+			match push_strict(ihdr(|x| x.filter_method = 1)) {
+				Err(Error::UnsupportedFilterMethod(1)) => {},
+				other => panic!("Not UnsupportedFilterMethod {:?}", other),
+			}
+		}
+
+		#[test]
+		fn invalid_interlace_method() {
+			match push_strict(ihdr(|x| x.interlace_method = 2)) {
+				Err(Error::UnsupportedInterlaceMethod(2)) => {},
+				other => panic!("Not UnsupportedInterlaceMethod {:?}", other),
+			}
+		}
+
+		#[test]
+		fn not_strict_passes_invalid_color_type() {
+			let dut = ihdr(|x| x.color_type = 1);
+			assert_eq!(
+				vec![dut.clone()],
+				ValidateIhdr::default().push(dut).unwrap()
+			);
+		}
+
+		#[test]
+		fn not_strict_rejects_compression_method() {
+			match ValidateIhdr::default().push(ihdr(|x| x.compression_method = 1)) {
+				Err(Error::UnsupportedCompressionMethod) => {},
+				other => panic!("Not UnsupportedCompressionMethod {:?}", other),
+			}
+		}
+	}
+
+	mod check_chunk_order {
+		use super::super::super::png;
+		use super::super::super::Error;
+		use super::super::CheckChunkOrder;
+		use super::super::ChunkTransform;
+		use std::cell::RefCell;
+		use std::rc::Rc;
+
+		fn chunk(typ: &[u8; 4]) -> png::Chunk {
+			png::Chunk {
+				typ: *typ,
+				data: Vec::new(),
+			}
+		}
+
+		fn push_all(
+			dut: &mut CheckChunkOrder,
+			types: &[&[u8; 4]],
+		) -> Result<Vec<png::Chunk>, Error> {
+			let mut retval = Vec::new();
+			for typ in types {
+				retval.extend(dut.push(chunk(typ))?);
+			}
+			retval.extend(dut.finish()?);
+			Ok(retval)
+		}
+
+		#[test]
+		fn reject_fails_at_first_problem() {
+			let mut dut = CheckChunkOrder::reject();
+			assert_eq!(vec![chunk(b"IHDR")], dut.push(chunk(b"IHDR")).unwrap());
+			assert_eq!(vec![chunk(b"IDAT")], dut.push(chunk(b"IDAT")).unwrap());
+			match dut.push(chunk(b"PLTE")) {
+				Err(Error::ChunkOrder(png::order::Diagnostic {
+					index: 2,
+					typ,
+					problem: png::order::Problem::MustPrecede(other),
+				})) => {
+					assert_eq!(*b"PLTE", typ);
+					assert_eq!(*b"IDAT", other);
+				},
+				other => panic!("Not ChunkOrder {:?}", other),
+			}
+		}
+
+		#[test]
+		fn reject_missing_iend() {
+			match push_all(&mut CheckChunkOrder::reject(), &[b"IHDR", b"IDAT"]) {
+				Err(Error::ChunkOrder(png::order::Diagnostic {
+					problem: png::order::Problem::Missing,
+					..
+				})) => {},
+				other => panic!("Not ChunkOrder {:?}", other),
+			}
+		}
+
+		#[test]
+		fn reject_non_consecutive_idat_before_passing_on_chunks_between() {
+			let mut dut = CheckChunkOrder::reject();
+			assert_eq!(vec![chunk(b"IHDR")], dut.push(chunk(b"IHDR")).unwrap());
+			assert_eq!(vec![chunk(b"IDAT")], dut.push(chunk(b"IDAT")).unwrap());
+			assert!(dut.push(chunk(b"tEXt")).unwrap().is_empty());
+			match dut.push(chunk(b"IDAT")) {
+				Err(Error::ChunkOrder(png::order::Diagnostic {
+					index: 3,
+					problem: png::order::Problem::NonConsecutiveIdat,
+					..
+				})) => {},
+				other => panic!("Not ChunkOrder {:?}", other),
+			}
+		}
+
+		#[test]
+		fn chunks_after_idat_are_held_until_iend() {
+			let mut dut = CheckChunkOrder::reject();
+			dut.push(chunk(b"IHDR")).unwrap();
+			dut.push(chunk(b"IDAT")).unwrap();
+			assert!(dut.push(chunk(b"tEXt")).unwrap().is_empty());
+			assert_eq!(
+				vec![chunk(b"tEXt"), chunk(b"IEND")],
+				dut.push(chunk(b"IEND")).unwrap()
+			);
+		}
+
+		#[test]
+		fn warn_passes_through() {
+			let warnings = Rc::new(RefCell::new(Vec::new()));
+			let warnings_copy = Rc::clone(&warnings);
+			let mut dut = CheckChunkOrder::warn(move |x| warnings_copy.borrow_mut().push(*x));
+			let types: [&[u8; 4]; 4] = [b"IHDR", b"IDAT", b"tRNS", b"IEND"];
+			let res = push_all(&mut dut, &types).unwrap();
+			assert_eq!(types.map(chunk).to_vec(), res);
+			assert_eq!(
+				vec![png::order::Diagnostic {
+					index: 2,
+					typ: *b"tRNS",
+					problem: png::order::Problem::MustPrecede(*b"IDAT"),
+				}],
+				*warnings.borrow()
+			);
+		}
+
+		#[test]
+		fn fix_reorders() {
+			let res = push_all(
+				&mut CheckChunkOrder::fix(),
+				&[b"IHDR", b"IDAT", b"tRNS", b"IEND"],
+			)
+			.unwrap();
+			assert_eq!(
+				[b"IHDR", b"tRNS", b"IDAT", b"IEND"].map(chunk).to_vec(),
+				res
+			);
+		}
+
+		#[test]
+		fn fix_fails_without_idat() {
+			match push_all(&mut CheckChunkOrder::fix(), &[b"IHDR", b"IEND"]) {
+				Err(Error::ChunkOrder(png::order::Diagnostic {
+					problem: png::order::Problem::Missing,
+					..
+				})) => {},
+				other => panic!("Not ChunkOrder {:?}", other),
+			}
+		}
+	}
+
+	mod inflate_chunks {
+		use super::super::super::png;
+		use super::super::super::zlib;
+		use super::super::super::Error;
+		use super::super::ChunkTransform;
+		use super::super::InflateBudget;
+		use super::super::InflateChunks;
+		use super::super::InflateLimits;
+		use super::super::ValidateIhdr;
+
+		#[test]
+		fn short_ihdr_is_error() {
+			#[rustfmt::skip]
+			let dut = png::Chunk{typ : *b"IHDR", data: vec![0, 0, 0, 1, 0, 0, 0, 1, 8]};
+			match ValidateIhdr::default().push(dut) {
+				Err(Error::MalformedChunk(_)) => {
+					// pass
+				},
+				other => panic!("Not MalformedChunk {:?}", other),
+			}
+		}
+
+		#[test]
+		fn long_ihdr_is_only_error_when_strict() {
+			#[rustfmt::skip]
+			let dut = png::Chunk{typ : *b"IHDR", data: vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0, 0]};
+			assert_eq!(
+				vec![dut.clone()],
+				ValidateIhdr::default().push(dut.clone()).unwrap()
+			);
+			match (ValidateIhdr { strict: true }).push(dut) {
+				Err(Error::MalformedChunk(_)) => {
+					// pass
+				},
+				other => panic!("Not MalformedChunk {:?}", other),
+			}
+		}
+
+		#[test]
+		fn ztxt_keeps_keyword() {
+			let text = b"lorem ipsum lorem ipsum";
+			#[rustfmt::skip]
+			let dut = png::Chunk{typ : *b"zTXt", data: [
+				b"Title\0\0".to_vec(),
+				vec![0x78, 0xda, 0xcb, 0xc9, 0x2f, 0x4a, 0xcd, 0x55, 0xc8, 0x2c, 0x28, 0x2e, 0xcd, 0x55, 0xc8, 0x41, 0xb0, 0x01, 0x6b, 0x6b, 0x08, 0xfb],
+			].concat()};
+			#[rustfmt::skip]
+			let exp = png::Chunk{typ : *b"zTXt", data: [
+				b"Title\0\0".to_vec(),
+				zlib::deflate_immediate(text),
+			].concat()};
+			assert_eq!(vec![exp], InflateChunks::default().push(dut).unwrap());
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn uncompressed_itxt_is_not_parsed() {
+			// missing the language tag's and translated keyword's separators
+			let dut = png::Chunk{typ : *b"iTXt", data: b"Title\0\0\0lorem ipsum".to_vec()};
+			assert_eq!(vec![dut.clone()], InflateChunks::default().push(dut).unwrap());
+		}
+
+		#[rustfmt::skip]
+		#[test]
+		fn fdat_is_not_inflated_unless_processing_apng() {
+			let dut = png::Chunk{typ : *b"fdAT", data: vec![0, 0, 0, 1, 2, 3]};
+			assert_eq!(vec![dut.clone()], InflateChunks::default().push(dut).unwrap());
+		}
+
+		fn idat_of_size(size: usize) -> png::Chunk {
+			png::Chunk {
+				typ: *b"IDAT",
+				data: zlib::deflate(&vec![0; size], zlib::CompressionLevel::Fast),
+			}
+		}
+
+		#[test]
+		fn stream_larger_than_limit_is_error() {
+			let mut dut = InflateChunks::default().with_limits(InflateLimits {
+				max_stream_size: 100,
+				max_total_size: 1000,
+				..InflateLimits::default()
+			});
+			assert!(dut.push(idat_of_size(100)).is_ok());
+			match dut.push(idat_of_size(101)) {
+				Err(Error::Zlib(zlib::InflateError::OutputLimitExceeded)) => {
+					// pass
+				},
+				other => panic!("Not OutputLimitExceeded {:?}", other),
+			}
+		}
+
+		#[test]
+		fn total_larger_than_limit_is_error() {
+			let mut dut = InflateChunks::default().with_limits(InflateLimits {
+				max_stream_size: 100,
+				max_total_size: 250,
+				..InflateLimits::default()
+			});
+			assert!(dut.push(idat_of_size(100)).is_ok());
+			assert!(dut.push(idat_of_size(100)).is_ok());
+			assert!(dut.push(idat_of_size(50)).is_ok());
+			match dut.push(idat_of_size(1)) {
+				Err(Error::TotalInflatedSizeExceeded) => {
+					// pass
+				},
+				other => panic!("Not TotalInflatedSizeExceeded {:?}", other),
+			}
+		}
+
+		#[test]
+		fn shared_budget_limits_total_of_all_transforms() {
+			let budget = InflateBudget::new(InflateLimits {
+				max_stream_size: 100,
+				max_total_size: 150,
+				..InflateLimits::default()
+			});
+			let mut first = InflateChunks::default().with_limits(budget.clone());
+			let mut second = InflateChunks::default().with_limits(budget);
+			assert!(first.push(idat_of_size(100)).is_ok());
+			match second.push(idat_of_size(100)) {
+				Err(Error::TotalInflatedSizeExceeded) => {
+					// pass
+				},
+				other => panic!("Not TotalInflatedSizeExceeded {:?}", other),
+			}
+		}
+	}
+
+	mod normalize_filters {
+		use super::super::super::png;
+		use super::super::super::png::typed::ChunkData;
+		use super::super::super::zlib;
+		use super::super::super::Error;
+		use super::super::ChunkTransform;
+		use super::super::NormalizeFilters;
+		use crate::test_util::idat;
+
+		fn ihdr() -> png::Chunk {
+			crate::test_util::ihdr(2, 2, 8, 0, 0).to_chunk()
+		}
+
+		#[test]
+		fn idat_is_unfiltered() {
+			let mut dut = NormalizeFilters::new();
+			assert_eq!(vec![ihdr()], dut.push(ihdr()).unwrap());
+			let res = dut.push(idat(&[1, 10, 5, 2, 1, 1])).unwrap();
+			assert_eq!(1, res.len());
+			assert_eq!(
+				vec![0, 10, 15, 0, 11, 16],
+				zlib::inflate(&res[0].data).unwrap()
+			);
+		}
+
+		#[test]
+		fn idat_before_ihdr_is_unchanged() {
+			let dut = idat(&[1, 10, 5, 2, 1, 1]);
+			assert_eq!(
+				vec![dut.clone()],
+				NormalizeFilters::new().push(dut).unwrap()
+			);
+		}
+
+		#[test]
+		fn other_chunks_are_unchanged() {
+			let mut dut = NormalizeFilters::new();
+			dut.push(ihdr()).unwrap();
+			let text = png::Chunk {
+				typ: *b"tEXt",
+				data: b"Title\0lorem".to_vec(),
+			};
+			assert_eq!(vec![text.clone()], dut.push(text).unwrap());
+		}
+
+		#[test]
+		fn unknown_filter_type_is_error() {
+			let mut dut = NormalizeFilters::new();
+			dut.push(ihdr()).unwrap();
+			match dut.push(idat(&[5, 10, 5, 2, 1, 1])) {
+				Err(Error::Filter(png::filter::FilterError::UnknownFilterType {
+					scanline: 0,
+					filter_type: 5,
+				})) => {
+					// pass
+				},
+				other => panic!("Not UnknownFilterType {:?}", other),
+			}
+		}
+	}
+
+	mod set_interlace_method {
+		use super::super::super::png;
+		use super::super::super::png::typed::ChunkData;
+		use super::super::super::zlib;
+		use super::super::super::Error;
+		use super::super::ChunkTransform;
+		use super::super::SetInterlaceMethod;
+		use crate::test_util::idat;
+
+		fn ihdr(interlace_method: u8) -> png::Chunk {
+			crate::test_util::ihdr(2, 2, 8, 0, interlace_method).to_chunk()
+		}
+
+		#[test]
+		fn deinterlaces() {
+			let mut dut = SetInterlaceMethod::new(0);
+			assert_eq!(vec![ihdr(0)], dut.push(ihdr(1)).unwrap());
+			let res = dut.push(idat(&[0, 1, 0, 2, 0, 3, 4])).unwrap();
+			assert_eq!(1, res.len());
+			assert_eq!(vec![0, 1, 2, 0, 3, 4], zlib::inflate(&res[0].data).unwrap());
+		}
+
+		#[test]
+		fn interlaces() {
+			let mut dut = SetInterlaceMethod::new(1);
+			assert_eq!(vec![ihdr(1)], dut.push(ihdr(0)).unwrap());
+			let res = dut.push(idat(&[0, 1, 2, 0, 3, 4])).unwrap();
+			assert_eq!(1, res.len());
+			assert_eq!(
+				vec![0, 1, 0, 2, 0, 3, 4],
+				zlib::inflate(&res[0].data).unwrap()
+			);
+		}
+
+		#[test]
+		fn same_interlace_method_is_unchanged() {
+			let mut dut = SetInterlaceMethod::new(0);
+			assert_eq!(vec![ihdr(0)], dut.push(ihdr(0)).unwrap());
+			let idat = idat(&[1, 1, 2, 0, 3, 4]);
+			assert_eq!(vec![idat.clone()], dut.push(idat).unwrap());
+		}
+
+		#[test]
+		fn animation_is_error() {
+			let mut dut = SetInterlaceMethod::new(0);
+			dut.push(ihdr(1)).unwrap();
+			let actl = png::typed::Actl {
+				num_frames: 1,
+				num_plays: 0,
+			}
+			.to_chunk();
+			match dut.push(actl) {
+				Err(Error::CannotReinterlaceAnimation) => {
+					// pass
+				},
+				other => panic!("Not CannotReinterlaceAnimation {:?}", other),
+			}
+		}
+	}
+
+	mod canonicalize {
+		use super::super::super::png;
+		use super::super::super::png::canonical::CanonicalForm;
+		use super::super::super::png::typed::ChunkData;
+		use super::super::super::zlib;
+		use super::super::Canonicalize;
+		use super::super::ChunkTransform;
+		use crate::test_util::chunk;
+		use crate::test_util::idat;
+		use crate::test_util::push_all;
+
+		fn ihdr(color_type: u8) -> png::Chunk {
+			crate::test_util::ihdr(2, 1, 8, color_type, 0).to_chunk()
+		}
+
+		#[test]
+		fn holds_chunks_until_image_data() {
+			let mut dut = Canonicalize::new(CanonicalForm::Smallest);
+			assert!(dut.push(ihdr(2)).unwrap().is_empty());
+			assert!(dut.push(chunk(b"gAMA", &[0, 0, 0, 1])).unwrap().is_empty());
+			let image = [0, 0, 0, 0, 255, 255, 255];
+			assert_eq!(3, dut.push(idat(&image)).unwrap().len());
+			let iend = chunk(b"IEND", &[]);
+			assert_eq!(vec![iend.clone()], dut.push(iend).unwrap());
+		}
+
+		#[test]
+		fn rgb_gray_becomes_grayscale() {
+			let mut dut = Canonicalize::new(CanonicalForm::Smallest);
+			let res = push_all(
+				&mut dut,
+				vec![
+					ihdr(2),
+					chunk(b"bKGD", &[0, 255, 0, 255, 0, 255]),
+					idat(&[0, 0, 0, 0, 255, 255, 255]),
+					chunk(b"IEND", &[]),
+				],
+			);
+			let typs: Vec<[u8; 4]> = res.iter().map(|x| x.typ).collect();
+			assert_eq!(vec![*b"IHDR", *b"bKGD", *b"IDAT", *b"IEND"], typs);
+			let ihdr = png::typed::Ihdr::parse(&res[0].data).unwrap();
+			assert_eq!((0, 1), (ihdr.color_type, ihdr.bit_depth));
+			assert_eq!(vec![0, 1], res[1].data);
+			assert_eq!(vec![0, 0b0100_0000], zlib::inflate(&res[2].data).unwrap());
+		}
+
+		#[test]
+		fn new_palette_follows_chunks_that_must_precede_it() {
+			let mut dut = Canonicalize::new(CanonicalForm::Smallest);
+			let res = push_all(
+				&mut dut,
+				vec![
+					ihdr(2),
+					chunk(b"bKGD", &[0, 1, 0, 2, 0, 3]),
+					chunk(b"cHRM", &[0; 32]),
+					idat(&[0, 1, 2, 3, 1, 2, 3]),
+				],
+			);
+			let typs: Vec<[u8; 4]> = res.iter().map(|x| x.typ).collect();
+			assert_eq!(vec![*b"IHDR", *b"cHRM", *b"PLTE", *b"bKGD", *b"IDAT"], typs);
+			assert_eq!(vec![1, 2, 3], res[2].data);
+			assert_eq!(vec![0], res[3].data);
+		}
+
+		#[test]
+		fn new_trns_precedes_image_data() {
+			let mut dut = Canonicalize::new(CanonicalForm::Smallest);
+			let res = push_all(
+				&mut dut,
+				vec![ihdr(6), idat(&[0, 1, 2, 3, 0, 1, 2, 3, 255])],
+			);
+			let typs: Vec<[u8; 4]> = res.iter().map(|x| x.typ).collect();
+			assert_eq!(vec![*b"IHDR", *b"PLTE", *b"tRNS", *b"IDAT"], typs);
+		}
+
+		#[test]
+		fn image_with_hist_is_unchanged() {
+			let mut dut = Canonicalize::new(CanonicalForm::Smallest);
+			let chunks = vec![
+				ihdr(3),
+				chunk(b"PLTE", &[1, 1, 1]),
+				chunk(b"hIST", &[0, 2]),
+				idat(&[0, 0, 0]),
+			];
+			assert_eq!(chunks.clone(), push_all(&mut dut, chunks));
+		}
+
+		#[test]
+		fn image_with_unknown_unsafe_chunk_is_unchanged() {
+			let mut dut = Canonicalize::new(CanonicalForm::Smallest);
+			let chunks = vec![ihdr(2), chunk(b"prIV", &[]), idat(&[0, 1, 1, 1, 7, 7, 7])];
+			assert_eq!(chunks.clone(), push_all(&mut dut, chunks));
+		}
+	}
+
+	mod normalize_palette {
+		use super::super::super::png;
+		use super::super::super::png::typed::ChunkData;
+		use super::super::super::zlib;
+		use super::super::ChunkTransform;
+		use super::super::NormalizePalette;
+		use crate::test_util::chunk;
+		use crate::test_util::idat;
+		use crate::test_util::push_all;
+
+		fn ihdr(color_type: u8) -> png::Chunk {
+			crate::test_util::ihdr(2, 1, 8, color_type, 0).to_chunk()
+		}
+
+		#[test]
+		fn holds_chunks_until_image_data() {
+			let mut dut = NormalizePalette::new();
+			assert!(dut.push(ihdr(3)).unwrap().is_empty());
+			assert!(dut.push(chunk(b"PLTE", &[1, 1, 1])).unwrap().is_empty());
+			assert_eq!(3, dut.push(idat(&[0, 0, 0])).unwrap().len());
+			let iend = chunk(b"IEND", &[]);
+			assert_eq!(vec![iend.clone()], dut.push(iend).unwrap());
+		}
+
+		#[test]
+		fn changes_palette_chunks() {
+			let mut dut = NormalizePalette::new();
+			let res = push_all(
+				&mut dut,
+				vec![
+					ihdr(3),
+					chunk(b"PLTE", &[9, 9, 9, 5, 5, 5, 1, 1, 1]),
+					chunk(b"tRNS", &[255, 255, 0]),
+					chunk(b"bKGD", &[0]),
+					chunk(b"hIST", &[0, 1, 0, 0, 0, 2]),
+					idat(&[0, 2, 0]),
+					chunk(b"IEND", &[]),
+				],
+			);
+			let typs: Vec<[u8; 4]> = res.iter().map(|x| x.typ).collect();
+			assert_eq!(
+				vec![*b"IHDR", *b"PLTE", *b"tRNS", *b"bKGD", *b"hIST", *b"IDAT", *b"IEND"],
+				typs
+			);
+			assert_eq!(vec![1, 1, 1, 9, 9, 9], res[1].data);
+			assert_eq!(vec![0], res[2].data);
+			assert_eq!(vec![1], res[3].data);
+			assert_eq!(vec![0, 2, 0, 1], res[4].data);
+			assert_eq!(vec![0, 0, 1], zlib::inflate(&res[5].data).unwrap());
+		}
+
+		#[test]
+		fn opaque_trns_is_removed() {
+			let mut dut = NormalizePalette::new();
+			let res = push_all(
+				&mut dut,
+				vec![
+					ihdr(3),
+					chunk(b"PLTE", &[9, 9, 9, 1, 1, 1]),
+					chunk(b"tRNS", &[255, 0]),
+					idat(&[0, 0, 0]),
+				],
+			);
+			let typs: Vec<[u8; 4]> = res.iter().map(|x| x.typ).collect();
+			assert_eq!(vec![*b"IHDR", *b"PLTE", *b"IDAT"], typs);
+		}
+
+		#[test]
+		fn truecolor_is_unchanged() {
+			let mut dut = NormalizePalette::new();
+			let chunks = vec![
+				ihdr(2),
+				chunk(b"PLTE", &[9, 9, 9, 1, 1, 1]),
+				idat(&[0, 1, 1, 1, 0, 0, 0]),
+			];
+			assert_eq!(chunks.clone(), push_all(&mut dut, chunks));
+		}
+
+		#[test]
+		fn image_with_unknown_unsafe_chunk_is_unchanged() {
+			let mut dut = NormalizePalette::new();
+			let chunks = vec![
+				ihdr(3),
+				chunk(b"PLTE", &[9, 9, 9, 1, 1, 1]),
+				chunk(b"prIV", &[]),
+				idat(&[0, 1, 0]),
+			];
+			assert_eq!(chunks.clone(), push_all(&mut dut, chunks));
+		}
+	}
+
+	mod deflate_chunks {
+		use super::super::super::png;
+		use super::super::super::zlib;
+		use super::super::ChunkTransform;
+		use super::super::DeflateChunks;
+		use super::super::InflateChunks;
+
+		#[test]
+		fn idat_is_compressed() {
+			let image = vec![0u8; 1000];
+			let dut = png::Chunk {
+				typ: *b"IDAT",
+				data: zlib::deflate_immediate(&image),
+			};
+			let res = DeflateChunks::default().push(dut.clone()).unwrap();
+			assert_eq!(1, res.len());
+			assert!(res[0].data.len() < 100, "{}", res[0].data.len());
+			assert_eq!(image, zlib::inflate(&res[0].data).unwrap());
+		}
+
+		#[test]
+		fn inflate_reverses_deflate() {
+			#[rustfmt::skip]
+			let dut = png::Chunk{typ : *b"iTXt", data: [
+				b"Title\0\x01\0en\0\0".to_vec(),
+				zlib::deflate_immediate(b"lorem ipsum lorem ipsum"),
+			].concat()};
+			let res = DeflateChunks::default().push(dut.clone()).unwrap();
+			assert_ne!(vec![dut.clone()], res);
+			let res = InflateChunks::default().push(res[0].clone()).unwrap();
+			assert_eq!(vec![dut], res);
+		}
+
+		#[test]
+		fn uncompressed_itxt_is_unchanged() {
+			#[rustfmt::skip]
+			let dut = png::Chunk{typ : *b"iTXt", data: b"Title\0\0\0en\0\0lorem ipsum lorem ipsum".to_vec()};
+			assert_eq!(
+				vec![dut.clone()],
+				DeflateChunks::default().push(dut).unwrap()
+			);
+		}
+	}
+}
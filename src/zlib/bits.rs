@@ -1,80 +1,349 @@
-//! Contains an iterator transformer that transforms a iterator\<u8\> iterator over the bits of that stream
+//! Contains readers over the bits of a byte stream, and the inverse, a writer that packs bits into bytes
 use super::u4;
-use super::u4ZeroToRangeIter;
 
-/// An iterator transformer that splits each u8 into its component bits,
-/// reading the bytes in LSB order
-pub struct Bits<I: Iterator<Item = u8>> {
+/// A source of bits that can be examined before they are consumed
+pub trait PeekBits {
+	/// Returns the bits that are available without reading more input, with the next bit in the
+	/// LSB, and the number of such bits. Bits past that number are zero.
+	fn peek(&self) -> (u32, u8);
+
+	/// Makes at least one more byte's worth of bits available to `peek`. Returns None if the input
+	/// has no more bits.
+	fn pull_byte(&mut self) -> Option<()>;
+
+	/// Discards `count` bits, which must not be more than the number of bits available
+	fn consume(&mut self, count: u8);
+}
+
+/// A source of the bytes that a [`Bits`] reads
+pub trait ByteSource {
+	/// Removes up to `max` bytes, which is at most eight, from the front of the source. Returns
+	/// those bytes packed into a u64 with the first byte in the LSB, and the number of bytes
+	/// removed, which is zero only if the source is exhausted.
+	fn take_bytes(&mut self, max: u8) -> (u64, u8);
+
+	/// Removes up to `count` bytes from the front of the source and appends them to `out`.
+	/// Returns the number of bytes appended, which is less than `count` only if the source is
+	/// exhausted.
+	fn take_slice(&mut self, out: &mut Vec<u8>, count: usize) -> usize;
+}
+
+impl ByteSource for &[u8] {
+	fn take_bytes(&mut self, max: u8) -> (u64, u8) {
+		let count = self.len().min(usize::from(max));
+		let mut bytes = [0u8; 8];
+		bytes[..count].copy_from_slice(&self[..count]);
+		*self = &self[count..];
+		(u64::from_le_bytes(bytes), count as u8)
+	}
+
+	fn take_slice(&mut self, out: &mut Vec<u8>, count: usize) -> usize {
+		let count = count.min(self.len());
+		out.extend_from_slice(&self[..count]);
+		*self = &self[count..];
+		count
+	}
+}
+
+/// Reads the bits of a byte stream, starting from the LSB of each byte
+///
+/// Up to eight bytes at a time are taken from the backing source, so the source's position after
+/// reading is unspecified; use `bit_position` to find the end of what has been read.
+pub struct Bits<S: ByteSource> {
 	/// the source of bytes
-	backing: I,
-	/// the current byte being read from
-	current_byte: u8,
-	/// the number of bits that have been read from the current byte
-	current_byte_read_bits: u8,
+	backing: S,
+	/// bits taken from `backing` that have not been read yet, with the next bit in the LSB
+	buffer: u64,
+	/// the number of bits in `buffer`
+	buffered: u8,
+	/// the number of bytes taken from `backing`
+	consumed: usize,
 }
 
-impl<I: Iterator<Item = u8>> Iterator for Bits<I> {
+impl<S: ByteSource> Iterator for Bits<S> {
 	type Item = bool;
 
 	fn next(&mut self) -> Option<bool> {
-		if self.current_byte_read_bits >= 8 {
-			self.current_byte_read_bits = 0;
-			match self.backing.next() {
-				Some(x) => self.current_byte = x,
-				None => return None,
-			}
+		if self.buffered == 0 {
+			self.pull_byte()?;
 		}
 
-		let retval = (self.current_byte & 0x01) != 0;
-		self.current_byte >>= 1;
-		self.current_byte_read_bits += 1;
+		let retval = (self.buffer & 0x01) != 0;
+		self.consume(1);
 		Some(retval)
 	}
 }
 
-impl<I: Iterator<Item = u8>> Bits<I> {
-	pub fn new(backing: I) -> Bits<I> {
+impl<S: ByteSource> PeekBits for Bits<S> {
+	fn peek(&self) -> (u32, u8) {
+		(self.buffer as u32, self.buffered.min(32))
+	}
+
+	fn pull_byte(&mut self) -> Option<()> {
+		if self.buffered > 56 {
+			// there is no room for another byte, but `peek` already has as many bits as it can return
+			return Some(());
+		}
+		if self.refill() {
+			Some(())
+		} else {
+			None
+		}
+	}
+
+	fn consume(&mut self, count: u8) {
+		debug_assert!(count <= self.buffered);
+		self.buffer = self.buffer.checked_shr(u32::from(count)).unwrap_or(0);
+		self.buffered -= count;
+	}
+}
+
+impl<S: ByteSource> Bits<S> {
+	pub fn new(backing: S) -> Bits<S> {
 		#[allow(clippy::redundant_field_names)]
 		Bits {
 			backing: backing,
-			current_byte: 0,
-			current_byte_read_bits: u8::MAX,
+			buffer: 0,
+			buffered: 0,
+			consumed: 0,
 		}
 	}
 
+	/// Takes as many whole bytes from the backing source as fit in the buffer. Returns false if
+	/// no bytes were taken.
+	fn refill(&mut self) -> bool {
+		let (bytes, count) = self.backing.take_bytes((64 - self.buffered) / 8);
+		if count == 0 {
+			return false;
+		}
+		self.buffer |= bytes << self.buffered;
+		self.buffered += count * 8;
+		self.consumed += usize::from(count);
+		true
+	}
+
 	/// reads n bits from this Iterator, packing the result into a single u16,
 	/// such that the first bit read becomes the MSB of the returned value
+	#[allow(dead_code)]
 	pub fn read_n(&mut self, bit_count: u4) -> Option<u16> {
-		let mut retval: u16 = 0;
-		for _ in u4ZeroToRangeIter::new(bit_count) {
-			retval <<= 1;
-			match self.next() {
-				Some(x) => retval += u16::from(x),
-				None => return None,
-			}
-		}
-		Some(retval)
+		let bit_count = u8::from(bit_count);
+		let value = self.read_bits(bit_count)? as u16;
+		Some(
+			value
+				.reverse_bits()
+				.checked_shr(16 - u32::from(bit_count))
+				.unwrap_or(0),
+		)
 	}
 
 	/// reads n bits from this Iterator, packing the result into a single u16
 	/// in the reverse order of `read_n`
 	pub fn read_n_rev(&mut self, bit_count: u4) -> Option<u16> {
-		let mut retval: u16 = 0;
-		for i in u4ZeroToRangeIter::new(bit_count) {
-			match self.next() {
-				Some(x) => retval += u16::from(x) * i.nth_bit(),
-				None => return None,
+		self.read_bits(u8::from(bit_count)).map(|x| x as u16)
+	}
+
+	/// reads `bit_count` bits, which must be at most 32, such that the first bit read becomes
+	/// the LSB of the returned value
+	pub fn read_bits(&mut self, bit_count: u8) -> Option<u32> {
+		debug_assert!(bit_count <= 32);
+		while self.buffered < bit_count {
+			if !self.refill() {
+				return None;
 			}
 		}
+		let retval = (self.buffer & ((1 << bit_count) - 1)) as u32;
+		self.consume(bit_count);
 		Some(retval)
 	}
 
 	/// Discards any bits remaining in the current byte
-	pub fn discard_til_byte_boundary(self) -> I {
+	pub fn discard_til_byte_boundary(&mut self) {
+		self.consume(self.buffered % 8);
+	}
+
+	/// Reads a whole byte. Must only be called at a byte boundary.
+	pub fn read_byte(&mut self) -> Option<u8> {
+		debug_assert!(self.buffered.is_multiple_of(8));
+		if self.buffered == 0 && !self.refill() {
+			return None;
+		}
+		let retval = (self.buffer & 0xFF) as u8;
+		self.consume(8);
+		Some(retval)
+	}
+
+	/// Reads `count` whole bytes, appending them to `out`. Must only be called at a byte boundary.
+	/// Returns None if the input ends first, after appending the bytes that were available.
+	pub fn read_bytes(&mut self, out: &mut Vec<u8>, count: usize) -> Option<()> {
+		debug_assert!(self.buffered.is_multiple_of(8));
+		let from_buffer = usize::from(self.buffered / 8).min(count);
+		for _ in 0..from_buffer {
+			out.push((self.buffer & 0xFF) as u8);
+			self.consume(8);
+		}
+		let remaining = count - from_buffer;
+		let taken = self.backing.take_slice(out, remaining);
+		self.consumed += taken;
+		if taken == remaining {
+			Some(())
+		} else {
+			None
+		}
+	}
+
+	/// The number of bits that have been read
+	pub fn bit_position(&self) -> usize {
+		self.consumed * 8 - usize::from(self.buffered)
+	}
+
+	/// The source of bytes
+	pub fn get_mut(&mut self) -> &mut S {
+		&mut self.backing
+	}
+}
+
+/// Packs bits into a byte vector, filling each byte starting from the LSB
+pub struct BitWriter {
+	/// the completely-written bytes
+	backing: Vec<u8>,
+	/// bits that have been written but not yet moved into `backing`
+	current: u64,
+	/// the number of bits in `current`
+	current_bits: u8,
+}
+
+impl BitWriter {
+	pub fn new() -> BitWriter {
+		BitWriter {
+			backing: Vec::new(),
+			current: 0,
+			current_bits: 0,
+		}
+	}
+
+	/// writes the `bit_count` low bits of `value`, starting with the LSB.
+	/// `bit_count` must be at most 32
+	pub fn write_n_rev(&mut self, value: u32, bit_count: u8) {
+		debug_assert!(bit_count <= 32);
+		let mask = (1u64 << bit_count) - 1;
+		self.current |= (u64::from(value) & mask) << self.current_bits;
+		self.current_bits += bit_count;
+		while self.current_bits >= 8 {
+			self.backing.push((self.current & 0xFF) as u8);
+			self.current >>= 8;
+			self.current_bits -= 8;
+		}
+	}
+
+	/// writes the `bit_count` low bits of `value`, starting with the MSB,
+	/// such that `Bits::read_n` would read the same value
+	pub fn write_n(&mut self, value: u16, bit_count: u8) {
+		let reversed = value.reverse_bits() >> (16 - u32::from(bit_count));
+		self.write_n_rev(u32::from(reversed), bit_count);
+	}
+
+	/// The number of bits that would have to be written to reach a byte boundary
+	pub fn bits_to_byte_boundary(&self) -> u8 {
+		(8 - self.current_bits) % 8
+	}
+
+	/// Pads the current byte with zeros
+	pub fn pad_to_byte_boundary(&mut self) {
+		if self.current_bits > 0 {
+			self.write_n_rev(0, 8 - self.current_bits);
+		}
+	}
+
+	/// Writes whole bytes. Must be called only when on a byte boundary
+	pub fn write_bytes(&mut self, bytes: &[u8]) {
+		debug_assert!(self.current_bits == 0);
+		self.backing.extend_from_slice(bytes);
+	}
+
+	/// Pads the current byte with zeros, then returns the written bytes
+	pub fn finish(mut self) -> Vec<u8> {
+		self.pad_to_byte_boundary();
 		self.backing
 	}
 }
 
+/// Reads bits from a byte slice, starting from the LSB of each byte, while keeping track of the
+/// position in the slice
+pub struct BitCursor<'a> {
+	backing: &'a [u8],
+	/// the number of bits that have been read
+	position: usize,
+}
+
+impl<'a> BitCursor<'a> {
+	pub fn new(backing: &'a [u8]) -> BitCursor<'a> {
+		BitCursor {
+			backing,
+			position: 0,
+		}
+	}
+
+	pub fn read_bit(&mut self) -> Option<bool> {
+		let byte = self.backing.get(self.position / 8)?;
+		let retval = (byte >> (self.position % 8)) & 1 != 0;
+		self.position += 1;
+		Some(retval)
+	}
+
+	/// reads n bits, such that the first bit read becomes the LSB of the returned value
+	pub fn read_n_rev(&mut self, bit_count: u8) -> Option<u32> {
+		let mut retval: u32 = 0;
+		for i in 0..bit_count {
+			retval |= u32::from(self.read_bit()?) << i;
+		}
+		Some(retval)
+	}
+
+	/// The number of bits that would have to be read to reach a byte boundary
+	pub fn bits_to_byte_boundary(&self) -> u8 {
+		((8 - self.position % 8) % 8) as u8
+	}
+
+	/// Reads `count` whole bytes. Must be called only when on a byte boundary
+	pub fn read_bytes(&mut self, count: usize) -> Option<&'a [u8]> {
+		debug_assert!(self.position.is_multiple_of(8));
+		let start = self.position / 8;
+		let retval = self.backing.get(start..(start + count))?;
+		self.position += count * 8;
+		Some(retval)
+	}
+
+	/// The bytes after the byte containing the current position
+	pub fn remaining_bytes(&self) -> &'a [u8] {
+		&self.backing[self.position.div_ceil(8).min(self.backing.len())..]
+	}
+}
+
+impl PeekBits for BitCursor<'_> {
+	fn peek(&self) -> (u32, u8) {
+		// 24 bits is enough to hold any huffman code, regardless of alignment
+		let start = self.position / 8;
+		let bytes = &self.backing[start.min(self.backing.len())..];
+		let bytes = &bytes[..bytes.len().min(4)];
+		let mut value: u32 = 0;
+		for (index, byte) in bytes.iter().enumerate() {
+			value |= u32::from(*byte) << (8 * index);
+		}
+		let skipped = (self.position % 8) as u8;
+		let count = (8 * bytes.len() as u8).saturating_sub(skipped).min(24);
+		((value >> skipped) & ((1 << count) - 1), count)
+	}
+
+	fn pull_byte(&mut self) -> Option<()> {
+		// `peek` already returns everything that a caller could need
+		None
+	}
+
+	fn consume(&mut self, count: u8) {
+		self.position += usize::from(count);
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	mod read_1 {
@@ -84,8 +353,7 @@ mod read_1 {
 		#[test]
 		fn one_byte() {
 			let dut: [u8; 1] = [0b11010110];
-			let dut = dut.iter().cloned();
-			let mut dut = Bits::new(dut);
+			let mut dut = Bits::new(&dut[..]);
 
 			assert!(false == dut.next().unwrap());
 			assert!(true == dut.next().unwrap());
@@ -105,8 +373,7 @@ mod read_n {
 		#[test]
 		fn one_byte() {
 			let dut: [u8; 1] = [0b11010110];
-			let dut = dut.iter().cloned();
-			let mut dut = Bits::new(dut);
+			let mut dut = Bits::new(&dut[..]);
 
 			assert!(0b0110 == dut.read_n(u4::_4).unwrap());
 			assert!(0b1011 == dut.read_n(u4::_4).unwrap());
@@ -116,12 +383,162 @@ fn one_byte() {
 		#[test]
 		fn one_byte_rev() {
 			let dut: [u8; 1] = [0b11010110];
-			let dut = dut.iter().cloned();
-			let mut dut = Bits::new(dut);
+			let mut dut = Bits::new(&dut[..]);
 
 			assert!(0b0110 == dut.read_n_rev(u4::_4).unwrap());
 			assert!(0b1101 == dut.read_n_rev(u4::_4).unwrap());
 			assert!(dut.next().is_none());
 		}
 	}
+	mod bit_position {
+		use super::super::super::u4;
+		use super::super::Bits;
+
+		#[test]
+		fn counts_bits_and_bytes() {
+			let dut: [u8; 4] = [0xAB, 0xCD, 0xEF, 0x01];
+			let mut dut = Bits::new(&dut[..]);
+
+			assert_eq!(0, dut.bit_position());
+			dut.read_n_rev(u4::_3).unwrap();
+			assert_eq!(3, dut.bit_position());
+			dut.read_n_rev(u4::_9).unwrap();
+			assert_eq!(12, dut.bit_position());
+			dut.discard_til_byte_boundary();
+			assert_eq!(16, dut.bit_position());
+			dut.read_byte().unwrap();
+			assert_eq!(24, dut.bit_position());
+		}
+	}
+	mod buffered {
+		use super::super::super::u4;
+		use super::super::Bits;
+
+		#[test]
+		fn read_bits_across_refills() {
+			let dut: Vec<u8> = (0..=255).collect();
+			let mut dut = Bits::new(&dut[..]);
+
+			// reading 3 bits at a time is never aligned with an eight-byte refill
+			let mut position: usize = 0;
+			while position + 3 <= 256 * 8 {
+				let expected = (0..3).fold(0, |acc, bit| {
+					let index = position + bit;
+					let value = (index / 8) >> (index % 8) & 1;
+					acc | (value << bit)
+				});
+				assert_eq!(
+					Some(expected as u16),
+					dut.read_n_rev(u4::_3),
+					"{}",
+					position
+				);
+				position += 3;
+			}
+			assert_eq!(position, dut.bit_position());
+		}
+
+		#[test]
+		fn read_32_bits() {
+			let dut: [u8; 9] = [0x01, 0x23, 0x45, 0x67, 0x89, 0xAB, 0xCD, 0xEF, 0xFF];
+			let mut dut = Bits::new(&dut[..]);
+
+			assert_eq!(Some(1), dut.read_bits(4));
+			assert_eq!(Some(0x9674_5230), dut.read_bits(32));
+			assert_eq!(Some(0xEFC_DAB8), dut.read_bits(28));
+			assert_eq!(Some(0xFF), dut.read_bits(8));
+			assert_eq!(None, dut.read_bits(1));
+		}
+
+		#[test]
+		fn read_bytes_from_buffer_then_source() {
+			let dut: Vec<u8> = (0..20).collect();
+			let mut dut = Bits::new(&dut[..]);
+
+			assert_eq!(Some(0), dut.read_n_rev(u4::_4));
+			dut.discard_til_byte_boundary();
+			let mut res = Vec::new();
+			dut.read_bytes(&mut res, 15).unwrap();
+			assert_eq!((1..16).collect::<Vec<u8>>(), res);
+			assert_eq!(128, dut.bit_position());
+			assert_eq!(Some(16), dut.read_byte());
+
+			let mut res = Vec::new();
+			assert_eq!(None, dut.read_bytes(&mut res, 4));
+			assert_eq!(vec![17, 18, 19], res);
+		}
+	}
+	mod bit_writer {
+		use super::super::super::u4;
+		use super::super::BitWriter;
+		use super::super::Bits;
+
+		#[test]
+		fn write_n_rev() {
+			let mut dut = BitWriter::new();
+			dut.write_n_rev(0b0110, 4);
+			dut.write_n_rev(0b1101, 4);
+			assert_eq!(vec![0b11010110], dut.finish());
+		}
+
+		#[test]
+		fn write_n() {
+			let mut dut = BitWriter::new();
+			dut.write_n(0b0110, 4);
+			dut.write_n(0b1011, 4);
+			assert_eq!(vec![0b11010110], dut.finish());
+		}
+
+		#[test]
+		fn finish_pads_with_zeros() {
+			let mut dut = BitWriter::new();
+			dut.write_n_rev(0b101, 3);
+			assert_eq!(vec![0b101], dut.finish());
+		}
+
+		#[test]
+		fn write_bytes_after_padding() {
+			let mut dut = BitWriter::new();
+			dut.write_n_rev(1, 1);
+			dut.pad_to_byte_boundary();
+			dut.write_bytes(&[0xAB, 0xCD]);
+			assert_eq!(vec![1, 0xAB, 0xCD], dut.finish());
+		}
+
+		#[test]
+		fn round_trip_through_bits() {
+			let mut dut = BitWriter::new();
+			dut.write_n(0x1FF, 9);
+			dut.write_n_rev(0x2A, 7);
+			dut.write_n(0x5, 3);
+			let res = dut.finish();
+			let mut res = Bits::new(&res[..]);
+			assert_eq!(0x1FF, res.read_n(u4::_9).unwrap());
+			assert_eq!(0x2A, res.read_n_rev(u4::_7).unwrap());
+			assert_eq!(0x5, res.read_n(u4::_3).unwrap());
+		}
+	}
+
+	mod bit_cursor {
+		use super::super::BitCursor;
+
+		#[test]
+		fn read_n_rev() {
+			let mut dut = BitCursor::new(&[0b11010110, 0xAB]);
+			assert_eq!(Some(0b0110), dut.read_n_rev(4));
+			assert_eq!(4, dut.bits_to_byte_boundary());
+			assert_eq!(Some(0b1101), dut.read_n_rev(4));
+			assert_eq!(0, dut.bits_to_byte_boundary());
+			assert_eq!(Some(&[0xAB][..]), dut.read_bytes(1));
+			assert_eq!(None, dut.read_bit());
+		}
+
+		#[test]
+		fn remaining_bytes() {
+			let mut dut = BitCursor::new(&[1, 2, 3]);
+			assert_eq!(&[1, 2, 3], dut.remaining_bytes());
+			dut.read_bit();
+			assert_eq!(&[2, 3], dut.remaining_bytes());
+		}
+	}
 }
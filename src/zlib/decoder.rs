@@ -0,0 +1,410 @@
+//! Decompresses a zlib stream incrementally, as an `std::io::Read` adapter
+
+use super::bits::Bits;
+use super::bits::ByteSource;
+use super::deflate;
+use super::Header;
+use super::InflateError;
+use crate::checksum::Adler32;
+use std::io::{ErrorKind, Read};
+
+/// The number of bytes to read from the backing reader at once
+const INPUT_BUFFER_SIZE: usize = 8192;
+
+/// A source of the bytes of a reader, which remembers the error that ended the iteration, if any
+struct ReadBytes<R: Read> {
+	reader: R,
+	buffer: Box<[u8]>,
+	/// the index of the next unread byte in `buffer`
+	start: usize,
+	/// the number of valid bytes in `buffer`
+	end: usize,
+	error: Option<std::io::Error>,
+}
+
+impl<R: Read> ReadBytes<R> {
+	fn new(reader: R) -> ReadBytes<R> {
+		ReadBytes {
+			reader,
+			buffer: vec![0; INPUT_BUFFER_SIZE].into_boxed_slice(),
+			start: 0,
+			end: 0,
+			error: None,
+		}
+	}
+}
+
+impl<R: Read> ReadBytes<R> {
+	/// Makes sure that `buffer` has unread bytes, unless the reader is exhausted or fails. Returns
+	/// the unread bytes.
+	fn fill_buffer(&mut self) -> &[u8] {
+		while self.start == self.end && self.error.is_none() {
+			match self.reader.read(&mut self.buffer) {
+				Ok(0) => break,
+				Ok(count) => {
+					self.start = 0;
+					self.end = count;
+				},
+				Err(err) if err.kind() == ErrorKind::Interrupted => {},
+				Err(err) => self.error = Some(err),
+			}
+		}
+		&self.buffer[self.start..self.end]
+	}
+}
+
+impl<R: Read> ByteSource for ReadBytes<R> {
+	fn take_bytes(&mut self, max: u8) -> (u64, u8) {
+		let mut value: u64 = 0;
+		let mut count: u8 = 0;
+		while count < max {
+			let mut available = self.fill_buffer();
+			if available.is_empty() {
+				break;
+			}
+			let (bytes, taken) = available.take_bytes(max - count);
+			value |= bytes << (8 * count);
+			count += taken;
+			self.start += usize::from(taken);
+		}
+		(value, count)
+	}
+
+	fn take_slice(&mut self, out: &mut Vec<u8>, count: usize) -> usize {
+		let mut taken = 0;
+		while taken < count {
+			let mut available = self.fill_buffer();
+			if available.is_empty() {
+				break;
+			}
+			let newly_taken = available.take_slice(out, count - taken);
+			taken += newly_taken;
+			self.start += newly_taken;
+		}
+		taken
+	}
+}
+
+/// The part of the zlib stream that a `Decoder` will read next
+enum DecoderState {
+	Header,
+	Data,
+	Checksum,
+	Done,
+}
+
+/// A reader that decompresses the zlib stream read from another reader
+///
+/// Only the most recent output that later matches can refer to, as limited by the window size in
+/// the stream's header, plus the output that has not been read yet, is held in memory. The adler32
+/// checksum at the end of the stream is checked once all the data has been read; if the checksum
+/// does not match, the final read returns an error instead of signaling the end of the stream.
+///
+/// Errors in the compressed data are reported as `std::io::Error`s wrapping an [`InflateError`].
+/// Reading from a decoder after it has returned an error other than `ErrorKind::Interrupted`
+/// gives unspecified results.
+///
+/// ```
+/// use png_inflate::zlib;
+/// use std::io::Read;
+///
+/// let compressed = zlib::deflate(b"Hello, World!", zlib::CompressionLevel::Slow);
+/// let mut decoder = zlib::Decoder::new(&compressed[..]);
+/// let mut inflated = Vec::new();
+/// decoder.read_to_end(&mut inflated)?;
+/// assert_eq!(b"Hello, World!".to_vec(), inflated);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Decoder<R: Read> {
+	bits: Bits<ReadBytes<R>>,
+	state: DecoderState,
+	inflater: deflate::Inflater,
+	/// Whether to ignore the window size in the stream's header
+	lenient_window: bool,
+	/// The furthest back that the stream's matches may refer to. Also the number of bytes of
+	/// output to produce before returning any of them to the caller.
+	window_size: usize,
+	/// Output that has been produced, which includes up to `window_size` bytes that have already been read
+	window: Vec<u8>,
+	/// The index of the first byte of `window` that has not been read yet
+	read_pos: usize,
+	/// The checksum of the output that has been read
+	checksum: Adler32,
+}
+
+impl<R: Read> Decoder<R> {
+	/// Creates a decoder that reads a zlib stream from `reader`
+	///
+	/// The decoder reads ahead from `reader`, so `reader`'s position after the end of the zlib
+	/// stream is unspecified.
+	pub fn new(reader: R) -> Decoder<R> {
+		Decoder {
+			bits: Bits::new(ReadBytes::new(reader)),
+			state: DecoderState::Header,
+			inflater: deflate::Inflater::new(0),
+			lenient_window: false,
+			window_size: 0,
+			window: Vec::new(),
+			read_pos: 0,
+			checksum: Adler32::new(),
+		}
+	}
+
+	/// Makes the decoder accept window sizes larger than deflate allows, and matches that refer
+	/// further back than the header's window size, as long as they stay within the largest window
+	/// that deflate allows
+	///
+	/// See [`InflateOptions::lenient_window`](super::InflateOptions::lenient_window).
+	pub fn lenient_window(mut self) -> Decoder<R> {
+		self.lenient_window = true;
+		self
+	}
+
+	/// Converts the error indicating that the input ended into the error that caused the input to end
+	fn convert_error(&mut self, err: InflateError) -> std::io::Error {
+		if err == InflateError::UnexpectedEof {
+			if let Some(io_err) = self.bits.get_mut().error.take() {
+				return io_err;
+			}
+			std::io::Error::new(ErrorKind::UnexpectedEof, err)
+		} else {
+			std::io::Error::new(ErrorKind::InvalidData, err)
+		}
+	}
+
+	fn read_byte(&mut self) -> Result<u8, InflateError> {
+		self.bits.read_byte().ok_or(InflateError::UnexpectedEof)
+	}
+
+	/// Reads until there is unread output or the stream ends
+	fn fill_window(&mut self) -> Result<(), InflateError> {
+		loop {
+			match self.state {
+				DecoderState::Header => {
+					let header =
+						Header::read(u16::from_be_bytes([self.read_byte()?, self.read_byte()?]))?;
+					if header.has_dictionary {
+						return Err(InflateError::HasPresetDictionary);
+					}
+					self.window_size = header.window_size(self.lenient_window)?;
+					self.inflater = deflate::Inflater::new(self.window_size);
+					self.state = DecoderState::Data;
+				},
+				DecoderState::Data => {
+					if self.window.len() > self.window_size {
+						self.window.drain(..(self.window.len() - self.window_size));
+						self.read_pos = self.window.len();
+					}
+					self.inflater.inflate_until(
+						&mut self.bits,
+						&mut self.window,
+						self.read_pos + self.window_size,
+					)?;
+					if self.inflater.is_done() {
+						self.state = DecoderState::Checksum;
+					}
+					if self.read_pos < self.window.len() {
+						return Ok(());
+					}
+				},
+				DecoderState::Checksum => {
+					self.bits.discard_til_byte_boundary();
+					let given = u32::from_be_bytes([
+						self.read_byte()?,
+						self.read_byte()?,
+						self.read_byte()?,
+						self.read_byte()?,
+					]);
+					let calculated = self.checksum.finish();
+					if given != calculated {
+						return Err(InflateError::ChecksumMismatch { given, calculated });
+					}
+					self.state = DecoderState::Done;
+				},
+				DecoderState::Done => return Ok(()),
+			}
+		}
+	}
+}
+
+impl<R: Read> Read for Decoder<R> {
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+		if buf.is_empty() {
+			return Ok(0);
+		}
+		if self.read_pos == self.window.len() {
+			self.fill_window().map_err(|err| self.convert_error(err))?;
+		}
+		let available = &self.window[self.read_pos..];
+		let count = available.len().min(buf.len());
+		buf[..count].copy_from_slice(&available[..count]);
+		self.checksum.update(&available[..count]);
+		self.read_pos += count;
+		Ok(count)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	mod read {
+		use super::super::super::deflate;
+		use super::super::super::deflate_immediate;
+		use super::super::super::u4;
+		use super::super::super::CompressionLevel;
+		use super::super::super::Header;
+		use super::super::super::InflateError;
+		use super::super::Decoder;
+		use std::io::{ErrorKind, Read};
+
+		/// Data that is larger than the window and that has matches almost as far back as the window
+		fn sample() -> Vec<u8> {
+			(0..200_000u32)
+				.map(|x| x % 30_000)
+				.map(|x| (x * x / 7 % 251) as u8)
+				.collect()
+		}
+
+		/// A reader that returns at most `N` bytes per read
+		struct Trickle<'a, const N: usize>(&'a [u8]);
+
+		impl<const N: usize> Read for Trickle<'_, N> {
+			fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+				let count = self.0.len().min(buf.len()).min(N);
+				buf[..count].copy_from_slice(&self.0[..count]);
+				self.0 = &self.0[count..];
+				Ok(count)
+			}
+		}
+
+		/// A reader that fails after its data is exhausted
+		struct Failing<'a>(&'a [u8]);
+
+		impl Read for Failing<'_> {
+			fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+				if self.0.is_empty() {
+					Err(std::io::Error::other("disconnected"))
+				} else {
+					self.0.read(buf)
+				}
+			}
+		}
+
+		#[test]
+		fn read_to_end() {
+			let data = sample();
+			let compressed = deflate(&data, CompressionLevel::Slowest);
+			let mut res = Vec::new();
+			Decoder::new(&compressed[..]).read_to_end(&mut res).unwrap();
+			assert_eq!(data, res);
+		}
+
+		#[test]
+		fn small_reads_from_small_reader() {
+			let data = sample();
+			let compressed = deflate(&data, CompressionLevel::Fast);
+			let mut dut = Decoder::new(Trickle::<3>(&compressed));
+			let mut res = Vec::new();
+			let mut buf = [0u8; 7];
+			loop {
+				let count = dut.read(&mut buf).unwrap();
+				if count == 0 {
+					break;
+				}
+				res.extend_from_slice(&buf[..count]);
+			}
+			assert_eq!(data, res);
+		}
+
+		#[test]
+		fn stored_blocks() {
+			let data = sample();
+			let compressed = deflate_immediate(&data);
+			let mut res = Vec::new();
+			Decoder::new(Trickle::<1000>(&compressed))
+				.read_to_end(&mut res)
+				.unwrap();
+			assert_eq!(data, res);
+		}
+
+		#[test]
+		fn empty() {
+			let compressed = deflate(&[], CompressionLevel::Slow);
+			let mut res = Vec::new();
+			Decoder::new(&compressed[..]).read_to_end(&mut res).unwrap();
+			assert!(res.is_empty());
+		}
+
+		#[test]
+		fn checksum_mismatch() {
+			let mut compressed = deflate(b"abcdabcdabcd", CompressionLevel::Slow);
+			let last = compressed.len() - 1;
+			compressed[last] ^= 1;
+			let mut res = Vec::new();
+			let err = Decoder::new(&compressed[..])
+				.read_to_end(&mut res)
+				.unwrap_err();
+			assert_eq!(ErrorKind::InvalidData, err.kind());
+			assert!(matches!(
+				err.into_inner().unwrap().downcast_ref::<InflateError>(),
+				Some(InflateError::ChecksumMismatch { .. })
+			));
+			assert_eq!(b"abcdabcdabcd".to_vec(), res);
+		}
+
+		#[test]
+		fn truncated() {
+			let compressed = deflate(&sample(), CompressionLevel::Slow);
+			let compressed = &compressed[..compressed.len() / 2];
+			let err = Decoder::new(compressed)
+				.read_to_end(&mut Vec::new())
+				.unwrap_err();
+			assert_eq!(ErrorKind::UnexpectedEof, err.kind());
+		}
+
+		#[test]
+		fn small_window() {
+			let data = b"abcdabcdabcd".repeat(10);
+			let compressed = deflate(&data, CompressionLevel::Slow);
+			assert_eq!(0x08, compressed[0]);
+			let mut res = Vec::new();
+			Decoder::new(Trickle::<5>(&compressed))
+				.read_to_end(&mut res)
+				.unwrap();
+			assert_eq!(data, res);
+		}
+
+		#[test]
+		fn too_large_window_size() {
+			let mut compressed = deflate(b"abcd", CompressionLevel::Slow);
+			let header = Header::new(u4::_8, CompressionLevel::Slow).write();
+			compressed[..2].copy_from_slice(&header.to_be_bytes());
+
+			let err = Decoder::new(&compressed[..])
+				.read_to_end(&mut Vec::new())
+				.unwrap_err();
+			assert_eq!(ErrorKind::InvalidData, err.kind());
+			assert_eq!(
+				Some(&InflateError::InvalidWindowSize(u4::_8)),
+				err.into_inner().unwrap().downcast_ref::<InflateError>()
+			);
+
+			let mut res = Vec::new();
+			Decoder::new(&compressed[..])
+				.lenient_window()
+				.read_to_end(&mut res)
+				.unwrap();
+			assert_eq!(b"abcd".to_vec(), res);
+		}
+
+		#[test]
+		fn reader_error() {
+			let compressed = deflate(&sample(), CompressionLevel::Slow);
+			let compressed = &compressed[..compressed.len() / 2];
+			let err = Decoder::new(Failing(compressed))
+				.read_to_end(&mut Vec::new())
+				.unwrap_err();
+			assert_eq!(ErrorKind::Other, err.kind());
+			assert_eq!("disconnected", err.to_string());
+		}
+	}
+}
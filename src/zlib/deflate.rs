@@ -1,11 +1,16 @@
 //! "DEFLATE Compressed Data Format Specification" <http://www.w3.org/Graphics/PNG/RFC-1951>
+use super::bits::BitWriter;
+use super::bits::ByteSource;
+use super::huffman;
+use super::lz77;
+use super::lz77::Token;
 use super::u4;
 use super::Bits;
 
 #[rustfmt::skip]
 /// The extra bits following a length code to store
 /// the actual value of the code
-const LENGTH_EXTRA_BITS:[u4;29] = [
+pub const LENGTH_EXTRA_BITS:[u4;29] = [
 	u4::_0, u4::_0, u4::_0, u4::_0, u4::_0,
 	u4::_0, u4::_0, u4::_0, u4::_1, u4::_1,
 	u4::_1, u4::_1, u4::_2, u4::_2, u4::_2,
@@ -17,7 +22,7 @@
 #[rustfmt::skip]
 /// The extra bits following a distance code to store
 /// the actual value of the code
-const DISTANCE_EXTRA_BITS:[u4;30] = [
+pub const DISTANCE_EXTRA_BITS:[u4;30] = [
 	u4::_0, u4::_0, u4::_0, u4::_0, u4::_1,
 	u4::_1, u4::_2, u4::_2, u4::_3, u4::_3,
 	u4::_4, u4::_4, u4::_5, u4::_5, u4::_6,
@@ -26,21 +31,75 @@
 	u4::_B, u4::_C, u4::_C, u4::_D, u4::_D,
 ];
 
+#[rustfmt::skip]
+/// The smallest length represented by each length code
+pub const LENGTH_BASE:[u16;29] = [
+	3, 4, 5, 6, 7,
+	8, 9, 10, 11, 13,
+	15, 17, 19, 23, 27,
+	31, 35, 43, 51, 59,
+	67, 83, 99, 115, 131,
+	163, 195, 227, 258,
+];
+
+#[rustfmt::skip]
+/// The smallest distance represented by each distance code
+pub const DISTANCE_BASE:[u16;30] = [
+	1, 2, 3, 4, 5,
+	7, 9, 13, 17, 25,
+	33, 49, 65, 97, 129,
+	193, 257, 385, 513, 769,
+	1025, 1537, 2049, 3073, 4097,
+	6145, 8193, 12289, 16385, 24577,
+];
+
 #[rustfmt::skip]
 /// The order that meta codes are stored in 10 mode codings
-const META_CODES_ORDER:[usize;19] = [
+pub const META_CODES_ORDER:[usize;19] = [
 	16, 17, 18, 0, 8,
 	7, 9, 6, 10, 5,
 	11, 4, 12, 3, 13,
 	2, 14, 1, 15,
 ];
 
+/// The number of bits used to index the primary decoding table of the length and literal codes
+pub const LENGTH_TABLE_BITS: u8 = 9;
+/// The number of bits used to index the primary decoding table of the distance codes
+pub const DISTANCE_TABLE_BITS: u8 = 6;
+/// The number of bits used to index the primary decoding table of the meta codes
+pub const META_TABLE_BITS: u8 = 7;
+
 /// An error that can occur while inflating a stream
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum InflateError {
 	UnexpectedEof,
 	NonCompressedLengthInvalid,
 	InvalidBtype,
+	/// The input contains a bit sequence that is not the code of any symbol
+	InvalidCode,
+	/// The input contains the code of a length or distance symbol that does not represent a value
+	InvalidSymbol,
+	/// A code length repeat code appears before any code length
+	RepeatWithoutPrevious,
+	/// A set of code lengths needs more codes than there are bit sequences of those lengths
+	OversubscribedCodeLengths,
+	/// The code lengths extend past the number of code lengths given in the block header
+	TooManyCodeLengths,
+	/// A match refers to data before the start of the stream
+	DistanceTooFar,
+	/// A match refers to data further back than the window size
+	DistanceOutsideWindow,
+	/// The inflated data is longer than the limit
+	OutputLimitExceeded,
+}
+
+impl From<huffman::DecodeError> for InflateError {
+	fn from(src: huffman::DecodeError) -> InflateError {
+		match src {
+			huffman::DecodeError::UnexpectedEof => InflateError::UnexpectedEof,
+			huffman::DecodeError::InvalidCode => InflateError::InvalidCode,
+		}
+	}
 }
 
 //impl From<::std::option::NoneError> for InflateError {
@@ -53,160 +112,409 @@ fn option_to_result<A>(a: Option<A>) -> Result<A, InflateError> {
 	}
 }
 
-/// Decompress the input deflate stream
-pub fn inflate<I: Iterator<Item = u8>>(input: &mut I) -> Result<Vec<u8>, InflateError> {
-	let mut bitreader = Bits::new(input);
+/// Decompress the input deflate stream, failing if the result would be longer than `max_size`
+/// `input` is advanced to the first byte after the stream.
+pub fn inflate(input: &mut &[u8], max_size: usize) -> Result<Vec<u8>, InflateError> {
+	inflate_with_dictionary(input, &[], lz77::WINDOW_SIZE, max_size)
+}
+
+/// Decompress the input deflate stream, whose matches may refer to the end of `dictionary` and
+/// must not refer further back than `window_size`. `input` is advanced to the first byte after the stream.
+pub fn inflate_with_dictionary(
+	input: &mut &[u8],
+	dictionary: &[u8],
+	window_size: usize,
+	max_size: usize,
+) -> Result<Vec<u8>, InflateError> {
+	let dictionary = &dictionary[dictionary.len().saturating_sub(window_size)..];
+	let mut bitreader = Bits::new(*input);
+	let mut retval: Vec<u8> = dictionary.to_vec();
+	let mut inflater = Inflater::new(window_size);
+	let limit = dictionary.len().saturating_add(max_size).saturating_add(1);
+	inflater.inflate_until(&mut bitreader, &mut retval, limit)?;
+	*input = &input[bitreader.bit_position().div_ceil(8)..];
+	if retval.len() - dictionary.len() > max_size {
+		return Err(InflateError::OutputLimitExceeded);
+	}
+	retval.drain(..dictionary.len());
+	Ok(retval)
+}
+
+/// Decompress the input deflate stream, failing if the result would be longer than `max_size`.
+/// Also returns the structure of each of the stream's blocks. `input` is advanced to the first
+/// byte after the stream.
+pub fn inflate_with_blocks(
+	input: &mut &[u8],
+	max_size: usize,
+) -> Result<(Vec<u8>, Vec<BlockInfo>), InflateError> {
+	let mut bitreader = Bits::new(*input);
 	let mut retval: Vec<u8> = Vec::new();
-	let mut read_final_chunk: bool = false;
-
-	while !read_final_chunk {
-		read_final_chunk = option_to_result(bitreader.next())?;
-		let typ: u16 = option_to_result(bitreader.read_n_rev(u4::_2))?;
-		match typ {
-			0 => {
-				// no compression
-				let bytes = bitreader.discard_til_byte_boundary();
-				let len = u16::from_le_bytes([
-					option_to_result(bytes.next())?,
-					option_to_result(bytes.next())?,
-				]);
-				let nlen = u16::from_le_bytes([
-					option_to_result(bytes.next())?,
-					option_to_result(bytes.next())?,
-				]);
-				if len != !nlen {
-					return Err(InflateError::NonCompressedLengthInvalid);
-				}
-				for _ in 0..len {
-					retval.push(option_to_result(bytes.next())?);
-				}
-				bitreader = Bits::new(bytes);
-			},
-			1 => {
-				// fixed codes
-				loop {
-					let code = option_to_result(decode_fixed_huffman_code(&mut bitreader))?;
-					match code.cmp(&256) {
-						::std::cmp::Ordering::Equal => {
-							break;
-						},
-						::std::cmp::Ordering::Less => {
-							retval.push((code & 0xFF) as u8);
-						},
-						::std::cmp::Ordering::Greater => {
-							let length_index = code - 257;
-							let length_extra_bits = LENGTH_EXTRA_BITS[usize::from(length_index)];
-							let length: u16 = 3
-								+ option_to_result(bitreader.read_n_rev(length_extra_bits))?
-								+ LENGTH_EXTRA_BITS
-									.iter()
-									.take(usize::from(length_index))
-									.map(|x| x.nth_bit())
-									.sum::<u16>();
-							let length = if code == 285 { length - 1 } else { length };
-
-							let distance_index = option_to_result(bitreader.read_n(u4::_5))?;
-							let distance_extra_bits =
-								DISTANCE_EXTRA_BITS[usize::from(distance_index)];
-							let distance: u16 = 1
-								+ option_to_result(bitreader.read_n_rev(distance_extra_bits))?
-								+ DISTANCE_EXTRA_BITS
-									.iter()
-									.take(usize::from(distance_index))
-									.map(|x| x.nth_bit())
-									.sum::<u16>();
-
-							for _ in 0..length {
-								let index_to_copy = retval.len() - usize::from(distance);
-								let value_to_copy = *retval.get(index_to_copy).unwrap();
-								retval.push(value_to_copy);
-							}
-						},
+	let mut inflater = Inflater::new(lz77::WINDOW_SIZE).record_blocks();
+	inflater.inflate_until(&mut bitreader, &mut retval, max_size.saturating_add(1))?;
+	*input = &input[bitreader.bit_position().div_ceil(8)..];
+	if retval.len() > max_size {
+		return Err(InflateError::OutputLimitExceeded);
+	}
+	let blocks = inflater.into_blocks().expect("blocks are recorded");
+	Ok((retval, blocks))
+}
+
+/// The position of an `Inflater` within a deflate stream
+enum InflaterState {
+	/// The next bits are a block header
+	BlockHeader,
+	/// Within a non-compressed block, with the given number of bytes remaining
+	Stored(u16),
+	/// Within a huffman-coded block
+	Coded {
+		length_codes: huffman::Table,
+		distance_codes: huffman::Table,
+	},
+	/// The final block has ended
+	Done,
+}
+
+/// The method that a deflate block uses to represent its data
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BlockType {
+	/// The data is stored without compression
+	Stored,
+	/// The data is compressed using the huffman codes defined by the deflate specification
+	Fixed,
+	/// The data is compressed using huffman codes described at the start of the block
+	Dynamic,
+}
+
+/// The structure of a single block of a deflate stream
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BlockInfo {
+	/// The method that the block uses to represent its data
+	pub block_type: BlockType,
+	/// Whether the block is the last block of the stream
+	pub is_final: bool,
+	/// The position of the block's first bit, counted from the start of the stream
+	pub bit_offset: usize,
+	/// The length of the block in bits, including the block header
+	pub compressed_bits: usize,
+	/// The number of bytes that the block inflates to
+	pub uncompressed_size: usize,
+	/// The lengths of the codes of each literal/length symbol, or empty for a stored block
+	pub literal_length_code_lengths: Vec<u8>,
+	/// The lengths of the codes of each distance symbol, or empty for a stored block
+	pub distance_code_lengths: Vec<u8>,
+	/// The number of literal symbols in the block
+	pub literal_count: usize,
+	/// The number of length-distance pairs in the block
+	pub match_count: usize,
+}
+
+/// The number of each kind of token that a block has produced so far
+#[derive(Copy, Clone, Default)]
+struct TokenCounts {
+	literals: usize,
+	matches: usize,
+	/// the number of bytes of output
+	bytes: usize,
+}
+
+/// Decompresses a deflate stream a piece at a time
+///
+/// Output is appended to a buffer provided by the caller, which must retain at least the last
+/// `window_size` bytes of output from previous calls, as later matches can refer to them.
+pub struct Inflater {
+	state: InflaterState,
+	/// Whether the current block is the final block
+	is_final_block: bool,
+	/// The furthest back that a match may refer to
+	window_size: usize,
+	/// The tokens in the current block
+	counts: TokenCounts,
+	/// The structure of each block that has been read so far, if requested
+	blocks: Option<Vec<BlockInfo>>,
+}
+
+impl Inflater {
+	pub fn new(window_size: usize) -> Inflater {
+		Inflater {
+			state: InflaterState::BlockHeader,
+			is_final_block: false,
+			window_size,
+			counts: TokenCounts::default(),
+			blocks: None,
+		}
+	}
+
+	/// Makes the inflater record the structure of each block it reads
+	pub fn record_blocks(mut self) -> Inflater {
+		self.blocks = Some(Vec::new());
+		self
+	}
+
+	/// The structure of each block read so far, if the inflater records them
+	pub fn into_blocks(self) -> Option<Vec<BlockInfo>> {
+		self.blocks
+	}
+
+	/// Whether the end of the final block has been read
+	pub fn is_done(&self) -> bool {
+		matches!(self.state, InflaterState::Done)
+	}
+
+	/// Decodes the stream, appending to `retval`, until either the stream ends or `retval` is at
+	/// least `limit` bytes long. `retval` may exceed `limit` by up to the length of a match.
+	pub fn inflate_until<S: ByteSource>(
+		&mut self,
+		bitreader: &mut Bits<S>,
+		retval: &mut Vec<u8>,
+		limit: usize,
+	) -> Result<(), InflateError> {
+		loop {
+			let block_ended = match &mut self.state {
+				InflaterState::Done => return Ok(()),
+				_ if retval.len() >= limit => return Ok(()),
+				InflaterState::BlockHeader => {
+					let bit_offset = bitreader.bit_position();
+					self.is_final_block = option_to_result(bitreader.next())?;
+					let (state, description) = read_block_header(bitreader)?;
+					self.state = state;
+					if let Some(blocks) = &mut self.blocks {
+						blocks.push(description.into_info(self.is_final_block, bit_offset));
 					}
+					self.counts = TokenCounts::default();
+					false
+				},
+				InflaterState::Stored(remaining) => {
+					let count = usize::from(*remaining).min(limit - retval.len());
+					option_to_result(bitreader.read_bytes(retval, count))?;
+					*remaining -= count as u16;
+					self.counts.bytes += count;
+					*remaining == 0
+				},
+				InflaterState::Coded {
+					length_codes,
+					distance_codes,
+				} => inflate_block(
+					bitreader,
+					retval,
+					length_codes,
+					distance_codes,
+					self.window_size,
+					limit,
+					&mut self.counts,
+				)?,
+			};
+			if block_ended {
+				if let Some(block) = self.blocks.as_mut().and_then(|x| x.last_mut()) {
+					block.compressed_bits = bitreader.bit_position() - block.bit_offset;
+					block.uncompressed_size = self.counts.bytes;
+					block.literal_count = self.counts.literals;
+					block.match_count = self.counts.matches;
 				}
-			},
-			2 => {
-				// custom codes
-				let num_length_codes = 257 + option_to_result(bitreader.read_n_rev(u4::_5))?;
-				let num_distance_codes = 1 + option_to_result(bitreader.read_n_rev(u4::_5))?;
-				let num_meta_codes = 4 + option_to_result(bitreader.read_n_rev(u4::_4))?;
-
-				//eprintln!("Lengths: {} {} {}", num_meta_codes, num_length_codes, num_distance_codes);
-				let mut meta_code_lengths: [u4; 19] = [u4::_0; 19];
-				for x in 0..usize::from(num_meta_codes) {
-					meta_code_lengths[META_CODES_ORDER[x]] =
-						u4::truncate(option_to_result(bitreader.read_n_rev(u4::_3))? as u8);
-				}
+				self.state = if self.is_final_block {
+					InflaterState::Done
+				} else {
+					InflaterState::BlockHeader
+				};
+			}
+		}
+	}
+}
 
-				let meta_codes = DynamicHuffmanCodes::from_lengths(&meta_code_lengths);
-				//eprintln!("{:?}", meta_codes);
+/// The part of a block header that is recorded in a [`BlockInfo`]
+enum BlockDescription {
+	Stored,
+	Fixed,
+	Dynamic {
+		length_lengths: Vec<u8>,
+		distance_lengths: Vec<u8>,
+	},
+}
 
-				let mut length_codes: Vec<u4> = Vec::new();
-				while length_codes.len() < usize::from(num_length_codes) {
-					let meta = option_to_result(meta_codes.decode(&mut bitreader))?;
-					act_upon_meta_code(&mut length_codes, &mut bitreader, meta)?;
-				}
-				let length_codes = DynamicHuffmanCodes::from_lengths(&length_codes);
+impl BlockDescription {
+	/// Creates the info of a block with this header, which has not had its contents read yet
+	fn into_info(self, is_final: bool, bit_offset: usize) -> BlockInfo {
+		let (block_type, length_lengths, distance_lengths) = match self {
+			BlockDescription::Stored => (BlockType::Stored, Vec::new(), Vec::new()),
+			BlockDescription::Fixed => (
+				BlockType::Fixed,
+				HuffmanEncoder::fixed_length_codes().lengths().to_vec(),
+				HuffmanEncoder::fixed_distance_codes().lengths().to_vec(),
+			),
+			BlockDescription::Dynamic {
+				length_lengths,
+				distance_lengths,
+			} => (BlockType::Dynamic, length_lengths, distance_lengths),
+		};
+		BlockInfo {
+			block_type,
+			is_final,
+			bit_offset,
+			compressed_bits: 0,
+			uncompressed_size: 0,
+			literal_length_code_lengths: length_lengths,
+			distance_code_lengths: distance_lengths,
+			literal_count: 0,
+			match_count: 0,
+		}
+	}
+}
+
+/// Reads the part of a block header after the BFINAL bit, returning the state for reading the
+/// block's contents and a description of the header
+fn read_block_header<S: ByteSource>(
+	bitreader: &mut Bits<S>,
+) -> Result<(InflaterState, BlockDescription), InflateError> {
+	let typ: u16 = option_to_result(bitreader.read_n_rev(u4::_2))?;
+	match typ {
+		0 => {
+			// no compression
+			bitreader.discard_til_byte_boundary();
+			let len = u16::from_le_bytes([
+				option_to_result(bitreader.read_byte())?,
+				option_to_result(bitreader.read_byte())?,
+			]);
+			let nlen = u16::from_le_bytes([
+				option_to_result(bitreader.read_byte())?,
+				option_to_result(bitreader.read_byte())?,
+			]);
+			if len != !nlen {
+				return Err(InflateError::NonCompressedLengthInvalid);
+			}
+			Ok((InflaterState::Stored(len), BlockDescription::Stored))
+		},
+		1 => {
+			// fixed codes
+			let length_codes = huffman::Table::from_lengths(
+				HuffmanEncoder::fixed_length_codes().lengths(),
+				LENGTH_TABLE_BITS,
+			)
+			.expect("the fixed codes are not oversubscribed");
+			// distance codes 30 and 31 are part of the fixed code, even though they never represent a distance
+			let distance_codes = huffman::Table::from_lengths(&[5; 32], DISTANCE_TABLE_BITS)
+				.expect("the fixed codes are not oversubscribed");
+			Ok((
+				InflaterState::Coded {
+					length_codes,
+					distance_codes,
+				},
+				BlockDescription::Fixed,
+			))
+		},
+		2 => {
+			// custom codes
+			let num_length_codes = 257 + option_to_result(bitreader.read_n_rev(u4::_5))?;
+			let num_distance_codes = 1 + option_to_result(bitreader.read_n_rev(u4::_5))?;
+			let num_meta_codes = 4 + option_to_result(bitreader.read_n_rev(u4::_4))?;
+
+			let mut meta_code_lengths: [u8; 19] = [0; 19];
+			for x in 0..usize::from(num_meta_codes) {
+				meta_code_lengths[META_CODES_ORDER[x]] =
+					option_to_result(bitreader.read_n_rev(u4::_3))? as u8;
+			}
+
+			let meta_codes = huffman::Table::from_lengths(&meta_code_lengths, META_TABLE_BITS)
+				.ok_or(InflateError::OversubscribedCodeLengths)?;
+
+			// A repeat code may cross from the length codes' lengths to the distance codes' lengths,
+			// so the two are read as one sequence
+			let num_codes = usize::from(num_length_codes) + usize::from(num_distance_codes);
+			let mut length_codes: Vec<u8> = Vec::with_capacity(num_codes);
+			while length_codes.len() < num_codes {
+				let meta = meta_codes.decode(bitreader)?;
+				act_upon_meta_code(&mut length_codes, bitreader, meta)?;
+			}
+			if length_codes.len() > num_codes {
+				return Err(InflateError::TooManyCodeLengths);
+			}
+			let distance_codes = length_codes.split_off(usize::from(num_length_codes));
+
+			let length_lengths = length_codes;
+			let distance_lengths = distance_codes;
+			let length_codes = huffman::Table::from_lengths(&length_lengths, LENGTH_TABLE_BITS)
+				.ok_or(InflateError::OversubscribedCodeLengths)?;
+			let distance_codes =
+				huffman::Table::from_lengths(&distance_lengths, DISTANCE_TABLE_BITS)
+					.ok_or(InflateError::OversubscribedCodeLengths)?;
+
+			Ok((
+				InflaterState::Coded {
+					length_codes,
+					distance_codes,
+				},
+				BlockDescription::Dynamic {
+					length_lengths,
+					distance_lengths,
+				},
+			))
+		},
+		_ => {
+			// error
+			Err(InflateError::InvalidBtype)
+		},
+	}
+}
 
-				let mut distance_codes: Vec<u4> = Vec::new();
-				while distance_codes.len() < usize::from(num_distance_codes) {
-					let meta = option_to_result(meta_codes.decode(&mut bitreader))?;
-					act_upon_meta_code(&mut distance_codes, &mut bitreader, meta)?;
+/// Decodes the contents of a huffman-coded block, up to and including the end-of-block code or
+/// until `retval` is at least `limit` bytes long. Returns whether the end-of-block code was read.
+/// Adds the decoded tokens to `counts`.
+fn inflate_block<S: ByteSource>(
+	bitreader: &mut Bits<S>,
+	retval: &mut Vec<u8>,
+	length_codes: &huffman::Table,
+	distance_codes: &huffman::Table,
+	window_size: usize,
+	limit: usize,
+	counts: &mut TokenCounts,
+) -> Result<bool, InflateError> {
+	while retval.len() < limit {
+		let code = length_codes.decode(bitreader)?;
+		match code.cmp(&256) {
+			::std::cmp::Ordering::Equal => return Ok(true),
+			::std::cmp::Ordering::Less => {
+				retval.push((code & 0xFF) as u8);
+				counts.literals += 1;
+				counts.bytes += 1;
+			},
+			::std::cmp::Ordering::Greater => {
+				let length_index = usize::from(code - 257);
+				let length_base = LENGTH_BASE
+					.get(length_index)
+					.ok_or(InflateError::InvalidSymbol)?;
+				let length: u16 = length_base
+					+ option_to_result(bitreader.read_n_rev(LENGTH_EXTRA_BITS[length_index]))?;
+
+				let distance_index = usize::from(distance_codes.decode(bitreader)?);
+				let distance_base = DISTANCE_BASE
+					.get(distance_index)
+					.ok_or(InflateError::InvalidSymbol)?;
+				let distance: u16 = distance_base
+					+ option_to_result(bitreader.read_n_rev(DISTANCE_EXTRA_BITS[distance_index]))?;
+
+				let start = retval
+					.len()
+					.checked_sub(usize::from(distance))
+					.ok_or(InflateError::DistanceTooFar)?;
+				if usize::from(distance) > window_size {
+					return Err(InflateError::DistanceOutsideWindow);
 				}
-				let distance_codes = DynamicHuffmanCodes::from_lengths(&distance_codes);
-
-				loop {
-					let code = option_to_result(length_codes.decode(&mut bitreader))?;
-					match code.cmp(&256) {
-						::std::cmp::Ordering::Equal => break,
-						::std::cmp::Ordering::Less => {
-							retval.push((code & 0xFF) as u8);
-						},
-						::std::cmp::Ordering::Greater => {
-							let length_index = code - 257;
-							let length_extra_bits = LENGTH_EXTRA_BITS[usize::from(length_index)];
-							let length: u16 = 3
-								+ option_to_result(bitreader.read_n_rev(length_extra_bits))?
-								+ LENGTH_EXTRA_BITS
-									.iter()
-									.take(usize::from(length_index))
-									.map(|x| x.nth_bit())
-									.sum::<u16>();
-							let length = if code == 285 { length - 1 } else { length };
-
-							let distance_index =
-								option_to_result(distance_codes.decode(&mut bitreader))?;
-							let distance_extra_bits =
-								DISTANCE_EXTRA_BITS[usize::from(distance_index)];
-							let distance: u16 = 1
-								+ option_to_result(bitreader.read_n_rev(distance_extra_bits))?
-								+ DISTANCE_EXTRA_BITS
-									.iter()
-									.take(usize::from(distance_index))
-									.map(|x| x.nth_bit())
-									.sum::<u16>();
-
-							for _ in 0..length {
-								let index_to_copy = retval.len() - usize::from(distance);
-								let value_to_copy = *retval.get(index_to_copy).unwrap();
-								retval.push(value_to_copy);
-							}
-						},
-					}
+				for index in start..(start + usize::from(length)) {
+					retval.push(retval[index]);
 				}
-			},
-			_ => {
-				// error
-				return Err(InflateError::InvalidBtype);
+				counts.matches += 1;
+				counts.bytes += usize::from(length);
 			},
 		}
 	}
-	Ok(retval)
+	Ok(false)
 }
 
 /// Store the the input in a deflate stream entirely using immediate mode (00)
 pub fn deflate_immediate<I: Iterator<Item = u8>>(input: I) -> Vec<u8> {
 	let input: Vec<u8> = input.collect();
+	if input.is_empty() {
+		// a stream must have at least one block, even if that block is empty
+		return vec![1, 0x00, 0x00, 0xFF, 0xFF];
+	}
 	let input = input.chunks(0xFFFF);
 
 	let last_item = input.len() - 1;
@@ -222,96 +530,354 @@ pub fn deflate_immediate<I: Iterator<Item = u8>>(input: I) -> Vec<u8> {
 	retval
 }
 
-/// Decode a single a fixed-mode huffman code from the given stream
-fn decode_fixed_huffman_code<I: Iterator<Item = u8>>(bitreader: &mut Bits<I>) -> Option<u16> {
-	match bitreader.read_n(u4::_2)? {
-		0 => match bitreader.read_n(u4::_2)? {
-			3 => bitreader.read_n(u4::_4),
-			x => bitreader.read_n(u4::_3).map(|y| 256 + (x << 3) + y),
-		},
-		1 => bitreader.read_n(u4::_6).map(|y| 16 + y),
-		2 => bitreader.read_n(u4::_6).map(|y| 80 + y),
-		3 => match bitreader.read_n(u4::_3)? {
-			0 => bitreader.read_n(u4::_3).map(|y| 280 + y),
-			x => bitreader.read_n(u4::_4).map(|y| 144 + ((x - 1) << 4) + y),
-		},
-		_ => panic!(""),
+/// The maximum number of tokens to put in a single compressed block
+const TOKENS_PER_BLOCK: usize = 1 << 14;
+
+/// Compress the input into a deflate stream, choosing for each block
+/// whichever of the non-compressed, fixed-code or custom-code modes is smallest
+///
+/// Matches may refer to the end of `dictionary`, and do not refer further back than `window_size`.
+pub fn deflate_with_dictionary(
+	input: &[u8],
+	dictionary: &[u8],
+	window_size: usize,
+	params: &lz77::Parameters,
+) -> Vec<u8> {
+	let dictionary = &dictionary[dictionary.len().saturating_sub(window_size)..];
+	let tokens = if dictionary.is_empty() {
+		lz77::tokenize_from(input, 0, window_size, params)
+	} else {
+		let data = [dictionary, input].concat();
+		lz77::tokenize_from(&data, dictionary.len(), window_size, params)
+	};
+	let blocks: Vec<&[Token]> = if tokens.is_empty() {
+		vec![&[]]
+	} else {
+		tokens.chunks(TOKENS_PER_BLOCK).collect()
+	};
+
+	let mut writer = BitWriter::new();
+	let mut block_start: usize = 0;
+	let last_item = blocks.len() - 1;
+	for (idx, block) in blocks.into_iter().enumerate() {
+		let block_len: usize = block.iter().map(Token::len).sum();
+		let raw = &input[block_start..(block_start + block_len)];
+		write_block(&mut writer, block, raw, idx == last_item);
+		block_start += block_len;
 	}
+	writer.finish()
 }
 
-#[derive(Debug)]
-struct DynamicHuffmanCodeValue {
-	length: u4,
-	value: u16,
+/// Returns the index of the length code that represents the given match length
+pub fn length_index(length: u16) -> usize {
+	LENGTH_BASE.partition_point(|x| *x <= length) - 1
 }
 
-/// Huffman codes that are encoded in the PNG stream
-#[derive(Debug)]
-struct DynamicHuffmanCodes {
-	backing: Vec<DynamicHuffmanCodeValue>,
+/// Returns the index of the distance code that represents the given match distance
+pub fn distance_index(distance: u16) -> usize {
+	DISTANCE_BASE.partition_point(|x| *x <= distance) - 1
 }
 
-impl DynamicHuffmanCodes {
-	/// Create the set of codes from the code lengths of each item
-	fn from_lengths(lengths: &[u4]) -> DynamicHuffmanCodes {
-		let mut lengths: Vec<DynamicHuffmanCodeValue> = lengths
+/// A huffman code, in the form used when writing a stream
+pub struct HuffmanEncoder {
+	lengths: Vec<u8>,
+	codes: Vec<u16>,
+}
+
+impl HuffmanEncoder {
+	pub fn from_lengths(lengths: Vec<u8>) -> HuffmanEncoder {
+		let codes = huffman::codes_from_lengths(&lengths);
+		HuffmanEncoder { lengths, codes }
+	}
+
+	/// Creates a code suited to the given symbol frequencies, with codes no longer than `max_length`
+	fn from_frequencies(frequencies: &[u32], max_length: u8) -> HuffmanEncoder {
+		// A code with a single symbol is not a complete prefix code, so make sure there are two
+		let mut frequencies = frequencies.to_vec();
+		let mut unused = 0;
+		while frequencies.iter().filter(|x| **x != 0).count() < 2 {
+			while frequencies[unused] != 0 {
+				unused += 1;
+			}
+			frequencies[unused] = 1;
+		}
+		HuffmanEncoder::from_lengths(huffman::code_lengths(&frequencies, max_length))
+	}
+
+	pub fn fixed_length_codes() -> HuffmanEncoder {
+		let lengths = (0..288)
+			.map(|x| match x {
+				0..=143 => 8,
+				144..=255 => 9,
+				256..=279 => 7,
+				_ => 8,
+			})
+			.collect();
+		HuffmanEncoder::from_lengths(lengths)
+	}
+
+	pub fn fixed_distance_codes() -> HuffmanEncoder {
+		HuffmanEncoder::from_lengths(vec![5; 30])
+	}
+
+	pub fn write(&self, writer: &mut BitWriter, symbol: usize) {
+		writer.write_n(self.codes[symbol], self.lengths[symbol]);
+	}
+
+	/// The length of each symbol's code. A length of zero means the symbol cannot be written
+	pub fn lengths(&self) -> &[u8] {
+		&self.lengths
+	}
+
+	/// The number of bits needed to write symbols with the given frequencies using this code
+	fn cost(&self, frequencies: &[u32]) -> u64 {
+		frequencies
 			.iter()
-			.cloned()
-			.zip(0..)
-			.map(|(length, value)| DynamicHuffmanCodeValue { length, value })
-			.filter(|x| x.length != u4::_0)
+			.zip(self.lengths.iter())
+			.map(|(freq, len)| u64::from(*freq) * u64::from(*len))
+			.sum()
+	}
+}
+
+/// The frequency of each symbol used to write the tokens
+pub struct Frequencies {
+	pub lengths: [u32; 286],
+	pub distances: [u32; 30],
+}
+
+impl Frequencies {
+	pub fn new(tokens: &[Token]) -> Frequencies {
+		let mut lengths = [0u32; 286];
+		let mut distances = [0u32; 30];
+		for token in tokens {
+			match token {
+				Token::Literal(x) => lengths[usize::from(*x)] += 1,
+				Token::Match { length, distance } => {
+					lengths[257 + length_index(*length)] += 1;
+					distances[distance_index(*distance)] += 1;
+				},
+			}
+		}
+		lengths[256] += 1;
+		Frequencies { lengths, distances }
+	}
+
+	/// The number of extra bits following length and distance codes
+	fn extra_bits(&self) -> u64 {
+		let length_extra: u64 = self.lengths[257..]
+			.iter()
+			.zip(LENGTH_EXTRA_BITS.iter())
+			.map(|(freq, extra)| u64::from(*freq) * u64::from(u8::from(*extra)))
+			.sum();
+		let distance_extra: u64 = self
+			.distances
+			.iter()
+			.zip(DISTANCE_EXTRA_BITS.iter())
+			.map(|(freq, extra)| u64::from(*freq) * u64::from(u8::from(*extra)))
+			.sum();
+		length_extra + distance_extra
+	}
+}
+
+/// The codes of a custom-code block, along with the meta codes used to describe those codes
+pub struct DynamicCodes {
+	pub lengths: HuffmanEncoder,
+	pub distances: HuffmanEncoder,
+	pub meta: HuffmanEncoder,
+	pub num_length_codes: usize,
+	pub num_distance_codes: usize,
+	pub num_meta_codes: usize,
+	/// The meta codes describing the code lengths, each with the value of its extra bits
+	pub meta_symbols: Vec<(u8, u8)>,
+}
+
+impl DynamicCodes {
+	pub fn new(frequencies: &Frequencies) -> DynamicCodes {
+		let lengths = HuffmanEncoder::from_frequencies(&frequencies.lengths, 15);
+		let distances = HuffmanEncoder::from_frequencies(&frequencies.distances, 15);
+		let num_length_codes =
+			257.max(1 + lengths.lengths.iter().rposition(|x| *x != 0).unwrap_or(0));
+		let num_distance_codes =
+			1.max(1 + distances.lengths.iter().rposition(|x| *x != 0).unwrap_or(0));
+
+		let meta_symbols: Vec<(u8, u8)> = run_length_encode(&lengths.lengths[..num_length_codes])
+			.into_iter()
+			.chain(run_length_encode(&distances.lengths[..num_distance_codes]))
 			.collect();
-		lengths.sort_by_key(|x| x.length);
-		DynamicHuffmanCodes { backing: lengths }
+		let mut meta_frequencies = [0u32; 19];
+		for (symbol, _) in &meta_symbols {
+			meta_frequencies[usize::from(*symbol)] += 1;
+		}
+		let meta = HuffmanEncoder::from_frequencies(&meta_frequencies, 7);
+		let num_meta_codes = 4.max(
+			1 + META_CODES_ORDER
+				.iter()
+				.rposition(|x| meta.lengths[*x] != 0)
+				.unwrap_or(0),
+		);
+
+		DynamicCodes {
+			lengths,
+			distances,
+			meta,
+			num_length_codes,
+			num_distance_codes,
+			num_meta_codes,
+			meta_symbols,
+		}
 	}
 
-	/// Decodes a value using this set of dynamic huffman codes
-	fn decode<I: Iterator<Item = u8>>(&self, bitreader: &mut Bits<I>) -> Option<u16> {
-		let mut index: usize = 0;
-		let mut index_code: u16 = 0;
-		let mut read_code: u16 = 0;
-		let mut read_len: u4 = u4::_0;
+	/// The number of bits needed to write the codes, not including the block header
+	fn cost(&self) -> u64 {
+		let meta_symbols_cost: u64 = self
+			.meta_symbols
+			.iter()
+			.map(|(symbol, _)| {
+				u64::from(self.meta.lengths[usize::from(*symbol)] + meta_extra_bits(*symbol))
+			})
+			.sum();
+		5 + 5 + 4 + 3 * self.num_meta_codes as u64 + meta_symbols_cost
+	}
 
-		//eprintln!("Enter");
-		//eprintln!("  self: {:?}", self);
-		loop {
-			//eprintln!("  Start of loop");
-			while read_len < self.backing[index].length {
-				read_code += if bitreader.next()? {
-					read_len.nth_bit()
-				} else {
-					0
-				};
-				read_len += u4::_1;
-				//eprintln!("    Read Code: {:0w$b}", read_code, w = usize::from(read_len));
+	fn write(&self, writer: &mut BitWriter) {
+		writer.write_n_rev((self.num_length_codes - 257) as u32, 5);
+		writer.write_n_rev((self.num_distance_codes - 1) as u32, 5);
+		writer.write_n_rev((self.num_meta_codes - 4) as u32, 4);
+		for x in META_CODES_ORDER.iter().take(self.num_meta_codes) {
+			writer.write_n_rev(u32::from(self.meta.lengths[*x]), 3);
+		}
+		for (symbol, extra) in &self.meta_symbols {
+			self.meta.write(writer, usize::from(*symbol));
+			writer.write_n_rev(u32::from(*extra), meta_extra_bits(*symbol));
+		}
+	}
+}
+
+/// The number of extra bits following a meta code
+pub fn meta_extra_bits(symbol: u8) -> u8 {
+	match symbol {
+		16 => 2,
+		17 => 3,
+		18 => 7,
+		_ => 0,
+	}
+}
+
+/// Converts a list of code lengths into meta codes, using the repeat codes where possible
+fn run_length_encode(lengths: &[u8]) -> Vec<(u8, u8)> {
+	let mut retval: Vec<(u8, u8)> = Vec::new();
+	let mut index: usize = 0;
+	while index < lengths.len() {
+		let value = lengths[index];
+		let run = lengths[index..].iter().take_while(|x| **x == value).count();
+		let mut remaining = run;
+		if value == 0 {
+			while remaining >= 11 {
+				let count = remaining.min(138);
+				retval.push((18, (count - 11) as u8));
+				remaining -= count;
 			}
-			if read_len == self.backing[index].length && read_code == index_code {
-				//eprintln!("    Ret Code: {:0w$b}", read_code, w = usize::from(read_len));
-				//eprintln!("    Retval: {}", self.backing[index].value);
-				break Some(self.backing[index].value);
+			if remaining >= 3 {
+				retval.push((17, (remaining - 3) as u8));
+				remaining = 0;
 			}
-			index_code = u16_reverse_bits(index_code);
-			index_code += (u4::_F - self.backing[index].length + u4::_1).nth_bit();
-			index_code = u16_reverse_bits(index_code);
-			index += 1;
-			if index >= self.backing.len() {
-				panic!("Code not found");
+		} else {
+			retval.push((value, 0));
+			remaining -= 1;
+			while remaining >= 3 {
+				let count = remaining.min(6);
+				retval.push((16, (count - 3) as u8));
+				remaining -= count;
 			}
-			//eprintln!("    Index Code: {:0w$b}", index_code, w = usize::from(self.backing[index].length));
 		}
+		for _ in 0..remaining {
+			retval.push((value, 0));
+		}
+		index += run;
 	}
+	retval
+}
+
+/// Writes the tokens as a single block, or as several non-compressed blocks
+/// if non-compressed blocks are the smallest representation
+fn write_block(writer: &mut BitWriter, tokens: &[Token], raw: &[u8], is_final: bool) {
+	let frequencies = Frequencies::new(tokens);
+	let fixed_lengths = HuffmanEncoder::fixed_length_codes();
+	let fixed_distances = HuffmanEncoder::fixed_distance_codes();
+	let dynamic = DynamicCodes::new(&frequencies);
+
+	let fixed_cost = fixed_lengths.cost(&frequencies.lengths)
+		+ fixed_distances.cost(&frequencies.distances)
+		+ frequencies.extra_bits();
+	let dynamic_cost = dynamic.cost()
+		+ dynamic.lengths.cost(&frequencies.lengths)
+		+ dynamic.distances.cost(&frequencies.distances)
+		+ frequencies.extra_bits();
+	let stored_blocks = raw.len().div_ceil(0xFFFF).max(1) as u64;
+	let stored_cost = stored_blocks * (3 + 7 + 32) + 8 * raw.len() as u64;
+
+	if stored_cost < fixed_cost.min(dynamic_cost) {
+		let chunks: Vec<&[u8]> = raw.chunks(0xFFFF).collect();
+		let last_item = chunks.len() - 1;
+		for (idx, chunk) in chunks.into_iter().enumerate() {
+			writer.write_n_rev(u32::from(is_final && idx == last_item), 1);
+			writer.write_n_rev(0, 2);
+			writer.pad_to_byte_boundary();
+			writer.write_bytes(&(chunk.len() as u16).to_le_bytes());
+			writer.write_bytes(&(!(chunk.len() as u16)).to_le_bytes());
+			writer.write_bytes(chunk);
+		}
+	} else if fixed_cost <= dynamic_cost {
+		writer.write_n_rev(u32::from(is_final), 1);
+		writer.write_n_rev(1, 2);
+		write_tokens(writer, tokens, &fixed_lengths, &fixed_distances);
+	} else {
+		writer.write_n_rev(u32::from(is_final), 1);
+		writer.write_n_rev(2, 2);
+		dynamic.write(writer);
+		write_tokens(writer, tokens, &dynamic.lengths, &dynamic.distances);
+	}
+}
+
+/// Writes the tokens, followed by an end-of-block code
+pub fn write_tokens(
+	writer: &mut BitWriter,
+	tokens: &[Token],
+	lengths: &HuffmanEncoder,
+	distances: &HuffmanEncoder,
+) {
+	for token in tokens {
+		match token {
+			Token::Literal(x) => lengths.write(writer, usize::from(*x)),
+			Token::Match { length, distance } => {
+				let length_index = length_index(*length);
+				lengths.write(writer, 257 + length_index);
+				writer.write_n_rev(
+					u32::from(*length - LENGTH_BASE[length_index]),
+					u8::from(LENGTH_EXTRA_BITS[length_index]),
+				);
+				let distance_index = distance_index(*distance);
+				distances.write(writer, distance_index);
+				writer.write_n_rev(
+					u32::from(*distance - DISTANCE_BASE[distance_index]),
+					u8::from(DISTANCE_EXTRA_BITS[distance_index]),
+				);
+			},
+		}
+	}
+	lengths.write(writer, 256);
 }
 
 /// Appends values to the results vector based on the provided meta code, and possibly the next few bits in the bitreader.
-fn act_upon_meta_code<I: Iterator<Item = u8>>(
-	results: &mut Vec<u4>,
-	bitreader: &mut Bits<I>,
+fn act_upon_meta_code<S: ByteSource>(
+	results: &mut Vec<u8>,
+	bitreader: &mut Bits<S>,
 	code: u16,
 ) -> Result<(), InflateError> {
 	if code < 16 {
-		results.push(u4::truncate(code as u8));
+		results.push(code as u8);
 	} else if code == 16 {
-		let prev_code = results[results.len() - 1];
+		let prev_code = *results.last().ok_or(InflateError::RepeatWithoutPrevious)?;
 		let times = 3 + option_to_result(bitreader.read_n_rev(u4::_2))?;
 		for _ in 0..times {
 			results.push(prev_code);
@@ -319,48 +885,41 @@ fn act_upon_meta_code<I: Iterator<Item = u8>>(
 	} else if code == 17 {
 		let times = 3 + option_to_result(bitreader.read_n_rev(u4::_3))?;
 		for _ in 0..times {
-			results.push(u4::_0);
+			results.push(0);
 		}
 	} else if code == 18 {
 		let times = 11 + option_to_result(bitreader.read_n_rev(u4::_7))?;
 		for _ in 0..times {
-			results.push(u4::_0);
+			results.push(0);
 		}
 	} else {
-		panic!("Illegal meta code: {}", code);
+		return Err(InflateError::InvalidSymbol);
 	}
 	Ok(())
 }
 
-/// "u16::reverse_bits is an experimental API"
-fn u16_reverse_bits(a: u16) -> u16 {
-	let mut retval = 0;
-	for bit in 0..16 {
-		retval += if (a & (1 << bit)) != 0 {
-			1 << (15 - bit)
-		} else {
-			0
-		}
-	}
-	retval
-}
-
 // bits are intentionally grouped by huffman instruction, not by nibble
 #[allow(clippy::unusual_byte_groupings)]
 #[cfg(test)]
 mod tests {
-	mod decode_fixed_huffman_code {
-		use super::super::decode_fixed_huffman_code;
+	mod fixed_huffman_codes {
+		use super::super::super::huffman::Table;
+		use super::super::HuffmanEncoder;
+		use super::super::LENGTH_TABLE_BITS;
 
 		#[test]
 		fn hit_every_output() {
+			let dut = Table::from_lengths(
+				HuffmanEncoder::fixed_length_codes().lengths(),
+				LENGTH_TABLE_BITS,
+			)
+			.unwrap();
 			let mut res: [bool; 288] = [false; 288];
 
 			for i in u16::MIN..u16::MAX {
 				let bits: [u8; 2] = [((i >> 8) & 0xFF) as u8, (i & 0xFF) as u8];
-				let bits = bits.iter().cloned();
-				let mut bits = super::super::super::bits::Bits::new(bits);
-				res[usize::from(decode_fixed_huffman_code(&mut bits).unwrap())] = true;
+				let mut bits = super::super::super::bits::Bits::new(&bits[..]);
+				res[usize::from(dut.decode(&mut bits).unwrap())] = true;
 			}
 
 			for (index, result_item_was_hit) in res.iter().enumerate() {
@@ -370,30 +929,28 @@ fn hit_every_output() {
 	}
 
 	mod dynamic_huffman_codes {
-		use super::super::super::u4;
+		use super::super::super::huffman::Table;
 		use super::super::super::Bits;
-		use super::super::DynamicHuffmanCodes;
 
-		fn assert_decode(expected: u16, dut: &DynamicHuffmanCodes, huffman_code: u8) {
+		fn assert_decode(expected: u16, dut: &Table, huffman_code: u8) {
 			assert_eq!(
 				expected,
-				dut.decode(&mut Bits::new([huffman_code].iter().cloned()))
-					.unwrap()
+				dut.decode(&mut Bits::new(&[huffman_code][..])).unwrap()
 			);
 		}
 
 		#[test]
 		fn two_constant_length() {
-			let dut = [u4::_1, u4::_1];
-			let dut = DynamicHuffmanCodes::from_lengths(&dut);
+			let dut = [1, 1];
+			let dut = Table::from_lengths(&dut, 9).unwrap();
 
 			assert_decode(0, &dut, 0b0u8);
 			assert_decode(1, &dut, 0b1u8);
 		}
 		#[test]
 		fn four_constant_length() {
-			let dut = [u4::_2, u4::_2, u4::_2, u4::_2];
-			let dut = DynamicHuffmanCodes::from_lengths(&dut);
+			let dut = [2, 2, 2, 2];
+			let dut = Table::from_lengths(&dut, 9).unwrap();
 
 			assert_decode(0, &dut, 0b00u8);
 			assert_decode(1, &dut, 0b10u8);
@@ -402,8 +959,8 @@ fn four_constant_length() {
 		}
 		#[test]
 		fn four_constant_delta() {
-			let dut = [u4::_1, u4::_2, u4::_3, u4::_4];
-			let dut = DynamicHuffmanCodes::from_lengths(&dut);
+			let dut = [1, 2, 3, 4];
+			let dut = Table::from_lengths(&dut, 9).unwrap();
 
 			assert_decode(0, &dut, 0b000_0u8);
 			assert_decode(1, &dut, 0b00_01u8);
@@ -413,8 +970,8 @@ fn four_constant_delta() {
 		#[test]
 		fn provided_sample() {
 			#[rustfmt::skip]
-			let dut = [u4::_3, u4::_3, u4::_3, u4::_3, u4::_3, u4::_2, u4::_4, u4::_4];
-			let dut = DynamicHuffmanCodes::from_lengths(&dut);
+			let dut = [3, 3, 3, 3, 3, 2, 4, 4];
+			let dut = Table::from_lengths(&dut, 9).unwrap();
 
 			assert_decode(5, &dut, 0b00_00u8);
 			assert_decode(0, &dut, 0b0_010u8);
@@ -435,24 +992,21 @@ fn immediate_mode() {
 			let exp: [u8; 10] = [1,2,3,4,5,6,7,8,9,10];
 			#[rustfmt::skip]
 			let dut: [u8; 15] = [1, 10, 0, !10, 0xFF, 1,2,3,4,5,6,7,8,9,10];
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert!(exp == res.as_slice(), "{:?}", res);
 		}
 		#[test]
 		fn one_zero() {
 			let exp: [u8; 1] = [0];
 			let dut: [u8; 3] = [0x63, 0x00, 0x00];
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert!(exp == res.as_slice(), "{:?}", res);
 		}
 		#[test]
 		fn four_zero() {
 			let exp: [u8; 4] = [0; 4];
 			let dut: [u8; 4] = [0x63, 0x00, 0x02, 0x00];
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert!(exp == res.as_slice(), "{:?}", res);
 		}
 		#[test]
@@ -461,8 +1015,7 @@ fn abcde_times_five() {
 			let exp: [u8; 25] = [0x61, 0x62, 0x63, 0x64, 0x65, 0x61, 0x62, 0x63, 0x64, 0x65, 0x61, 0x62, 0x63, 0x64, 0x65, 0x61, 0x62, 0x63, 0x64, 0x65, 0x61, 0x62, 0x63, 0x64, 0x65];
 			#[rustfmt::skip]
 			let dut: [u8; 9] = [0x4b, 0x4c, 0x4a, 0x4e, 0x49, 0xc5, 0x46, 0x00, 0x00];
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert!(exp == res.as_slice(), "{:?}", res);
 		}
 		#[test]
@@ -476,8 +1029,7 @@ fn black_square_4x4() {
 			];
 			#[rustfmt::skip]
 			let dut: [u8; 21] = [0x9d, 0xc8, 0xb1, 0x0d, 0x00, 0x00, 0x00, 0x82, 0x30, 0xff, 0x7f, 0x5a, 0x1d, 0x99, 0x21, 0x61, 0x69, 0x5e, 0xb9, 0x80, 0x01];
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert_eq!(exp.len(), res.len(), "LENGTH");
 			assert!(exp.iter().zip(res.iter()).all(|(a, b)| a == b));
 		}
@@ -494,8 +1046,7 @@ fn immediate() {
 				.cloned()
 				.chain(0..=255)
 				.collect();
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert_eq!(exp.len(), res.len(), "LENGTH");
 			assert!(exp.iter().zip(res.iter()).all(|(a, b)| a == b));
 		}
@@ -509,8 +1060,7 @@ fn fixed_coded_l003d1() {
 				.chain(0..=255)
 				.chain([0b00000_011, 0b0_00000_10, 0b000000].iter().cloned())
 				.collect();
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert_eq!(exp.len(), res.len(), "LENGTH");
 			assert!(exp.iter().zip(res.iter()).all(|(a, b)| a == b));
 		}
@@ -524,8 +1074,7 @@ fn fixed_coded_l010d1() {
 				.chain(0..=255)
 				.chain([0b01000_011, 0b0_00000_00, 0b000000].iter().cloned())
 				.collect();
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert_eq!(exp.len(), res.len(), "LENGTH");
 			assert!(exp.iter().zip(res.iter()).all(|(a, b)| a == b));
 		}
@@ -539,8 +1088,7 @@ fn fixed_coded_l257d1() {
 				.chain(0..=255)
 				.chain([0b00011_011, 0b_11110_001, 0, 0].iter().cloned())
 				.collect();
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert_eq!(exp.len(), res.len(), "LENGTH");
 			assert!(exp.iter().zip(res.iter()).all(|(a, b)| a == b));
 		}
@@ -554,8 +1102,7 @@ fn fixed_coded_l258d1() {
 				.chain(0..=255)
 				.chain([0b00011_011, 0b_00000_101, 0].iter().cloned())
 				.collect();
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert_eq!(exp.len(), res.len(), "LENGTH");
 			assert!(exp.iter().zip(res.iter()).all(|(a, b)| a == b));
 		}
@@ -569,8 +1116,7 @@ fn fixed_coded_l003d4() {
 				.chain(0..=255)
 				.chain([0b00000_011, 0b0_11000_10, 0b000000].iter().cloned())
 				.collect();
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert_eq!(exp.len(), res.len(), "LENGTH");
 			assert!(exp.iter().zip(res.iter()).all(|(a, b)| a == b));
 		}
@@ -584,8 +1130,7 @@ fn fixed_coded_l003d97() {
 				.chain(0..=255)
 				.chain([0b00000_011, 0b0_10110_10, 0, 0].iter().cloned())
 				.collect();
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert_eq!(exp.len(), res.len(), "LENGTH");
 			assert!(exp.iter().zip(res.iter()).all(|(a, b)| a == b));
 		}
@@ -599,8 +1144,7 @@ fn fixed_coded_l003d128() {
 				.chain(0..=255)
 				.chain([0b00000_011, 0b1_10110_10, 0b0000_1111, 0].iter().cloned())
 				.collect();
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert_eq!(exp.len(), res.len(), "LENGTH");
 			assert!(exp.iter().zip(res.iter()).all(|(a, b)| a == b));
 		}
@@ -622,8 +1166,7 @@ fn fixed_codes_l003d24577() {
 				.chain(base)
 				.chain([0b00000_011, 0b0_10111_10, 0, 0, 0].iter().cloned())
 				.collect();
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert_eq!(exp.len(), res.len(), "LENGTH");
 			assert!(exp.iter().zip(res.iter()).all(|(a, b)| a == b));
 		}
@@ -649,10 +1192,165 @@ fn fixed_codes_l003d32768() {
 						.cloned(),
 				)
 				.collect();
-			let mut dut = dut.iter().cloned();
-			let res = inflate(&mut dut).unwrap();
+			let res = inflate(&mut &dut[..], usize::MAX).unwrap();
 			assert_eq!(exp.len(), res.len(), "LENGTH");
 			assert!(exp.iter().zip(res.iter()).all(|(a, b)| a == b));
 		}
 	}
+
+	mod inflate_position {
+		use super::super::deflate_immediate;
+		use super::super::inflate;
+
+		#[test]
+		fn input_advances_to_end_of_stream() {
+			let compressed = deflate_immediate(b"abcdefghijklmnopqrstuvwxyz".iter().cloned());
+			let trailing = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+			let dut: Vec<u8> = compressed.iter().chain(trailing.iter()).cloned().collect();
+			let mut input = &dut[..];
+			let res = inflate(&mut input, usize::MAX).unwrap();
+			assert_eq!(b"abcdefghijklmnopqrstuvwxyz".to_vec(), res);
+			assert_eq!(&trailing[..], input);
+		}
+
+		#[test]
+		fn input_advances_past_partial_byte() {
+			// a final fixed block holding only the end-of-block code, which ends mid-byte
+			let dut = [0x03, 0x00, 0xAA, 0xBB];
+			let mut input = &dut[..];
+			let res = inflate(&mut input, usize::MAX).unwrap();
+			assert!(res.is_empty());
+			assert_eq!(&[0xAA, 0xBB][..], input);
+		}
+	}
+
+	mod inflate_errors {
+		use super::super::super::bits::BitWriter;
+		use super::super::inflate;
+		use super::super::InflateError;
+
+		fn inflate_bits(writer: BitWriter) -> Result<Vec<u8>, InflateError> {
+			let dut = writer.finish();
+			inflate(&mut &dut[..], usize::MAX)
+		}
+
+		/// Writes the start of a final dynamic block whose meta code lengths are, in order, the
+		/// lengths of meta symbols 16, 17, 18 and 0
+		fn dynamic_header(
+			num_length_codes: u32,
+			num_distance_codes: u32,
+			meta: [u32; 4],
+		) -> BitWriter {
+			let mut writer = BitWriter::new();
+			writer.write_n_rev(1, 1);
+			writer.write_n_rev(2, 2);
+			writer.write_n_rev(num_length_codes - 257, 5);
+			writer.write_n_rev(num_distance_codes - 1, 5);
+			writer.write_n_rev(0, 4);
+			for length in meta {
+				writer.write_n_rev(length, 3);
+			}
+			writer
+		}
+
+		#[test]
+		fn fixed_invalid_length_symbol() {
+			let mut writer = BitWriter::new();
+			writer.write_n_rev(1, 1);
+			writer.write_n_rev(1, 2);
+			writer.write_n(0b11000110, 8);
+			assert_eq!(Err(InflateError::InvalidSymbol), inflate_bits(writer));
+		}
+
+		#[test]
+		fn fixed_invalid_distance_symbol() {
+			let mut writer = BitWriter::new();
+			writer.write_n_rev(1, 1);
+			writer.write_n_rev(1, 2);
+			writer.write_n(0b00110000, 8);
+			writer.write_n(0b0000001, 7);
+			writer.write_n(30, 5);
+			assert_eq!(Err(InflateError::InvalidSymbol), inflate_bits(writer));
+		}
+
+		#[test]
+		fn distance_too_far() {
+			let mut writer = BitWriter::new();
+			writer.write_n_rev(1, 1);
+			writer.write_n_rev(1, 2);
+			writer.write_n(0b00110000, 8);
+			writer.write_n(0b0000001, 7);
+			writer.write_n(1, 5);
+			assert_eq!(Err(InflateError::DistanceTooFar), inflate_bits(writer));
+		}
+
+		#[test]
+		fn repeat_without_previous() {
+			let mut writer = dynamic_header(257, 1, [1, 0, 0, 1]);
+			writer.write_n(0b1, 1);
+			writer.write_n_rev(0, 2);
+			assert_eq!(
+				Err(InflateError::RepeatWithoutPrevious),
+				inflate_bits(writer)
+			);
+		}
+
+		#[test]
+		fn oversubscribed_meta_codes() {
+			let writer = dynamic_header(257, 1, [1, 1, 1, 1]);
+			assert_eq!(
+				Err(InflateError::OversubscribedCodeLengths),
+				inflate_bits(writer)
+			);
+		}
+
+		#[test]
+		fn invalid_meta_code() {
+			let mut writer = dynamic_header(257, 1, [0, 0, 0, 1]);
+			writer.write_n(0b1, 1);
+			assert_eq!(Err(InflateError::InvalidCode), inflate_bits(writer));
+		}
+
+		#[test]
+		fn too_many_code_lengths() {
+			let mut writer = dynamic_header(257, 1, [0, 0, 1, 1]);
+			writer.write_n(0b1, 1);
+			writer.write_n_rev(127, 7);
+			writer.write_n(0b1, 1);
+			writer.write_n_rev(127, 7);
+			assert_eq!(Err(InflateError::TooManyCodeLengths), inflate_bits(writer));
+		}
+
+		#[test]
+		fn repeat_crosses_into_distance_lengths() {
+			let mut writer = BitWriter::new();
+			writer.write_n_rev(1, 1);
+			writer.write_n_rev(2, 2);
+			writer.write_n_rev(258 - 257, 5);
+			writer.write_n_rev(2 - 1, 5);
+			writer.write_n_rev(18 - 4, 4);
+			// meta symbol 1 has length 1; meta symbols 17 and 18 have length 2
+			#[rustfmt::skip]
+			let meta = [0, 2, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+			for length in meta {
+				writer.write_n_rev(length, 3);
+			}
+			// literal 0
+			writer.write_n(0b0, 1);
+			// literals 1 through 255
+			writer.write_n(0b11, 2);
+			writer.write_n_rev(127, 7);
+			writer.write_n(0b11, 2);
+			writer.write_n_rev(106, 7);
+			// end of block
+			writer.write_n(0b0, 1);
+			// length 257 and both distance codes
+			writer.write_n(0b10, 2);
+			writer.write_n_rev(0, 3);
+
+			writer.write_n(0b0, 1);
+			writer.write_n(0b1, 1);
+			assert_eq!(Ok(vec![0]), inflate_bits(writer));
+		}
+	}
 }
@@ -0,0 +1,402 @@
+//! Construction and decoding of the length-limited canonical huffman codes used by deflate
+
+use super::bits::PeekBits;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// Determines the code length of each symbol, such that the most frequent symbols get the shortest
+/// codes and such that no code is longer than `max_length`.
+///
+/// Symbols with a frequency of zero get a code length of zero. If there are at least two symbols
+/// with a nonzero frequency, the resulting lengths form a complete prefix code.
+pub fn code_lengths(frequencies: &[u32], max_length: u8) -> Vec<u8> {
+	let mut retval = vec![0u8; frequencies.len()];
+	let used: Vec<usize> = (0..frequencies.len())
+		.filter(|x| frequencies[*x] != 0)
+		.collect();
+
+	match used.len() {
+		0 => return retval,
+		1 => {
+			retval[used[0]] = 1;
+			return retval;
+		},
+		_ => {},
+	}
+
+	// Build an unrestricted huffman tree, tracking only each node's parent
+	let mut parents: Vec<usize> = vec![usize::MAX; used.len()];
+	let mut heap: BinaryHeap<Reverse<(u64, usize)>> = used
+		.iter()
+		.enumerate()
+		.map(|(node, symbol)| Reverse((u64::from(frequencies[*symbol]), node)))
+		.collect();
+	while heap.len() > 1 {
+		let Reverse((weight_a, node_a)) = heap.pop().expect("heap.len() > 1");
+		let Reverse((weight_b, node_b)) = heap.pop().expect("heap.len() > 1");
+		let new_node = parents.len();
+		parents.push(usize::MAX);
+		parents[node_a] = new_node;
+		parents[node_b] = new_node;
+		heap.push(Reverse((weight_a + weight_b, new_node)));
+	}
+
+	// Count the number of leaves at each depth, clamping the depth to max_length
+	let max_length = usize::from(max_length);
+	let mut length_counts: Vec<u32> = vec![0; max_length + 1];
+	for leaf in 0..used.len() {
+		let mut depth = 0;
+		let mut node = leaf;
+		while parents[node] != usize::MAX {
+			node = parents[node];
+			depth += 1;
+		}
+		length_counts[depth.min(max_length)] += 1;
+	}
+
+	// Clamping lengths may have oversubscribed the code; repeatedly remove a leaf at the
+	// maximum depth and split a shallower leaf into two until the code is complete again.
+	let kraft = |counts: &[u32]| -> u64 {
+		counts
+			.iter()
+			.enumerate()
+			.map(|(length, count)| u64::from(*count) << (max_length - length))
+			.sum()
+	};
+	let mut total = kraft(&length_counts);
+	while total > (1 << max_length) {
+		length_counts[max_length] -= 1;
+		for length in (1..max_length).rev() {
+			if length_counts[length] != 0 {
+				length_counts[length] -= 1;
+				length_counts[length + 1] += 2;
+				break;
+			}
+		}
+		total -= 1;
+	}
+
+	// Assign the shortest lengths to the most frequent symbols
+	let mut by_frequency = used;
+	by_frequency.sort_by_key(|x| (Reverse(frequencies[*x]), *x));
+	let mut by_frequency = by_frequency.into_iter();
+	for (length, count) in length_counts.iter().enumerate() {
+		for _ in 0..*count {
+			let symbol = by_frequency
+				.next()
+				.expect("counts sum to the number of symbols");
+			retval[symbol] = length as u8;
+		}
+	}
+	retval
+}
+
+/// Assigns canonical huffman codes, as defined by RFC 1951 section 3.2.2, to symbols with the
+/// given code lengths.
+///
+/// Symbols with a length of zero are assigned a code of zero, which should not be used.
+pub fn codes_from_lengths(lengths: &[u8]) -> Vec<u16> {
+	let max_length = usize::from(lengths.iter().cloned().max().unwrap_or(0));
+	let mut length_counts: Vec<u16> = vec![0; max_length + 1];
+	for length in lengths {
+		length_counts[usize::from(*length)] += 1;
+	}
+	length_counts[0] = 0;
+
+	let mut next_code: Vec<u16> = vec![0; max_length + 1];
+	let mut code: u16 = 0;
+	for length in 1..=max_length {
+		code = (code + length_counts[length - 1]) << 1;
+		next_code[length] = code;
+	}
+
+	lengths
+		.iter()
+		.map(|length| {
+			let length = usize::from(*length);
+			if length == 0 {
+				0
+			} else {
+				let retval = next_code[length];
+				next_code[length] += 1;
+				retval
+			}
+		})
+		.collect()
+}
+
+/// Whether the given code lengths need more codes than there are bit sequences of those lengths,
+/// in which case they do not describe a prefix code. Each length must be at most 15.
+pub fn is_oversubscribed(lengths: &[u8]) -> bool {
+	let used: u32 = lengths
+		.iter()
+		.filter(|x| **x != 0)
+		.map(|x| 1 << (15 - x))
+		.sum();
+	used > 1 << 15
+}
+
+/// An error that can occur while decoding a symbol
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum DecodeError {
+	/// The input ended partway through a code
+	UnexpectedEof,
+	/// The input contains a bit sequence that is not the code of any symbol
+	InvalidCode,
+}
+
+/// An entry of a decoding [`Table`]
+#[derive(Copy, Clone, Debug)]
+enum Entry {
+	/// No code starts with the bits that lead to this entry
+	Invalid,
+	/// The bits leading to this entry start with the code for `symbol`, which is `length` bits long
+	Symbol { symbol: u16, length: u8 },
+	/// The bits leading to this entry are the start of codes longer than the primary table's index,
+	/// and the next `bits` bits are an index into the sub-table starting at `offset`
+	Link { offset: u16, bits: u8 },
+}
+
+/// Decodes symbols of a canonical huffman code using lookup tables
+///
+/// A primary table is indexed by the next several bits of input; entries for codes longer than
+/// that index point to sub-tables indexed by the bits after it.
+pub struct Table {
+	/// the number of bits used to index the primary table
+	primary_bits: u8,
+	/// the primary table, followed by each sub-table
+	entries: Vec<Entry>,
+}
+
+impl Table {
+	/// Creates a decoder for the code with the given lengths. Each length must be at most 15.
+	/// Returns None if the lengths are oversubscribed.
+	///
+	/// `primary_bits` is the number of bits used to index the primary table. A larger primary table
+	/// makes decoding faster, but takes longer to build.
+	pub fn from_lengths(lengths: &[u8], primary_bits: u8) -> Option<Table> {
+		if is_oversubscribed(lengths) {
+			return None;
+		}
+		let codes = codes_from_lengths(lengths);
+		let max_length = lengths.iter().cloned().max().unwrap_or(0);
+		let primary_bits = primary_bits.min(max_length).max(1);
+		let primary_mask = (1usize << primary_bits) - 1;
+
+		// The bits of a code, in the order they are read
+		let reversed = |symbol: usize| -> usize {
+			let length = u32::from(lengths[symbol]);
+			usize::from(codes[symbol].reverse_bits() >> (16 - length))
+		};
+		let mut entries: Vec<Entry> = vec![Entry::Invalid; 1 << primary_bits];
+
+		let mut sub_table_bits: Vec<u8> = vec![0; 1 << primary_bits];
+		for (symbol, length) in lengths.iter().enumerate() {
+			if *length > primary_bits {
+				let prefix = reversed(symbol) & primary_mask;
+				sub_table_bits[prefix] = sub_table_bits[prefix].max(length - primary_bits);
+			}
+		}
+		for (prefix, bits) in sub_table_bits.into_iter().enumerate() {
+			if bits != 0 {
+				entries[prefix] = Entry::Link {
+					offset: entries.len() as u16,
+					bits,
+				};
+				entries.extend(std::iter::repeat_n(Entry::Invalid, 1 << bits));
+			}
+		}
+
+		for (symbol, length) in lengths.iter().cloned().enumerate() {
+			if length == 0 {
+				continue;
+			}
+			let entry = Entry::Symbol {
+				symbol: symbol as u16,
+				length,
+			};
+			let code = reversed(symbol);
+			if length <= primary_bits {
+				for index in (code..(1 << primary_bits)).step_by(1 << length) {
+					entries[index] = entry;
+				}
+			} else if let Entry::Link { offset, bits } = entries[code & primary_mask] {
+				let offset = usize::from(offset);
+				let rest = code >> primary_bits;
+				let rest_length = length - primary_bits;
+				for index in (rest..(1 << bits)).step_by(1 << rest_length) {
+					entries[offset + index] = entry;
+				}
+			}
+		}
+
+		Some(Table {
+			primary_bits,
+			entries,
+		})
+	}
+
+	/// Reads one symbol
+	///
+	/// Reads no more input than is needed to determine the symbol.
+	pub fn decode<B: PeekBits>(&self, bits: &mut B) -> Result<u16, DecodeError> {
+		let primary_mask = (1u32 << self.primary_bits) - 1;
+		loop {
+			let (buffer, available) = bits.peek();
+			let (symbol, length) = match self.entries[(buffer & primary_mask) as usize] {
+				Entry::Symbol { symbol, length } => (Some(symbol), length),
+				Entry::Invalid => (None, self.primary_bits),
+				Entry::Link { offset, bits } => {
+					let index = (buffer >> self.primary_bits) & ((1 << bits) - 1);
+					match self.entries[usize::from(offset) + index as usize] {
+						Entry::Symbol { symbol, length } => (Some(symbol), length),
+						_ => (None, self.primary_bits + bits),
+					}
+				},
+			};
+			// If not enough bits were available, then the entry may have been chosen based on
+			// the zeros past the available bits
+			if length <= available {
+				let symbol = symbol.ok_or(DecodeError::InvalidCode)?;
+				bits.consume(length);
+				return Ok(symbol);
+			}
+			bits.pull_byte().ok_or(DecodeError::UnexpectedEof)?;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	mod code_lengths {
+		use super::super::code_lengths;
+
+		fn kraft_sum_is_complete(lengths: &[u8], max_length: u8) -> bool {
+			let sum: u64 = lengths
+				.iter()
+				.filter(|x| **x != 0)
+				.map(|x| 1u64 << (max_length - x))
+				.sum();
+			sum == (1 << max_length)
+		}
+
+		#[test]
+		fn empty() {
+			assert_eq!(vec![0u8; 4], code_lengths(&[0, 0, 0, 0], 15));
+		}
+
+		#[test]
+		fn single_symbol() {
+			assert_eq!(vec![0, 0, 1, 0], code_lengths(&[0, 0, 5, 0], 15));
+		}
+
+		#[test]
+		fn equal_frequencies() {
+			assert_eq!(vec![2, 2, 2, 2], code_lengths(&[3, 3, 3, 3], 15));
+		}
+
+		#[test]
+		fn skewed_frequencies() {
+			let res = code_lengths(&[8, 4, 2, 1, 1], 15);
+			assert_eq!(vec![1, 2, 3, 4, 4], res);
+		}
+
+		#[test]
+		fn respects_length_limit() {
+			// fibonacci frequencies produce a maximally deep tree
+			let mut freqs: Vec<u32> = vec![1, 1];
+			while freqs.len() < 30 {
+				freqs.push(freqs[freqs.len() - 1] + freqs[freqs.len() - 2]);
+			}
+			let res = code_lengths(&freqs, 15);
+			assert!(res.iter().all(|x| *x <= 15 && *x >= 1), "{:?}", res);
+			assert!(kraft_sum_is_complete(&res, 15), "{:?}", res);
+			// more frequent symbols never get longer codes
+			for i in 1..res.len() {
+				assert!(res[i] <= res[i - 1], "{:?}", res);
+			}
+		}
+
+		#[test]
+		fn respects_short_length_limit() {
+			let freqs: Vec<u32> = (0..19).map(|x| 1 << x).collect();
+			let res = code_lengths(&freqs, 7);
+			assert!(res.iter().all(|x| *x <= 7 && *x >= 1), "{:?}", res);
+			assert!(kraft_sum_is_complete(&res, 7), "{:?}", res);
+		}
+	}
+
+	mod codes_from_lengths {
+		use super::super::codes_from_lengths;
+
+		#[test]
+		fn rfc1951_example() {
+			// the example from RFC 1951 section 3.2.2
+			let res = codes_from_lengths(&[3, 3, 3, 3, 3, 2, 4, 4]);
+			assert_eq!(
+				vec![0b010, 0b011, 0b100, 0b101, 0b110, 0b00, 0b1110, 0b1111],
+				res
+			);
+		}
+
+		#[test]
+		fn unused_symbols() {
+			let res = codes_from_lengths(&[1, 0, 1]);
+			assert_eq!(vec![0, 0, 1], res);
+		}
+	}
+
+	mod table {
+		use super::super::super::bits::BitCursor;
+		use super::super::DecodeError;
+		use super::super::Table;
+
+		#[test]
+		fn rfc1951_example() {
+			let dut = Table::from_lengths(&[3, 3, 3, 3, 3, 2, 4, 4], 9).unwrap();
+			// the codes 00, 010 and 1111; each code is read MSB first, but bytes are read LSB first
+			let mut bits = BitCursor::new(&[0b1110_1000, 0b1]);
+			assert_eq!(Ok(5), dut.decode(&mut bits));
+			assert_eq!(Ok(0), dut.decode(&mut bits));
+			assert_eq!(Ok(7), dut.decode(&mut bits));
+		}
+
+		#[test]
+		fn incomplete_code() {
+			let dut = Table::from_lengths(&[1], 9).unwrap();
+			let mut bits = BitCursor::new(&[0b10]);
+			assert_eq!(Ok(0), dut.decode(&mut bits));
+			assert_eq!(Err(DecodeError::InvalidCode), dut.decode(&mut bits));
+		}
+
+		#[test]
+		fn oversubscribed_code() {
+			assert!(Table::from_lengths(&[1, 1, 1], 9).is_none());
+			assert!(Table::from_lengths(&[1, 2, 3, 4, 5, 5, 5], 9).is_none());
+		}
+
+		#[test]
+		fn codes_longer_than_primary_table() {
+			// lengths 1, 2, 3, ..., 14, 15, 15
+			let lengths: Vec<u8> = (1..=15).chain([15]).collect();
+			let dut = Table::from_lengths(&lengths, 4).unwrap();
+			// the code for symbol 14 is fourteen ones then a zero; then the code for symbol 0
+			let mut bits = BitCursor::new(&[0xFF, 0b0011_1111]);
+			assert_eq!(Ok(14), dut.decode(&mut bits));
+			assert_eq!(Ok(0), dut.decode(&mut bits));
+			// the code for symbol 15 is fifteen ones
+			let mut bits = BitCursor::new(&[0xFF, 0b0111_1111]);
+			assert_eq!(Ok(15), dut.decode(&mut bits));
+		}
+
+		#[test]
+		fn code_at_end_of_input() {
+			let dut = Table::from_lengths(&[2, 2, 2, 3, 3], 9).unwrap();
+			// the code for symbol 4 is 111, and is followed by nothing
+			let mut bits = BitCursor::new(&[0b1111_1111]);
+			assert_eq!(Ok(4), dut.decode(&mut bits));
+			assert_eq!(Ok(4), dut.decode(&mut bits));
+			assert_eq!(Err(DecodeError::UnexpectedEof), dut.decode(&mut bits));
+		}
+	}
+}
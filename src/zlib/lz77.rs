@@ -0,0 +1,328 @@
+//! Finds repeated substrings in a byte sequence, so that they can be encoded as back-references
+//! ("matches") instead of as literal bytes
+
+/// The shortest match that deflate can represent
+pub const MIN_MATCH: usize = 3;
+/// The longest match that deflate can represent
+pub const MAX_MATCH: usize = 258;
+/// The furthest back that a deflate match can reference
+pub const WINDOW_SIZE: usize = 32768;
+
+const HASH_BITS: u32 = 15;
+const NO_POSITION: usize = usize::MAX;
+
+/// A single item of the tokenized input
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Token {
+	/// A single byte, copied as-is
+	Literal(u8),
+	/// A copy of `length` bytes starting `distance` bytes before the current position
+	Match { length: u16, distance: u16 },
+}
+
+impl Token {
+	/// The number of input bytes this token represents
+	pub fn len(&self) -> usize {
+		match self {
+			Token::Literal(_) => 1,
+			Token::Match { length, .. } => usize::from(*length),
+		}
+	}
+}
+
+/// The parameters that control how hard the matcher tries to find long matches
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Parameters {
+	/// The maximum number of previous positions to examine when looking for a match
+	pub max_chain: usize,
+	/// Stop searching for a longer match once a match at least this long is found
+	pub nice_length: usize,
+	/// If true, before using a match, check whether starting a match at the next byte would give a longer match
+	pub lazy: bool,
+}
+
+/// Remembers, for each hash of three bytes, the positions in the input that had that hash
+struct HashChains {
+	/// The most recent position with each hash
+	head: Vec<usize>,
+	/// For each position in the window, the previous position with the same hash. The length of
+	/// this is the window size.
+	prev: Vec<usize>,
+}
+
+impl HashChains {
+	fn new(window_size: usize) -> HashChains {
+		HashChains {
+			head: vec![NO_POSITION; 1 << HASH_BITS],
+			prev: vec![NO_POSITION; window_size],
+		}
+	}
+
+	fn hash(data: &[u8], pos: usize) -> usize {
+		let value = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], 0]);
+		(value.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+	}
+
+	/// Records that `pos` starts with the bytes it starts with
+	fn insert(&mut self, data: &[u8], pos: usize) {
+		if pos + MIN_MATCH <= data.len() {
+			let hash = HashChains::hash(data, pos);
+			let slot = pos % self.prev.len();
+			self.prev[slot] = self.head[hash];
+			self.head[hash] = pos;
+		}
+	}
+
+	/// Finds the longest match for the bytes starting at `pos`, considering only previous positions
+	/// already inserted into the chains. Returns the match length and distance.
+	fn longest_match(&self, data: &[u8], pos: usize, params: &Parameters) -> (usize, usize) {
+		let max_length = MAX_MATCH.min(data.len() - pos);
+		if max_length < MIN_MATCH {
+			return (0, 0);
+		}
+		let mut best_length = MIN_MATCH - 1;
+		let mut best_distance = 0;
+		let mut candidate = self.head[HashChains::hash(data, pos)];
+		let mut chain = params.max_chain;
+
+		while candidate != NO_POSITION && chain > 0 {
+			if candidate >= pos || pos - candidate > self.prev.len() {
+				break;
+			}
+			// a cheap check of the byte that would make this match better than the current best
+			if data[candidate + best_length] == data[pos + best_length] {
+				let length = data[candidate..candidate + max_length]
+					.iter()
+					.zip(&data[pos..pos + max_length])
+					.take_while(|(a, b)| a == b)
+					.count();
+				if length > best_length {
+					best_length = length;
+					best_distance = pos - candidate;
+					if length >= params.nice_length || length == max_length {
+						break;
+					}
+				}
+			}
+			let next = self.prev[candidate % self.prev.len()];
+			if next != NO_POSITION && next >= candidate {
+				// the slot has been overwritten by a newer position
+				break;
+			}
+			candidate = next;
+			chain -= 1;
+		}
+
+		if best_length >= MIN_MATCH {
+			(best_length, best_distance)
+		} else {
+			(0, 0)
+		}
+	}
+}
+
+/// Decides, one position at a time, which token to use to represent the input
+///
+/// The decision at each position depends only on the data and on the positions that have
+/// previously been passed to `advance`, so the same decisions can be recreated later.
+pub trait Predictor {
+	/// Returns the token that should represent the data starting at `pos`
+	///
+	/// `pos` must be the position passed to `advance` most recently plus that call's length.
+	fn predict(&mut self, data: &[u8], pos: usize) -> Token;
+
+	/// Records that the `length` bytes starting at `pos` have been represented by a token
+	fn advance(&mut self, data: &[u8], pos: usize, length: usize);
+}
+
+/// This crate's own match finder
+pub struct Matcher {
+	params: Parameters,
+	chains: HashChains,
+	/// the positions before this one have been inserted into `chains`
+	inserted_until: usize,
+}
+
+impl Matcher {
+	pub fn new(params: Parameters) -> Matcher {
+		Matcher::with_window(params, WINDOW_SIZE)
+	}
+
+	/// Creates a matcher whose matches do not refer further back than `window_size`
+	pub fn with_window(params: Parameters, window_size: usize) -> Matcher {
+		Matcher {
+			params,
+			chains: HashChains::new(window_size),
+			inserted_until: 0,
+		}
+	}
+
+	fn insert_until(&mut self, data: &[u8], end: usize) {
+		while self.inserted_until < end {
+			self.chains.insert(data, self.inserted_until);
+			self.inserted_until += 1;
+		}
+	}
+}
+
+impl Predictor for Matcher {
+	fn predict(&mut self, data: &[u8], pos: usize) -> Token {
+		let (length, distance) = self.chains.longest_match(data, pos, &self.params);
+		if length < MIN_MATCH {
+			return Token::Literal(data[pos]);
+		}
+		if self.params.lazy && length < self.params.nice_length && pos + 1 < data.len() {
+			// Any token starting here covers `pos`, so `pos` can be inserted already
+			self.insert_until(data, pos + 1);
+			let (next_length, _) = self.chains.longest_match(data, pos + 1, &self.params);
+			if next_length > length {
+				return Token::Literal(data[pos]);
+			}
+		}
+		Token::Match {
+			length: length as u16,
+			distance: distance as u16,
+		}
+	}
+
+	fn advance(&mut self, data: &[u8], pos: usize, length: usize) {
+		self.insert_until(data, pos + length);
+	}
+}
+
+/// Converts the input into a sequence of literals and matches
+#[allow(dead_code)]
+pub fn tokenize(data: &[u8], params: &Parameters) -> Vec<Token> {
+	tokenize_from(data, 0, WINDOW_SIZE, params)
+}
+
+/// Converts the input, starting at `start`, into a sequence of literals and matches. Matches may
+/// refer to the data before `start`, but not further back than `window_size`.
+pub fn tokenize_from(
+	data: &[u8],
+	start: usize,
+	window_size: usize,
+	params: &Parameters,
+) -> Vec<Token> {
+	let mut retval: Vec<Token> = Vec::new();
+	let mut matcher = Matcher::with_window(*params, window_size);
+	matcher.advance(data, 0, start);
+	let mut pos: usize = start;
+
+	while pos < data.len() {
+		let token = matcher.predict(data, pos);
+		matcher.advance(data, pos, token.len());
+		pos += token.len();
+		retval.push(token);
+	}
+	retval
+}
+
+#[cfg(test)]
+mod tests {
+	mod tokenize {
+		use super::super::tokenize;
+		use super::super::tokenize_from;
+		use super::super::Parameters;
+		use super::super::Token;
+
+		const PARAMS: Parameters = Parameters {
+			max_chain: 128,
+			nice_length: 258,
+			lazy: true,
+		};
+
+		/// Reverses the tokenization, to confirm that the tokens represent the input
+		fn expand(tokens: &[Token]) -> Vec<u8> {
+			let mut retval = Vec::new();
+			for token in tokens {
+				match token {
+					Token::Literal(x) => retval.push(*x),
+					Token::Match { length, distance } => {
+						for _ in 0..*length {
+							retval.push(retval[retval.len() - usize::from(*distance)]);
+						}
+					},
+				}
+			}
+			retval
+		}
+
+		#[test]
+		fn empty() {
+			assert_eq!(Vec::<Token>::new(), tokenize(&[], &PARAMS));
+		}
+
+		#[test]
+		fn no_repeats() {
+			let res = tokenize(b"abcdefg", &PARAMS);
+			assert!(res.iter().all(|x| matches!(x, Token::Literal(_))));
+			assert_eq!(b"abcdefg".to_vec(), expand(&res));
+		}
+
+		#[test]
+		fn repeated_string() {
+			let dut = b"abcdeabcdeabcdeabcdeabcde";
+			let res = tokenize(dut, &PARAMS);
+			assert_eq!(
+				vec![
+					Token::Literal(b'a'),
+					Token::Literal(b'b'),
+					Token::Literal(b'c'),
+					Token::Literal(b'd'),
+					Token::Literal(b'e'),
+					Token::Match {
+						length: 20,
+						distance: 5
+					},
+				],
+				res
+			);
+		}
+
+		#[test]
+		fn run_is_limited_to_max_match() {
+			let dut = [7u8; 1000];
+			let res = tokenize(&dut, &PARAMS);
+			assert!(res.iter().all(|x| x.len() <= 258));
+			assert_eq!(dut.to_vec(), expand(&res));
+		}
+
+		#[test]
+		fn lazy_matching_finds_longer_match() {
+			// at `b`, "bcd" matches, but at `c`, "cdefgh" matches
+			let dut = b"bcdxcdefghybcdefgh";
+			let res = tokenize(dut, &PARAMS);
+			assert!(res.contains(&Token::Literal(b'b')));
+			assert!(res.contains(&Token::Match {
+				length: 6,
+				distance: 8
+			}));
+			assert_eq!(dut.to_vec(), expand(&res));
+		}
+
+		#[test]
+		fn matches_refer_to_data_before_start() {
+			let dut = b"abcdefabcdef";
+			let res = tokenize_from(dut, 6, 32768, &PARAMS);
+			assert_eq!(
+				vec![Token::Match {
+					length: 6,
+					distance: 6
+				}],
+				res
+			);
+		}
+
+		#[test]
+		fn matches_never_exceed_window() {
+			let dut: Vec<u8> = (0..100_000u32).map(|x| (x % 40_000 % 251) as u8).collect();
+			let res = tokenize(&dut, &PARAMS);
+			assert!(res.iter().all(|x| match x {
+				Token::Match { distance, .. } => usize::from(*distance) <= 32768,
+				_ => true,
+			}));
+			assert_eq!(dut, expand(&res));
+		}
+	}
+}
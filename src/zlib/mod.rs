@@ -2,20 +2,62 @@
 
 mod u4mod;
 pub use self::u4mod::u4;
-pub use self::u4mod::ZeroToRangeIter as u4ZeroToRangeIter;
 mod bits;
 use self::bits::Bits;
-mod deflate;
+mod decoder;
+pub use self::decoder::Decoder;
+pub(crate) mod deflate;
+mod huffman;
+mod lz77;
+pub mod raw;
+pub mod reconstruct;
+mod reference;
+
+use crate::checksum::Adler32;
 
 /// A u2 representing a hint indicating the algorithm used when compressing
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
-enum CompressionLevel {
+///
+/// When passed to [`deflate`], also determines how hard the compressor looks for repeated data.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, Default)]
+pub enum CompressionLevel {
+	/// Consider only a few previous occurrences of each string
 	Fastest,
+	/// Consider more previous occurrences of each string
 	Fast,
+	/// Also check whether delaying a match by one byte results in a longer match
+	#[default]
 	Slow,
+	/// Search exhaustively for the longest match
 	Slowest,
 }
 
+impl CompressionLevel {
+	fn parameters(self) -> lz77::Parameters {
+		match self {
+			CompressionLevel::Fastest => lz77::Parameters {
+				max_chain: 4,
+				nice_length: 8,
+				lazy: false,
+			},
+			CompressionLevel::Fast => lz77::Parameters {
+				max_chain: 32,
+				nice_length: 32,
+				lazy: false,
+			},
+			CompressionLevel::Slow => lz77::Parameters {
+				max_chain: 128,
+				nice_length: 128,
+				lazy: true,
+			},
+			CompressionLevel::Slowest => lz77::Parameters {
+				max_chain: 4096,
+				nice_length: lz77::MAX_MATCH,
+				lazy: true,
+			},
+		}
+	}
+}
+
 impl From<CompressionLevel> for u8 {
 	fn from(src: CompressionLevel) -> u8 {
 		/*
@@ -32,11 +74,13 @@ fn from(src: CompressionLevel) -> u8 {
 
 /// Represents the zlib format header
 ///
-/// Assumes that the compression_method is 8 (deflate) and that has_dictionary is false.
+/// Assumes that the compression_method is 8 (deflate). If `has_dictionary` is true, the header is
+/// followed by the adler32 checksum of the dictionary.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 struct Header {
 	window_size_exponent: u4,
 	compression_level: CompressionLevel,
+	has_dictionary: bool,
 }
 
 impl Header {
@@ -44,16 +88,36 @@ fn new(window_size_exponent: u4, compression_level: CompressionLevel) -> Header
 		Header {
 			window_size_exponent,
 			compression_level,
+			has_dictionary: false,
 		}
 	}
 
-	#[allow(dead_code)]
-	fn window_size(&self) -> u32 {
-		1 << (8 + u8::from(self.window_size_exponent))
+	/// The furthest back that the stream's matches may refer to
+	///
+	/// If `lenient`, the header's window size is ignored in favor of the largest window that
+	/// deflate allows. Otherwise, a window size larger than that is an error.
+	fn window_size(&self, lenient: bool) -> Result<usize, InflateError> {
+		if lenient {
+			Ok(lz77::WINDOW_SIZE)
+		} else if u8::from(self.window_size_exponent) > 7 {
+			Err(InflateError::InvalidWindowSize(self.window_size_exponent))
+		} else {
+			Ok(256 << u8::from(self.window_size_exponent))
+		}
+	}
+
+	/// The smallest window size exponent whose window covers `len` bytes, or the largest allowed
+	/// window size exponent if none do
+	fn window_size_exponent_for(len: usize) -> u4 {
+		let mut retval = u4::_0;
+		while retval != u4::_7 && (256 << u8::from(retval)) < len {
+			retval += u4::_1;
+		}
+		retval
 	}
 
 	fn read(val: u16) -> Result<Header, InflateError> {
-		if (val % 31) != 0 {
+		if !val.is_multiple_of(31) {
 			Err(InflateError::ChecksumMismatchHeader)
 		} else {
 			let b1 = ((val >> 8) & 255) as u8;
@@ -73,17 +137,18 @@ fn read(val: u16) -> Result<Header, InflateError> {
 
 			if method != u4::_8 {
 				Err(InflateError::UnknownCompressionMethod(method))
-			} else if dict {
-				Err(InflateError::HasPresetDictionary)
 			} else {
-				Ok(Header::new(info, level))
+				Ok(Header {
+					has_dictionary: dict,
+					..Header::new(info, level)
+				})
 			}
 		}
 	}
 
 	fn write(&self) -> u16 {
 		let b1 = u4::concat(self.window_size_exponent, u4::_8);
-		let b2 = u8::from(self.compression_level);
+		let b2 = u8::from(self.compression_level) | if self.has_dictionary { 0x20 } else { 0 };
 		let retval = (u16::from(b1) << 8) | u16::from(b2);
 		// Set the check bits so that the output value is a multiple of 31
 		let modulus = retval % 31;
@@ -94,18 +159,113 @@ fn write(&self) -> u16 {
 	}
 }
 
+/// An error that can occur while inflating a zlib stream
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
 pub enum InflateError {
+	/// The stream ended before the end of the zlib stream
 	UnexpectedEof,
+	/// The header's check bits do not make the header a multiple of 31
 	ChecksumMismatchHeader,
+	/// The header's compression method is not deflate
 	UnknownCompressionMethod(u4),
+	/// The header's window size is larger than deflate allows
+	InvalidWindowSize(u4),
+	/// The adler32 checksum of the inflated data did not match the checksum given in the stream
 	ChecksumMismatch { given: u32, calculated: u32 },
+	/// The header indicates that the stream uses a preset dictionary, but no dictionary was provided
 	HasPresetDictionary,
+	/// The provided dictionary is not the preset dictionary that the stream uses
+	DictionaryMismatch { required: u32, given: u32 },
 
+	/// A non-compressed deflate block's LEN and NLEN are not complements of each other
 	DeflateNonCompressedLengthInvalid,
+	/// A deflate block's BTYPE is the reserved value
 	DeflateInvalidBtype,
+	/// A deflate block contains a bit sequence that is not the code of any symbol
+	DeflateInvalidCode,
+	/// A deflate block contains the code of a length or distance symbol that does not represent a value
+	DeflateInvalidSymbol,
+	/// A deflate block's code lengths start with a repeat of the previous code length
+	DeflateRepeatWithoutPrevious,
+	/// A deflate block's code lengths need more codes than there are bit sequences of those lengths
+	DeflateOversubscribedCodeLengths,
+	/// A deflate block's code lengths extend past the number given in the block's header
+	DeflateTooManyCodeLengths,
+	/// A deflate block contains a match that refers to data before the start of the stream
+	DeflateDistanceTooFar,
+	/// A deflate block contains a match that refers further back than the header's window size
+	DeflateDistanceOutsideWindow,
+	/// The inflated data is longer than the limit passed to [`inflate_limited`]
+	OutputLimitExceeded,
+}
+
+impl ::std::fmt::Display for InflateError {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		match self {
+			InflateError::UnexpectedEof => write!(f, "Unexpected End of File"),
+			InflateError::ChecksumMismatchHeader => write!(f, "ZLib Header Checksum Mismatch"),
+			InflateError::UnknownCompressionMethod(method) => {
+				write!(f, "Unsupported Zlib Compression Method: {method:X}")
+			},
+			InflateError::InvalidWindowSize(info) => {
+				write!(f, "Invalid Zlib Window Size: {info:X}")
+			},
+			InflateError::ChecksumMismatch { given, calculated } => {
+				write!(
+					f,
+					"ZLib Checksum Mismatch: given `{given:x}`, calculated `{calculated:x}`"
+				)
+			},
+			InflateError::HasPresetDictionary => write!(f, "ZLib Segment has preset dictionary"),
+			InflateError::DictionaryMismatch { required, given } => {
+				write!(
+					f,
+					"ZLib Dictionary Mismatch: requires `{required:x}`, given `{given:x}`"
+				)
+			},
+			InflateError::DeflateNonCompressedLengthInvalid => {
+				write!(f, "Malformed deflate block: LEN and NLEN mismatch")
+			},
+			InflateError::DeflateInvalidBtype => {
+				write!(f, "Malformed deflate block: invalid BTYPE")
+			},
+			InflateError::DeflateInvalidCode => {
+				write!(f, "Malformed deflate block: invalid huffman code")
+			},
+			InflateError::DeflateInvalidSymbol => {
+				write!(
+					f,
+					"Malformed deflate block: invalid length or distance symbol"
+				)
+			},
+			InflateError::DeflateRepeatWithoutPrevious => {
+				write!(
+					f,
+					"Malformed deflate block: code length repeat without a previous length"
+				)
+			},
+			InflateError::DeflateOversubscribedCodeLengths => {
+				write!(f, "Malformed deflate block: oversubscribed code lengths")
+			},
+			InflateError::DeflateTooManyCodeLengths => {
+				write!(f, "Malformed deflate block: too many code lengths")
+			},
+			InflateError::DeflateDistanceTooFar => {
+				write!(f, "Malformed deflate block: distance too far back")
+			},
+			InflateError::DeflateDistanceOutsideWindow => {
+				write!(f, "Malformed deflate block: distance outside of window")
+			},
+			InflateError::OutputLimitExceeded => {
+				write!(f, "Inflated data is larger than the size limit")
+			},
+		}
+	}
 }
 
+impl ::std::error::Error for InflateError {}
+
 impl From<deflate::InflateError> for InflateError {
 	fn from(src: deflate::InflateError) -> InflateError {
 		match src {
@@ -114,32 +274,108 @@ fn from(src: deflate::InflateError) -> InflateError {
 				InflateError::DeflateNonCompressedLengthInvalid
 			},
 			deflate::InflateError::InvalidBtype => InflateError::DeflateInvalidBtype,
+			deflate::InflateError::InvalidCode => InflateError::DeflateInvalidCode,
+			deflate::InflateError::InvalidSymbol => InflateError::DeflateInvalidSymbol,
+			deflate::InflateError::RepeatWithoutPrevious => {
+				InflateError::DeflateRepeatWithoutPrevious
+			},
+			deflate::InflateError::OversubscribedCodeLengths => {
+				InflateError::DeflateOversubscribedCodeLengths
+			},
+			deflate::InflateError::TooManyCodeLengths => InflateError::DeflateTooManyCodeLengths,
+			deflate::InflateError::DistanceTooFar => InflateError::DeflateDistanceTooFar,
+			deflate::InflateError::DistanceOutsideWindow => {
+				InflateError::DeflateDistanceOutsideWindow
+			},
+			deflate::InflateError::OutputLimitExceeded => InflateError::OutputLimitExceeded,
 		}
 	}
 }
 
-fn option_to_eof_result<A>(a: Option<A>) -> Result<A, InflateError> {
-	match a {
-		Some(x) => Ok(x),
-		None => Err(InflateError::UnexpectedEof),
+/// Removes the first `N` bytes of `r`, and returns them
+fn take_array<const N: usize>(r: &mut &[u8]) -> Result<[u8; N], InflateError> {
+	let (retval, rest) = r
+		.split_first_chunk::<N>()
+		.ok_or(InflateError::UnexpectedEof)?;
+	*r = rest;
+	Ok(*retval)
+}
+
+/// Options that control how [`inflate_with_options`] inflates a zlib stream
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct InflateOptions<'a> {
+	/// Fail with [`InflateError::OutputLimitExceeded`] instead of producing more than this many bytes
+	pub max_size: usize,
+	/// The preset dictionary to use if the stream's header indicates that it uses one
+	pub dictionary: Option<&'a [u8]>,
+	/// Accept window sizes larger than deflate allows, and matches that refer further back than
+	/// the header's window size, as long as they stay within the largest window that deflate allows
+	pub lenient_window: bool,
+}
+
+impl Default for InflateOptions<'_> {
+	fn default() -> Self {
+		InflateOptions {
+			max_size: usize::MAX,
+			dictionary: None,
+			lenient_window: false,
+		}
 	}
 }
 
 /// Decompresses a zlib stream
 pub fn inflate(r: &[u8]) -> Result<Vec<u8>, InflateError> {
-	let mut r = r.iter().cloned();
-	let _header = Header::read(u16::from_be_bytes([
-		option_to_eof_result(r.next())?,
-		option_to_eof_result(r.next())?,
-	]))?;
-	let result = deflate::inflate(&mut r)?;
-	let given_chksum = u32::from_be_bytes([
-		option_to_eof_result(r.next())?,
-		option_to_eof_result(r.next())?,
-		option_to_eof_result(r.next())?,
-		option_to_eof_result(r.next())?,
-	]);
-	let calculated_chksum = adler32(&result);
+	inflate_with_options(r, &InflateOptions::default())
+}
+
+/// Decompresses a zlib stream, failing with [`InflateError::OutputLimitExceeded`] instead of
+/// producing more than `max_size` bytes
+pub fn inflate_limited(r: &[u8], max_size: usize) -> Result<Vec<u8>, InflateError> {
+	inflate_with_options(
+		r,
+		&InflateOptions {
+			max_size,
+			..InflateOptions::default()
+		},
+	)
+}
+
+/// Decompresses a zlib stream that may use a preset dictionary
+///
+/// If the stream's header indicates a preset dictionary, `dictionary` must be that dictionary,
+/// as identified by its adler32 checksum. If the header does not, `dictionary` is not used.
+pub fn inflate_with_dictionary(r: &[u8], dictionary: &[u8]) -> Result<Vec<u8>, InflateError> {
+	inflate_with_options(
+		r,
+		&InflateOptions {
+			dictionary: Some(dictionary),
+			..InflateOptions::default()
+		},
+	)
+}
+
+/// Decompresses a zlib stream, as configured by `options`
+pub fn inflate_with_options(r: &[u8], options: &InflateOptions) -> Result<Vec<u8>, InflateError> {
+	let mut r = r;
+	let header = Header::read(u16::from_be_bytes(take_array(&mut r)?))?;
+	let window_size = header.window_size(options.lenient_window)?;
+	let dictionary: &[u8] = if header.has_dictionary {
+		let required = u32::from_be_bytes(take_array(&mut r)?);
+		let dictionary = options
+			.dictionary
+			.ok_or(InflateError::HasPresetDictionary)?;
+		let given = Adler32::checksum(dictionary);
+		if given != required {
+			return Err(InflateError::DictionaryMismatch { required, given });
+		}
+		dictionary
+	} else {
+		&[]
+	};
+	let result =
+		deflate::inflate_with_dictionary(&mut r, dictionary, window_size, options.max_size)?;
+	let given_chksum = u32::from_be_bytes(take_array(&mut r)?);
+	let calculated_chksum = Adler32::checksum(&result);
 
 	if given_chksum != calculated_chksum {
 		Err(InflateError::ChecksumMismatch {
@@ -159,24 +395,58 @@ pub fn deflate_immediate(r: &[u8]) -> Vec<u8> {
 		.iter()
 		.cloned()
 		.chain(deflate::deflate_immediate(r.iter().cloned()))
-		.chain(adler32(r).to_be_bytes().iter().cloned())
+		.chain(Adler32::checksum(r).to_be_bytes().iter().cloned())
 		.collect()
 }
 
-/// Computes an adler 32 checksum
-fn adler32(input: &[u8]) -> u32 {
-	const DIVISOR: u32 = 65521;
-	let mut s1: u32 = 1;
-	let mut s2: u32 = 0;
-
-	for x in input {
-		s1 += u32::from(*x);
-		s1 %= DIVISOR;
-		s2 += s1;
-		s2 %= DIVISOR;
-	}
+/// Compresses the input into a zlib stream
+///
+/// The stream's header records `level` as the stream's compression level, and the smallest window
+/// size that covers the input.
+pub fn deflate(r: &[u8], level: CompressionLevel) -> Vec<u8> {
+	let window_size_exponent = Header::window_size_exponent_for(r.len());
+	let window_size = 256 << u8::from(window_size_exponent);
+	Header::new(window_size_exponent, level)
+		.write()
+		.to_be_bytes()
+		.iter()
+		.cloned()
+		.chain(deflate::deflate_with_dictionary(
+			r,
+			&[],
+			window_size,
+			&level.parameters(),
+		))
+		.chain(Adler32::checksum(r).to_be_bytes().iter().cloned())
+		.collect()
+}
 
-	(s2 << 16) | s1
+/// Compresses the input into a zlib stream that uses a preset dictionary
+///
+/// Matches may refer to the last 32 KiB of `dictionary`, which must be passed to
+/// [`inflate_with_dictionary`] to inflate the stream.
+pub fn deflate_with_dictionary(r: &[u8], level: CompressionLevel, dictionary: &[u8]) -> Vec<u8> {
+	let window_size_exponent =
+		Header::window_size_exponent_for(dictionary.len().saturating_add(r.len()));
+	let window_size = 256 << u8::from(window_size_exponent);
+	let header = Header {
+		has_dictionary: true,
+		..Header::new(window_size_exponent, level)
+	};
+	header
+		.write()
+		.to_be_bytes()
+		.iter()
+		.cloned()
+		.chain(Adler32::checksum(dictionary).to_be_bytes().iter().cloned())
+		.chain(deflate::deflate_with_dictionary(
+			r,
+			dictionary,
+			window_size,
+			&level.parameters(),
+		))
+		.chain(Adler32::checksum(r).to_be_bytes().iter().cloned())
+		.collect()
 }
 
 #[cfg(test)]
@@ -231,8 +501,11 @@ fn invalid_checksum_fails() {
 			assert!(exp == res, "{:?} != {:?}", exp, res);
 		}
 		#[test]
-		fn has_dict_fails() {
-			let exp: Result<Header, InflateError> = Err(InflateError::HasPresetDictionary);
+		fn has_dict() {
+			let exp: Result<Header, InflateError> = Ok(Header {
+				has_dictionary: true,
+				..Header::new(u4::_6, CompressionLevel::Slow)
+			});
 			let dut: u16 = 0x68A0;
 			let res = Header::read(dut);
 			assert!(exp == res, "{:?} != {:?}", exp, res);
@@ -247,28 +520,263 @@ fn method_not_deflate_fails() {
 		}
 	}
 
-	mod adler32 {
-		use super::super::adler32;
+	mod deflate {
+		use super::super::deflate;
+		use super::super::inflate;
+		use super::super::CompressionLevel;
+		use super::super::Header;
+
+		const LEVELS: [CompressionLevel; 4] = [
+			CompressionLevel::Fastest,
+			CompressionLevel::Fast,
+			CompressionLevel::Slow,
+			CompressionLevel::Slowest,
+		];
+
+		fn assert_round_trip(data: &[u8]) {
+			for level in LEVELS {
+				let compressed = deflate(data, level);
+				let res = inflate(&compressed).unwrap();
+				assert!(data == res.as_slice(), "{:?}", level);
+			}
+		}
+
+		#[test]
+		fn empty() {
+			assert_round_trip(&[]);
+		}
+
+		#[test]
+		fn single_byte() {
+			assert_round_trip(&[42]);
+		}
+
+		#[test]
+		fn text() {
+			let data = b"lorem ipsum dolor sit amet, lorem ipsum dolor sit amet, consectetur adipiscing elit";
+			assert_round_trip(data);
+		}
+
+		#[test]
+		fn long_run() {
+			assert_round_trip(&[0u8; 100_000]);
+		}
+
+		#[test]
+		fn every_byte_value() {
+			let data: Vec<u8> = (0..=255).cycle().take(5000).collect();
+			assert_round_trip(&data);
+		}
+
+		#[test]
+		fn pseudorandom() {
+			// incompressible data is written as non-compressed blocks
+			let data = crate::test_util::pseudorandom(0x1234_5678, 200_000);
+			assert_round_trip(&data);
+			let compressed = deflate(&data, CompressionLevel::Slow);
+			assert!(
+				compressed.len() < data.len() + data.len() / 1000,
+				"{}",
+				compressed.len()
+			);
+		}
+
+		#[test]
+		fn skewed_data_is_smaller() {
+			let data: Vec<u8> = (0..50_000u32)
+				.map(|x| {
+					if x % 7 == 0 {
+						b'a' + (x % 13) as u8
+					} else {
+						b'z'
+					}
+				})
+				.collect();
+			assert_round_trip(&data);
+			for level in LEVELS {
+				let compressed = deflate(&data, level);
+				assert!(
+					compressed.len() < data.len() / 4,
+					"{:?} {}",
+					level,
+					compressed.len()
+				);
+			}
+		}
+
+		#[test]
+		fn header_has_level() {
+			for level in LEVELS {
+				let compressed = deflate(b"abc", level);
+				let header = Header::read(u16::from_be_bytes([compressed[0], compressed[1]]));
+				assert_eq!(Ok(level), header.map(|x| x.compression_level));
+			}
+		}
+	}
+
+	mod dictionary {
+		use super::super::deflate;
+		use super::super::deflate_with_dictionary;
+		use super::super::inflate;
+		use super::super::inflate_with_dictionary;
+		use super::super::CompressionLevel;
+		use super::super::InflateError;
+
+		const DICTIONARY: &[u8] = b"lorem ipsum dolor sit amet";
+
+		#[test]
+		fn round_trip() {
+			let data = b"lorem ipsum dolor sit amet, lorem ipsum";
+			let compressed = deflate_with_dictionary(data, CompressionLevel::Slow, DICTIONARY);
+			assert_eq!(
+				Ok(data.to_vec()),
+				inflate_with_dictionary(&compressed, DICTIONARY)
+			);
+			assert!(compressed.len() < deflate(data, CompressionLevel::Slow).len());
+		}
+
 		#[test]
-		fn wikipedia() {
-			let exp = 0x11E60398;
-			let data = b"Wikipedia";
-			let res = adler32(data);
-			assert_eq!(exp, res);
+		fn long_dictionary() {
+			let dictionary: Vec<u8> = (0..100_000u32).map(|x| (x % 251) as u8).collect();
+			let data = &dictionary[70_000..72_000];
+			let compressed = deflate_with_dictionary(data, CompressionLevel::Slow, &dictionary);
+			assert_eq!(
+				Ok(data.to_vec()),
+				inflate_with_dictionary(&compressed, &dictionary)
+			);
 		}
+
+		#[test]
+		fn inflates_zlib_output() {
+			// python: zlib.compressobj(9, zlib.DEFLATED, 15, 9, zdict=DICTIONARY)
+			#[rustfmt::skip]
+			let compressed = [
+				0x78, 0xf9, 0x87, 0x15, 0x09, 0xe5, 0xcb, 0xc1, 0x29, 0xa3, 0xa3, 0x80, 0x24, 0x07,
+				0x00, 0x25, 0xdd, 0x0e, 0x9e,
+			];
+			assert_eq!(
+				Ok(b"lorem ipsum dolor sit amet, lorem ipsum".to_vec()),
+				inflate_with_dictionary(&compressed, DICTIONARY)
+			);
+		}
+
 		#[test]
-		fn zero_x1() {
-			let exp = 0x00010001;
-			let data = [0u8; 1];
-			let res = adler32(&data);
-			assert_eq!(exp, res);
+		fn missing_dictionary() {
+			let compressed = deflate_with_dictionary(b"lorem", CompressionLevel::Slow, DICTIONARY);
+			assert_eq!(Err(InflateError::HasPresetDictionary), inflate(&compressed));
 		}
+
+		#[test]
+		fn mismatched_dictionary() {
+			let compressed = deflate_with_dictionary(b"lorem", CompressionLevel::Slow, DICTIONARY);
+			assert!(matches!(
+				inflate_with_dictionary(&compressed, b"dolor sit amet"),
+				Err(InflateError::DictionaryMismatch { .. })
+			));
+		}
+
+		#[test]
+		fn dictionary_unused_without_header_flag() {
+			let compressed = deflate(b"lorem", CompressionLevel::Slow);
+			assert_eq!(
+				Ok(b"lorem".to_vec()),
+				inflate_with_dictionary(&compressed, DICTIONARY)
+			);
+		}
+	}
+
+	mod inflate_limited {
+		use super::super::deflate;
+		use super::super::inflate_limited;
+		use super::super::CompressionLevel;
+		use super::super::InflateError;
+
+		#[test]
+		fn exactly_at_limit() {
+			let data = [7u8; 1000];
+			let compressed = deflate(&data, CompressionLevel::Slow);
+			assert_eq!(Ok(data.to_vec()), inflate_limited(&compressed, 1000));
+		}
+
+		#[test]
+		fn over_limit() {
+			let data = [7u8; 1000];
+			let compressed = deflate(&data, CompressionLevel::Slow);
+			assert_eq!(
+				Err(InflateError::OutputLimitExceeded),
+				inflate_limited(&compressed, 999)
+			);
+		}
+	}
+
+	mod window_size {
+		use super::super::deflate;
+		use super::super::inflate;
+		use super::super::inflate_with_options;
+		use super::super::u4;
+		use super::super::CompressionLevel;
+		use super::super::Header;
+		use super::super::InflateError;
+		use super::super::InflateOptions;
+
+		const LENIENT: InflateOptions = InflateOptions {
+			max_size: usize::MAX,
+			dictionary: None,
+			lenient_window: true,
+		};
+
+		/// Replaces the stream's header with one declaring the given window size exponent
+		fn with_window_size_exponent(mut stream: Vec<u8>, exponent: u4) -> Vec<u8> {
+			let header = Header::new(exponent, CompressionLevel::Slow).write();
+			stream[..2].copy_from_slice(&header.to_be_bytes());
+			stream
+		}
+
+		/// 300 bytes without repeats, followed by a repeat of the first 50 of those bytes
+		fn far_repeat() -> Vec<u8> {
+			let mut retval = crate::test_util::pseudorandom(0x2545_F491, 300);
+			retval.extend_from_within(..50);
+			retval
+		}
+
+		#[test]
+		fn declares_smallest_sufficient_window() {
+			assert_eq!(0x08, deflate(b"abc", CompressionLevel::Slow)[0]);
+			assert_eq!(0x18, deflate(&far_repeat(), CompressionLevel::Slow)[0]);
+			assert_eq!(0x78, deflate(&[0; 100_000], CompressionLevel::Slow)[0]);
+		}
+
+		#[test]
+		fn round_trip_small_window() {
+			let data = far_repeat();
+			let compressed = deflate(&data, CompressionLevel::Slowest);
+			assert_eq!(Ok(data), inflate(&compressed));
+		}
+
+		#[test]
+		fn too_large_window_size() {
+			let compressed =
+				with_window_size_exponent(deflate(b"abc", CompressionLevel::Slow), u4::_8);
+			assert_eq!(
+				Err(InflateError::InvalidWindowSize(u4::_8)),
+				inflate(&compressed)
+			);
+			assert_eq!(
+				Ok(b"abc".to_vec()),
+				inflate_with_options(&compressed, &LENIENT)
+			);
+		}
+
 		#[test]
-		fn zero_x65536() {
-			let exp = 0x000F0001;
-			let data = [0u8; 65536];
-			let res = adler32(&data);
-			assert_eq!(exp, res, "{:x} {:x}", exp, res);
+		fn distance_outside_window() {
+			let data = far_repeat();
+			let compressed =
+				with_window_size_exponent(deflate(&data, CompressionLevel::Slow), u4::_0);
+			assert_eq!(
+				Err(InflateError::DeflateDistanceOutsideWindow),
+				inflate(&compressed)
+			);
+			assert_eq!(Ok(data), inflate_with_options(&compressed, &LENIENT));
 		}
 	}
 }
@@ -0,0 +1,183 @@
+//! Reads and writes deflate streams that are not wrapped in a zlib stream, as used by other
+//! container formats
+//!
+//! [`inflate_with_blocks`] also describes the structure of each block of the stream, which shows
+//! how the stream's encoder chose to represent the data.
+//!
+//! ```
+//! use png_inflate::zlib::raw;
+//! use png_inflate::zlib::CompressionLevel;
+//!
+//! let compressed = raw::deflate(b"abcabcabcabc", CompressionLevel::Slow);
+//! let (inflated, blocks) = raw::inflate_with_blocks(&compressed, usize::MAX)?;
+//! assert_eq!(b"abcabcabcabc".to_vec(), inflated);
+//! assert_eq!(1, blocks.len());
+//! assert_eq!(3, blocks[0].literal_count);
+//! assert_eq!(1, blocks[0].match_count);
+//! # Ok::<(), png_inflate::zlib::InflateError>(())
+//! ```
+
+use super::lz77;
+use super::CompressionLevel;
+use super::InflateError;
+
+pub use super::deflate::BlockInfo;
+pub use super::deflate::BlockType;
+
+/// Decompresses a deflate stream
+///
+/// Any bytes after the end of the stream are ignored.
+pub fn inflate(r: &[u8]) -> Result<Vec<u8>, InflateError> {
+	inflate_limited(r, usize::MAX)
+}
+
+/// Decompresses a deflate stream, failing with [`InflateError::OutputLimitExceeded`] instead of
+/// producing more than `max_size` bytes
+pub fn inflate_limited(r: &[u8], max_size: usize) -> Result<Vec<u8>, InflateError> {
+	Ok(super::deflate::inflate(&mut &r[..], max_size)?)
+}
+
+/// Decompresses a deflate stream, failing with [`InflateError::OutputLimitExceeded`] instead of
+/// producing more than `max_size` bytes. Also returns the structure of each of the stream's blocks.
+///
+/// The stream ends `(last.bit_offset + last.compressed_bits).div_ceil(8)` bytes into `r`, where
+/// `last` is the last block.
+pub fn inflate_with_blocks(
+	r: &[u8],
+	max_size: usize,
+) -> Result<(Vec<u8>, Vec<BlockInfo>), InflateError> {
+	Ok(super::deflate::inflate_with_blocks(&mut &r[..], max_size)?)
+}
+
+/// Compresses the input into a deflate stream
+pub fn deflate(r: &[u8], level: CompressionLevel) -> Vec<u8> {
+	super::deflate::deflate_with_dictionary(r, &[], lz77::WINDOW_SIZE, &level.parameters())
+}
+
+/// Stores the input in a deflate stream entirely using non-compressed blocks
+pub fn deflate_immediate(r: &[u8]) -> Vec<u8> {
+	super::deflate::deflate_immediate(r.iter().cloned())
+}
+
+#[cfg(test)]
+mod tests {
+	mod inflate_with_blocks {
+		use super::super::deflate;
+		use super::super::deflate_immediate;
+		use super::super::inflate;
+		use super::super::inflate_with_blocks;
+		use super::super::BlockType;
+		use super::super::CompressionLevel;
+		use super::super::InflateError;
+
+		/// Data that needs more than one block
+		fn sample() -> Vec<u8> {
+			(0..100_000u32)
+				.map(|x| x % 30_000)
+				.map(|x| (x * x / 7 % 251) as u8)
+				.collect()
+		}
+
+		#[test]
+		fn round_trip() {
+			let data = sample();
+			assert_eq!(
+				Ok(data.clone()),
+				inflate(&deflate(&data, CompressionLevel::Fast))
+			);
+			assert_eq!(Ok(data.clone()), inflate(&deflate_immediate(&data)));
+		}
+
+		#[test]
+		fn stored() {
+			let data = sample();
+			let (res, blocks) = inflate_with_blocks(&deflate_immediate(&data), usize::MAX).unwrap();
+			assert_eq!(data, res);
+			assert_eq!(2, blocks.len());
+			assert_eq!(BlockType::Stored, blocks[0].block_type);
+			assert!(!blocks[0].is_final);
+			assert_eq!(0, blocks[0].bit_offset);
+			assert_eq!((5 + 65535) * 8, blocks[0].compressed_bits);
+			assert_eq!(65535, blocks[0].uncompressed_size);
+			assert!(blocks[0].literal_length_code_lengths.is_empty());
+			assert_eq!(BlockType::Stored, blocks[1].block_type);
+			assert!(blocks[1].is_final);
+			assert_eq!((5 + 65535) * 8, blocks[1].bit_offset);
+			assert_eq!(100_000 - 65535, blocks[1].uncompressed_size);
+		}
+
+		#[test]
+		fn fixed() {
+			// python: zlib.compressobj(9, zlib.DEFLATED, -15) of b"abc"
+			let dut = [0x4b, 0x4c, 0x4a, 0x06, 0x00];
+			let (res, blocks) = inflate_with_blocks(&dut, usize::MAX).unwrap();
+			assert_eq!(b"abc".to_vec(), res);
+			assert_eq!(1, blocks.len());
+			assert_eq!(BlockType::Fixed, blocks[0].block_type);
+			assert!(blocks[0].is_final);
+			// the block header, three 8-bit literals and the 7-bit end-of-block code
+			assert_eq!(3 + 3 * 8 + 7, blocks[0].compressed_bits);
+			assert_eq!(3, blocks[0].literal_count);
+			assert_eq!(0, blocks[0].match_count);
+			assert_eq!(288, blocks[0].literal_length_code_lengths.len());
+			assert_eq!(8, blocks[0].literal_length_code_lengths[0]);
+			assert_eq!(9, blocks[0].literal_length_code_lengths[255]);
+		}
+
+		#[test]
+		fn dynamic() {
+			let data = sample();
+			let compressed = deflate(&data, CompressionLevel::Slow);
+			let (res, blocks) = inflate_with_blocks(&compressed, usize::MAX).unwrap();
+			assert_eq!(data, res);
+			assert!(blocks.iter().any(|x| x.block_type == BlockType::Dynamic));
+			assert!(blocks.iter().any(|x| x.match_count > 0));
+			assert_eq!(
+				data.len(),
+				blocks.iter().map(|x| x.uncompressed_size).sum::<usize>()
+			);
+			for pair in blocks.windows(2) {
+				assert_eq!(
+					pair[0].bit_offset + pair[0].compressed_bits,
+					pair[1].bit_offset
+				);
+				assert!(!pair[0].is_final);
+			}
+			let last = blocks.last().unwrap();
+			assert!(last.is_final);
+			assert_eq!(
+				compressed.len(),
+				(last.bit_offset + last.compressed_bits).div_ceil(8)
+			);
+			for block in blocks.iter().filter(|x| x.block_type == BlockType::Dynamic) {
+				assert!(block.literal_length_code_lengths.len() >= 257);
+				assert!(!block.distance_code_lengths.is_empty());
+			}
+		}
+
+		#[test]
+		fn trailing_data_is_ignored() {
+			let dut = [0x4b, 0x4c, 0x4a, 0x06, 0x00, 0xFF, 0xFF];
+			assert_eq!(Ok(b"abc".to_vec()), inflate(&dut));
+		}
+
+		#[test]
+		fn truncated() {
+			let dut = [0x4b, 0x4c, 0x4a];
+			assert_eq!(
+				Err(InflateError::UnexpectedEof),
+				inflate_with_blocks(&dut, usize::MAX)
+			);
+		}
+
+		#[test]
+		fn over_limit() {
+			let dut = deflate(&[7; 1000], CompressionLevel::Slow);
+			assert!(inflate_with_blocks(&dut, 1000).is_ok());
+			assert_eq!(
+				Err(InflateError::OutputLimitExceeded),
+				inflate_with_blocks(&dut, 999)
+			);
+		}
+	}
+}
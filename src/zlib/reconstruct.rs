@@ -0,0 +1,1017 @@
+//! Records the details of a zlib stream that are lost when the stream is inflated, so that the
+//! exact stream can later be recreated from the inflated data
+//!
+//! Rather than storing the stream's matches directly, the stream is compared against the choices
+//! that a known compressor (this crate's own, or zlib at one of its levels) would have made, and
+//! only the places where the stream's encoder chose differently are recorded. Huffman code
+//! descriptions are likewise only recorded if they differ from what the known compressors would
+//! write. Together with the block boundaries and any stray bits, this is enough to recreate the
+//! stream bit for bit. For a stream created by one of the known compressors, the result is a small
+//! fraction of the size of the compressed stream.
+
+use super::bits::BitCursor;
+use super::bits::BitWriter;
+use super::deflate;
+use super::deflate::HuffmanEncoder;
+use super::huffman;
+use super::inflate_limited;
+use super::lz77;
+use super::lz77::Matcher;
+use super::lz77::Predictor;
+use super::lz77::Token;
+use super::reference;
+use super::reference::ZlibMatcher;
+use super::CompressionLevel;
+use super::InflateError;
+use crate::checksum::Adler32;
+
+/// The version of the reconstruction data format
+const VERSION: u8 = 0;
+
+/// The number of tokens compared when choosing which predictor to use, not counting literals
+/// that the predictor also would have written as literals
+const TRIAL_TOKENS: usize = 4096;
+
+/// A compressor that a stream can be compared against
+#[derive(Copy, Clone)]
+enum PredictorKind {
+	Own(CompressionLevel),
+	Zlib(reference::Parameters),
+}
+
+/// The compressors that a stream can be compared against; reconstruction data stores an index
+/// into this list
+const PREDICTORS: [PredictorKind; 19] = [
+	PredictorKind::Own(CompressionLevel::Fastest),
+	PredictorKind::Own(CompressionLevel::Fast),
+	PredictorKind::Own(CompressionLevel::Slow),
+	PredictorKind::Own(CompressionLevel::Slowest),
+	PredictorKind::Zlib(reference::Parameters::level(1, false)),
+	PredictorKind::Zlib(reference::Parameters::level(2, false)),
+	PredictorKind::Zlib(reference::Parameters::level(3, false)),
+	PredictorKind::Zlib(reference::Parameters::level(4, false)),
+	PredictorKind::Zlib(reference::Parameters::level(5, false)),
+	PredictorKind::Zlib(reference::Parameters::level(6, false)),
+	PredictorKind::Zlib(reference::Parameters::level(7, false)),
+	PredictorKind::Zlib(reference::Parameters::level(8, false)),
+	PredictorKind::Zlib(reference::Parameters::level(9, false)),
+	// libpng uses the filtered strategy by default
+	PredictorKind::Zlib(reference::Parameters::level(4, true)),
+	PredictorKind::Zlib(reference::Parameters::level(5, true)),
+	PredictorKind::Zlib(reference::Parameters::level(6, true)),
+	PredictorKind::Zlib(reference::Parameters::level(7, true)),
+	PredictorKind::Zlib(reference::Parameters::level(8, true)),
+	PredictorKind::Zlib(reference::Parameters::level(9, true)),
+];
+
+impl PredictorKind {
+	/// Creates a predictor for a stream with the given zlib header
+	fn build(self, header: &[u8]) -> Box<dyn Predictor> {
+		match self {
+			PredictorKind::Own(level) => Box::new(Matcher::new(level.parameters())),
+			PredictorKind::Zlib(params) => Box::new(ZlibMatcher::new(params, (header[0] >> 4) + 8)),
+		}
+	}
+}
+
+/// Where the description of a custom-code block's codes comes from
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum HeaderSource {
+	/// The description is stored in the reconstruction data
+	Explicit = 0,
+	/// The description is the one zlib would write
+	Zlib = 1,
+	/// The description is the one this crate's compressor would write
+	Own = 2,
+}
+
+/// An error that can occur while recreating a zlib stream
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[non_exhaustive]
+pub enum RebuildError {
+	/// The reconstruction data is truncated or contains an invalid value
+	Malformed,
+	/// The reconstruction data was written by a newer version of this library
+	UnknownVersion(u8),
+	/// The reconstruction data does not describe a stream for the given inflated data
+	DataMismatch,
+}
+
+impl ::std::fmt::Display for RebuildError {
+	fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+		match self {
+			RebuildError::Malformed => write!(f, "Malformed reconstruction data"),
+			RebuildError::UnknownVersion(version) => {
+				write!(f, "Unknown reconstruction data version: {version}")
+			},
+			RebuildError::DataMismatch => {
+				write!(f, "Reconstruction data does not match the image data")
+			},
+		}
+	}
+}
+
+impl ::std::error::Error for RebuildError {}
+
+/// Inflates the zlib stream, and also returns the data needed to recreate the stream from the
+/// inflated data using [`rebuild`]
+///
+/// The reconstruction data is None if the stream cannot be recreated. Like [`inflate_limited`],
+/// fails instead of inflating more than `max_size` bytes.
+pub fn analyze(stream: &[u8], max_size: usize) -> Result<(Vec<u8>, Option<Vec<u8>>), InflateError> {
+	let data = inflate_limited(stream, max_size)?;
+	let record = parse(stream)
+		.map(|parsed| {
+			let trial: Vec<usize> = (0..PREDICTORS.len())
+				.map(|predictor| count_misses(&parsed, &data, predictor, TRIAL_TOKENS))
+				.collect();
+			let best = *trial.iter().min().expect("PREDICTORS is not empty");
+			let tied: Vec<usize> = (0..PREDICTORS.len())
+				.filter(|predictor| trial[*predictor] == best)
+				.collect();
+			// the trial cannot tell these apart, so compare them over the whole stream instead
+			let predictor = if tied.len() == 1 {
+				tied[0]
+			} else {
+				tied.into_iter()
+					.min_by_key(|predictor| count_misses(&parsed, &data, *predictor, usize::MAX))
+					.expect("at least one predictor has the best score")
+			};
+			encode(&parsed, &data, predictor)
+		})
+		// Only return data that is known to work
+		.filter(|record| rebuild(&data, record).as_deref() == Ok(stream));
+	Ok((data, record))
+}
+
+/// Recreates a zlib stream from its inflated data and the reconstruction data returned by
+/// [`analyze`]
+pub fn rebuild(data: &[u8], record: &[u8]) -> Result<Vec<u8>, RebuildError> {
+	let mut record = RecordReader { backing: record };
+	let version = record.u8()?;
+	if version != VERSION {
+		return Err(RebuildError::UnknownVersion(version));
+	}
+	let header = record.bytes(2)?;
+	let predictor = PREDICTORS
+		.get(usize::from(record.u8()?))
+		.ok_or(RebuildError::Malformed)?;
+
+	let mut matcher = predictor.build(header);
+	let mut writer = BitWriter::new();
+	let mut pos: usize = 0;
+	let block_count = record.varint()?;
+	for _ in 0..block_count {
+		let flags = record.u8()?;
+		writer.write_n_rev(u32::from(flags & 1), 1);
+		let btype = (flags >> 1) & 3;
+		writer.write_n_rev(u32::from(btype), 2);
+		if btype != 2 && flags >> 3 != 0 {
+			return Err(RebuildError::Malformed);
+		}
+		match btype {
+			0 => {
+				let padding = record.u8()?;
+				write_padding(&mut writer, padding)?;
+				let length = record.varint()?;
+				let length = u16::try_from(length).map_err(|_| RebuildError::Malformed)?;
+				let bytes = data
+					.get(pos..(pos + usize::from(length)))
+					.ok_or(RebuildError::DataMismatch)?;
+				writer.write_bytes(&length.to_le_bytes());
+				writer.write_bytes(&(!length).to_le_bytes());
+				writer.write_bytes(bytes);
+				matcher.advance(data, pos, bytes.len());
+				pos += bytes.len();
+			},
+			1 => {
+				let tokens = rebuild_tokens(&mut record, data, &mut pos, matcher.as_mut())?;
+				let lengths = HuffmanEncoder::fixed_length_codes();
+				let distances = HuffmanEncoder::fixed_distance_codes();
+				write_tokens(&mut writer, &tokens, &lengths, &distances)?;
+			},
+			2 => {
+				let source = flags >> 3;
+				let header = if source == HeaderSource::Explicit as u8 {
+					Some(DynamicHeader::read(&mut record)?)
+				} else {
+					None
+				};
+				let tokens = rebuild_tokens(&mut record, data, &mut pos, matcher.as_mut())?;
+				let header = match header {
+					Some(header) => header,
+					None if source == HeaderSource::Zlib as u8 => DynamicHeader::zlib(&tokens),
+					None if source == HeaderSource::Own as u8 => DynamicHeader::own(&tokens),
+					None => return Err(RebuildError::Malformed),
+				};
+				let (lengths, distances) = header.write(&mut writer)?;
+				write_tokens(&mut writer, &tokens, &lengths, &distances)?;
+			},
+			_ => return Err(RebuildError::Malformed),
+		}
+	}
+	let final_padding = record.u8()?;
+	write_padding(&mut writer, final_padding)?;
+	let trailing_length = record.varint()?;
+	let trailing = record.bytes(trailing_length)?;
+	if !record.backing.is_empty() {
+		return Err(RebuildError::Malformed);
+	}
+	if pos != data.len() {
+		return Err(RebuildError::DataMismatch);
+	}
+
+	Ok(header
+		.iter()
+		.cloned()
+		.chain(writer.finish())
+		.chain(Adler32::checksum(data).to_be_bytes())
+		.chain(trailing.iter().cloned())
+		.collect())
+}
+
+/// Writes the bits between the current position and the next byte boundary
+fn write_padding(writer: &mut BitWriter, padding: u8) -> Result<(), RebuildError> {
+	let bit_count = writer.bits_to_byte_boundary();
+	if u32::from(padding) >> bit_count != 0 {
+		return Err(RebuildError::Malformed);
+	}
+	writer.write_n_rev(u32::from(padding), bit_count);
+	Ok(())
+}
+
+/// Recreates the tokens of a huffman-coded block
+fn rebuild_tokens(
+	record: &mut RecordReader,
+	data: &[u8],
+	pos: &mut usize,
+	matcher: &mut dyn Predictor,
+) -> Result<Vec<Token>, RebuildError> {
+	let block_length = record.varint()?;
+	let end = pos
+		.checked_add(block_length)
+		.filter(|x| *x <= data.len())
+		.ok_or(RebuildError::DataMismatch)?;
+	let mut misses_remaining = record.varint()?;
+	let mut next_miss = if misses_remaining > 0 {
+		Some(read_miss(record)?)
+	} else {
+		None
+	};
+
+	let mut tokens: Vec<Token> = Vec::new();
+	while *pos < end {
+		let predicted = matcher.predict(data, *pos);
+		let token = match next_miss.as_mut() {
+			Some((0, token)) => {
+				let token = *token;
+				misses_remaining -= 1;
+				next_miss = if misses_remaining > 0 {
+					Some(read_miss(record)?)
+				} else {
+					None
+				};
+				token
+			},
+			Some((hits, _)) => {
+				*hits -= 1;
+				predicted
+			},
+			None => predicted,
+		};
+		let token = match token {
+			Token::Literal(_) => Token::Literal(data[*pos]),
+			Token::Match { length, distance } => {
+				let length_usize = usize::from(length);
+				let distance_usize = usize::from(distance);
+				if !(lz77::MIN_MATCH..=lz77::MAX_MATCH).contains(&length_usize)
+					|| distance_usize == 0
+					|| distance_usize > lz77::WINDOW_SIZE
+				{
+					return Err(RebuildError::Malformed);
+				}
+				if distance_usize > *pos || *pos + length_usize > end {
+					return Err(RebuildError::DataMismatch);
+				}
+				let matches =
+					(*pos..(*pos + length_usize)).all(|x| data[x] == data[x - distance_usize]);
+				if !matches {
+					return Err(RebuildError::DataMismatch);
+				}
+				token
+			},
+		};
+		matcher.advance(data, *pos, token.len());
+		*pos += token.len();
+		tokens.push(token);
+	}
+	if next_miss.is_some() {
+		return Err(RebuildError::Malformed);
+	}
+	Ok(tokens)
+}
+
+/// Writes the tokens of a huffman-coded block, after checking that the codes can represent them
+fn write_tokens(
+	writer: &mut BitWriter,
+	tokens: &[Token],
+	lengths: &HuffmanEncoder,
+	distances: &HuffmanEncoder,
+) -> Result<(), RebuildError> {
+	if lengths.lengths().get(256).is_none_or(|x| *x == 0)
+		|| !tokens.iter().all(|x| can_write(x, lengths, distances))
+	{
+		return Err(RebuildError::Malformed);
+	}
+	deflate::write_tokens(writer, tokens, lengths, distances);
+	Ok(())
+}
+
+fn read_miss(record: &mut RecordReader) -> Result<(usize, Token), RebuildError> {
+	let hits = record.varint()?;
+	let length = record.varint()?;
+	let token = if length == 0 {
+		// the value is taken from the data
+		Token::Literal(0)
+	} else {
+		let distance = record.varint()?;
+		Token::Match {
+			length: u16::try_from(length).map_err(|_| RebuildError::Malformed)?,
+			distance: u16::try_from(distance).map_err(|_| RebuildError::Malformed)?,
+		}
+	};
+	Ok((hits, token))
+}
+
+/// Whether the codes have a code for each symbol needed to write the token
+fn can_write(token: &Token, lengths: &HuffmanEncoder, distances: &HuffmanEncoder) -> bool {
+	match token {
+		Token::Literal(x) => lengths.lengths()[usize::from(*x)] != 0,
+		Token::Match { length, distance } => {
+			let length_symbol = 257 + deflate::length_index(*length);
+			let distance_symbol = deflate::distance_index(*distance);
+			lengths
+				.lengths()
+				.get(length_symbol)
+				.is_some_and(|x| *x != 0)
+				&& distances
+					.lengths()
+					.get(distance_symbol)
+					.is_some_and(|x| *x != 0)
+		},
+	}
+}
+
+/// The description of a custom-code block's codes, as it appears in the stream
+#[derive(PartialEq, Eq)]
+struct DynamicHeader {
+	num_length_codes: usize,
+	num_distance_codes: usize,
+	/// The meta code lengths, in the order they appear in the stream
+	meta_lengths: Vec<u8>,
+	/// The meta codes describing the code lengths, each with the value of its extra bits
+	meta_symbols: Vec<(u8, u8)>,
+}
+
+impl DynamicHeader {
+	/// Reads the header from a deflate stream, returning the header and the code lengths it describes
+	fn parse(bits: &mut BitCursor) -> Option<(DynamicHeader, Vec<u8>)> {
+		let num_length_codes = 257 + bits.read_n_rev(5)? as usize;
+		let num_distance_codes = 1 + bits.read_n_rev(5)? as usize;
+		let num_meta_codes = 4 + bits.read_n_rev(4)? as usize;
+		let meta_lengths: Vec<u8> = (0..num_meta_codes)
+			.map(|_| bits.read_n_rev(3).map(|x| x as u8))
+			.collect::<Option<_>>()?;
+		let meta = huffman::Table::from_lengths(
+			&DynamicHeader::unshuffle(&meta_lengths),
+			deflate::META_TABLE_BITS,
+		)?;
+
+		let mut meta_symbols: Vec<(u8, u8)> = Vec::new();
+		let mut lengths: Vec<u8> = Vec::new();
+		while lengths.len() < num_length_codes + num_distance_codes {
+			let symbol = meta.decode(bits).ok()? as u8;
+			let extra = bits.read_n_rev(deflate::meta_extra_bits(symbol))? as u8;
+			meta_symbols.push((symbol, extra));
+			expand_meta_symbol(&mut lengths, symbol, extra)?;
+		}
+		if lengths.len() != num_length_codes + num_distance_codes {
+			return None;
+		}
+
+		let header = DynamicHeader {
+			num_length_codes,
+			num_distance_codes,
+			meta_lengths,
+			meta_symbols,
+		};
+		Some((header, lengths))
+	}
+
+	/// The header that zlib would write for a block containing the tokens
+	fn zlib(tokens: &[Token]) -> DynamicHeader {
+		let frequencies = deflate::Frequencies::new(tokens);
+		let (lengths, max_length_code) = reference::code_lengths(&frequencies.lengths, 15);
+		let (distances, max_distance_code) = reference::code_lengths(&frequencies.distances, 15);
+		let mut meta_symbols: Vec<(u8, u8)> = Vec::new();
+		reference::run_length_encode(&lengths[..=max_length_code], &mut meta_symbols);
+		reference::run_length_encode(&distances[..=max_distance_code], &mut meta_symbols);
+		let mut meta_frequencies = [0u32; 19];
+		for (symbol, _) in &meta_symbols {
+			meta_frequencies[usize::from(*symbol)] += 1;
+		}
+		let (meta, _) = reference::code_lengths(&meta_frequencies, 7);
+		let num_meta_codes = 4.max(
+			1 + deflate::META_CODES_ORDER
+				.iter()
+				.rposition(|x| meta[*x] != 0)
+				.unwrap_or(0),
+		);
+		DynamicHeader {
+			num_length_codes: max_length_code + 1,
+			num_distance_codes: max_distance_code + 1,
+			meta_lengths: DynamicHeader::shuffle(&meta, num_meta_codes),
+			meta_symbols,
+		}
+	}
+
+	/// The header that this crate's compressor would write for a block containing the tokens
+	fn own(tokens: &[Token]) -> DynamicHeader {
+		let codes = deflate::DynamicCodes::new(&deflate::Frequencies::new(tokens));
+		DynamicHeader {
+			num_length_codes: codes.num_length_codes,
+			num_distance_codes: codes.num_distance_codes,
+			meta_lengths: DynamicHeader::shuffle(codes.meta.lengths(), codes.num_meta_codes),
+			meta_symbols: codes.meta_symbols,
+		}
+	}
+
+	/// Converts meta code lengths from symbol order to stream order
+	fn shuffle(meta_lengths: &[u8], count: usize) -> Vec<u8> {
+		deflate::META_CODES_ORDER[..count]
+			.iter()
+			.map(|x| meta_lengths[*x])
+			.collect()
+	}
+
+	/// Converts meta code lengths from stream order to symbol order
+	fn unshuffle(meta_lengths: &[u8]) -> [u8; 19] {
+		let mut retval = [0u8; 19];
+		for (length, symbol) in meta_lengths.iter().zip(deflate::META_CODES_ORDER.iter()) {
+			retval[*symbol] = *length;
+		}
+		retval
+	}
+
+	fn serialize(&self, out: &mut Vec<u8>) {
+		write_varint(out, self.num_length_codes - 257);
+		write_varint(out, self.num_distance_codes - 1);
+		write_varint(out, self.meta_lengths.len() - 4);
+		out.extend_from_slice(&self.meta_lengths);
+		write_varint(out, self.meta_symbols.len());
+		for (symbol, extra) in &self.meta_symbols {
+			out.push(*symbol);
+			if deflate::meta_extra_bits(*symbol) != 0 {
+				out.push(*extra);
+			}
+		}
+	}
+
+	fn read(record: &mut RecordReader) -> Result<DynamicHeader, RebuildError> {
+		let num_length_codes = record.varint()?.saturating_add(257);
+		let num_distance_codes = record.varint()?.saturating_add(1);
+		let num_meta_codes = record.varint()?.saturating_add(4);
+		if num_length_codes > 288 || num_distance_codes > 32 || num_meta_codes > 19 {
+			return Err(RebuildError::Malformed);
+		}
+		let meta_lengths = record.bytes(num_meta_codes)?.to_vec();
+		if meta_lengths.iter().any(|x| *x > 7) {
+			return Err(RebuildError::Malformed);
+		}
+		let meta_symbol_count = record.varint()?;
+		let mut meta_symbols: Vec<(u8, u8)> = Vec::new();
+		for _ in 0..meta_symbol_count {
+			let symbol = record.u8()?;
+			let extra = if deflate::meta_extra_bits(symbol) != 0 {
+				record.u8()?
+			} else {
+				0
+			};
+			meta_symbols.push((symbol, extra));
+		}
+		Ok(DynamicHeader {
+			num_length_codes,
+			num_distance_codes,
+			meta_lengths,
+			meta_symbols,
+		})
+	}
+
+	/// Writes the header to a deflate stream, returning the codes it describes
+	fn write(
+		&self,
+		writer: &mut BitWriter,
+	) -> Result<(HuffmanEncoder, HuffmanEncoder), RebuildError> {
+		writer.write_n_rev((self.num_length_codes - 257) as u32, 5);
+		writer.write_n_rev((self.num_distance_codes - 1) as u32, 5);
+		writer.write_n_rev((self.meta_lengths.len() - 4) as u32, 4);
+		for length in &self.meta_lengths {
+			writer.write_n_rev(u32::from(*length), 3);
+		}
+		let meta_lengths = DynamicHeader::unshuffle(&self.meta_lengths);
+		if huffman::is_oversubscribed(&meta_lengths) {
+			return Err(RebuildError::Malformed);
+		}
+		let meta = HuffmanEncoder::from_lengths(meta_lengths.to_vec());
+		let mut lengths: Vec<u8> = Vec::new();
+		for (symbol, extra) in &self.meta_symbols {
+			let extra_bits = deflate::meta_extra_bits(*symbol);
+			if meta
+				.lengths()
+				.get(usize::from(*symbol))
+				.is_none_or(|x| *x == 0)
+				|| u32::from(*extra) >> extra_bits != 0
+			{
+				return Err(RebuildError::Malformed);
+			}
+			meta.write(writer, usize::from(*symbol));
+			writer.write_n_rev(u32::from(*extra), extra_bits);
+			expand_meta_symbol(&mut lengths, *symbol, *extra).ok_or(RebuildError::Malformed)?;
+		}
+		if lengths.len() != self.num_length_codes + self.num_distance_codes {
+			return Err(RebuildError::Malformed);
+		}
+		let distances = lengths.split_off(self.num_length_codes);
+		if huffman::is_oversubscribed(&lengths) || huffman::is_oversubscribed(&distances) {
+			return Err(RebuildError::Malformed);
+		}
+		Ok((
+			HuffmanEncoder::from_lengths(lengths),
+			HuffmanEncoder::from_lengths(distances),
+		))
+	}
+}
+
+/// Appends the code lengths represented by a meta code
+fn expand_meta_symbol(lengths: &mut Vec<u8>, symbol: u8, extra: u8) -> Option<()> {
+	match symbol {
+		0..=15 => lengths.push(symbol),
+		16 => {
+			let previous = *lengths.last()?;
+			lengths.extend(std::iter::repeat_n(previous, 3 + usize::from(extra)));
+		},
+		17 => lengths.extend(std::iter::repeat_n(0, 3 + usize::from(extra))),
+		18 => lengths.extend(std::iter::repeat_n(0, 11 + usize::from(extra))),
+		_ => return None,
+	}
+	Some(())
+}
+
+/// The structure of a deflate block
+enum BlockKind {
+	/// A non-compressed block, and the value of the bits skipped to reach a byte boundary
+	Stored {
+		padding: u8,
+		length: u16,
+	},
+	Fixed,
+	Dynamic(DynamicHeader),
+}
+
+struct Block {
+	is_final: bool,
+	kind: BlockKind,
+	/// The tokens of a huffman-coded block
+	tokens: Vec<Token>,
+}
+
+/// The structure of a zlib stream
+struct ParsedStream {
+	header: [u8; 2],
+	blocks: Vec<Block>,
+	/// The value of the bits after the final block
+	final_padding: u8,
+	/// Any bytes after the end of the zlib stream
+	trailing: Vec<u8>,
+}
+
+/// Splits a zlib stream into its components
+fn parse(stream: &[u8]) -> Option<ParsedStream> {
+	let header = [*stream.first()?, *stream.get(1)?];
+	let mut bits = BitCursor::new(&stream[2..]);
+	let mut blocks: Vec<Block> = Vec::new();
+	let mut pos: usize = 0;
+
+	loop {
+		let is_final = bits.read_bit()?;
+		let block = match bits.read_n_rev(2)? {
+			0 => {
+				let padding = bits.read_n_rev(bits.bits_to_byte_boundary())? as u8;
+				let lengths = bits.read_bytes(4)?;
+				let length = u16::from_le_bytes([lengths[0], lengths[1]]);
+				bits.read_bytes(usize::from(length))?;
+				pos += usize::from(length);
+				Block {
+					is_final,
+					kind: BlockKind::Stored { padding, length },
+					tokens: Vec::new(),
+				}
+			},
+			1 => {
+				let lengths = HuffmanEncoder::fixed_length_codes();
+				let distances = HuffmanEncoder::fixed_distance_codes();
+				let lengths =
+					huffman::Table::from_lengths(lengths.lengths(), deflate::LENGTH_TABLE_BITS)?;
+				let distances = huffman::Table::from_lengths(
+					distances.lengths(),
+					deflate::DISTANCE_TABLE_BITS,
+				)?;
+				Block {
+					is_final,
+					kind: BlockKind::Fixed,
+					tokens: parse_tokens(&mut bits, &lengths, &distances, &mut pos)?,
+				}
+			},
+			2 => {
+				let (header, lengths) = DynamicHeader::parse(&mut bits)?;
+				let (lengths, distances) = lengths.split_at(header.num_length_codes);
+				let lengths = huffman::Table::from_lengths(lengths, deflate::LENGTH_TABLE_BITS)?;
+				let distances =
+					huffman::Table::from_lengths(distances, deflate::DISTANCE_TABLE_BITS)?;
+				Block {
+					is_final,
+					tokens: parse_tokens(&mut bits, &lengths, &distances, &mut pos)?,
+					kind: BlockKind::Dynamic(header),
+				}
+			},
+			_ => return None,
+		};
+		blocks.push(block);
+		if is_final {
+			break;
+		}
+	}
+
+	let final_padding = bits.read_n_rev(bits.bits_to_byte_boundary())? as u8;
+	let trailing = bits.remaining_bytes().get(4..)?.to_vec();
+	Some(ParsedStream {
+		header,
+		blocks,
+		final_padding,
+		trailing,
+	})
+}
+
+/// Reads the tokens of a huffman-coded block, up to and excluding the end-of-block code
+fn parse_tokens(
+	bits: &mut BitCursor,
+	lengths: &huffman::Table,
+	distances: &huffman::Table,
+	pos: &mut usize,
+) -> Option<Vec<Token>> {
+	let mut retval: Vec<Token> = Vec::new();
+	loop {
+		let symbol = lengths.decode(bits).ok()?;
+		let token = match symbol {
+			0..=255 => Token::Literal(symbol as u8),
+			256 => break,
+			257..=285 => {
+				let index = usize::from(symbol - 257);
+				let extra = u8::from(deflate::LENGTH_EXTRA_BITS[index]);
+				let length = deflate::LENGTH_BASE[index] + bits.read_n_rev(extra)? as u16;
+				let index = usize::from(distances.decode(bits).ok()?);
+				let extra = u8::from(*deflate::DISTANCE_EXTRA_BITS.get(index)?);
+				let distance = deflate::DISTANCE_BASE[index] + bits.read_n_rev(extra)? as u16;
+				if usize::from(distance) > *pos {
+					return None;
+				}
+				Token::Match { length, distance }
+			},
+			_ => return None,
+		};
+		*pos += token.len();
+		retval.push(token);
+	}
+	Some(retval)
+}
+
+/// Writes the reconstruction data for a stream, using the given predictor
+fn encode(parsed: &ParsedStream, data: &[u8], predictor: usize) -> Vec<u8> {
+	let mut retval: Vec<u8> = vec![VERSION];
+	retval.extend_from_slice(&parsed.header);
+	retval.push(predictor as u8);
+	write_varint(&mut retval, parsed.blocks.len());
+
+	let mut matcher = PREDICTORS[predictor].build(&parsed.header);
+	let mut pos: usize = 0;
+	for block in &parsed.blocks {
+		let flags = u8::from(block.is_final);
+		match &block.kind {
+			BlockKind::Stored { padding, length } => {
+				retval.push(flags);
+				retval.push(*padding);
+				write_varint(&mut retval, usize::from(*length));
+				matcher.advance(data, pos, usize::from(*length));
+				pos += usize::from(*length);
+				continue;
+			},
+			BlockKind::Fixed => retval.push(flags | (1 << 1)),
+			BlockKind::Dynamic(header) => {
+				let source = if *header == DynamicHeader::zlib(&block.tokens) {
+					HeaderSource::Zlib
+				} else if *header == DynamicHeader::own(&block.tokens) {
+					HeaderSource::Own
+				} else {
+					HeaderSource::Explicit
+				};
+				retval.push(flags | (2 << 1) | ((source as u8) << 3));
+				if source == HeaderSource::Explicit {
+					header.serialize(&mut retval);
+				}
+			},
+		}
+
+		write_varint(&mut retval, block.tokens.iter().map(Token::len).sum());
+		let mut misses: Vec<(usize, Token)> = Vec::new();
+		let mut hits: usize = 0;
+		for token in &block.tokens {
+			if matcher.predict(data, pos) == *token {
+				hits += 1;
+			} else {
+				misses.push((hits, *token));
+				hits = 0;
+			}
+			matcher.advance(data, pos, token.len());
+			pos += token.len();
+		}
+		write_varint(&mut retval, misses.len());
+		for (hits, token) in misses {
+			write_varint(&mut retval, hits);
+			match token {
+				Token::Literal(_) => write_varint(&mut retval, 0),
+				Token::Match { length, distance } => {
+					write_varint(&mut retval, usize::from(length));
+					write_varint(&mut retval, usize::from(distance));
+				},
+			}
+		}
+	}
+
+	retval.push(parsed.final_padding);
+	write_varint(&mut retval, parsed.trailing.len());
+	retval.extend_from_slice(&parsed.trailing);
+	retval
+}
+
+/// Counts the tokens, among the first `limit` tokens of the stream, that the predictor would not
+/// have chosen
+///
+/// Literals that the predictor also would have written as literals are not counted towards the
+/// limit, as every predictor agrees on those where the data has nothing to match, such as at the
+/// start of most streams.
+fn count_misses(parsed: &ParsedStream, data: &[u8], predictor: usize, limit: usize) -> usize {
+	let mut matcher = PREDICTORS[predictor].build(&parsed.header);
+	let mut pos: usize = 0;
+	let mut misses: usize = 0;
+	let mut remaining = limit;
+	for block in &parsed.blocks {
+		if let BlockKind::Stored { length, .. } = block.kind {
+			matcher.advance(data, pos, usize::from(length));
+			pos += usize::from(length);
+		}
+		for token in &block.tokens {
+			if remaining == 0 {
+				return misses;
+			}
+			let prediction = matcher.predict(data, pos);
+			if prediction != *token {
+				misses += 1;
+				remaining -= 1;
+			} else if let Token::Match { .. } = token {
+				remaining -= 1;
+			}
+			matcher.advance(data, pos, token.len());
+			pos += token.len();
+		}
+	}
+	misses
+}
+
+/// Writes a LEB128 variable-length integer
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+	while value >= 0x80 {
+		out.push((value & 0x7F) as u8 | 0x80);
+		value >>= 7;
+	}
+	out.push(value as u8);
+}
+
+/// Reads values from reconstruction data
+struct RecordReader<'a> {
+	backing: &'a [u8],
+}
+
+impl<'a> RecordReader<'a> {
+	fn u8(&mut self) -> Result<u8, RebuildError> {
+		Ok(self.bytes(1)?[0])
+	}
+
+	fn bytes(&mut self, count: usize) -> Result<&'a [u8], RebuildError> {
+		if count > self.backing.len() {
+			return Err(RebuildError::Malformed);
+		}
+		let (retval, rest) = self.backing.split_at(count);
+		self.backing = rest;
+		Ok(retval)
+	}
+
+	/// Reads a LEB128 variable-length integer
+	fn varint(&mut self) -> Result<usize, RebuildError> {
+		let mut retval: usize = 0;
+		for shift in (0..usize::BITS).step_by(7) {
+			let byte = self.u8()?;
+			retval |= usize::from(byte & 0x7F)
+				.checked_shl(shift)
+				.ok_or(RebuildError::Malformed)?;
+			if byte & 0x80 == 0 {
+				return Ok(retval);
+			}
+		}
+		Err(RebuildError::Malformed)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	mod round_trip {
+		use super::super::super::deflate;
+		use super::super::super::deflate_immediate;
+		use super::super::super::CompressionLevel;
+		use super::super::analyze;
+		use super::super::rebuild;
+
+		fn assert_round_trip(stream: &[u8]) -> Vec<u8> {
+			let (data, record) = analyze(stream, usize::MAX).unwrap();
+			let record = record.expect("stream could not be recorded");
+			assert_eq!(Ok(stream.to_vec()), rebuild(&data, &record));
+			record
+		}
+
+		/// Pseudorandomly-chosen words
+		fn sample_data() -> Vec<u8> {
+			let words: [&[u8]; 8] = [
+				b"lorem ",
+				b"ipsum ",
+				b"dolor ",
+				b"sit ",
+				b"amet ",
+				b"consectetur ",
+				b"adipiscing ",
+				b"elit ",
+			];
+			crate::test_util::pseudorandom(0x1234_5678, 2_000)
+				.into_iter()
+				.flat_map(|x| words[usize::from(x >> 5)].iter().cloned())
+				.collect()
+		}
+
+		#[test]
+		fn immediate() {
+			assert_round_trip(&deflate_immediate(&sample_data()));
+		}
+
+		#[test]
+		fn own_compressor_needs_no_corrections() {
+			for level in [
+				CompressionLevel::Fastest,
+				CompressionLevel::Fast,
+				CompressionLevel::Slow,
+				CompressionLevel::Slowest,
+			] {
+				let stream = deflate(&sample_data(), level);
+				let record = assert_round_trip(&stream);
+				assert!(
+					record.len() < stream.len() / 20,
+					"{:?} {}",
+					level,
+					record.len()
+				);
+			}
+		}
+
+		#[test]
+		fn long_unmatched_prefix() {
+			// the big-endian bytes of consecutive numbers, in which no three bytes repeat, so that
+			// the stream starts with more literals than the predictors' trial looks at
+			let data: Vec<u8> = (0..5_000u16)
+				.flat_map(|x| x.to_be_bytes())
+				.chain(sample_data())
+				.collect();
+			let stream = deflate(&data, CompressionLevel::Slowest);
+			let record = assert_round_trip(&stream);
+			assert!(record.len() < stream.len() / 20, "{}", record.len());
+		}
+
+		#[test]
+		fn empty() {
+			assert_round_trip(&deflate(&[], CompressionLevel::Slow));
+		}
+
+		#[test]
+		fn fixed_codes_from_other_encoder() {
+			// "abcde" five times, as compressed by zlib
+			#[rustfmt::skip]
+			let stream = [
+				0x78, 0x9c,
+				0x4b, 0x4c, 0x4a, 0x4e, 0x49, 0x4d, 0xc4, 0x42, 0x00, 0x00,
+				0x7d, 0x96, 0x09, 0xac,
+			];
+			assert_round_trip(&stream);
+		}
+
+		#[test]
+		fn zlib_needs_no_corrections() {
+			// a sentence of lorem ipsum, as compressed by zlib at level 9
+			#[rustfmt::skip]
+			let stream = [
+				0x78, 0xda, 0x25, 0x8f, 0x51, 0x8e, 0x03, 0x31, 0x08, 0x43, 0xaf, 0xe2, 0x03, 0x54, 0x3d, 0x49,
+				0x7f, 0xf7, 0x00, 0x34, 0x41, 0x95, 0xa5, 0x10, 0x66, 0x02, 0x59, 0xed, 0xf1, 0x97, 0xe9, 0xfc,
+				0x81, 0xb0, 0x9f, 0xcd, 0xcb, 0x97, 0x1a, 0x78, 0xc4, 0x36, 0x74, 0x1f, 0xbe, 0x10, 0x4c, 0x88,
+				0x69, 0x3e, 0xd0, 0x7c, 0x86, 0xb6, 0xd4, 0xdc, 0x0b, 0xd2, 0x79, 0x30, 0x1a, 0xe7, 0x07, 0x3a,
+				0x58, 0xc7, 0xd0, 0x5e, 0x06, 0x28, 0x77, 0x98, 0x77, 0xa4, 0xda, 0x51, 0x66, 0xce, 0xc6, 0xce,
+				0xbe, 0x67, 0x62, 0x27, 0x86, 0xbc, 0x0b, 0x0f, 0xcd, 0x1b, 0xad, 0x30, 0xf9, 0x4c, 0x81, 0x0c,
+				0x9e, 0x5b, 0x9e, 0xf8, 0x49, 0xe8, 0xa4, 0x15, 0x1b, 0xc6, 0x6b, 0xf8, 0xad, 0x55, 0xec, 0x81,
+				0x73, 0x33, 0x30, 0x3d, 0x72, 0xed, 0x0e, 0xfd, 0xd3, 0xd5, 0x98, 0x92, 0xf4, 0x89, 0x3d, 0x86,
+				0x58, 0xf3, 0x9b, 0x7c, 0x89, 0x18, 0xbc, 0x92, 0xbe, 0x48, 0x1e, 0x25, 0x86, 0x4a, 0x15, 0xb7,
+				0xea, 0xe4, 0xf7, 0x03, 0x15, 0x95, 0xcf, 0x7f, 0xae, 0x3a, 0x55, 0x80,
+			];
+			let record = assert_round_trip(&stream);
+			assert!(record.len() < 16, "{}", record.len());
+		}
+
+		#[test]
+		fn nonzero_padding_and_trailing_bytes() {
+			// a non-compressed block preceded by nonzero padding bits, followed by garbage
+			#[rustfmt::skip]
+			let stream = [
+				0x78, 0x01,
+				0b1111_1001, 3, 0, !3, 0xFF, b'a', b'b', b'c',
+				0x02, 0x4d, 0x01, 0x27,
+				0xDE, 0xAD,
+			];
+			assert_round_trip(&stream);
+		}
+
+		#[test]
+		fn invalid_stream_is_error() {
+			assert!(analyze(&[0x78, 0x01, 0x07], usize::MAX).is_err());
+		}
+	}
+
+	mod rebuild {
+		use super::super::super::deflate;
+		use super::super::super::CompressionLevel;
+		use super::super::analyze;
+		use super::super::rebuild;
+		use super::super::RebuildError;
+
+		#[test]
+		fn different_length_data_is_mismatch() {
+			let data = b"lorem ipsum dolor sit amet, lorem ipsum dolor sit amet";
+			let stream = deflate(data, CompressionLevel::Slow);
+			let (_, record) = analyze(&stream, usize::MAX).unwrap();
+			let other = b"lorem ipsum dolor sit amet, lorem ipsum dolor sit";
+			assert_eq!(
+				Err(RebuildError::DataMismatch),
+				rebuild(other, &record.unwrap())
+			);
+		}
+
+		#[test]
+		fn unknown_version() {
+			assert_eq!(Err(RebuildError::UnknownVersion(9)), rebuild(&[], &[9]));
+		}
+
+		#[test]
+		fn truncated_is_malformed() {
+			let stream = deflate(b"abcabcabc", CompressionLevel::Slow);
+			let (data, record) = analyze(&stream, usize::MAX).unwrap();
+			let record = record.unwrap();
+			assert_eq!(
+				Err(RebuildError::Malformed),
+				rebuild(&data, &record[..record.len() - 1])
+			);
+		}
+
+		#[test]
+		fn huge_code_count_is_malformed() {
+			// one explicit custom-code block, whose number of length codes is usize::MAX
+			let mut record = vec![0, 0x78, 0x9C, 0, 1, 0b101];
+			record.extend([0xFF; 9]);
+			record.push(0x01);
+			record.extend([0; 4]);
+			assert_eq!(20, record.len());
+			assert_eq!(Err(RebuildError::Malformed), rebuild(&[], &record));
+		}
+	}
+}
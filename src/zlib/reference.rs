@@ -0,0 +1,452 @@
+//! Reproduces the choices made by the reference zlib implementation's compressor, so that streams
+//! created by zlib (and by libpng, which uses zlib) can be recreated with few corrections
+//!
+//! This follows zlib's `deflate.c` and `trees.c`, assuming the default memory level.
+
+use super::lz77::Predictor;
+use super::lz77::Token;
+use super::lz77::MAX_MATCH;
+use super::lz77::MIN_MATCH;
+
+/// The number of bytes zlib keeps available past the current position; matches cannot reach
+/// further back than the window size minus this
+const MIN_LOOKAHEAD: usize = MAX_MATCH + MIN_MATCH + 1;
+/// zlib discards length-3 matches further away than this
+const TOO_FAR: usize = 4096;
+const HASH_BITS: u32 = 15;
+const HASH_SHIFT: u32 = HASH_BITS.div_ceil(MIN_MATCH as u32);
+/// zlib uses position zero to mean "no position"
+const NIL: usize = 0;
+
+/// The settings of one of zlib's compression levels
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Parameters {
+	/// Search less thoroughly after finding a match at least this long
+	good_length: usize,
+	/// In a lazy level, do not look for a better match after a match this long. In a non-lazy
+	/// level, do not insert the positions covered by matches longer than this into the hash chains.
+	max_lazy: usize,
+	/// Stop searching once a match at least this long is found
+	nice_length: usize,
+	/// The maximum number of previous positions to examine when looking for a match
+	max_chain: usize,
+	/// Whether the level uses zlib's `deflate_slow` rather than `deflate_fast`
+	lazy: bool,
+	/// Whether the `Z_FILTERED` strategy is in use, which discards short matches
+	filtered: bool,
+}
+
+impl Parameters {
+	/// Returns the parameters of zlib's compression level `level`, which is between 1 and 9
+	pub const fn level(level: u8, filtered: bool) -> Parameters {
+		let (good_length, max_lazy, nice_length, max_chain) = match level {
+			1 => (4, 4, 8, 4),
+			2 => (4, 5, 16, 8),
+			3 => (4, 6, 32, 32),
+			4 => (4, 4, 16, 16),
+			5 => (8, 16, 32, 32),
+			6 => (8, 16, 128, 128),
+			7 => (8, 32, 128, 256),
+			8 => (32, 128, 258, 1024),
+			_ => (32, 258, 258, 4096),
+		};
+		Parameters {
+			good_length,
+			max_lazy,
+			nice_length,
+			max_chain,
+			lazy: level >= 4,
+			filtered,
+		}
+	}
+}
+
+/// A match finder that makes the same decisions as zlib
+pub struct ZlibMatcher {
+	params: Parameters,
+	/// The furthest back a match can reach
+	max_distance: usize,
+	window_mask: usize,
+	/// The most recent position with each hash
+	head: Vec<usize>,
+	/// For each position in the window, the previous position with the same hash
+	prev: Vec<usize>,
+	/// the positions before this one have been inserted into `head` and `prev`, or skipped
+	inserted_until: usize,
+	/// The match found at a position while deciding what to do with the previous position
+	pending: Option<(usize, (usize, usize))>,
+}
+
+impl ZlibMatcher {
+	/// Creates a matcher with the given parameters and a window of `1 << window_bits` bytes
+	pub fn new(params: Parameters, window_bits: u8) -> ZlibMatcher {
+		let window_size = 1usize << window_bits.clamp(9, 15);
+		ZlibMatcher {
+			params,
+			max_distance: window_size - MIN_LOOKAHEAD,
+			window_mask: window_size - 1,
+			head: vec![NIL; 1 << HASH_BITS],
+			prev: vec![NIL; window_size],
+			inserted_until: 0,
+			pending: None,
+		}
+	}
+
+	fn hash(data: &[u8], pos: usize) -> usize {
+		let value = (usize::from(data[pos]) << (2 * HASH_SHIFT))
+			^ (usize::from(data[pos + 1]) << HASH_SHIFT)
+			^ usize::from(data[pos + 2]);
+		value & ((1 << HASH_BITS) - 1)
+	}
+
+	fn insert_until(&mut self, data: &[u8], end: usize) {
+		while self.inserted_until < end {
+			let pos = self.inserted_until;
+			if pos + MIN_MATCH <= data.len() {
+				let hash = ZlibMatcher::hash(data, pos);
+				self.prev[pos & self.window_mask] = self.head[hash];
+				self.head[hash] = pos;
+			}
+			self.inserted_until += 1;
+		}
+	}
+
+	/// Finds a match at `pos` that is longer than `prev_length`, as zlib's `deflate_slow` and
+	/// `deflate_fast` would. Returns the match length and distance, or a length less than
+	/// `MIN_MATCH` if there is no such match.
+	fn find(&self, data: &[u8], pos: usize, prev_length: usize) -> (usize, usize) {
+		const NONE: (usize, usize) = (MIN_MATCH - 1, 0);
+		if pos + MIN_MATCH > data.len() {
+			return NONE;
+		}
+		let head = self.head[ZlibMatcher::hash(data, pos)];
+		if head == NIL
+			|| pos - head > self.max_distance
+			|| (self.params.lazy && prev_length >= self.params.max_lazy)
+		{
+			return NONE;
+		}
+		let (length, distance) = self.longest_match(data, pos, head, prev_length);
+		if length <= prev_length {
+			return NONE;
+		}
+		if self.params.lazy
+			&& length <= 5
+			&& (self.params.filtered || (length == MIN_MATCH && distance > TOO_FAR))
+		{
+			return NONE;
+		}
+		(length, distance)
+	}
+
+	/// zlib's `longest_match`
+	fn longest_match(
+		&self,
+		data: &[u8],
+		pos: usize,
+		mut candidate: usize,
+		prev_length: usize,
+	) -> (usize, usize) {
+		let lookahead = data.len() - pos;
+		let max_length = MAX_MATCH.min(lookahead);
+		let nice_length = self.params.nice_length.min(lookahead);
+		let limit = pos.saturating_sub(self.max_distance);
+		let mut chain = self.params.max_chain;
+		if prev_length >= self.params.good_length {
+			chain >>= 2;
+		}
+
+		let mut best_length = prev_length;
+		let mut best_distance = 0;
+		loop {
+			let length = data[candidate..candidate + max_length]
+				.iter()
+				.zip(&data[pos..pos + max_length])
+				.take_while(|(a, b)| a == b)
+				.count();
+			if length > best_length {
+				best_length = length;
+				best_distance = pos - candidate;
+				if length >= nice_length {
+					break;
+				}
+			}
+			candidate = self.prev[candidate & self.window_mask];
+			chain -= 1;
+			if candidate <= limit || chain == 0 {
+				break;
+			}
+		}
+		(best_length, best_distance)
+	}
+}
+
+impl Predictor for ZlibMatcher {
+	fn predict(&mut self, data: &[u8], pos: usize) -> Token {
+		let (length, distance) = match self.pending.take() {
+			Some((pending_pos, found)) if pending_pos == pos => found,
+			_ => self.find(data, pos, MIN_MATCH - 1),
+		};
+		let here = if length >= MIN_MATCH {
+			Token::Match {
+				length: length as u16,
+				distance: distance as u16,
+			}
+		} else {
+			Token::Literal(data[pos])
+		};
+		if !self.params.lazy || pos + 1 >= data.len() {
+			return here;
+		}
+
+		// zlib inserts each position before looking for a match at the next position
+		self.insert_until(data, pos + 1);
+		let next = self.find(data, pos + 1, length.max(MIN_MATCH - 1));
+		if length >= MIN_MATCH && next.0 < MIN_MATCH {
+			here
+		} else {
+			self.pending = Some((pos + 1, next));
+			Token::Literal(data[pos])
+		}
+	}
+
+	fn advance(&mut self, data: &[u8], pos: usize, length: usize) {
+		self.insert_until(data, pos + 1);
+		let skip = !self.params.lazy
+			&& length >= MIN_MATCH
+			&& (length > self.params.max_lazy || pos + length + MIN_MATCH > data.len());
+		if skip {
+			// deflate_fast does not insert the positions covered by long matches
+			self.inserted_until = self.inserted_until.max(pos + length);
+		} else {
+			self.insert_until(data, pos + length);
+		}
+	}
+}
+
+/// Determines code lengths from symbol frequencies as zlib's `build_tree` and `gen_bitlen` do.
+/// Returns the lengths and the highest symbol with a nonzero length.
+pub fn code_lengths(frequencies: &[u32], max_length: u8) -> (Vec<u8>, usize) {
+	let elems = frequencies.len();
+	let node_count = 2 * elems + 1;
+	let mut freq: Vec<u32> = frequencies.to_vec();
+	freq.resize(node_count, 0);
+	let mut depth: Vec<u8> = vec![0; node_count];
+	let mut dad: Vec<usize> = vec![0; node_count];
+	// one-based, as in zlib; the front is a priority queue and the back collects the sorted nodes
+	let mut heap: Vec<usize> = vec![0; node_count + 1];
+	let heap_size = node_count;
+	let mut heap_len: usize = 0;
+	let mut heap_max: usize = heap_size;
+
+	let mut max_code: Option<usize> = None;
+	for (n, frequency) in frequencies.iter().enumerate() {
+		if *frequency != 0 {
+			heap_len += 1;
+			heap[heap_len] = n;
+			max_code = Some(n);
+		}
+	}
+	// zlib forces at least two codes to exist
+	while heap_len < 2 {
+		let node = match max_code {
+			None => 0,
+			Some(x) if x < 2 => x + 1,
+			Some(_) => 0,
+		};
+		if max_code.is_none_or(|x| x < 2) {
+			max_code = Some(node);
+		}
+		heap_len += 1;
+		heap[heap_len] = node;
+		freq[node] = 1;
+	}
+	let max_code = max_code.expect("at least two codes were forced");
+
+	let smaller = |freq: &[u32], depth: &[u8], n: usize, m: usize| {
+		freq[n] < freq[m] || (freq[n] == freq[m] && depth[n] <= depth[m])
+	};
+	let down_heap =
+		|heap: &mut [usize], heap_len: usize, freq: &[u32], depth: &[u8], mut k: usize| {
+			let v = heap[k];
+			let mut j = k << 1;
+			while j <= heap_len {
+				if j < heap_len && smaller(freq, depth, heap[j + 1], heap[j]) {
+					j += 1;
+				}
+				if smaller(freq, depth, v, heap[j]) {
+					break;
+				}
+				heap[k] = heap[j];
+				k = j;
+				j <<= 1;
+			}
+			heap[k] = v;
+		};
+
+	for n in (1..=heap_len / 2).rev() {
+		down_heap(&mut heap, heap_len, &freq, &depth, n);
+	}
+	let mut node = elems;
+	loop {
+		let n = heap[1];
+		heap[1] = heap[heap_len];
+		heap_len -= 1;
+		down_heap(&mut heap, heap_len, &freq, &depth, 1);
+		let m = heap[1];
+
+		heap_max -= 1;
+		heap[heap_max] = n;
+		heap_max -= 1;
+		heap[heap_max] = m;
+		freq[node] = freq[n] + freq[m];
+		depth[node] = depth[n].max(depth[m]) + 1;
+		dad[n] = node;
+		dad[m] = node;
+		heap[1] = node;
+		node += 1;
+		down_heap(&mut heap, heap_len, &freq, &depth, 1);
+		if heap_len < 2 {
+			break;
+		}
+	}
+	heap_max -= 1;
+	heap[heap_max] = heap[1];
+
+	// gen_bitlen
+	let mut lengths: Vec<u8> = vec![0; node_count];
+	let mut length_counts = [0u16; 16];
+	let mut overflow: i32 = 0;
+	for n in heap[(heap_max + 1)..heap_size].iter().cloned() {
+		let mut bits = lengths[dad[n]] + 1;
+		if bits > max_length {
+			bits = max_length;
+			overflow += 1;
+		}
+		lengths[n] = bits;
+		if n <= max_code {
+			length_counts[usize::from(bits)] += 1;
+		}
+	}
+	if overflow > 0 {
+		let max_length = usize::from(max_length);
+		while overflow > 0 {
+			let mut bits = max_length - 1;
+			while length_counts[bits] == 0 {
+				bits -= 1;
+			}
+			length_counts[bits] -= 1;
+			length_counts[bits + 1] += 2;
+			length_counts[max_length] -= 1;
+			overflow -= 2;
+		}
+		let mut h = heap_size;
+		for bits in (1..=max_length).rev() {
+			let mut n = length_counts[bits];
+			while n != 0 {
+				h -= 1;
+				let m = heap[h];
+				if m > max_code {
+					continue;
+				}
+				lengths[m] = bits as u8;
+				n -= 1;
+			}
+		}
+	}
+
+	lengths.truncate(elems);
+	(lengths, max_code)
+}
+
+/// Run-length encodes code lengths into meta symbols, as zlib's `scan_tree` and `send_tree` do.
+/// Appends each meta symbol and the value of its extra bits to `out`.
+pub fn run_length_encode(lengths: &[u8], out: &mut Vec<(u8, u8)>) {
+	let mut prev_length: Option<u8> = None;
+	let mut next_length = lengths.first().cloned();
+	let mut count: usize = 0;
+	let (mut max_count, mut min_count) = if next_length == Some(0) {
+		(138, 3)
+	} else {
+		(7, 4)
+	};
+
+	for n in 0..lengths.len() {
+		let length = lengths[n];
+		next_length = lengths.get(n + 1).cloned();
+		count += 1;
+		if count < max_count && Some(length) == next_length {
+			continue;
+		} else if count < min_count {
+			out.extend(std::iter::repeat_n((length, 0), count));
+		} else if length != 0 {
+			if Some(length) != prev_length {
+				out.push((length, 0));
+				count -= 1;
+			}
+			out.push((16, (count - 3) as u8));
+		} else if count <= 10 {
+			out.push((17, (count - 3) as u8));
+		} else {
+			out.push((18, (count - 11) as u8));
+		}
+		count = 0;
+		prev_length = Some(length);
+		(max_count, min_count) = if next_length == Some(0) {
+			(138, 3)
+		} else if Some(length) == next_length {
+			(6, 3)
+		} else {
+			(7, 4)
+		};
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	mod code_lengths {
+		use super::super::code_lengths;
+
+		#[test]
+		fn no_symbols_forces_two_codes() {
+			assert_eq!((vec![1, 1, 0, 0], 1), code_lengths(&[0, 0, 0, 0], 15));
+		}
+
+		#[test]
+		fn single_high_symbol_forces_symbol_zero() {
+			assert_eq!((vec![1, 0, 0, 1], 3), code_lengths(&[0, 0, 0, 5], 15));
+		}
+
+		#[test]
+		fn skewed_frequencies() {
+			assert_eq!((vec![1, 2, 3, 4, 4], 4), code_lengths(&[8, 4, 2, 1, 1], 15));
+		}
+
+		#[test]
+		fn respects_length_limit() {
+			let freqs: Vec<u32> = (0..19).map(|x| 1 << x).collect();
+			let (res, _) = code_lengths(&freqs, 7);
+			assert!(res.iter().all(|x| *x <= 7 && *x >= 1), "{:?}", res);
+			let sum: u32 = res.iter().map(|x| 1 << (7 - x)).sum();
+			assert_eq!(1 << 7, sum);
+		}
+	}
+
+	mod run_length_encode {
+		use super::super::run_length_encode;
+
+		#[test]
+		fn repeats() {
+			let mut res = Vec::new();
+			run_length_encode(&[0, 0, 0, 0, 5, 5, 5, 5, 5, 5, 5, 2, 2], &mut res);
+			assert_eq!(vec![(17, 1), (5, 0), (16, 3), (2, 0), (2, 0)], res);
+		}
+
+		#[test]
+		fn long_zero_run() {
+			let mut res = Vec::new();
+			run_length_encode(&[0; 150], &mut res);
+			assert_eq!(vec![(18, 127), (18, 1)], res);
+		}
+	}
+}
@@ -117,31 +117,3 @@ fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
 		<u8 as ::std::fmt::UpperHex>::fmt(&u8::from(*self), f)
 	}
 }
-
-/// An iterator over u4s that begins at 0, and ends (exclusive) of a given value
-pub struct ZeroToRangeIter {
-	current: u4,
-	end: u4,
-}
-
-impl ZeroToRangeIter {
-	pub fn new(end: u4) -> ZeroToRangeIter {
-		ZeroToRangeIter {
-			current: u4::_0,
-			end,
-		}
-	}
-}
-
-impl Iterator for ZeroToRangeIter {
-	type Item = u4;
-	fn next(&mut self) -> Option<u4> {
-		if self.current >= self.end {
-			None
-		} else {
-			let retval = self.current;
-			self.current += u4::_1;
-			Some(retval)
-		}
-	}
-}
@@ -0,0 +1,73 @@
+//! Checks what happens to the data after the IEND chunk under each `--after-iend` policy
+
+extern crate png_inflate_derive;
+extern crate tempfile;
+
+use png_inflate_derive::generate_for_each_files;
+use std::fs::read;
+use std::path::Path;
+use std::process::Command;
+use std::process::Output;
+use tempfile::NamedTempFile;
+
+const PROGRAM_EXE: &str = env!("CARGO_BIN_EXE_png_inflate");
+
+const IEND: &[u8] = &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82];
+
+generate_for_each_files!();
+
+/// Runs the program on `infile`, then returns the program's output,
+/// the data after the input's IEND chunk and the output file's data
+fn run(infile: &Path, extra_args: &[&str]) -> (Output, Vec<u8>, Vec<u8>) {
+	let outfile = NamedTempFile::new().expect("").into_temp_path();
+
+	let output = Command::new(PROGRAM_EXE)
+		.arg(infile)
+		.arg(&outfile)
+		.args(extra_args)
+		.output()
+		.expect("failed to execute subprocess");
+	assert!(
+		output.status.success(),
+		"subprocess execution was not success\n\n-- stderr:\n{}\n",
+		std::str::from_utf8(&output.stderr).expect("")
+	);
+
+	let indata = read(infile).expect("could not read input file");
+	let iend_end = indata
+		.windows(IEND.len())
+		.position(|x| x == IEND)
+		.expect("input has no IEND")
+		+ IEND.len();
+	let trailing = indata[iend_end..].to_vec();
+	let outdata = read(outfile).expect("could not read subprocess's output file");
+	(output, trailing, outdata)
+}
+
+/// Asserts that the output ends with the input's trailing data
+fn ends_with_trailing(infile: &Path, extra_args: &[&str]) {
+	let (_, trailing, outdata) = run(infile, extra_args);
+	assert!(!trailing.is_empty());
+	assert!(outdata.ends_with(&trailing));
+	assert!(outdata[..outdata.len() - trailing.len()].ends_with(IEND));
+}
+
+/// Asserts that the output ends with the IEND chunk, and that a warning was printed
+fn ends_with_iend(infile: &Path, extra_args: &[&str]) {
+	let (output, _, outdata) = run(infile, extra_args);
+	assert!(outdata.ends_with(IEND));
+	let stderr = std::str::from_utf8(&output.stderr).expect("");
+	assert!(stderr.contains("--after-iend strip"), "stderr: {}", stderr);
+}
+
+mod keep {
+	for_each_chunkafteriend_file!(super::ends_with_trailing, &["--after-iend", "keep"]);
+}
+mod strip {
+	for_each_chunkafteriend_file!(super::ends_with_iend, &["--after-iend", "strip"]);
+	for_each_bytesafteriend_file!(super::ends_with_iend, &["--after-iend=strip"]);
+}
+mod raw {
+	for_each_chunkafteriend_file!(super::ends_with_trailing, &["--after-iend", "raw"]);
+	for_each_bytesafteriend_file!(super::ends_with_trailing, &["--after-iend=raw"]);
+}
@@ -0,0 +1,48 @@
+//! Checks that `--chunk-order` reports chunk ordering problems before the image data is processed
+
+extern crate png_inflate_derive;
+extern crate tempfile;
+
+use png_inflate_derive::generate_for_each_files;
+use std::path::Path;
+use std::process::Command;
+use tempfile::NamedTempFile;
+
+const PROGRAM_EXE: &str = env!("CARGO_BIN_EXE_png_inflate");
+
+generate_for_each_files!();
+
+/// Asserts that the program fails, after printing a message about non-consecutive IDATs
+fn reports_non_consecutive_idat(infile: &Path, extra_args: &[&str]) {
+	let outfile = NamedTempFile::new().expect("").into_temp_path();
+
+	let output = Command::new(PROGRAM_EXE)
+		.arg(infile)
+		.arg(&outfile)
+		.args(extra_args)
+		.output()
+		.expect("failed to execute subprocess");
+	let stderr = std::str::from_utf8(&output.stderr).expect("");
+	assert!(
+		!output.status.success(),
+		"subprocess execution should not have been success"
+	);
+	assert!(
+		stderr.contains("is an IDAT that does not immediately follow the previous IDAT"),
+		"stderr: {}",
+		stderr
+	);
+}
+
+mod reject {
+	for_each_nonconsecutiveidat_file!(
+		super::reports_non_consecutive_idat,
+		&["--chunk-order", "reject"]
+	);
+}
+mod warn {
+	for_each_nonconsecutiveidat_file!(
+		super::reports_non_consecutive_idat,
+		&["--chunk-order", "warn"]
+	);
+}
@@ -0,0 +1,67 @@
+//! For each valid test case, asserts that inflating the dut's compressed output
+//! gives the same result as inflating the original file
+
+extern crate png_inflate_derive;
+extern crate tempfile;
+
+use png_inflate_derive::generate_for_each_files;
+use std::fs::read;
+use std::path::Path;
+use std::process::Command;
+use tempfile::NamedTempFile;
+
+const PROGRAM_EXE: &str = env!("CARGO_BIN_EXE_png_inflate");
+
+generate_for_each_files!();
+
+fn run(infile: &Path, outfile: &Path, extra_args: &[&str]) {
+	let output = Command::new(PROGRAM_EXE)
+		.arg(infile)
+		.arg(outfile)
+		.args(extra_args)
+		.output()
+		.expect("failed to execute subprocess");
+	assert!(
+		output.status.success(),
+		"subprocess execution was not success\n\n-- args:\n{:?}\n\n-- stderr:\n{}\n",
+		extra_args,
+		std::str::from_utf8(&output.stderr).expect("")
+	);
+}
+
+fn test_one(infile: &Path, extra_args: &[&str]) {
+	let clean1file = NamedTempFile::new().expect("").into_temp_path();
+	let smudgefile = NamedTempFile::new().expect("").into_temp_path();
+	let clean2file = NamedTempFile::new().expect("").into_temp_path();
+
+	run(infile, &clean1file, extra_args);
+	run(
+		&clean1file,
+		&smudgefile,
+		&[extra_args, &["--compress"]].concat(),
+	);
+	run(&smudgefile, &clean2file, extra_args);
+
+	let clean1 = read(clean1file).expect("could not read first clean output file");
+	let smudge = read(smudgefile).expect("could not read smudge output file");
+	let clean2 = read(clean2file).expect("could not read second clean output file");
+	assert!(smudge.len() <= clean1.len());
+	assert_eq!(clean1, clean2);
+}
+
+mod noargs {
+	for_each_valid_file!(super::test_one, &[]);
+}
+mod max_chunk_size {
+	// larger than every chunk of the input files, but smaller than some inflated image data
+	for_each_valid_file!(super::test_one, &["--max-chunk-size", "4200"]);
+}
+mod copy_unsafe {
+	for_each_unsafecopy_file!(super::test_one, &["--copy-unsafe"]);
+}
+mod apng {
+	for_each_apng_file!(super::test_one, &["--apng"]);
+}
+mod lenient_zlib_window {
+	for_each_widezlibwindow_file!(super::test_one, &["--lenient-zlib-window"]);
+}
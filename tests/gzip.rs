@@ -0,0 +1,109 @@
+//! Checks that gzip files are rewritten to use only stored blocks
+
+extern crate tempfile;
+
+use png_inflate::gzip;
+use std::fs::read;
+use std::fs::write;
+use std::process::Command;
+use std::process::Output;
+use tempfile::NamedTempFile;
+
+const PROGRAM_EXE: &str = env!("CARGO_BIN_EXE_png_inflate");
+
+// python: gzip.compress(b"Hello, World! Hello, World!", compresslevel=9, mtime=0)
+#[rustfmt::skip]
+const HELLO_WORLD: [u8; 37] = [
+	0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xf3, 0x48, 0xcd, 0xc9,
+	0xc9, 0xd7, 0x51, 0x08, 0xcf, 0x2f, 0xca, 0x49, 0x51, 0x54, 0xf0, 0x40, 0xe6, 0x01,
+	0x00, 0xb1, 0x16, 0xcf, 0x0c, 0x1b, 0x00, 0x00, 0x00,
+];
+
+/// Runs the program on `indata`, returning the program's output and the written file's contents
+fn run(indata: &[u8], extra_args: &[&str]) -> (Output, Vec<u8>) {
+	let infile = NamedTempFile::new().expect("").into_temp_path();
+	let outfile = NamedTempFile::new().expect("").into_temp_path();
+	write(&infile, indata).expect("could not write input file");
+
+	let output = Command::new(PROGRAM_EXE)
+		.arg(&infile)
+		.arg(&outfile)
+		.args(extra_args)
+		.output()
+		.expect("failed to execute subprocess");
+	let outdata = read(&outfile).expect("could not read output file");
+	(output, outdata)
+}
+
+#[test]
+fn inflates_gzip() {
+	let indata = [&HELLO_WORLD[..], &HELLO_WORLD[..]].concat();
+	let (output, outdata) = run(&indata, &[]);
+	assert!(
+		output.status.success(),
+		"subprocess execution was not success\n\n-- stderr:\n{}\n",
+		std::str::from_utf8(&output.stderr).expect("")
+	);
+
+	let inmembers = gzip::read(&indata).unwrap();
+	let outmembers = gzip::read(&outdata).unwrap();
+	assert_eq!(2, outmembers.len());
+	for (inmember, outmember) in inmembers.iter().zip(&outmembers) {
+		assert_eq!(inmember.data, outmember.data);
+		assert_eq!(
+			inmember.header.modification_time,
+			outmember.header.modification_time
+		);
+		assert_eq!(
+			inmember.header.operating_system,
+			outmember.header.operating_system
+		);
+	}
+	// a single final stored block: the data, plus a 10-byte header, 5-byte block header and 8-byte trailer
+	assert_eq!(2 * (27 + 10 + 5 + 8), outdata.len());
+	assert_eq!(0x01, outdata[10]);
+
+	let (_, outdata2) = run(&outdata, &[]);
+	assert_eq!(outdata, outdata2);
+}
+
+#[test]
+fn rejects_corrupt_gzip() {
+	let mut indata = HELLO_WORLD;
+	indata[30] ^= 1;
+	let (output, outdata) = run(&indata, &[]);
+	assert!(!output.status.success());
+	assert!(outdata.is_empty());
+}
+
+#[test]
+fn rejects_compress() {
+	let (output, outdata) = run(&HELLO_WORLD, &["--compress"]);
+	assert!(!output.status.success());
+	assert!(outdata.is_empty());
+}
+
+#[test]
+fn applies_limits() {
+	let indata = [&HELLO_WORLD[..], &HELLO_WORLD[..]].concat();
+	for args in [
+		["--max-chunk-size", "73"],
+		["--max-chunks", "1"],
+		["--max-inflated-size", "26"],
+		["--max-total-inflated-size", "53"],
+	] {
+		let (output, outdata) = run(&indata, &args);
+		assert!(!output.status.success(), "{:?}", args);
+		assert!(outdata.is_empty(), "{:?}", args);
+	}
+
+	for args in [
+		["--max-chunk-size", "74"],
+		["--max-chunks", "2"],
+		["--max-inflated-size", "27"],
+		["--max-total-inflated-size", "54"],
+	] {
+		let (output, _) = run(&indata, &args);
+		assert!(output.status.success(), "{:?}", args);
+	}
+}
@@ -48,7 +48,10 @@ fn test_one(infile: &Path, extra_args: &[&str]) {
 
 mod noargs {
 	for_each_valid_file!(super::test_one, &[]);
+	for_each_badcolortype_file!(super::test_one, &[]);
+	for_each_badbitdepth_file!(super::test_one, &[]);
 	for_each_otherinvalid_file!(super::test_one, &[]);
+	for_each_chunkafteriend_file!(super::test_one, &[]);
 }
 mod copy_unsafe {
 	for_each_apng_file!(super::test_one, &["--copy-unsafe"]);
@@ -58,3 +61,65 @@ mod apng {
 	for_each_valid_file!(super::test_one, &["--apng"]);
 	for_each_apng_file!(super::test_one, &["--apng"]);
 }
+mod strict {
+	for_each_valid_file!(super::test_one, &["--strict"]);
+	for_each_apng_file!(super::test_one, &["--apng", "--strict"]);
+}
+mod chunk_order {
+	mod reject {
+		for_each_valid_file!(super::super::test_one, &["--chunk-order", "reject"]);
+		for_each_apng_file!(
+			super::super::test_one,
+			&["--apng", "--chunk-order", "reject"]
+		);
+	}
+	mod fix {
+		for_each_valid_file!(super::super::test_one, &["--chunk-order", "fix"]);
+		for_each_nonconsecutiveidat_file!(super::super::test_one, &["--chunk-order", "fix"]);
+	}
+}
+mod idat_size {
+	for_each_valid_file!(super::test_one, &["--idat-size", "100"]);
+}
+mod after_iend {
+	mod strip {
+		for_each_chunkafteriend_file!(super::super::test_one, &["--after-iend", "strip"]);
+		for_each_bytesafteriend_file!(super::super::test_one, &["--after-iend", "strip"]);
+	}
+	mod error {
+		for_each_valid_file!(super::super::test_one, &["--after-iend", "error"]);
+	}
+	mod raw {
+		for_each_chunkafteriend_file!(super::super::test_one, &["--after-iend", "raw"]);
+		for_each_bytesafteriend_file!(super::super::test_one, &["--after-iend", "raw"]);
+	}
+}
+mod normalize_filters {
+	for_each_valid_file!(super::test_one, &["--normalize-filters"]);
+	for_each_apng_file!(super::test_one, &["--apng", "--normalize-filters"]);
+}
+mod deinterlace {
+	for_each_valid_file!(super::test_one, &["--deinterlace"]);
+	for_each_apng_file!(super::test_one, &["--apng", "--deinterlace"]);
+}
+mod interlace {
+	for_each_valid_file!(super::test_one, &["--interlace"]);
+}
+mod canonicalize {
+	mod smallest {
+		for_each_valid_file!(super::super::test_one, &["--canonicalize"]);
+	}
+	mod rgba {
+		for_each_valid_file!(
+			super::super::test_one,
+			&["--canonicalize", "--canonical-form", "rgba"]
+		);
+	}
+}
+mod normalize_palette {
+	for_each_valid_file!(super::test_one, &["--normalize-palette"]);
+}
+mod lenient_zlib_window {
+	for_each_valid_file!(super::test_one, &["--lenient-zlib-window"]);
+	for_each_widezlibwindow_file!(super::test_one, &["--lenient-zlib-window"]);
+}
@@ -39,9 +39,96 @@ mod noargs {
 	for_each_badmagic_file!(super::test_one, &[]);
 	for_each_badchecksum_file!(super::test_one, &[]);
 	for_each_unsafecopy_file!(super::test_one, &[]);
+	// the zlib header declares a window larger than the zlib specification allows
+	for_each_widezlibwindow_file!(super::test_one, &[]);
 
 	// acTL, fcTL and fdAT are private and not safe-to-copy, and so should be
 	// rejected without an argument explicitly allowing it, either `--copy-unsafe`
 	// or a future `--apng` argument
 	for_each_apng_file!(super::test_one, &[]);
 }
+
+mod strict {
+	for_each_badcolortype_file!(super::test_one, &["--strict"]);
+	for_each_badbitdepth_file!(super::test_one, &["--strict"]);
+}
+
+mod chunk_order {
+	// the only such file is missing its IDAT
+	mod reject {
+		for_each_otherinvalid_file!(super::super::test_one, &["--chunk-order", "reject"]);
+	}
+	mod fix {
+		for_each_otherinvalid_file!(super::super::test_one, &["--chunk-order", "fix"]);
+	}
+}
+
+mod after_iend {
+	mod keep {
+		// the data after the IEND is not chunk-shaped
+		for_each_bytesafteriend_file!(super::super::test_one, &[]);
+	}
+	mod error {
+		for_each_chunkafteriend_file!(super::super::test_one, &["--after-iend", "error"]);
+		for_each_bytesafteriend_file!(super::super::test_one, &["--after-iend", "error"]);
+	}
+}
+
+mod interlace {
+	// APNG frames cannot be reinterlaced
+	for_each_apng_file!(super::test_one, &["--apng", "--interlace"]);
+}
+
+mod limits {
+	mod max_chunk_size {
+		// every image has a 13-byte IHDR
+		for_each_valid_file!(super::super::test_one, &["--max-chunk-size", "12"]);
+	}
+	mod max_chunks {
+		// every image has at least an IHDR, an IDAT and an IEND
+		for_each_valid_file!(super::super::test_one, &["--max-chunks", "2"]);
+	}
+	mod max_inflated_size {
+		for_each_valid_file!(super::super::test_one, &["--max-inflated-size", "0"]);
+	}
+	mod max_total_inflated_size {
+		use super::super::test_one;
+		use super::super::PROGRAM_EXE;
+		use std::path::Path;
+		use std::process::Command;
+		use tempfile::NamedTempFile;
+
+		for_each_valid_file!(super::super::test_one, &["--max-total-inflated-size", "0"]);
+
+		/// Each stage that processes the image data inflates it again, which counts against the
+		/// same total
+		#[test]
+		fn applies_to_all_stages_together() {
+			// the image data inflates to 1056 bytes
+			let infile = Path::new(concat!(
+				env!("CARGO_MANIFEST_DIR"),
+				"/tests/PngSuite/basn0g08.png"
+			));
+			let outfile = NamedTempFile::new().expect("").into_temp_path();
+			let output = Command::new(PROGRAM_EXE)
+				.arg(infile)
+				.arg(&outfile)
+				.args(["--max-total-inflated-size", "2000"])
+				.output()
+				.expect("failed to execute subprocess");
+			assert!(output.status.success(), "one stage should fit in the limit");
+
+			test_one(
+				infile,
+				&[
+					"--max-total-inflated-size",
+					"2000",
+					"--canonicalize",
+					"--normalize-palette",
+					"--normalize-filters",
+					"--deinterlace",
+				],
+			);
+		}
+	}
+}
@@ -0,0 +1,67 @@
+//! For each valid test case, asserts that restoring the dut's reversible output
+//! gives the original file
+
+extern crate png_inflate_derive;
+extern crate tempfile;
+
+use png_inflate_derive::generate_for_each_files;
+use std::fs::read;
+use std::path::Path;
+use std::process::Command;
+use tempfile::NamedTempFile;
+
+const PROGRAM_EXE: &str = env!("CARGO_BIN_EXE_png_inflate");
+
+generate_for_each_files!();
+
+fn run(infile: &Path, outfile: &Path, extra_args: &[&str]) {
+	let output = Command::new(PROGRAM_EXE)
+		.arg(infile)
+		.arg(outfile)
+		.args(extra_args)
+		.output()
+		.expect("failed to execute subprocess");
+	assert!(
+		output.status.success(),
+		"subprocess execution was not success\n\n-- args:\n{:?}\n\n-- stderr:\n{}\n",
+		extra_args,
+		std::str::from_utf8(&output.stderr).expect("")
+	);
+}
+
+fn test_one(infile: &Path, extra_args: &[&str]) {
+	let cleanfile = NamedTempFile::new().expect("").into_temp_path();
+	let smudgefile = NamedTempFile::new().expect("").into_temp_path();
+
+	run(
+		infile,
+		&cleanfile,
+		&[extra_args, &["--reversible"]].concat(),
+	);
+	run(
+		&cleanfile,
+		&smudgefile,
+		&[extra_args, &["--restore"]].concat(),
+	);
+
+	let original = read(infile).expect("could not read input file");
+	let smudge = read(smudgefile).expect("could not read smudge output file");
+	assert!(original == smudge, "restored file differs from original");
+}
+
+mod noargs {
+	for_each_valid_file!(super::test_one, &[]);
+}
+mod copy_unsafe {
+	for_each_unsafecopy_file!(super::test_one, &["--copy-unsafe"]);
+}
+mod apng {
+	for_each_apng_file!(super::test_one, &["--apng"]);
+}
+mod lenient_zlib_window {
+	for_each_widezlibwindow_file!(super::test_one, &["--lenient-zlib-window"]);
+}
+mod max_chunk_size {
+	// larger than every chunk of the input files, but smaller than some inflated image data
+	for_each_valid_file!(super::test_one, &["--max-chunk-size", "4200"]);
+}
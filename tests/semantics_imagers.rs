@@ -4,7 +4,9 @@
 extern crate png_inflate_derive;
 extern crate tempfile;
 
+use png::ColorType;
 use png::Info;
+use png::Transformations;
 use png_inflate_derive::generate_for_each_files;
 use std::fs::File;
 use std::path::Path;
@@ -106,7 +108,104 @@ fn assert_info_equals(left: &Info, right: &Info) {
 	}
 }
 
+/// Asserts that two infos are equal, except for whether the image is interlaced
+fn assert_info_equals_except_interlacing(left: &Info, right: &Info) {
+	let mut left = left.clone();
+	left.interlaced = right.interlaced;
+	assert_info_equals(&left, right);
+}
+
+/// Asserts that two infos are equal, except for how the pixels are represented
+fn assert_info_equals_except_representation(left: &Info, right: &Info) {
+	let mut left = left.clone();
+	left.color_type = right.color_type;
+	left.bit_depth = right.bit_depth;
+	left.trns = right.trns.clone();
+	left.palette = right.palette.clone();
+	assert_info_equals(&left, right);
+}
+
+/// Decodes every frame of a png file, with each pixel's red, green, blue and alpha samples scaled
+/// to 16 bits
+fn decode_rgba16(file: &Path) -> (Info<'static>, Vec<Vec<[u16; 4]>>) {
+	let mut decoder = png::Decoder::new(File::open(file).unwrap());
+	decoder.set_transformations(Transformations::EXPAND);
+	let mut reader = decoder.read_info().unwrap();
+	let (color_type, bit_depth) = reader.output_color_type();
+	let channels = color_type.samples();
+	let sample_bytes = if bit_depth == png::BitDepth::Sixteen {
+		2
+	} else {
+		1
+	};
+	let frame_count = reader
+		.info()
+		.animation_control()
+		.map(|x| x.num_frames)
+		.unwrap_or(1);
+
+	let mut buffer = vec![0; reader.output_buffer_size()];
+	let mut frames = Vec::new();
+	for _ in 0..frame_count {
+		let frame_info = reader.next_frame(&mut buffer).unwrap();
+		let frame = buffer[..frame_info.buffer_size()]
+			.chunks_exact(channels * sample_bytes)
+			.map(|pixel| {
+				let samples: Vec<u16> = pixel
+					.chunks_exact(sample_bytes)
+					.map(|x| match x {
+						[x] => u16::from(*x) * 257,
+						[x, y] => u16::from_be_bytes([*x, *y]),
+						_ => unreachable!(),
+					})
+					.collect();
+				match color_type {
+					ColorType::Grayscale => [samples[0], samples[0], samples[0], u16::MAX],
+					ColorType::GrayscaleAlpha => [samples[0], samples[0], samples[0], samples[1]],
+					ColorType::Rgb => [samples[0], samples[1], samples[2], u16::MAX],
+					ColorType::Rgba => [samples[0], samples[1], samples[2], samples[3]],
+					ColorType::Indexed => panic!("palette was not expanded"),
+				}
+			})
+			.collect();
+		frames.push(frame);
+	}
+	(reader.info().clone().to_owned(), frames)
+}
+
+/// Like `test_one`, but for arguments that change how the pixels are represented
+fn test_canonicalized(infile: &Path, extra_args: &[&str]) {
+	let cleanfile = NamedTempFile::new().expect("");
+	let cleanfile = cleanfile.into_temp_path();
+
+	let output_inflate = Command::new(PROGRAM_EXE)
+		.arg(infile)
+		.arg(&cleanfile)
+		.args(extra_args)
+		.output()
+		.expect("failed to execute png_inflate process");
+	assert!(
+		output_inflate.status.success(),
+		"png_inflate execution was not success\n\n-- stderr:\n{}\n",
+		std::str::from_utf8(&output_inflate.stderr).expect("")
+	);
+
+	let (input_info, input_frames) = decode_rgba16(infile);
+	let (clean_info, clean_frames) = decode_rgba16(&cleanfile);
+	assert_info_equals_except_representation(&input_info, &clean_info);
+	assert!(input_frames == clean_frames);
+}
+
 fn test_one(infile: &Path, extra_args: &[&str]) {
+	test_with(infile, extra_args, assert_info_equals);
+}
+
+/// Like `test_one`, but for arguments that change the interlace method
+fn test_reinterlaced(infile: &Path, extra_args: &[&str]) {
+	test_with(infile, extra_args, assert_info_equals_except_interlacing);
+}
+
+fn test_with(infile: &Path, extra_args: &[&str], assert_info_equals: fn(&Info, &Info)) {
 	let cleanfile = NamedTempFile::new().expect("");
 	let cleanfile = cleanfile.into_temp_path();
 
@@ -158,3 +257,40 @@ mod copy_unsafe {
 mod apng {
 	for_each_apng_file!(super::test_one, &["--apng"]);
 }
+mod compress {
+	for_each_valid_file!(super::test_one, &["--compress"]);
+	for_each_apng_file!(super::test_one, &["--apng", "--compress"]);
+}
+mod idat_size {
+	for_each_valid_file!(super::test_one, &["--idat-size", "100"]);
+	for_each_apng_file!(super::test_one, &["--apng", "--idat-size", "100"]);
+}
+mod normalize_filters {
+	for_each_valid_file!(super::test_one, &["--normalize-filters"]);
+	for_each_apng_file!(super::test_one, &["--apng", "--normalize-filters"]);
+}
+mod deinterlace {
+	for_each_valid_file!(super::test_reinterlaced, &["--deinterlace"]);
+	for_each_apng_file!(super::test_reinterlaced, &["--apng", "--deinterlace"]);
+}
+mod interlace {
+	for_each_valid_file!(super::test_reinterlaced, &["--interlace"]);
+}
+mod canonicalize {
+	mod smallest {
+		for_each_valid_file!(super::super::test_canonicalized, &["--canonicalize"]);
+		for_each_apng_file!(
+			super::super::test_canonicalized,
+			&["--apng", "--canonicalize"]
+		);
+	}
+	mod rgba {
+		for_each_valid_file!(
+			super::super::test_canonicalized,
+			&["--canonicalize", "--canonical-form", "rgba"]
+		);
+	}
+}
+mod normalize_palette {
+	for_each_valid_file!(super::test_canonicalized, &["--normalize-palette"]);
+}
@@ -48,6 +48,8 @@ fn test_one(infile: &Path, extra_args: &[&str]) {
 
 mod noargs {
 	for_each_valid_file!(super::test_one, &[]);
+	for_each_badcolortype_file!(super::test_one, &[]);
+	for_each_badbitdepth_file!(super::test_one, &[]);
 	for_each_otherinvalid_file!(super::test_one, &[]);
 }
 mod copy_unsafe {